@@ -0,0 +1,65 @@
+//! Sealing and opening messages of 64 B, 64 KB and 8 MB with the default noise parameters.
+//!
+//! Run with `cargo bench --bench encrypt`.
+
+use canary::async_snow::{self, Decrypt, Encrypt, RefDividedSnow, StatelessTransportState};
+use canary::providers::Memory;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const SIZES: [(usize, &str); 3] = [(64, "64B"), (64 << 10, "64KB"), (8 << 20, "8MB")];
+
+/// transports of both ends of a channel after a handshake
+fn transports() -> (StatelessTransportState, StatelessTransportState) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let (a, b) = Memory::pair();
+        let (mut a, mut b) = (a.raw(), b.raw());
+        tokio::try_join!(async_snow::new(&mut a), async_snow::new(&mut b)).unwrap()
+    })
+}
+
+fn encrypt(c: &mut Criterion) {
+    let (a, b) = transports();
+    let mut group = c.benchmark_group("encrypt");
+    for (len, name) in SIZES {
+        let plain = vec![0x5a; len];
+        group.throughput(Throughput::Bytes(len as u64));
+        if len > 1 << 20 {
+            group.sample_size(20);
+        }
+
+        group.bench_with_input(BenchmarkId::new("seal", name), &plain, |bench, plain| {
+            let mut nonce = 0;
+            bench.iter(|| {
+                RefDividedSnow {
+                    transport: &a,
+                    nonce: &mut nonce,
+                }
+                .encrypt_packets(plain)
+                .unwrap()
+            })
+        });
+
+        let sealed = RefDividedSnow {
+            transport: &a,
+            nonce: &mut 0,
+        }
+        .encrypt_packets(&plain)
+        .unwrap();
+        group.bench_with_input(BenchmarkId::new("open", name), &sealed, |bench, sealed| {
+            // every iteration opens the same packets, sealed from the first nonce
+            bench.iter(|| {
+                RefDividedSnow {
+                    transport: &b,
+                    nonce: &mut 0,
+                }
+                .decrypt(sealed)
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encrypt);
+criterion_main!(benches);
@@ -0,0 +1,94 @@
+//! Serializing messages into a reused buffer with `serialize_into`
+//! against allocating a new one for every message with `serialize`,
+//! on their own and through the send path of a channel.
+//!
+//! Run with `cargo bench --bench serialize`.
+
+use canary::providers::Memory;
+use canary::serialization::formats::{Format, SendFormat};
+use canary::Channel;
+use criterion::{criterion_group, criterion_main, Criterion};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct Message {
+    id: u64,
+    name: String,
+    tags: Vec<String>,
+    samples: Vec<u32>,
+}
+
+fn message() -> Message {
+    Message {
+        id: 42,
+        name: "temperature".into(),
+        tags: vec!["kitchen".into(), "celsius".into()],
+        samples: (0..256).collect(),
+    }
+}
+
+/// Bincode without its `serialize_into`, so every message gets a buffer of its own
+/// which is then copied into the frame, like before the scratch buffer of channels
+struct Allocating;
+
+impl SendFormat for Allocating {
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> canary::Result<Vec<u8>> {
+        SendFormat::serialize(&mut Format::Bincode, obj)
+    }
+}
+
+fn formats(c: &mut Criterion) {
+    let message = message();
+    let mut group = c.benchmark_group("format");
+    group.bench_function("serialize", |bench| {
+        bench.iter(|| SendFormat::serialize(&mut Format::Bincode, &message).unwrap())
+    });
+    let mut buffer = Vec::new();
+    group.bench_function("serialize_into", |bench| {
+        bench.iter(|| {
+            buffer.clear();
+            Format::Bincode
+                .serialize_into(&message, &mut buffer)
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+/// both ends of a raw channel, the first one sending with `format`
+fn pair<W>(format: W) -> (Channel<Format, W>, Channel) {
+    let (a, b) = Memory::pair();
+    (a.raw().with_formats(Format::Bincode, format), b.raw())
+}
+
+fn channels(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let message = message();
+    let mut group = c.benchmark_group("channel send");
+
+    // the receiving side allocates the same in both, only the sending side differs
+    let (mut a, mut b) = pair(Allocating);
+    group.bench_function("serialize", |bench| {
+        bench.iter(|| {
+            runtime.block_on(async {
+                a.send(&message).await.unwrap();
+                b.receive::<Message>().await.unwrap()
+            })
+        })
+    });
+    let (mut a, mut b) = pair(Format::Bincode);
+    group.bench_function("serialize_into", |bench| {
+        bench.iter(|| {
+            runtime.block_on(async {
+                a.send(&message).await.unwrap();
+                b.receive::<Message>().await.unwrap()
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, formats, channels);
+criterion_main!(benches);
@@ -0,0 +1,55 @@
+//! Feed arbitrary bytes to every format through `Format::try_deserialize`,
+//! which has to return an error instead of panicking on whatever it is given.
+//!
+//! Run with `cargo fuzz run try_deserialize` from the root of the repository.
+#![no_main]
+
+use std::collections::{BTreeMap, HashMap};
+
+use canary::serialization::formats::Format;
+use libfuzzer_sys::fuzz_target;
+use serde::Deserialize;
+
+/// the kind of message services exchange, covering what serde can describe
+/// without recursion, which could overflow the stack on deep enough inputs
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Request {
+    id: u64,
+    name: String,
+    payload: Vec<u8>,
+    tags: HashMap<String, i32>,
+    scores: BTreeMap<u16, f64>,
+    reply_to: Option<(String, u16)>,
+    kind: Kind,
+    flags: [bool; 4],
+    character: char,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+enum Kind {
+    Ping,
+    Get(String),
+    Put { key: String, value: Vec<u8> },
+    Batch(Vec<(u32, i128)>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+#[allow(dead_code)]
+enum Untagged {
+    Number(i64),
+    Text(String),
+    Pair(u8, Option<bool>),
+}
+
+fuzz_target!(|bytes: &[u8]| {
+    for format in Format::SUPPORTED {
+        let _ = format.try_deserialize::<Request>(bytes);
+        let _ = format.try_deserialize::<Kind>(bytes);
+        let _ = format.try_deserialize::<Untagged>(bytes);
+        let _ = format.try_deserialize::<Vec<String>>(bytes);
+        let _ = format.try_deserialize::<()>(bytes);
+    }
+});
@@ -1,22 +1,83 @@
-use crate::Result;
+use std::collections::HashSet;
+use std::future::Future;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+use base64::prelude::{Engine, BASE64_STANDARD};
+
+use crate::channel::frame;
+use crate::error::HandshakeError;
+use crate::io::{Read, ReadExt, Write, WriteExt};
+use crate::serialization::framing::{decode_len, encode_len, LEN_PREFIX};
 use crate::{err, Channel};
-use snow::{params::*, StatelessTransportState};
+use crate::{Error, Result};
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::{params::*, HandshakeState};
+use tracing::Instrument;
+
+pub use snow::params::{HandshakePattern, NoiseParams};
+pub use snow::StatelessTransportState;
+
+pub(crate) const PACKET_LEN: u64 = 65519;
+/// length of the authentication tag appended to every packet
+const TAG_LEN: usize = 16;
 
-const PACKET_LEN: u64 = 65519;
+/// time a peer has to complete the handshake before it is aborted
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// bytes starting the first message of every handshake,
+/// they tell a peer starting a handshake apart from one sending plaintext messages
+const HELLO: &[u8] = b"canary";
+/// version of the handshake, sent right after `HELLO`.
+/// Version 2 added the primitives of each side to the hello, version 3 the padding,
+/// version 4 the confirmation of the hellos once the handshake finished
+const HANDSHAKE_VERSION: u8 = 4;
+/// times both sides can draw the same number before the handshake is given up.
+/// Honest peers tie with a chance of one in 2^64, so this only stops peers echoing the hello
+const HELLO_ATTEMPTS: usize = 16;
+/// longest message the Noise protocol allows,
+/// also the longest frame received while the handshake runs
+const MAX_HANDSHAKE_LEN: usize = 65535;
+/// length of the buffer handshake messages are written into and read from,
+/// the messages of the supported patterns are far shorter
+const HANDSHAKE_BUFFER_LEN: usize = 256;
+/// first byte of a frame padded to hide its length, never the kind of a frame
+const PADDED: u8 = 0xff;
+
+/// Transport state shared by the send and receive halves of a split channel.
+/// Sealing and opening packets only takes the read lock, rekeying a direction takes the write lock
+pub type SharedTransport = Arc<RwLock<StatelessTransportState>>;
+
+#[inline]
+/// read the transport shared by the halves of a channel
+pub(crate) fn read(transport: &SharedTransport) -> RwLockReadGuard<'_, StatelessTransportState> {
+    transport.read().unwrap_or_else(|e| e.into_inner())
+}
+
+#[inline]
+/// write the transport shared by the halves of a channel
+pub(crate) fn write(transport: &SharedTransport) -> RwLockWriteGuard<'_, StatelessTransportState> {
+    transport.write().unwrap_or_else(|e| e.into_inner())
+}
 
 /// helper struct that can be used to encrypt messages.
 /// it contains the transport and a nonce.
 pub struct RefDividedSnow<'a> {
     /// reference to transport state
     pub transport: &'a StatelessTransportState,
-    /// external nonce
-    pub nonce: &'a mut u32,
+    /// nonce of the next packet in this direction, incremented with every packet.
+    /// Both peers count the packets of each direction, so nonces are never sent,
+    /// and a replayed or reordered packet fails to decrypt
+    pub nonce: &'a mut u64,
 }
 
 /// helper trait used to encrypt
 pub trait Encrypt {
     /// encrypt buffer into another
-    fn encrypt_packets(&mut self, buf: Vec<u8>) -> Result<Vec<u8>>;
+    fn encrypt_packets(&mut self, buf: &[u8]) -> Result<Vec<u8>>;
 }
 
 /// helper trait used to decrypt
@@ -26,90 +87,1348 @@ pub trait Decrypt {
 }
 
 impl RefDividedSnow<'_> {
-    // returns an error if length of buf is greater than the packet length
-    fn encrypt_packet(&mut self, buf: &[u8]) -> Result<Vec<u8>> {
-        // create message buffer
-        let mut msg = vec![0u8; buf.len() + 16];
-        // encrypt into message buffer
-        self.encrypt_packet_raw(buf, &mut msg)?;
-        Ok(msg)
+    /// take the nonce of the next packet, a nonce is never used twice with the same key
+    fn next_nonce(&mut self) -> Result<u64> {
+        let nonce = *self.nonce;
+        *self.nonce = nonce
+            .checked_add(1)
+            .ok_or_else(|| err!(other, "nonces of the channel are exhausted"))?;
+        Ok(nonce)
     }
-    fn encrypt_packet_raw(&mut self, buf: &[u8], mut msg: &mut [u8]) -> Result<()> {
+    /// Encrypt a frame, padding it first with the given scheme.
+    ///
+    /// A padded frame starts with `PADDED` followed by the length prefix of the frame,
+    /// then the frame itself and zeros up to the length given by the scheme,
+    /// so only the padded length is visible outside the encryption.
+    /// `decrypt` removes the padding of any frame starting with `PADDED`
+    pub(crate) fn seal(&mut self, frame: &[u8], padding: Padding) -> Result<Vec<u8>> {
+        let len = match padding.padded_len(1 + LEN_PREFIX + frame.len()) {
+            Some(len) => len,
+            None => return self.encrypt_packets(frame),
+        };
+        let mut padded = Vec::with_capacity(len);
+        padded.push(PADDED);
+        padded.extend_from_slice(&encode_len(frame.len()));
+        padded.extend_from_slice(frame);
+        padded.resize(len, 0);
+        self.encrypt_packets(&padded)
+    }
+    // returns an error if length of buf is greater than the packet length
+    fn encrypt_packet_raw(&mut self, buf: &[u8], msg: &mut [u8]) -> Result<usize> {
         // encrypt into message buffer
-        let nonce = self.nonce.wrapping_add(1) as _;
+        let nonce = self.next_nonce()?;
         self.transport
-            .write_message(nonce, buf, &mut msg)
-            .map_err(err!(@invalid_data))?;
-        Ok(())
+            .write_message(nonce, buf, msg)
+            .map_err(err!(@invalid_data))
+    }
+    fn decrypt_packet_raw(&mut self, buf: &[u8], msg: &mut [u8]) -> Result<usize> {
+        let nonce = self.next_nonce()?;
+        self.transport.read_message(nonce, buf, msg).map_err(|_| {
+            err!(
+                invalid_data,
+                "packet failed to decrypt, it was tampered with, replayed or reordered"
+            )
+        })
+    }
+
+    /// Encrypt everything the reader yields into the writer one packet at a time,
+    /// so only a single packet is kept in memory. Returns the length of the plaintext.
+    ///
+    /// Every packet is prefixed with its length, and its plaintext starts with a flag
+    /// marking the last packet, which lets `decrypt_stream` detect a truncated stream.
+    /// Nonces come from `nonce`, which has to be the only counter used
+    /// with this transport in this direction
+    /// ```no_run
+    /// # async fn example(transport: snow::StatelessTransportState) -> canary::Result<()> {
+    /// use canary::async_snow::RefDividedSnow;
+    ///
+    /// let mut nonce = 0;
+    /// let mut snow = RefDividedSnow { transport: &transport, nonce: &mut nonce };
+    /// let file = tokio::fs::File::open("backup.tar").await?;
+    /// let encrypted = tokio::fs::File::create("backup.tar.enc").await?;
+    /// snow.encrypt_stream(file, encrypted).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn encrypt_stream<Rd: Read + Unpin, Wr: Write + Unpin>(
+        &mut self,
+        mut reader: Rd,
+        mut writer: Wr,
+    ) -> Result<u64> {
+        let mut plain = vec![0u8; PACKET_LEN as usize];
+        let mut cipher = vec![0u8; PACKET_LEN as usize + TAG_LEN];
+        let mut total = 0;
+        loop {
+            // the first byte is the flag
+            let mut filled = 1;
+            while filled < plain.len() {
+                match reader.read(&mut plain[filled..]).await? {
+                    0 => break,
+                    read => filled += read,
+                }
+            }
+            let last = filled < plain.len();
+            plain[0] = last as u8;
+            let len = self.encrypt_packet_raw(&plain[..filled], &mut cipher)?;
+            writer.write_all(&encode_len(len)).await?;
+            writer.write_all(&cipher[..len]).await?;
+            total += filled as u64 - 1;
+            if last {
+                writer.flush().await?;
+                return Ok(total);
+            }
+        }
+    }
+
+    /// Decrypt a stream written by `encrypt_stream` into the writer one packet at a time.
+    /// Returns the length of the plaintext,
+    /// or an `UnexpectedEof` error if the stream ends before its last packet
+    /// ```no_run
+    /// # async fn example(transport: snow::StatelessTransportState) -> canary::Result<()> {
+    /// use canary::async_snow::RefDividedSnow;
+    ///
+    /// let mut nonce = 0;
+    /// let mut snow = RefDividedSnow { transport: &transport, nonce: &mut nonce };
+    /// let encrypted = tokio::fs::File::open("backup.tar.enc").await?;
+    /// let file = tokio::fs::File::create("backup.tar").await?;
+    /// snow.decrypt_stream(encrypted, file).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn decrypt_stream<Rd: Read + Unpin, Wr: Write + Unpin>(
+        &mut self,
+        mut reader: Rd,
+        mut writer: Wr,
+    ) -> Result<u64> {
+        let truncated = |e: std::io::Error| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => {
+                err!(
+                    unexpected_eof,
+                    "encrypted stream ended before its last packet"
+                )
+            }
+            _ => e.into(),
+        };
+        let mut cipher = vec![0u8; PACKET_LEN as usize + TAG_LEN];
+        let mut plain = vec![0u8; PACKET_LEN as usize];
+        let mut total = 0;
+        loop {
+            let mut prefix = [0; LEN_PREFIX];
+            reader.read_exact(&mut prefix).await.map_err(truncated)?;
+            let len = decode_len(prefix)?;
+            if len > cipher.len() {
+                err!((invalid_data, "packet of the encrypted stream is too long"))?
+            }
+            reader
+                .read_exact(&mut cipher[..len])
+                .await
+                .map_err(truncated)?;
+            let read = self.decrypt_packet_raw(&cipher[..len], &mut plain)?;
+            let last = match plain[..read].first() {
+                Some(0) => false,
+                Some(1) => true,
+                _ => err!((invalid_data, "malformed packet in the encrypted stream"))?,
+            };
+            writer.write_all(&plain[1..read]).await?;
+            total += read as u64 - 1;
+            if last {
+                writer.flush().await?;
+                return Ok(total);
+            }
+        }
     }
 }
 
 impl Encrypt for RefDividedSnow<'_> {
-    fn encrypt_packets(&mut self, buf: Vec<u8>) -> Result<Vec<u8>> {
-        let mut total = Vec::with_capacity(buf.len() + 16);
+    fn encrypt_packets(&mut self, buf: &[u8]) -> Result<Vec<u8>> {
+        // every packet carries its own tag, an empty buffer is sent as a packet with just a tag
+        // so that it is authenticated like any other frame
+        let packets = buf.len().div_ceil(PACKET_LEN as usize).max(1);
+        let mut total = vec![0u8; buf.len() + packets * TAG_LEN];
+        let mut written = 0;
+        if buf.is_empty() {
+            written += self.encrypt_packet_raw(buf, &mut total)?;
+        }
         for buf in buf.chunks(PACKET_LEN as _) {
-            let mut buf = self.encrypt_packet(buf)?;
-            total.append(&mut buf);
+            written += self.encrypt_packet_raw(buf, &mut total[written..])?;
         }
+        total.truncate(written);
         Ok(total)
     }
 }
 
 impl Decrypt for RefDividedSnow<'_> {
     fn decrypt(&mut self, buf: &[u8]) -> Result<Vec<u8>> {
-        let mut bytes = vec![];
-        for buf in buf.chunks(PACKET_LEN as usize + 16) {
-            let mut message = vec![0u8; buf.len()]; // move message outside the loop
+        // every packet carries its own tag, a packet too short to hold one fails to decrypt
+        if buf.is_empty() {
+            return err!((invalid_data, "received an encrypted frame without a packet"));
+        }
+        let packets = buf.len().div_ceil(PACKET_LEN as usize + TAG_LEN);
+        let mut bytes = vec![0u8; buf.len().saturating_sub(packets * TAG_LEN)];
+        let mut read = 0;
+        for buf in buf.chunks(PACKET_LEN as usize + TAG_LEN) {
+            read += self.decrypt_packet_raw(buf, &mut bytes[read..])?;
+        }
+        bytes.truncate(read);
+        unpad(bytes)
+    }
+}
 
-            let nonce = self.nonce.wrapping_add(1) as _;
+/// remove the padding of a frame sealed with a hidden length, other frames are left as is
+fn unpad(mut bytes: Vec<u8>) -> Result<Vec<u8>> {
+    if bytes.first() != Some(&PADDED) {
+        return Ok(bytes);
+    }
+    let malformed = || err!(invalid_data, "malformed padded frame");
+    let prefix = bytes
+        .get(1..1 + LEN_PREFIX)
+        .and_then(|prefix| <[u8; LEN_PREFIX]>::try_from(prefix).ok())
+        .ok_or_else(malformed)?;
+    let len = decode_len(prefix)?;
+    if len > bytes.len() - 1 - LEN_PREFIX {
+        return Err(malformed());
+    }
+    bytes.drain(..1 + LEN_PREFIX);
+    bytes.truncate(len);
+    Ok(bytes)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// How frames are padded inside the encryption so their length is hidden,
+/// see `Channel::pad_frames`.
+///
+/// A padded frame carries its real length inside the encryption, along with a marker byte,
+/// so these 9 bytes come on top of the frame before it is rounded up.
+/// Every frame also keeps the 16-byte tag of each packet it is encrypted in
+pub enum Padding {
+    /// Frames are sent as they are, the length of each is visible to observers
+    #[default]
+    None,
+    /// Frames are padded up to the next power of two, so only the order of magnitude
+    /// of their length is visible. Costs up to as many bytes as the frame itself,
+    /// close to doubling the bandwidth in the worst case
+    PowerOfTwo,
+    /// Frames are padded up to a multiple of the given number of bytes, so lengths are
+    /// visible to the nearest bucket. Costs up to the size of a bucket per frame,
+    /// a lot for small messages and next to nothing for large ones.
+    /// A bucket of 0 bytes is taken as 1
+    Bucket(u32),
+}
+
+impl Padding {
+    /// length a padded frame of `len` bytes is rounded up to, `None` if frames aren't padded
+    fn padded_len(&self, len: usize) -> Option<usize> {
+        match *self {
+            Padding::None => None,
+            Padding::PowerOfTwo => Some(len.next_power_of_two()),
+            Padding::Bucket(size) => Some(len.next_multiple_of((size as usize).max(1))),
+        }
+    }
+    /// tag of the scheme sent in the hello, its kind followed by the size of its buckets
+    fn tag(&self) -> [u8; 5] {
+        let (kind, size) = match *self {
+            Padding::None => (0, 0),
+            Padding::PowerOfTwo => (1, 0),
+            Padding::Bucket(size) => (2, size),
+        };
+        let [a, b, c, d] = size.to_be_bytes();
+        [kind, a, b, c, d]
+    }
+    /// scheme of a tag sent in the hello, `None` if the tag is unknown
+    fn of_tag([kind, size @ ..]: [u8; 5]) -> Option<Self> {
+        match kind {
+            0 => Some(Padding::None),
+            1 => Some(Padding::PowerOfTwo),
+            2 => Some(Padding::Bucket(u32::from_be_bytes(size))),
+            _ => None,
+        }
+    }
+    /// Fail if the peer pads its frames with another scheme.
+    /// As with primitives, neither side adopts the scheme of the other
+    fn check_peer(&self, theirs: [u8; 5]) -> Result<()> {
+        if theirs == self.tag() {
+            return Ok(());
+        }
+        let theirs = match Padding::of_tag(theirs) {
+            Some(theirs) => format!("{:?}", theirs),
+            None => "an unknown scheme".into(),
+        };
+        err!((
+            invalid_data,
+            format!(
+                "peer pads frames with {}, this side pads with {:?}, both sides need the same padding",
+                theirs, self
+            )
+        ))
+    }
+}
+
+/// length of the keys of a `StaticKeypair`
+const KEY_LEN: usize = 32;
+
+#[derive(Clone)]
+/// Long-lived keypair that identifies a side of a channel,
+/// used by the XX and IK handshakes to authenticate peers
+pub struct StaticKeypair {
+    private: [u8; KEY_LEN],
+    public: [u8; KEY_LEN],
+}
+
+impl StaticKeypair {
+    /// Generate a new random keypair
+    pub fn generate() -> Result<Self> {
+        let keypair = snow::Builder::new(params(HandshakePattern::XX, vec![]))
+            .generate_keypair()
+            .map_err(err!(@other))?;
+        let mut bytes = [0; 2 * KEY_LEN];
+        bytes[..KEY_LEN].copy_from_slice(&keypair.private);
+        bytes[KEY_LEN..].copy_from_slice(&keypair.public);
+        Ok(Self::from_bytes(bytes))
+    }
+    /// Restore a keypair persisted with `to_bytes`
+    pub fn from_bytes(bytes: [u8; 2 * KEY_LEN]) -> Self {
+        let mut private = [0; KEY_LEN];
+        let mut public = [0; KEY_LEN];
+        private.copy_from_slice(&bytes[..KEY_LEN]);
+        public.copy_from_slice(&bytes[KEY_LEN..]);
+        StaticKeypair { private, public }
+    }
+    /// Private key followed by the public key, to persist the identity.
+    /// Keep them secret, anyone with them can impersonate this side
+    pub fn to_bytes(&self) -> [u8; 2 * KEY_LEN] {
+        let mut bytes = [0; 2 * KEY_LEN];
+        bytes[..KEY_LEN].copy_from_slice(&self.private);
+        bytes[KEY_LEN..].copy_from_slice(&self.public);
+        bytes
+    }
+    /// Public key, which peers use to recognize this side
+    pub fn public(&self) -> &[u8; KEY_LEN] {
+        &self.public
+    }
+}
 
-            self.transport
-                .read_message(nonce, &buf, &mut message)
-                .map_err(|e| err!(other, e.to_string()))?;
-            bytes.append(&mut message);
+#[derive(Clone)]
+/// Configuration of a handshake started with `new_with_config`
+pub struct SnowConfig {
+    /// handshake pattern, such as `HandshakePattern::XX`
+    pub pattern: HandshakePattern,
+    /// pre-shared keys, each with the position of its `psk` modifier in the pattern
+    pub psks: Vec<(u8, [u8; 32])>,
+    /// data mixed into the handshake, which both sides need to agree on
+    pub prologue: Option<Vec<u8>>,
+    /// static keypair of this side, needed by patterns that send or know it
+    pub local_static: Option<StaticKeypair>,
+    /// static key of the peer, needed by patterns where it is known beforehand
+    pub remote_public: Option<Vec<u8>>,
+    /// whether this side initiates the handshake,
+    /// elected by exchanging random numbers with the peer if `None`
+    pub initiator: Option<bool>,
+    /// bytes sent before the key is rotated automatically,
+    /// set on the channel the handshake runs on, see `Channel::rekey_after`
+    pub rekey_after_bytes: Option<u64>,
+    /// time the peer has to complete the handshake, `None` waits forever
+    pub timeout: Option<Duration>,
+    /// primitives used by the handshake and the channel it encrypts
+    pub encryption: Encryption,
+    /// Padding of the frames of the channel the handshake runs on, see `Channel::pad_frames`.
+    /// Both sides need the same scheme: it is checked against the one of the peer
+    /// in the hello. When the initiator is set beforehand there's no hello, so it is
+    /// mixed into the prologue instead, and a mismatch fails to decrypt
+    pub padding: Padding,
+}
+
+impl SnowConfig {
+    /// Configuration of a handshake with the given pattern and nothing else
+    pub fn new(pattern: HandshakePattern) -> Self {
+        SnowConfig {
+            pattern,
+            psks: vec![],
+            prologue: None,
+            local_static: None,
+            remote_public: None,
+            initiator: None,
+            rekey_after_bytes: None,
+            timeout: Some(HANDSHAKE_TIMEOUT),
+            encryption: Encryption::default(),
+            padding: Padding::None,
         }
-        Ok(bytes)
+    }
+
+    /// noise parameters of the handshake, with the modifiers of the psks
+    fn noise_params(&self) -> NoiseParams {
+        let modifiers = self
+            .psks
+            .iter()
+            .map(|(location, _)| HandshakeModifier::Psk(*location))
+            .collect();
+        self.encryption.params(self.pattern, modifiers)
     }
 }
 
-/// Starts a new snow stream using the default noise parameters
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// Cipher used to encrypt the frames of a channel
+pub enum Cipher {
+    /// ChaCha20-Poly1305
+    #[default]
+    ChaChaPoly,
+    /// AES-256-GCM
+    Aes256Gcm,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// Hash function used by the handshake
+pub enum Hash {
+    /// BLAKE2s
+    #[default]
+    Blake2s,
+    /// BLAKE2b
+    Blake2b,
+    /// SHA-256
+    Sha256,
+    /// SHA-512
+    Sha512,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// Diffie-Hellman function used by the handshake
+pub enum Dh {
+    /// X25519
+    #[default]
+    Curve25519,
+    /// X448, not supported by the noise implementation yet
+    Curve448,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// Builder of the primitives used by a handshake and the channel it encrypts,
+/// so they can be chosen without building `NoiseParams` by hand.
+/// Defaults to the primitives used by `new`.
+///
+/// Both sides need to choose the same primitives.
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+/// use canary::async_snow::{
+///     new_with_config, Cipher, EncryptionConfig, HandshakePattern, Hash, SnowConfig,
+/// };
+///
+/// let config = SnowConfig {
+///     encryption: EncryptionConfig::default()
+///         .cipher(Cipher::Aes256Gcm)
+///         .hash(Hash::Sha512)
+///         .build()?,
+///     ..SnowConfig::new(HandshakePattern::NN)
+/// };
+/// let transport = new_with_config(&mut chan, &config).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct EncryptionConfig {
+    cipher: Cipher,
+    hash: Hash,
+    dh: Dh,
+}
+
+impl EncryptionConfig {
+    #[inline]
+    /// Set the cipher
+    pub fn cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = cipher;
+        self
+    }
+    #[inline]
+    /// Set the hash function
+    pub fn hash(mut self, hash: Hash) -> Self {
+        self.hash = hash;
+        self
+    }
+    #[inline]
+    /// Set the Diffie-Hellman function
+    pub fn dh(mut self, dh: Dh) -> Self {
+        self.dh = dh;
+        self
+    }
+    /// Check that every primitive is supported,
+    /// so an unsupported choice fails here instead of in the middle of a handshake
+    pub fn build(self) -> Result<Encryption> {
+        let encryption = Encryption(self);
+        let resolver = DefaultResolver;
+        if resolver.resolve_dh(&encryption.dh_choice()).is_none() {
+            err!((unsupported, format!("{:?} is not supported", self.dh)))?
+        }
+        if resolver.resolve_hash(&encryption.hash_choice()).is_none() {
+            err!((unsupported, format!("{:?} is not supported", self.hash)))?
+        }
+        if resolver
+            .resolve_cipher(&encryption.cipher_choice())
+            .is_none()
+        {
+            err!((unsupported, format!("{:?} is not supported", self.cipher)))?
+        }
+        Ok(encryption)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+/// Supported primitives used by a handshake, built by `EncryptionConfig`
+pub struct Encryption(EncryptionConfig);
+
+impl Encryption {
+    /// noise parameters of a handshake with these primitives
+    fn params(&self, pattern: HandshakePattern, modifiers: Vec<HandshakeModifier>) -> NoiseParams {
+        NoiseParams::new(
+            "".into(),
+            BaseChoice::Noise,
+            HandshakeChoice {
+                pattern,
+                modifiers: HandshakeModifierList { list: modifiers },
+            },
+            self.dh_choice(),
+            self.cipher_choice(),
+            self.hash_choice(),
+        )
+    }
+    fn cipher_choice(&self) -> CipherChoice {
+        match self.0.cipher {
+            Cipher::ChaChaPoly => CipherChoice::ChaChaPoly,
+            Cipher::Aes256Gcm => CipherChoice::AESGCM,
+        }
+    }
+    fn hash_choice(&self) -> HashChoice {
+        match self.0.hash {
+            Hash::Blake2s => HashChoice::Blake2s,
+            Hash::Blake2b => HashChoice::Blake2b,
+            Hash::Sha256 => HashChoice::SHA256,
+            Hash::Sha512 => HashChoice::SHA512,
+        }
+    }
+    fn dh_choice(&self) -> DHChoice {
+        match self.0.dh {
+            Dh::Curve25519 => DHChoice::Curve25519,
+            Dh::Curve448 => DHChoice::Ed448,
+        }
+    }
+    /// primitives of noise parameters built by hand
+    fn of(params: &NoiseParams) -> Self {
+        Encryption(EncryptionConfig {
+            cipher: match params.cipher {
+                CipherChoice::ChaChaPoly => Cipher::ChaChaPoly,
+                CipherChoice::AESGCM => Cipher::Aes256Gcm,
+            },
+            hash: match params.hash {
+                HashChoice::Blake2s => Hash::Blake2s,
+                HashChoice::Blake2b => Hash::Blake2b,
+                HashChoice::SHA256 => Hash::Sha256,
+                HashChoice::SHA512 => Hash::Sha512,
+            },
+            dh: match params.dh {
+                DHChoice::Curve25519 => Dh::Curve25519,
+                DHChoice::Ed448 => Dh::Curve448,
+            },
+        })
+    }
+    /// tags of the cipher, hash and DH function, sent in the hello
+    fn tags(&self) -> [u8; 3] {
+        let cipher = match self.0.cipher {
+            Cipher::ChaChaPoly => 0,
+            Cipher::Aes256Gcm => 1,
+        };
+        let hash = match self.0.hash {
+            Hash::Blake2s => 0,
+            Hash::Blake2b => 1,
+            Hash::Sha256 => 2,
+            Hash::Sha512 => 3,
+        };
+        let dh = match self.0.dh {
+            Dh::Curve25519 => 0,
+            Dh::Curve448 => 1,
+        };
+        [cipher, hash, dh]
+    }
+    /// Fail if the peer chose other primitives, naming the first that differs.
+    /// Neither side adopts the choice of the other, so the hello can't be used
+    /// to downgrade a side to primitives it didn't choose
+    fn check_peer(&self, theirs: [u8; 3]) -> Result<()> {
+        let ours = self.tags();
+        let names = [
+            ("cipher", ["ChaChaPoly", "Aes256Gcm"].as_slice()),
+            ("hash", &["Blake2s", "Blake2b", "Sha256", "Sha512"]),
+            ("dh", &["Curve25519", "Curve448"]),
+        ];
+        for ((ours, theirs), (primitive, names)) in ours.into_iter().zip(theirs).zip(names) {
+            if ours != theirs {
+                let name = |tag: u8| names.get(tag as usize).copied().unwrap_or("unknown");
+                err!((
+                    invalid_data,
+                    format!(
+                        "peer uses the {} {}, this side uses {}, both sides need the same primitives",
+                        primitive,
+                        name(theirs),
+                        name(ours)
+                    )
+                ))?
+            }
+        }
+        Ok(())
+    }
+    /// hash of the bytes with the hash function of the handshake
+    fn digest(&self, bytes: &[u8]) -> Vec<u8> {
+        let mut hash = DefaultResolver
+            .resolve_hash(&self.hash_choice())
+            .expect("the default resolver supports every hash function");
+        hash.input(bytes);
+        let mut digest = vec![0u8; hash.hash_len()];
+        hash.result(&mut digest);
+        digest
+    }
+}
+
+#[cfg(feature = "bench")]
+#[derive(Clone, Copy, Debug)]
+/// Result of `Encryption::benchmark_throughput`
+pub struct ThroughputReport {
+    /// length of the buffer encrypted and decrypted by every iteration
+    pub size: usize,
+    /// times the buffer was encrypted and decrypted
+    pub iterations: u32,
+    /// packets sealed and opened across every iteration
+    pub packets: u64,
+    /// time spent encrypting, framing and decrypting
+    pub elapsed: Duration,
+}
+
+#[cfg(feature = "bench")]
+impl ThroughputReport {
+    /// megabytes of plaintext encrypted and decrypted per second
+    pub fn megabytes_per_second(&self) -> f64 {
+        let bytes = self.size as f64 * self.iterations as f64;
+        if bytes == 0.0 {
+            return 0.0;
+        }
+        bytes / 1e6 / self.elapsed.as_secs_f64()
+    }
+    /// mean time to seal and open a single packet
+    pub fn packet_latency(&self) -> Duration {
+        match self.packets {
+            0 => Duration::ZERO,
+            packets => self.elapsed.div_f64(packets as f64),
+        }
+    }
+}
+
+#[cfg(feature = "bench")]
+impl Encryption {
+    /// Encrypt, frame and decrypt a buffer of `size` bytes `iterations` times
+    /// with these primitives, without a channel or serialization in the way.
+    ///
+    /// Tells how fast a cipher is on this machine, such as whether
+    /// `Cipher::Aes256Gcm` beats `Cipher::ChaChaPoly` thanks to hardware support.
+    /// Runs on the calling thread, so it blocks until every iteration is done
+    /// ```no_run
+    /// # fn example() -> canary::Result<()> {
+    /// use canary::async_snow::{Cipher, EncryptionConfig};
+    ///
+    /// for cipher in [Cipher::ChaChaPoly, Cipher::Aes256Gcm] {
+    ///     let encryption = EncryptionConfig::default().cipher(cipher).build()?;
+    ///     let report = encryption.benchmark_throughput(1 << 20, 100)?;
+    ///     println!(
+    ///         "{:?}: {:.0} MB/s, {:?} per packet",
+    ///         cipher,
+    ///         report.megabytes_per_second(),
+    ///         report.packet_latency()
+    ///     );
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn benchmark_throughput(&self, size: usize, iterations: u32) -> Result<ThroughputReport> {
+        let (initiator, responder) = self.transport_pair()?;
+        let plain = vec![0xa5u8; size];
+        let (mut send_nonce, mut receive_nonce) = (0, 0);
+        let start = std::time::Instant::now();
+        for _ in 0..iterations {
+            let mut sender = RefDividedSnow {
+                transport: &initiator,
+                nonce: &mut send_nonce,
+            };
+            let sealed = sender.encrypt_packets(std::hint::black_box(&plain))?;
+            let mut frame = Vec::with_capacity(LEN_PREFIX + sealed.len());
+            frame.extend_from_slice(&encode_len(sealed.len()));
+            frame.extend_from_slice(&sealed);
+
+            let mut prefix = [0u8; LEN_PREFIX];
+            prefix.copy_from_slice(&frame[..LEN_PREFIX]);
+            let len = decode_len(prefix)?;
+            let mut receiver = RefDividedSnow {
+                transport: &responder,
+                nonce: &mut receive_nonce,
+            };
+            let opened = receiver.decrypt(&frame[LEN_PREFIX..LEN_PREFIX + len])?;
+            std::hint::black_box(opened);
+        }
+        let elapsed = start.elapsed();
+        Ok(ThroughputReport {
+            size,
+            iterations,
+            packets: receive_nonce,
+            elapsed,
+        })
+    }
+
+    /// both ends of an NN handshake run in memory
+    fn transport_pair(&self) -> Result<(StatelessTransportState, StatelessTransportState)> {
+        let params = self.params(HandshakePattern::NN, vec![]);
+        let mut initiator = snow::Builder::new(params.clone())
+            .build_initiator()
+            .map_err(err!(@other))?;
+        let mut responder = snow::Builder::new(params)
+            .build_responder()
+            .map_err(err!(@other))?;
+        let (mut message, mut payload) = (vec![0u8; 256], vec![0u8; 256]);
+        while !initiator.is_handshake_finished() || !responder.is_handshake_finished() {
+            let (writer, reader) = if initiator.is_my_turn() {
+                (&mut initiator, &mut responder)
+            } else {
+                (&mut responder, &mut initiator)
+            };
+            let len = writer
+                .write_message(&[], &mut message)
+                .map_err(err!(@other))?;
+            reader
+                .read_message(&message[..len], &mut payload)
+                .map_err(err!(@other))?;
+        }
+        Ok((
+            initiator
+                .into_stateless_transport_mode()
+                .map_err(err!(@other))?,
+            responder
+                .into_stateless_transport_mode()
+                .map_err(err!(@other))?,
+        ))
+    }
+}
+
+/// noise parameters used by channels with the given handshake pattern
+fn params(pattern: HandshakePattern, modifiers: Vec<HandshakeModifier>) -> NoiseParams {
+    Encryption::default().params(pattern, modifiers)
+}
+
+/// Starts a new snow stream using the default noise parameters.
+/// The NN pattern encrypts the channel but doesn't authenticate the peer,
+/// use `new_xx` or `new_ik` for that
 pub async fn new(stream: &mut Channel) -> Result<StatelessTransportState> {
-    let noise_params = NoiseParams::new(
-        "".into(),
-        BaseChoice::Noise,
-        HandshakeChoice {
-            pattern: HandshakePattern::NN,
-            modifiers: HandshakeModifierList { list: vec![] },
-        },
-        DHChoice::Curve25519,
-        CipherChoice::ChaChaPoly,
-        HashChoice::Blake2s,
-    );
-    new_with_params(stream, noise_params).await
+    new_with_timeout(stream, HANDSHAKE_TIMEOUT).await
 }
 
-/// starts a new snow stream using the provided parameters.
-pub async fn new_with_params(
+/// Starts a new snow stream using the default noise parameters,
+/// failing with a `TimedOut` error if the peer doesn't complete it within `timeout`
+pub async fn new_with_timeout(
+    stream: &mut Channel,
+    timeout: Duration,
+) -> Result<StatelessTransportState> {
+    new_with_params_timeout(stream, params(HandshakePattern::NN, vec![]), timeout).await
+}
+
+/// Starts a new snow stream like `new`, hashing with `hash` instead of BLAKE2s.
+///
+/// Both sides need to choose the same hash. Each side sends its primitives along with
+/// the hello, so a peer that chose another one fails with an `InvalidData` error naming
+/// both instead of an obscure decryption failure. Neither side switches to the hash of
+/// the other, so a tampered hello can only make the handshake fail.
+///
+/// BLAKE2s and SHA-256 both give the handshake its full security, the choice is about
+/// compliance and speed: SHA-256 is FIPS-approved and fast on CPUs with SHA extensions,
+/// BLAKE2s is faster elsewhere. For FIPS-leaning deployments, also choose
+/// `Cipher::Aes256Gcm` with `new_with_config`. The primitives come from the default
+/// resolver of `snow`, which isn't a validated module
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+/// use canary::async_snow::{new_with_hash, Hash};
+///
+/// let transport = new_with_hash(&mut chan, Hash::Sha256).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn new_with_hash(stream: &mut Channel, hash: Hash) -> Result<StatelessTransportState> {
+    let encryption = EncryptionConfig::default().hash(hash).build()?;
+    let params = encryption.params(HandshakePattern::NN, vec![]);
+    new_with_params_timeout(stream, params, HANDSHAKE_TIMEOUT).await
+}
+
+/// Starts a new snow stream using the XX pattern, where both sides send their static key.
+/// Once it finishes, `get_remote_static` on the transport returns the key of the peer,
+/// which should be checked against the expected one to pin it
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel, pinned: [u8; 32]) -> canary::Result<()> {
+/// use canary::async_snow::{new_xx, StaticKeypair};
+///
+/// let keypair = StaticKeypair::generate()?;
+/// let transport = new_xx(&mut chan, &keypair).await?;
+/// if transport.get_remote_static() != Some(&pinned[..]) {
+///     return Err(canary::err!(permission_denied, "unknown peer"));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn new_xx(
     chan: &mut Channel,
-    noise_params: NoiseParams,
+    local_static: &StaticKeypair,
 ) -> Result<StatelessTransportState> {
-    let should_init = loop {
-        let local_num = rand::random::<u64>();
+    let config = SnowConfig {
+        local_static: Some(local_static.clone()),
+        ..SnowConfig::new(HandshakePattern::XX)
+    };
+    new_with_config(chan, &config).await
+}
+
+/// Starts a new snow stream using the IK pattern,
+/// where the initiator already knows the static key of the responder.
+/// The initiator passes the public key of the responder and the responder passes `None`,
+/// the responder can read the key of the initiator with `get_remote_static` on the transport
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel, server_public: [u8; 32]) -> canary::Result<()> {
+/// use canary::async_snow::{new_ik, StaticKeypair};
+///
+/// let keypair = StaticKeypair::generate()?;
+/// let transport = new_ik(&mut chan, &keypair, Some(&server_public)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn new_ik(
+    chan: &mut Channel,
+    local_static: &StaticKeypair,
+    remote_public: Option<&[u8]>,
+) -> Result<StatelessTransportState> {
+    let config = SnowConfig {
+        local_static: Some(local_static.clone()),
+        remote_public: remote_public.map(<[u8]>::to_vec),
+        initiator: Some(remote_public.is_some()),
+        ..SnowConfig::new(HandshakePattern::IK)
+    };
+    new_with_config(chan, &config).await
+}
 
-        chan.send(local_num).await?;
-        let peer_num: u64 = chan.receive().await?;
+/// Starts a new snow stream using the XX pattern and keeps it only if the verifier
+/// accepts the static key of the peer, otherwise fails with a `PermissionDenied` error.
+/// No application data goes through the handshake, and the transport is only returned
+/// once the key is verified, so nothing can be exchanged with a rejected peer.
+/// `Handshake::verified` also drops the channel on rejection
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+/// use canary::async_snow::{new_verified, KeyAllowlist, StaticKeypair};
+///
+/// let keypair = StaticKeypair::generate()?;
+/// let allowlist = KeyAllowlist::load("allowed_keys")?;
+/// let transport = new_verified(&mut chan, &keypair, |key| allowlist.contains(key)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn new_verified(
+    chan: &mut Channel,
+    local_static: &StaticKeypair,
+    verifier: impl Fn(&[u8]) -> bool,
+) -> Result<StatelessTransportState> {
+    let transport = new_xx(chan, local_static).await?;
+    let reason = match transport.get_remote_static() {
+        Some(key) if verifier(key) => return Ok(transport),
+        Some(_) => "static key of the peer is not allowed",
+        None => "peer did not send a static key",
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    crate::audit::emit(crate::audit::AuditKind::AccessDenied {
+        peer_key: crate::audit::peer_key(transport.get_remote_static()),
+        service: None,
+        reason: reason.into(),
+    });
+    err!((permission_denied, reason))
+}
 
-        if local_num == peer_num {
-            continue;
-        } else {
-            break local_num > peer_num;
+#[derive(Clone, Default)]
+/// Set of static keys allowed to connect, to be used as the verifier of `new_verified`.
+///
+/// Keys can be loaded from a file holding one base64 key per line,
+/// empty lines and lines starting with `#` are ignored.
+/// Clones share the same set, so a `reload` is seen by every clone.
+pub struct KeyAllowlist {
+    #[cfg(not(target_arch = "wasm32"))]
+    path: Option<PathBuf>,
+    keys: Arc<RwLock<HashSet<[u8; KEY_LEN]>>>,
+}
+
+impl KeyAllowlist {
+    /// Allow the given keys
+    pub fn new(keys: impl IntoIterator<Item = [u8; KEY_LEN]>) -> Self {
+        KeyAllowlist {
+            #[cfg(not(target_arch = "wasm32"))]
+            path: None,
+            keys: Arc::new(RwLock::new(keys.into_iter().collect())),
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Allow the keys listed in the file, which can be read again with `reload`
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let keys = Self::read(&path)?;
+        Ok(KeyAllowlist {
+            path: Some(path),
+            keys: Arc::new(RwLock::new(keys)),
+        })
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Replace the keys with the ones currently listed in the file.
+    /// If the file can't be read or has an invalid key the previous keys are kept
+    pub fn reload(&self) -> Result<()> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or(err!(invalid_input, "allowlist was not loaded from a file"))?;
+        let keys = Self::read(path)?;
+        *self.keys.write().unwrap_or_else(|e| e.into_inner()) = keys;
+        Ok(())
+    }
+    /// Allow a key
+    pub fn insert(&self, key: [u8; KEY_LEN]) {
+        self.keys
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key);
+    }
+    /// Stop allowing a key, returns whether it was allowed
+    pub fn remove(&self, key: &[u8; KEY_LEN]) -> bool {
+        self.keys
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(key)
+    }
+    /// Whether the key is allowed
+    pub fn contains(&self, key: &[u8]) -> bool {
+        match <[u8; KEY_LEN]>::try_from(key) {
+            Ok(key) => self
+                .keys
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .contains(&key),
+            Err(_) => false,
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    /// read the keys listed in a file
+    fn read(path: &std::path::Path) -> Result<HashSet<[u8; KEY_LEN]>> {
+        let contents = std::fs::read_to_string(path)?;
+        contents
+            .lines()
+            .enumerate()
+            .map(|(line, key)| (line, key.trim()))
+            .filter(|(_, key)| !key.is_empty() && !key.starts_with('#'))
+            .map(|(line, key)| {
+                let key = BASE64_STANDARD
+                    .decode(key)
+                    .map_err(|e| err!(invalid_data, format!("line {}: {}", line + 1, e)))?;
+                <[u8; KEY_LEN]>::try_from(key).map_err(|key| {
+                    err!(
+                        invalid_data,
+                        format!(
+                            "line {}: key of {} bytes, expected {}",
+                            line + 1,
+                            key.len(),
+                            KEY_LEN
+                        )
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// Starts a new snow stream using the handshake described by the configuration.
+/// Both sides need compatible configurations, and a handshake that fails
+/// because the peer holds a different key returns a `PermissionDenied` error.
+///
+/// The `psk` modifiers of the pattern come from the pre-shared keys,
+/// so only peers holding the same keys can complete the handshake
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel, fleet_key: [u8; 32]) -> canary::Result<()> {
+/// use canary::async_snow::{new_with_config, HandshakePattern, SnowConfig};
+///
+/// let config = SnowConfig {
+///     psks: vec![(0, fleet_key)],
+///     ..SnowConfig::new(HandshakePattern::NN)
+/// };
+/// let transport = new_with_config(&mut chan, &config).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn new_with_config(
+    chan: &mut Channel,
+    config: &SnowConfig,
+) -> Result<StatelessTransportState> {
+    let span = handshake_span(&config.noise_params(), config.padding);
+    chan.limit_frames(Some(MAX_HANDSHAKE_LEN));
+    let result = match config.timeout {
+        Some(timeout) => traced(span, within(timeout, run_config(chan, config))).await,
+        None => traced(span, run_config(chan, config)).await,
+    };
+    chan.limit_frames(None);
+    result
+}
+
+/// run the handshake described by the configuration, with errors left as they are
+async fn run_config(chan: &mut Channel, config: &SnowConfig) -> Result<StatelessTransportState> {
+    let mut builder = snow::Builder::new(config.noise_params());
+    for (location, psk) in &config.psks {
+        builder = builder.psk(*location, psk);
+    }
+    // a peer padding differently then fails the handshake even without the hello
+    let mut prologue = config.prologue.clone().unwrap_or_default();
+    if config.padding != Padding::None {
+        prologue.extend(config.padding.tag());
+    }
+    if !prologue.is_empty() {
+        builder = builder.prologue(&prologue);
+    }
+    if let Some(local_static) = &config.local_static {
+        builder = builder.local_private_key(&local_static.private);
+    }
+    if let Some(remote_public) = &config.remote_public {
+        builder = builder.remote_public_key(remote_public);
+    }
+    let (initiator, hellos) = match config.initiator {
+        Some(initiator) => (initiator, None),
+        None => {
+            let (initiator, hellos) =
+                should_initiate(chan, &config.encryption, config.padding).await?;
+            (initiator, Some(hellos))
         }
     };
-    if should_init {
-        initialize_initiator(chan, noise_params).await
+    record_role(initiator);
+    let handshake = if initiator {
+        builder.build_initiator()
     } else {
-        initialize_responder(chan, noise_params).await
+        builder.build_responder()
+    };
+    if let Some(bytes) = config.rekey_after_bytes {
+        chan.rekey_after(Some(bytes));
+    }
+    chan.pad_frames(config.padding);
+    let mut transport = run_handshake(chan, handshake.map_err(err!(@other))?).await?;
+    if let Some(hellos) = hellos {
+        confirm_hellos(chan, &mut transport, &config.encryption, &hellos).await?;
+    }
+    Ok(transport)
+}
+
+/// Span a handshake runs in. Its role is recorded once it is known,
+/// the address of the peer comes from the spans of the provider or of the connection
+fn handshake_span(params: &NoiseParams, padding: Padding) -> tracing::Span {
+    tracing::debug_span!(
+        "handshake",
+        params = %describe(params),
+        padding = ?padding,
+        role = tracing::field::Empty,
+    )
+}
+
+/// readable name of the parameters, since the ones built by the crate have none
+fn describe(params: &NoiseParams) -> String {
+    let mut pattern = format!("{:?}", params.handshake.pattern);
+    for modifier in &params.handshake.modifiers.list {
+        pattern += &format!("+{:?}", modifier);
+    }
+    format!(
+        "{} {:?} {:?} {:?}",
+        pattern, params.dh, params.cipher, params.hash
+    )
+}
+
+/// record the side elected to initiate in the span of the handshake
+fn record_role(initiator: bool) {
+    let role = if initiator { "initiator" } else { "responder" };
+    tracing::Span::current().record("role", role);
+}
+
+/// Run the handshake in its span and log how it ended.
+/// Errors failing the handshake are marked with `HandshakeError`
+async fn traced(
+    span: tracing::Span,
+    handshake: impl Future<Output = Result<StatelessTransportState>>,
+) -> Result<StatelessTransportState> {
+    let result = handshake
+        .instrument(span.clone())
+        .await
+        .map_err(HandshakeError::wrap);
+    span.in_scope(|| match &result {
+        Ok(_) => tracing::debug!("handshake finished"),
+        Err(e) => tracing::debug!("handshake failed: {}", e),
+    });
+    #[cfg(feature = "metrics")]
+    if result.is_err() {
+        crate::metrics::handshake_failed();
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Ok(transport) = &result {
+        crate::audit::emit(crate::audit::AuditKind::HandshakeCompleted {
+            peer_key: crate::audit::peer_key(transport.get_remote_static()),
+        });
+    }
+    result
+}
+
+/// exchange the messages of a handshake until it finishes
+async fn run_handshake(
+    chan: &mut Channel,
+    mut handshake: HandshakeState,
+) -> Result<StatelessTransportState> {
+    let mut buffer = vec![0u8; HANDSHAKE_BUFFER_LEN];
+    while !handshake.is_handshake_finished() {
+        if handshake.is_my_turn() {
+            let len = handshake
+                .write_message(&[], &mut buffer)
+                .map_err(err!(@other))?;
+            chan.send(&buffer[..len]).await?;
+        } else {
+            let message: Vec<u8> = chan.receive().await?;
+            check_handshake_message(message.len())?;
+            handshake
+                .read_message(&message, &mut buffer)
+                .map_err(|e| match e {
+                    snow::Error::Decrypt => {
+                        err!(permission_denied, "the peer holds different keys")
+                    }
+                    e => err!(other, e),
+                })?;
+        }
+    }
+    chan.set_channel_binding(handshake.get_handshake_hash());
+    handshake
+        .into_stateless_transport_mode()
+        .map_err(err!(@other))
+}
+
+/// Fail if a received handshake message doesn't fit the buffer it is read into.
+/// Frames over the Noise limit never get here, the channel refuses them on their prefix
+fn check_handshake_message(len: usize) -> Result<()> {
+    if len > HANDSHAKE_BUFFER_LEN {
+        return err!((
+            invalid_data,
+            format!(
+                "handshake message of {} bytes doesn't fit the {} byte handshake buffer",
+                len, HANDSHAKE_BUFFER_LEN
+            )
+        ));
+    }
+    Ok(())
+}
+
+/// Decide which side initiates the handshake, by exchanging random numbers.
+/// Also returns both hellos, the one of the initiator first, to be checked by `confirm_hellos`
+async fn should_initiate(
+    chan: &mut Channel,
+    encryption: &Encryption,
+    padding: Padding,
+) -> Result<(bool, Vec<u8>)> {
+    for _ in 0..HELLO_ATTEMPTS {
+        let local_num = rand::random::<u64>();
+
+        let local_hello = hello(local_num, encryption, padding);
+        chan.send_frame(&frame::message(local_hello.clone()))
+            .await?;
+        let bytes = chan.try_receive_data().await?.ok_or_else(frame::closed)?;
+        let peer_hello_bytes = frame::message_payload(&bytes)?;
+        let (peer_num, primitives, peer_padding) = peer_hello(peer_hello_bytes)?;
+        encryption.check_peer(primitives)?;
+        padding.check_peer(peer_padding)?;
+
+        if local_num != peer_num {
+            let initiator = local_num > peer_num;
+            let hellos = if initiator {
+                [&local_hello[..], peer_hello_bytes].concat()
+            } else {
+                [peer_hello_bytes, &local_hello[..]].concat()
+            };
+            return Ok((initiator, hellos));
+        }
+    }
+    err!((
+        invalid_data,
+        "could not elect the initiator of the handshake, the peer keeps echoing the hello"
+    ))
+}
+
+/// first message of a handshake, carrying the random number that elects the initiator,
+/// the primitives and the padding of this side
+fn hello(num: u64, encryption: &Encryption, padding: Padding) -> Vec<u8> {
+    let mut hello = HELLO.to_vec();
+    hello.push(HANDSHAKE_VERSION);
+    hello.extend(num.to_be_bytes());
+    hello.extend(encryption.tags());
+    hello.extend(padding.tag());
+    hello
+}
+
+/// read the random number, the primitives and the padding of the peer from its first message,
+/// failing if the peer isn't starting a handshake of the same version
+fn peer_hello(payload: &[u8]) -> Result<(u64, [u8; 3], [u8; 5])> {
+    let hello = payload.strip_prefix(HELLO).ok_or_else(|| {
+        err!(
+            invalid_data,
+            "peer is not starting a handshake, it may expect an unencrypted channel"
+        )
+    })?;
+    match hello.split_first() {
+        Some((&HANDSHAKE_VERSION, hello)) => match <[u8; 16]>::try_from(hello) {
+            Ok([n0, n1, n2, n3, n4, n5, n6, n7, cipher, hash, dh, padding @ ..]) => Ok((
+                u64::from_be_bytes([n0, n1, n2, n3, n4, n5, n6, n7]),
+                [cipher, hash, dh],
+                padding,
+            )),
+            Err(_) => err!((invalid_data, "malformed handshake hello")),
+        },
+        Some((version, _)) => err!((
+            invalid_data,
+            format!(
+                "peer uses handshake version {}, this side uses version {}",
+                version, HANDSHAKE_VERSION
+            )
+        )),
+        None => err!((invalid_data, "malformed handshake hello")),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Error returned when the hellos exchanged before a handshake were tampered with.
+///
+/// The hellos carry the primitives and padding of each side in the clear. Once the handshake
+/// finished, each side sends the hash of the hellos it saw, encrypted with the new keys,
+/// so an attacker on the path that altered them, to downgrade a side or otherwise,
+/// is caught even if both sides accepted what they received.
+/// Both sides return it as an `InvalidData` error, use `DowngradeDetected::of` to find it.
+///
+/// Handshakes with a preset initiator exchange no hellos, so there's nothing to check.
+/// Only the XX and IK patterns authenticate the peer: an attacker completing an NN handshake
+/// with each side holds both sets of keys, and can't be detected this way
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+/// use canary::async_snow::{new, DowngradeDetected};
+///
+/// if let Err(e) = new(&mut chan).await {
+///     if DowngradeDetected::of(&e).is_some() {
+///         tracing::warn!("handshake was tampered with");
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct DowngradeDetected;
+
+impl DowngradeDetected {
+    #[inline]
+    /// Returns the downgrade error carried by the error, if any
+    pub fn of(error: &Error) -> Option<&DowngradeDetected> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl std::fmt::Display for DowngradeDetected {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "downgrade detected: the peer saw other handshake hellos, they were tampered with"
+        )
+    }
+}
+
+impl std::error::Error for DowngradeDetected {}
+
+/// Send the hash of the hellos this side saw, encrypted with the keys of the handshake,
+/// and check the peer saw the same ones.
+/// Both directions are rekeyed afterwards, so the channel never reuses the nonce of the hash
+async fn confirm_hellos(
+    chan: &mut Channel,
+    transport: &mut StatelessTransportState,
+    encryption: &Encryption,
+    hellos: &[u8],
+) -> Result<()> {
+    let ours = encryption.digest(hellos);
+    let mut sealed = vec![0u8; ours.len() + TAG_LEN];
+    let len = transport
+        .write_message(0, &ours, &mut sealed)
+        .map_err(err!(@other))?;
+    chan.send(&sealed[..len]).await?;
+
+    let sealed: Vec<u8> = chan.receive().await?;
+    let mut theirs = vec![0u8; sealed.len()];
+    let len = transport
+        .read_message(0, &sealed, &mut theirs)
+        .map_err(|_| err!(permission_denied, "the peer holds different keys"))?;
+    transport.rekey_outgoing();
+    transport.rekey_incoming();
+    if theirs[..len] != ours[..] {
+        return err!((invalid_data, DowngradeDetected));
     }
+    Ok(())
+}
+
+/// fail with a `TimedOut` error if the handshake doesn't finish within `timeout`
+async fn within<T>(timeout: Duration, handshake: impl Future<Output = Result<T>>) -> Result<T> {
+    crate::runtime::timeout(timeout, handshake)
+        .await
+        .unwrap_or_else(|_| err!((timeout, "peer did not complete the handshake in time")))
+}
+
+/// Starts a new snow stream using the provided parameters.
+/// Waits for the peer forever, use `new_with_params_timeout` to bound it
+pub async fn new_with_params(
+    chan: &mut Channel,
+    noise_params: NoiseParams,
+) -> Result<StatelessTransportState> {
+    let span = handshake_span(&noise_params, Padding::None);
+    chan.limit_frames(Some(MAX_HANDSHAKE_LEN));
+    let result = traced(span, run_params(chan, noise_params)).await;
+    chan.limit_frames(None);
+    result
+}
+
+/// run a handshake with the parameters, with errors left as they are
+async fn run_params(
+    chan: &mut Channel,
+    noise_params: NoiseParams,
+) -> Result<StatelessTransportState> {
+    let encryption = Encryption::of(&noise_params);
+    let (initiator, hellos) = should_initiate(chan, &encryption, Padding::None).await?;
+    record_role(initiator);
+    let mut transport = if initiator {
+        initialize_initiator(chan, noise_params).await?
+    } else {
+        initialize_responder(chan, noise_params).await?
+    };
+    confirm_hellos(chan, &mut transport, &encryption, &hellos).await?;
+    Ok(transport)
+}
+
+/// Starts a new snow stream using the provided parameters, failing with a `TimedOut` error
+/// if the peer doesn't complete it within `timeout`.
+/// The deadline covers the whole exchange, including the election of the initiator,
+/// so a peer that stalls at any point can't hold on to the channel
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel, params: canary::async_snow::NoiseParams) -> canary::Result<()> {
+/// use canary::async_snow::new_with_params_timeout;
+/// use std::time::Duration;
+///
+/// let transport = new_with_params_timeout(&mut chan, params, Duration::from_secs(5)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn new_with_params_timeout(
+    chan: &mut Channel,
+    noise_params: NoiseParams,
+    timeout: Duration,
+) -> Result<StatelessTransportState> {
+    let span = handshake_span(&noise_params, Padding::None);
+    chan.limit_frames(Some(MAX_HANDSHAKE_LEN));
+    let result = traced(span, within(timeout, run_params(chan, noise_params))).await;
+    chan.limit_frames(None);
+    result
 }
 
 /// starts a new snow stream using the provided parameters.
@@ -120,7 +1439,7 @@ pub(crate) async fn initialize_initiator(
     let mut initiator = snow::Builder::new(noise_params)
         .build_initiator()
         .map_err(err!(@other))?;
-    let mut buffer_msg = vec![0u8; 128];
+    let mut buffer_msg = vec![0u8; HANDSHAKE_BUFFER_LEN];
     let rand_payload: &[u8; 16] = &rand::random();
 
     let len = initiator
@@ -129,11 +1448,15 @@ pub(crate) async fn initialize_initiator(
 
     chan.send((&buffer_msg, len as u64)).await?;
 
-    let (mut buffer_out, buffer_msg): (Vec<u8>, Vec<u8>) = chan.receive().await?;
+    // the buffer sent along with the message is ignored, the message is read into our own
+    let (_, buffer_msg): (Vec<u8>, Vec<u8>) = chan.receive().await?;
+    check_handshake_message(buffer_msg.len())?;
+    let mut buffer_out = vec![0u8; HANDSHAKE_BUFFER_LEN];
     initiator
         .read_message(&buffer_msg, &mut buffer_out)
         .map_err(err!(@other))?;
 
+    chan.set_channel_binding(initiator.get_handshake_hash());
     initiator
         .into_stateless_transport_mode()
         .map_err(err!(@other))
@@ -147,20 +1470,28 @@ pub(crate) async fn initialize_responder(
     let mut responder = snow::Builder::new(noise_params)
         .build_responder()
         .map_err(err!(@other))?;
-    let mut buffer_out = vec![0u8; 128];
+    let mut buffer_out = vec![0u8; HANDSHAKE_BUFFER_LEN];
 
-    let (mut buffer_msg, len): (Vec<u8>, u64) = chan.receive().await?;
+    let (buffer_msg, len): (Vec<u8>, u64) = chan.receive().await?;
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|len| *len <= buffer_msg.len())
+        .ok_or_else(|| err!(invalid_data, "handshake message is longer than its buffer"))?;
+    check_handshake_message(len)?;
     responder
-        .read_message(&buffer_msg[..len as usize], &mut buffer_out)
+        .read_message(&buffer_msg[..len], &mut buffer_out)
         .map_err(err!(@other))?;
 
     let rand_payload: &[u8; 16] = &rand::random();
 
+    // written into our own buffer, the one sent by the peer can have any length
+    let mut buffer_msg = vec![0u8; HANDSHAKE_BUFFER_LEN];
     let len = responder
         .write_message(rand_payload, &mut buffer_msg)
         .map_err(err!(@other))?;
     chan.send((&buffer_out, &buffer_msg[..len])).await?;
 
+    chan.set_channel_binding(responder.get_handshake_hash());
     responder
         .into_stateless_transport_mode()
         .map_err(err!(@other))
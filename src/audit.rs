@@ -0,0 +1,130 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// events queued for the sink before new ones are dropped
+pub const QUEUE_LEN: usize = 1024;
+
+static QUEUE: RwLock<Option<SyncSender<AuditEvent>>> = RwLock::new(None);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// Security-relevant event passed to the sink set with `set_sink`
+pub struct AuditEvent {
+    /// when the event happened, rather than when the sink got it
+    pub time: SystemTime,
+    /// what happened
+    pub kind: AuditKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+#[non_exhaustive]
+/// What an `AuditEvent` records
+pub enum AuditKind {
+    /// a noise handshake finished, before any verifier checked the key of the peer,
+    /// so a refused peer is followed by `AccessDenied`
+    HandshakeCompleted {
+        /// base64 static key of the peer, if the pattern made it send one
+        peer_key: Option<String>,
+    },
+    /// a peer was refused, by the verifier of `async_snow::new_verified`
+    /// or by a service answering with a `PermissionDenied` error
+    AccessDenied {
+        /// base64 static key of the peer, if known
+        peer_key: Option<String>,
+        /// type name of the service that refused the request, if one did
+        service: Option<String>,
+        /// why the peer was refused
+        reason: String,
+    },
+    /// the shutdown began and providers stopped accepting
+    ShutdownBegun {
+        /// time given to running tasks to finish
+        grace: Duration,
+    },
+    /// the shutdown completed
+    ShutdownCompleted {
+        /// tasks aborted because they were still running after the grace period
+        aborted: usize,
+    },
+}
+
+/// Destination of audit events, such as an append-only file
+pub trait AuditSink: Send + 'static {
+    /// Record the event. Called on the thread of the sink, so it may block
+    fn emit(&mut self, event: AuditEvent);
+}
+
+impl<F: FnMut(AuditEvent) + Send + 'static> AuditSink for F {
+    #[inline]
+    fn emit(&mut self, event: AuditEvent) {
+        self(event)
+    }
+}
+
+/// Send every audit event from now on to the sink, replacing the previous one.
+///
+/// Events are recorded whether or not tracing is enabled. They are queued and passed
+/// to the sink on a thread of its own, so a slow sink never stalls a channel.
+/// Once `QUEUE_LEN` events are waiting new ones are dropped and counted, see `dropped`.
+/// Events still queued when the process exits are lost
+/// ```no_run
+/// # fn example() -> canary::Result<()> {
+/// use std::io::Write;
+///
+/// let mut log = std::fs::OpenOptions::new()
+///     .create(true)
+///     .append(true)
+///     .open("audit.log")?;
+/// canary::audit::set_sink(move |event| {
+///     if let Ok(line) = serde_json::to_string(&event) {
+///         writeln!(log, "{}", line).ok();
+///     }
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn set_sink(mut sink: impl AuditSink) -> Result<()> {
+    let (sender, receiver) = sync_channel(QUEUE_LEN);
+    std::thread::Builder::new()
+        .name("canary-audit".into())
+        // ends once the sink is replaced and the queued events are emitted
+        .spawn(move || receiver.iter().for_each(|event| sink.emit(event)))?;
+    *QUEUE.write().unwrap_or_else(|e| e.into_inner()) = Some(sender);
+    Ok(())
+}
+
+#[inline]
+/// events dropped because the queue of the sink was full
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// queue the event for the sink, if one is set
+pub(crate) fn emit(kind: AuditKind) {
+    let queue = QUEUE.read().unwrap_or_else(|e| e.into_inner());
+    let Some(queue) = &*queue else {
+        return;
+    };
+    let event = AuditEvent {
+        time: SystemTime::now(),
+        kind,
+    };
+    if let Err(TrySendError::Full(_)) = queue.try_send(event) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// static key of the peer as it appears in events
+pub(crate) fn peer_key(key: Option<&[u8]>) -> Option<String> {
+    key.map(|key| BASE64_STANDARD.encode(key))
+}
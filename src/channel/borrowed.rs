@@ -0,0 +1,33 @@
+use serde::Deserialize;
+
+use crate::{channel::frame, serialization::formats::ReadFormat, Result};
+
+/// Message received with `Channel::receive_borrowed`, holding the received frame
+/// so objects can be deserialized borrowing from it instead of copying out of it.
+///
+/// Objects borrow from the guard, so they can't outlive it,
+/// and the guard borrows the channel, so it has to be dropped before receiving again
+pub struct Received<'c, R> {
+    format: &'c mut R,
+    frame: Vec<u8>,
+}
+
+impl<'c, R: ReadFormat> Received<'c, R> {
+    #[inline]
+    pub(crate) fn new(format: &'c mut R, frame: Vec<u8>) -> Result<Self> {
+        frame::message_payload(&frame)?;
+        Ok(Received { format, frame })
+    }
+    #[inline]
+    /// Deserialize the message, borrowing the strings and bytes of the object from it
+    /// where its type allows. Can be called again to read the message as another type
+    pub fn deserialize<'a, T: Deserialize<'a>>(&'a mut self) -> Result<T> {
+        // checked to be a message frame in `new`
+        self.format.deserialize_borrowed(&self.frame[1..])
+    }
+    #[inline]
+    /// The serialized message
+    pub fn payload(&self) -> &[u8] {
+        &self.frame[1..]
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt::Display;
+
+use crate::{err, Error, Result};
+
+/// length of the checksum trailer appended to every frame
+const TRAILER_LEN: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Error returned when a frame fails its checksum.
+///
+/// `receive` returns it as an `InvalidData` error, use `CorruptedFrame::of` to find it.
+pub struct CorruptedFrame {
+    /// checksum carried by the frame
+    pub expected: u32,
+    /// checksum of the received bytes
+    pub actual: u32,
+}
+
+impl CorruptedFrame {
+    #[inline]
+    /// Returns the corrupted frame error carried by the error, if any
+    pub fn of(error: &Error) -> Option<&CorruptedFrame> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl Display for CorruptedFrame {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "corrupted frame: expected checksum {:#010x}, got {:#010x}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for CorruptedFrame {}
+
+#[inline]
+/// append the CRC32C of the frame to it
+pub(crate) fn append(frame: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(frame.len() + TRAILER_LEN);
+    bytes.extend_from_slice(frame);
+    bytes.extend_from_slice(&crc32c::crc32c(frame).to_le_bytes());
+    bytes
+}
+
+#[inline]
+/// check the CRC32C trailer of a frame and remove it
+pub(crate) fn verify(mut bytes: Vec<u8>) -> Result<Vec<u8>> {
+    if bytes.len() < TRAILER_LEN {
+        err!((invalid_data, "frame is too short to carry a checksum"))?
+    }
+    let mut trailer = [0; TRAILER_LEN];
+    trailer.copy_from_slice(&bytes[bytes.len() - TRAILER_LEN..]);
+    bytes.truncate(bytes.len() - TRAILER_LEN);
+    let expected = u32::from_le_bytes(trailer);
+    let actual = crc32c::crc32c(&bytes);
+    if expected != actual {
+        err!((invalid_data, CorruptedFrame { expected, actual }))?
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,89 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::{io, serialization::formats::Format, Channel, Result};
+
+/// Channel that is closed in the background when dropped,
+/// so the peer sees a clean shutdown instead of the connection going away.
+///
+/// `Drop` can't wait, so the close is spawned on the current tokio runtime, or on
+/// the event loop of the browser on wasm, and may still be running, or never be
+/// acknowledged, once the guard is gone. If there is no runtime the channel is
+/// dropped without closing it.
+/// Frames are flushed as they are sent, so nothing buffered is lost either way.
+///
+/// Use `close` when the close has to be complete before moving on.
+/// ```no_run
+/// # async fn example(chan: canary::Channel) -> canary::Result<()> {
+/// let mut chan = chan.close_on_drop();
+/// chan.send("Hello world!").await?;
+/// // the peer receives a close frame once `chan` goes out of scope
+/// # Ok(())
+/// # }
+/// ```
+pub struct CloseOnDrop<R = Format, W = Format>
+where
+    R: Send + 'static,
+    W: Send + 'static,
+{
+    chan: Option<Channel<R, W>>,
+}
+
+impl<R, W> CloseOnDrop<R, W>
+where
+    R: Send + 'static,
+    W: Send + 'static,
+{
+    #[inline]
+    /// Close the channel when the guard is dropped
+    pub fn new(chan: Channel<R, W>) -> Self {
+        CloseOnDrop { chan: Some(chan) }
+    }
+    #[inline]
+    /// Get the channel back, it won't be closed when dropped anymore
+    pub fn into_inner(mut self) -> Channel<R, W> {
+        self.chan.take().expect("channel is only taken on drop")
+    }
+    /// Close the channel and wait for the peer to acknowledge it, see `Channel::close`
+    pub async fn close(self) -> Result<()> {
+        self.into_inner().close().await
+    }
+}
+
+impl<R, W> Deref for CloseOnDrop<R, W>
+where
+    R: Send + 'static,
+    W: Send + 'static,
+{
+    type Target = Channel<R, W>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.chan.as_ref().expect("channel is only taken on drop")
+    }
+}
+
+impl<R, W> DerefMut for CloseOnDrop<R, W>
+where
+    R: Send + 'static,
+    W: Send + 'static,
+{
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.chan.as_mut().expect("channel is only taken on drop")
+    }
+}
+
+impl<R, W> Drop for CloseOnDrop<R, W>
+where
+    R: Send + 'static,
+    W: Send + 'static,
+{
+    fn drop(&mut self) {
+        if let Some(chan) = self.chan.take() {
+            // nobody is left to report a failed close to
+            io::spawn_detached(async move {
+                chan.close().await.ok();
+            });
+        }
+    }
+}
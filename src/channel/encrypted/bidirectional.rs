@@ -1,24 +1,51 @@
-use std::sync::Arc;
+use std::future::Future;
+#[cfg(unix)]
+use std::os::fd::{AsFd, OwnedFd};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 use derive_more::From;
 use serde::{de::DeserializeOwned, Serialize};
 use snow::StatelessTransportState;
 
 use crate::{
-    async_snow::RefDividedSnow,
-    channel::raw::{
-        joint::unformatted::RefUnformattedRawChannel,
-        unified::unformatted::UnformattedRawUnifiedChannel,
+    async_snow::{Decrypt, Encrypt, Padding, RefDividedSnow, SharedTransport},
+    channel::{
+        borrowed::Received,
+        close_on_drop::CloseOnDrop,
+        frame::{self, FrameKind},
+        keepalive::Keepalive,
+        negotiation,
+        raw::{
+            joint::unformatted::RefUnformattedRawChannel,
+            unified::unformatted::UnformattedRawUnifiedChannel,
+        },
+        rekey::Rekey,
+        remote,
+        tap::{Direction, Tap},
     },
-    serialization::formats::{Format, ReadFormat, SendFormat},
-    Result,
+    err,
+    io::{Read, Write},
+    runtime::{self, CancelToken},
+    serialization::{
+        compressed::{Compressed, Compression},
+        formats::{Format, ReadFormat, SendFormat},
+        versioned::{Header, Versioned},
+    },
+    Error, Result,
 };
 
+#[cfg(all(feature = "json_ser", not(target_arch = "wasm32")))]
+use crate::channel::ndjson::NdjsonChannel;
+#[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+use crate::io::WssStream;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::providers::{Overflow, RateLimit};
+
 use super::{
     bipartite::{BipartiteChannel, UnformattedBipartiteChannel},
     receive_channel::{ReceiveChannel, UnformattedReceiveChannel},
     send_channel::{SendChannel, UnformattedSendChannel},
-    snowwith::WithCipher,
     unified::{UnformattedUnifiedChannel, UnifiedChannel},
 };
 
@@ -31,7 +58,7 @@ pub enum RefUnformattedBidirectionalChannel<'a> {
     Encrypted(
         RefUnformattedRawChannel<'a>,
         &'a StatelessTransportState,
-        &'a mut u32,
+        &'a mut u64,
     ),
 }
 
@@ -93,24 +120,43 @@ impl<R, W> Channel<R, W> {
         receive_format: R,
         send_format: W,
     ) -> Self {
+        let raw = raw.into();
+        #[cfg(feature = "metrics")]
+        let active = crate::metrics::Active::new(raw.transport());
         Self::Unified(UnifiedChannel {
-            channel: UnformattedUnifiedChannel::Raw(raw.into()),
+            channel: UnformattedUnifiedChannel::Raw(raw),
             receive_format,
             send_format,
+            receive_closed: false,
+            send_closed: false,
+            buffer: Vec::new(),
+            #[cfg(all(feature = "capture", not(target_arch = "wasm32")))]
+            tap: crate::debug::tap(),
+            #[cfg(not(all(feature = "capture", not(target_arch = "wasm32"))))]
+            tap: None,
+            rekey: Rekey::default(),
+            padding: Padding::None,
+            channel_binding: None,
+            frame_limit: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            limiter: None,
+            #[cfg(feature = "metrics")]
+            active: Some(active),
+            #[cfg(unix)]
+            received_fds: None,
         })
     }
 
     /// Try to encrypt channel using the provided transport.
     /// Will return an error if channel is already encrypted.
-    /// To turn `Arc<StatelessTransportState>` into the inner transport state
-    /// use `Arc::try_unwrap(transport)`.
-    pub fn encrypt(
-        &mut self,
-        transport: StatelessTransportState,
-    ) -> Result<(), Arc<StatelessTransportState>> {
+    /// To turn `SharedTransport` into the inner transport state
+    /// use `Arc::try_unwrap(transport)` followed by `RwLock::into_inner`.
+    pub fn encrypt(&mut self, transport: StatelessTransportState) -> Result<(), SharedTransport> {
         match self {
-            Channel::Unified(unified) => unified.encrypt(transport).map_err(Arc::new),
-            Channel::Bipartite(bipartite) => bipartite.encrypt(Arc::new(transport)),
+            Channel::Unified(unified) => unified
+                .encrypt(transport)
+                .map_err(|transport| Arc::new(RwLock::new(transport))),
+            Channel::Bipartite(bipartite) => bipartite.encrypt(Arc::new(RwLock::new(transport))),
         }
     }
 
@@ -140,8 +186,487 @@ impl<R, W> Channel<R, W> {
             Channel::Bipartite(chan) => chan.receive().await,
         }
     }
+    /// Send an object through the channel, unless the token is cancelled first.
+    /// See `receive_cancellable`
+    pub async fn send_cancellable<T: Serialize>(
+        &mut self,
+        obj: T,
+        token: &CancelToken,
+    ) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        runtime::cancellable(token, self.send(obj)).await
+    }
+    /// Receive an object sent through the channel, unless the token is cancelled first,
+    /// in which case this fails with a `Cancelled` error.
+    ///
+    /// If the channel fails or is closed the token is cancelled as well, stopping whatever
+    /// else races against it, such as the other direction of a proxy.
+    /// Objects that fail to deserialize and errors sent by the peer leave the token alone.
+    /// A frame may have been partly read when cancelled, so the channel should be dropped
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel, token: canary::runtime::CancelToken) -> canary::Result<()> {
+    /// let string: String = chan.receive_cancellable(&token).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_cancellable<T: DeserializeOwned>(
+        &mut self,
+        token: &CancelToken,
+    ) -> Result<T>
+    where
+        R: ReadFormat,
+    {
+        runtime::cancellable(token, self.receive()).await
+    }
+    /// Send an error to the peer. Its `receive` returns an error of the same kind
+    /// carrying a `RemoteError`, see `channel::remote::redact_with` to hide internal details.
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// chan.send_error(&canary::err!(not_found, "no such user")).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_error(&mut self, error: &Error) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        match self {
+            Channel::Unified(chan) => chan.send_error(error).await,
+            Channel::Bipartite(chan) => chan.send_error(error).await,
+        }
+    }
+    /// Send a result through the channel, received by the peer with `receive_result`.
+    ///
+    /// The frame carries a byte telling `Ok` and `Err` apart followed by the value of the arm,
+    /// serialized with the format of the channel on its own, so the error type can change
+    /// without touching the success type. Unlike `send_error` the error arrives as a value,
+    /// apart from the errors of the channel itself
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// let result: Result<u64, String> = Err("no such user".into());
+    /// chan.send_result(result).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_result<T: Serialize, E: Serialize>(
+        &mut self,
+        result: std::result::Result<T, E>,
+    ) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        match self {
+            Channel::Unified(chan) => chan.send_result(result).await,
+            Channel::Bipartite(chan) => chan.send_result(result).await,
+        }
+    }
+    /// Receive a result sent with `send_result`.
+    /// The outer result fails if the channel does, or if the message isn't a result
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// match chan.receive_result::<u64, String>().await? {
+    ///     Ok(id) => println!("user {}", id),
+    ///     Err(e) => println!("lookup failed: {}", e),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_result<T: DeserializeOwned, E: DeserializeOwned>(
+        &mut self,
+    ) -> Result<std::result::Result<T, E>>
+    where
+        R: ReadFormat,
+    {
+        match self {
+            Channel::Unified(chan) => chan.receive_result().await,
+            Channel::Bipartite(chan) => chan.receive_result().await,
+        }
+    }
+    #[cfg(feature = "json_ser")]
+    /// Receive the next message as a dynamic JSON value, for when its type isn't known,
+    /// such as in a gateway forwarding messages between peers.
+    ///
+    /// The value is read with the format of the channel, so JSON, MessagePack, CBOR and BSON
+    /// channels all work. Some values don't map cleanly:
+    /// - bincode and postcard don't describe their types, so they fail with `Unsupported`
+    /// - maps with keys other than strings fail, since JSON objects only have string keys
+    /// - bytes become arrays of numbers, and integers beyond 64 bits fail
+    /// - structs sent with the default, compact MessagePack are arrays of their fields,
+    ///   the peer has to use `MessagePack::named()` for objects keyed by field name
+    /// - BSON types such as object ids and dates come out in relaxed extended JSON,
+    ///   and a document holding only a `v` key reads as its value, since that is how
+    ///   values other than documents are sent
+    ///
+    /// Deserialize the value into a concrete type with `serde_json::from_value`
+    /// once it is known
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// let value = chan.receive_value().await?;
+    /// if let Some(kind) = value.get("kind").and_then(|kind| kind.as_str()) {
+    ///     println!("got a {} message", kind);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_value(&mut self) -> Result<serde_json::Value>
+    where
+        R: ReadFormat,
+    {
+        match self {
+            Channel::Unified(chan) => chan.receive_value().await,
+            Channel::Bipartite(chan) => chan.receive_value().await,
+        }
+    }
+    /// Receive the next message and keep it, so that objects can be deserialized
+    /// borrowing their strings and bytes from it instead of copying them out, such as
+    /// `&str`, `&[u8]` or `Cow<str>` fields marked `#[serde(borrow)]`.
+    ///
+    /// The returned `Received` owns the message and borrows the channel:
+    /// objects deserialized from it borrow the guard and can't outlive it,
+    /// and the channel can't be used again until the guard is dropped.
+    /// Bincode, Postcard, MessagePack, BSON and JSON messages can be borrowed from,
+    /// though JSON strings with escapes can only be read into a `Cow`, which copies them.
+    /// CBOR messages, and formats that decode the message into a new buffer first
+    /// such as compressed ones, fail with `Unsupported`
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// let mut message = chan.receive_borrowed().await?;
+    /// let name: &str = message.deserialize()?;
+    /// println!("hello {}", name);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_borrowed(&mut self) -> Result<Received<'_, R>>
+    where
+        R: ReadFormat,
+    {
+        match self {
+            Channel::Unified(chan) => chan.receive_borrowed().await,
+            Channel::Bipartite(chan) => chan.receive_borrowed().await,
+        }
+    }
+    /// Send everything the reader yields as a stream of chunks,
+    /// received by the peer with `receive_writer`. Returns the length of the stream.
+    ///
+    /// If `len` is set, at most `len` bytes are sent and the reader ending earlier is an error.
+    /// Keepalive and control frames keep flowing while the stream is being sent.
+    /// If the future is dropped midway the channel is poisoned and every following send fails.
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// let file = tokio::fs::File::open("backup.tar").await?;
+    /// let len = file.metadata().await?.len();
+    /// chan.send_reader(file, Some(len)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_reader<Rd: Read + Unpin>(
+        &mut self,
+        reader: Rd,
+        len: Option<u64>,
+    ) -> Result<u64>
+    where
+        W: SendFormat,
+    {
+        self.bipartite().send_reader(reader, len).await
+    }
+    /// Receive a stream sent with `send_reader` and write it to the writer,
+    /// returns the length of the stream.
+    ///
+    /// If the future is dropped or writing fails midway the channel is poisoned
+    /// and every following receive fails, since the rest of the stream is still pending.
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// let file = tokio::fs::File::create("backup.tar").await?;
+    /// let len = chan.receive_writer(file).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_writer<Wr: Write + Unpin>(&mut self, writer: Wr) -> Result<u64>
+    where
+        R: ReadFormat,
+    {
+        self.bipartite().receive_writer(writer).await
+    }
+    /// Send the length of the stream followed by the stream itself,
+    /// received by the peer with `receive_chunked`. Returns the length of the stream.
+    ///
+    /// The reader ending before `len` bytes is an error, as with `send_reader`
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// let file = tokio::fs::File::open("backup.tar").await?;
+    /// let len = file.metadata().await?.len();
+    /// chan.send_chunked(file, len).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_chunked<Rd: Read + Unpin>(&mut self, reader: Rd, len: u64) -> Result<u64>
+    where
+        W: SendFormat,
+    {
+        self.bipartite().send_chunked(reader, len).await
+    }
+    /// Receive a stream sent with `send_chunked` and write it to the writer,
+    /// calling `progress` with the bytes received so far and the length the peer declared
+    /// after every chunk. Returns the length of the stream.
+    ///
+    /// A stream going past the declared length fails with an `InvalidData` error
+    /// and poisons the channel like any other failed stream.
+    /// A stream ending short of it fails with an `UnexpectedEof` error
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// let file = tokio::fs::File::create("backup.tar").await?;
+    /// chan.receive_chunked(file, |received, total| {
+    ///     println!("{}/{} bytes", received, total);
+    /// })
+    /// .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_chunked<Wr: Write + Unpin>(
+        &mut self,
+        writer: Wr,
+        progress: impl FnMut(u64, u64),
+    ) -> Result<u64>
+    where
+        R: ReadFormat,
+    {
+        self.bipartite().receive_chunked(writer, progress).await
+    }
+    #[cfg(unix)]
+    /// Pass a file descriptor to the peer with `SCM_RIGHTS`, received with `receive_fd`.
+    /// The descriptor is duplicated into the peer's process, so it can be closed afterwards.
+    ///
+    /// Only channels over unix sockets that haven't been split can pass file descriptors,
+    /// other channels fail with an `Unsupported` error. Channels that were split,
+    /// for example by `enable_keepalive` or `send_reader`, aren't supported either.
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// let file = std::fs::File::open("shared.log")?;
+    /// chan.send_fd(&file).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_fd(&mut self, fd: impl AsFd) -> Result<usize> {
+        match self {
+            Channel::Unified(chan) => chan.send_fd(fd.as_fd()).await,
+            Channel::Bipartite(_) => Err(fds_split()),
+        }
+    }
+    #[cfg(unix)]
+    /// Receive a file descriptor passed by the peer with `send_fd`.
+    /// Fails with an `InvalidData` error if the next message isn't a file descriptor
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// let file = std::fs::File::from(chan.receive_fd().await?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_fd(&mut self) -> Result<OwnedFd>
+    where
+        R: ReadFormat,
+    {
+        match self {
+            Channel::Unified(chan) => chan.receive_fd().await,
+            Channel::Bipartite(_) => Err(fds_split()),
+        }
+    }
+    /// Receive an object sent through the channel,
+    /// returns `None` if the peer closed the channel
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// while let Some(string) = chan.try_receive::<String>().await? {
+    ///     println!("{}", string);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn try_receive<T: DeserializeOwned>(&mut self) -> Result<Option<T>>
+    where
+        R: ReadFormat,
+    {
+        match self {
+            Channel::Unified(chan) => chan.try_receive().await,
+            Channel::Bipartite(chan) => chan.try_receive().await,
+        }
+    }
+    /// Tell the peer no more messages will be sent while still being able to receive.
+    /// Sending afterwards fails with a `BrokenPipe` error, and the peer's `try_receive`
+    /// returns `None` once it has received every message sent before.
+    pub async fn close_send(&mut self) -> Result<()> {
+        match self {
+            Channel::Unified(chan) => chan.close_send().await,
+            Channel::Bipartite(chan) => chan.close_send().await,
+        }
+    }
+    /// Close the channel, waiting a few seconds at most for the peer to acknowledge it.
+    /// Messages received in the meantime are discarded.
+    ///
+    /// Once the peer has closed the channel, `receive` fails with a `NotConnected` error
+    /// and `try_receive` returns `None`.
+    ///
+    /// Dropping a channel doesn't close it, the peer sees the connection go away instead.
+    /// Use `close_on_drop` to close it in the background when dropped.
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// chan.send("bye").await?;
+    /// chan.close().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn close(self) -> Result<()> {
+        match self {
+            Channel::Unified(chan) => chan.close().await,
+            Channel::Bipartite(chan) => chan.close().await,
+        }
+    }
+    /// Ping the peer and wait for its pong, telling whether the connection still works.
+    ///
+    /// The peer answers from within its own `receive` or `send`, so it has to be using
+    /// the channel for the pong to come. Fails if a message arrives before the pong,
+    /// so only ping channels that have no messages in flight
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// use std::time::Duration;
+    ///
+    /// match canary::runtime::timeout(Duration::from_secs(5), chan.ping()).await {
+    ///     Ok(Ok(())) => chan.send("still there").await?,
+    ///     _ => return chan.close().await,
+    /// };
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn ping(&mut self) -> Result<()>
+    where
+        R: ReadFormat,
+    {
+        match self {
+            Channel::Unified(chan) => chan.ping().await,
+            Channel::Bipartite(chan) => chan.ping().await,
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    /// whether neither side closed the channel and it can still be used in both directions
+    pub(crate) fn is_open(&self) -> bool {
+        match self {
+            Channel::Unified(chan) => !chan.receive_closed && !chan.send_closed,
+            Channel::Bipartite(chan) => {
+                !chan.receive_channel.closed
+                    && !chan.receive_channel.poisoned
+                    && !chan.send_channel.closed
+                    && !chan.send_channel.poisoned
+                    && chan.keepalive.as_ref().is_none_or(|k| k.check().is_ok())
+            }
+        }
+    }
+    #[inline]
+    /// Close the channel in the background once it is dropped,
+    /// see `CloseOnDrop` for the limits of closing from `Drop`
+    pub fn close_on_drop(self) -> CloseOnDrop<R, W>
+    where
+        R: Send + 'static,
+        W: Send + 'static,
+    {
+        CloseOnDrop::new(self)
+    }
+    /// Send a request through the channel and wait for its response
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// let len: usize = chan.request(&"Hello world!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn request<Req: Serialize, Resp: DeserializeOwned>(
+        &mut self,
+        req: &Req,
+    ) -> Result<Resp>
+    where
+        R: ReadFormat,
+        W: SendFormat,
+    {
+        self.send(req).await?;
+        self.receive().await
+    }
+    /// Receive a request from the channel and send back the response returned by the handler
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// chan.respond(|req: String| async move { req.len() }).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn respond<Req, Resp, F, Fut>(&mut self, handler: F) -> Result<usize>
+    where
+        R: ReadFormat,
+        W: SendFormat,
+        Req: DeserializeOwned,
+        Resp: Serialize,
+        F: FnOnce(Req) -> Fut,
+        Fut: Future<Output = Resp>,
+    {
+        let req = self.receive().await?;
+        let resp = handler(req).await;
+        self.send(resp).await
+    }
+    /// Receive a request from the channel and answer it with the result of the handler.
+    /// If the handler fails, its error is sent to the peer and returned.
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// chan.try_respond(|id: u64| async move {
+    ///     match id {
+    ///         0 => canary::err!((not_found, "no such user")),
+    ///         id => Ok(format!("user {}", id)),
+    ///     }
+    /// })
+    /// .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn try_respond<Req, Resp, F, Fut>(&mut self, handler: F) -> Result<usize>
+    where
+        R: ReadFormat,
+        W: SendFormat,
+        Req: DeserializeOwned,
+        Resp: Serialize,
+        F: FnOnce(Req) -> Fut,
+        Fut: Future<Output = Result<Resp>>,
+    {
+        let req = self.receive().await?;
+        match handler(req).await {
+            Ok(resp) => self.send(resp).await,
+            Err(e) => {
+                self.send_error(&e).await?;
+                Err(e)
+            }
+        }
+    }
     #[must_use]
-    /// Split channel into its send and receive components
+    /// Split channel into its send and receive components.
+    ///
+    /// Both halves are owned and `Send`, so they can be moved to separate tasks
+    /// for full-duplex services, and put back together with `Channel::join`.
+    /// On encrypted channels the halves share the cipher, but each one keeps
+    /// the nonce of its own direction, so they never reuse each other's nonces
+    /// ```no_run
+    /// # async fn example(chan: canary::Channel) -> canary::Result<()> {
+    /// let (mut send, mut receive) = chan.split();
+    /// let reader = tokio::spawn(async move {
+    ///     while let Ok(msg) = receive.receive::<String>().await {
+    ///         println!("{}", msg);
+    ///     }
+    /// });
+    /// let writer = tokio::spawn(async move {
+    ///     for i in 0..10u32 {
+    ///         if send.send(i.to_string()).await.is_err() {
+    ///             break;
+    ///         }
+    ///     }
+    /// });
+    /// writer.await.ok();
+    /// reader.await.ok();
+    /// # Ok(())
+    /// # }
+    /// ```
     pub fn split(self) -> (SendChannel<W>, ReceiveChannel<R>) {
         match self {
             Channel::Unified(chan) => chan.split(),
@@ -153,8 +678,574 @@ impl<R, W> Channel<R, W> {
         Self::Bipartite(BipartiteChannel {
             receive_channel: receive,
             send_channel: send,
+            keepalive: None,
         })
     }
+    /// Append a CRC32C trailer to every frame and check it on receive,
+    /// `receive` fails with a `CorruptedFrame` error on mismatch.
+    /// Adds 4 bytes to every frame, and both peers must agree on the setting.
+    ///
+    /// Only unencrypted channels are affected, encrypted ones already
+    /// check the integrity of every frame.
+    /// ```no_run
+    /// # async fn example(handshake: canary::channel::handshake::Handshake) -> canary::Result<()> {
+    /// let mut chan = handshake.raw();
+    /// chan.set_checksum(true);
+    /// chan.send("Hello world!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_checksum(&mut self, enabled: bool) {
+        match self {
+            Channel::Unified(chan) => chan.channel.set_checksum(enabled),
+            Channel::Bipartite(chan) => {
+                chan.send_channel.channel.set_checksum(enabled);
+                chan.receive_channel.channel.set_checksum(enabled);
+            }
+        }
+    }
+    /// Tell the peer whether this side checksums its frames and check that it does the same,
+    /// then enable checksums if `enabled`, see `set_checksum`.
+    /// Both peers call it right after setting up the channel,
+    /// so a mismatch fails here instead of corrupting the first message.
+    /// ```no_run
+    /// # async fn example(handshake: canary::channel::handshake::Handshake) -> canary::Result<()> {
+    /// let mut chan = handshake.raw();
+    /// chan.negotiate_checksum(true).await?;
+    /// chan.send("Hello world!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn negotiate_checksum(&mut self, enabled: bool) -> Result<()>
+    where
+        R: ReadFormat,
+    {
+        let ours = negotiation::capabilities(enabled);
+        self.send_frame(&frame::message(vec![ours])).await?;
+        let theirs = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        negotiation::check_capabilities(ours, frame::message_payload(&theirs)?)?;
+        self.set_checksum(enabled);
+        Ok(())
+    }
+    /// Call `sink` with every frame sent or received through the channel,
+    /// after serialization and before encryption, to log or dump the traffic.
+    /// The first byte of a frame is its `FrameKind`, the serialized message follows.
+    /// Replaces the previous tap, if any.
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// chan.tap(|direction, frame| println!("{:?} {:?}", direction, frame));
+    /// chan.send("Hello world!").await?;
+    /// chan.untap();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn tap(&mut self, sink: impl Fn(Direction, &[u8]) + Send + Sync + 'static) {
+        self.set_tap(Some(Arc::new(sink)));
+    }
+    /// Remove the tap of the channel
+    pub fn untap(&mut self) {
+        self.set_tap(None);
+    }
+    fn set_tap(&mut self, tap: Option<Tap>) {
+        match self {
+            Channel::Unified(chan) => chan.tap = tap,
+            Channel::Bipartite(chan) => {
+                chan.send_channel.tap = tap.clone();
+                chan.receive_channel.tap = tap;
+            }
+        }
+    }
+    /// Ping the peer whenever a `receive` has been waiting for `interval` without
+    /// anything arriving, and fail with a `TimedOut` error if the peer doesn't answer
+    /// within `timeout`. Once the peer is considered dead, `send` fails as well.
+    ///
+    /// Keepalive needs to write while a receive is pending, so unified channels
+    /// are turned into bipartite ones. Splitting the channel disables the keepalive.
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// # use std::time::Duration;
+    /// chan.enable_keepalive(Duration::from_secs(15), Duration::from_secs(30));
+    /// let string: String = chan.receive().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn enable_keepalive(&mut self, interval: Duration, timeout: Duration) {
+        self.bipartite().keepalive = Some(Keepalive::new(interval, timeout));
+    }
+    /// Rotate the key used to encrypt the messages sent through the channel.
+    /// The peer rotates its key when receiving the rekey frame, so no message is lost.
+    /// Fails if the channel isn't encrypted.
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// chan.send("Hello world!").await?;
+    /// chan.rekey().await?;
+    /// chan.send("Hello again!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn rekey(&mut self) -> Result<()> {
+        match self {
+            Channel::Unified(chan) => chan.rekey().await,
+            Channel::Bipartite(chan) => chan.send_channel.rekey().await,
+        }
+    }
+    /// Rotate the key automatically every time `bytes` bytes have been sent,
+    /// `None` disables automatic rekeying. Has no effect on unencrypted channels.
+    ///
+    /// Each side rotates the key of the messages it sends,
+    /// so both peers should enable it on long-lived channels.
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// chan.rekey_after(Some(1 << 30));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rekey_after(&mut self, bytes: Option<u64>) {
+        match self {
+            Channel::Unified(chan) => chan.rekey_after(bytes),
+            Channel::Bipartite(chan) => chan.send_channel.rekey_after(bytes),
+        }
+    }
+    /// Move the length of the frames sent from now on inside the encryption,
+    /// padding every frame to a multiple of 256 bytes, the same as
+    /// `pad_frames(Padding::Bucket(256))`. Disabled by default,
+    /// and has no effect on unencrypted channels.
+    ///
+    /// On stream transports the clear length prefix of every frame then only tells the
+    /// padded length, so an observer can't tell a ping from a short message, or two
+    /// messages of similar size apart. Sizes are still visible to the nearest 256 bytes,
+    /// and so are the timing and the number of frames: large transfers and traffic
+    /// patterns stand out either way. Padding costs up to 264 bytes per frame,
+    /// a lot for small messages and next to nothing for large ones.
+    ///
+    /// With it disabled, the clear prefix is the exact length of the encrypted frame,
+    /// which lets middleboxes route or account traffic by size, and
+    /// lets observers guess what is being sent from it.
+    ///
+    /// Only the sending side decides: padded frames are recognized and unpadded on
+    /// receive whatever the setting of the receiver, so each peer can enable it alone.
+    /// Peers on releases without it fail to read padded frames
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// chan.encrypt_length_prefix(true);
+    /// chan.send("the size of this message is hidden").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn encrypt_length_prefix(&mut self, enabled: bool) {
+        match self {
+            Channel::Unified(chan) => chan.encrypt_length_prefix(enabled),
+            Channel::Bipartite(chan) => chan.send_channel.encrypt_length_prefix(enabled),
+        }
+    }
+    /// Pad the frames sent from now on inside the encryption with the given scheme,
+    /// so observers only see the padded length of each. Off by default,
+    /// and has no effect on unencrypted channels.
+    ///
+    /// `Padding::PowerOfTwo` only leaks the order of magnitude of every frame but can
+    /// nearly double the bandwidth, `Padding::Bucket` leaks lengths to the nearest bucket
+    /// and costs up to a bucket per frame. Either way every padded frame carries 9 more bytes,
+    /// and timing and frame counts stay visible.
+    ///
+    /// Setting the scheme in `SnowConfig::padding` instead has both sides check they agree
+    /// on it during the handshake. Receiving doesn't depend on the setting,
+    /// so a scheme set here is only known to this side
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// use canary::async_snow::Padding;
+    ///
+    /// chan.pad_frames(Padding::PowerOfTwo);
+    /// chan.send("only the order of magnitude of this length is visible").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn pad_frames(&mut self, padding: Padding) {
+        match self {
+            Channel::Unified(chan) => chan.pad_frames(padding),
+            Channel::Bipartite(chan) => chan.send_channel.pad_frames(padding),
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Limit the messages received from now on with a token bucket holding `limit.burst`
+    /// messages and refilling at `limit.per_second`. Messages received while the bucket
+    /// is empty are delayed or rejected depending on `overflow`.
+    /// Only messages, results and errors sent by the peer count,
+    /// control frames such as keepalive pings and the chunks of streams don't.
+    /// Replaces the previous limit, if any.
+    ///
+    /// To cap how many connections a peer can open instead, see `Tcp::rate_limit`
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// use canary::providers::{Overflow, RateLimit};
+    ///
+    /// chan.rate_limit(RateLimit::per_second(100).burst(200), Overflow::Reject);
+    /// let message: String = chan.receive().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rate_limit(&mut self, limit: RateLimit, overflow: Overflow) {
+        match self {
+            Channel::Unified(chan) => chan.rate_limit(limit, overflow),
+            Channel::Bipartite(chan) => chan.receive_channel.rate_limit(limit, overflow),
+        }
+    }
+    /// Hash of the handshake that encrypted the channel, `None` if it isn't encrypted.
+    ///
+    /// Both peers see the same value and every handshake produces a different one,
+    /// so signing it or mixing it into an authentication token binds the token
+    /// to this channel, and a stolen token can't be replayed on another connection.
+    /// It is 32 bytes long with the default hash, and 64 bytes with `Blake2b` or `Sha512`.
+    /// ```no_run
+    /// # async fn example(chan: canary::Channel) -> canary::Result<()> {
+    /// if let Some(binding) = chan.channel_binding() {
+    ///     println!("channel bound to {:x?}", binding);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn channel_binding(&self) -> Option<&[u8]> {
+        match self {
+            Channel::Unified(chan) if chan.channel.is_encrypted() => {
+                chan.channel_binding.as_deref()
+            }
+            Channel::Bipartite(chan) if chan.receive_channel.channel.is_encrypted() => {
+                chan.receive_channel.channel_binding.as_deref()
+            }
+            _ => None,
+        }
+    }
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// Certificate chain the peer presented during the TLS handshake,
+    /// the one of the peer first. `None` if the channel doesn't run over TLS,
+    /// if the peer didn't authenticate or if the channel has been split
+    /// ```no_run
+    /// # async fn example(chan: canary::Channel) -> canary::Result<()> {
+    /// if let Some(chain) = chan.peer_certificates() {
+    ///     println!("peer authenticated with {} certificates", chain.len());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn peer_certificates(
+        &self,
+    ) -> Option<&[tokio_rustls::rustls::pki_types::CertificateDer<'static>]> {
+        let chan = match self {
+            Channel::Unified(chan) => &chan.channel,
+            Channel::Bipartite(_) => return None,
+        };
+        let raw = match chan {
+            UnformattedUnifiedChannel::Raw(raw)
+            | UnformattedUnifiedChannel::Checksummed(raw)
+            | UnformattedUnifiedChannel::Encrypted { chan: raw, .. } => raw,
+        };
+        match raw {
+            UnformattedRawUnifiedChannel::Tls(stream) => stream.get_ref().1.peer_certificates(),
+            UnformattedRawUnifiedChannel::Wss(stream) => match stream.get_ref().get_ref() {
+                WssStream::Tls(stream) => stream.get_ref().1.peer_certificates(),
+                WssStream::Tcp(_) => None,
+            },
+            _ => None,
+        }
+    }
+    /// remember the hash of the handshake run over the channel
+    pub(crate) fn set_channel_binding(&mut self, hash: &[u8]) {
+        let hash: Arc<[u8]> = Arc::from(hash);
+        match self {
+            Channel::Unified(chan) => chan.channel_binding = Some(hash),
+            Channel::Bipartite(chan) => {
+                chan.send_channel.channel_binding = Some(hash.clone());
+                chan.receive_channel.channel_binding = Some(hash);
+            }
+        }
+    }
+    /// Bound the length of the frames received from now on, checked on their prefix
+    /// before anything is allocated for them. `None` lifts the bound
+    pub(crate) fn limit_frames(&mut self, max: Option<usize>) {
+        match self {
+            Channel::Unified(chan) => chan.frame_limit = max,
+            Channel::Bipartite(chan) => chan.receive_channel.frame_limit = max,
+        }
+    }
+    /// send a frame through the channel
+    pub(crate) async fn send_frame(&mut self, bytes: &[u8]) -> Result<usize> {
+        match self {
+            Channel::Unified(chan) => chan.send_frame(bytes).await,
+            Channel::Bipartite(chan) => chan.send_channel.send_frame(bytes).await,
+        }
+    }
+    /// receive the next message frame, answering control frames in the meantime
+    pub(crate) async fn try_receive_data(&mut self) -> Result<Option<Vec<u8>>>
+    where
+        R: ReadFormat,
+    {
+        match self {
+            Channel::Unified(chan) => chan.try_receive_data().await,
+            Channel::Bipartite(chan) => chan.try_receive_data().await,
+        }
+    }
+    /// Replace the formats of the channel, keeping its state.
+    /// Lets channels use formats that aren't part of `Format`,
+    /// such as `Json::pretty()` for responses meant to be read by humans.
+    /// ```no_run
+    /// # async fn example(chan: canary::Channel) -> canary::Result<()> {
+    /// use canary::serialization::formats::{Format, Json};
+    ///
+    /// let mut chan = chan.with_formats(Format::Json, Json::pretty());
+    /// chan.send(&["indented", "over", "several", "lines"]).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_formats<R2, W2>(self, receive_format: R2, send_format: W2) -> Channel<R2, W2> {
+        self.map_formats(|_| receive_format, |_| send_format)
+    }
+    /// replace the formats of the channel, keeping its state
+    fn map_formats<R2, W2>(
+        self,
+        receive_format: impl FnOnce(R) -> R2,
+        send_format: impl FnOnce(W) -> W2,
+    ) -> Channel<R2, W2> {
+        match self {
+            Channel::Unified(chan) => Channel::Unified(UnifiedChannel {
+                channel: chan.channel,
+                receive_format: receive_format(chan.receive_format),
+                send_format: send_format(chan.send_format),
+                receive_closed: chan.receive_closed,
+                send_closed: chan.send_closed,
+                buffer: chan.buffer,
+                tap: chan.tap,
+                rekey: chan.rekey,
+                padding: chan.padding,
+                channel_binding: chan.channel_binding,
+                frame_limit: chan.frame_limit,
+                #[cfg(not(target_arch = "wasm32"))]
+                limiter: chan.limiter,
+                #[cfg(feature = "metrics")]
+                active: chan.active,
+                #[cfg(unix)]
+                received_fds: chan.received_fds,
+            }),
+            Channel::Bipartite(chan) => {
+                let receive = chan.receive_channel;
+                let send = chan.send_channel;
+                Channel::Bipartite(BipartiteChannel {
+                    receive_channel: ReceiveChannel {
+                        channel: receive.channel,
+                        format: receive_format(receive.format),
+                        closed: receive.closed,
+                        tap: receive.tap,
+                        poisoned: receive.poisoned,
+                        channel_binding: receive.channel_binding,
+                        frame_limit: receive.frame_limit,
+                        #[cfg(not(target_arch = "wasm32"))]
+                        limiter: receive.limiter,
+                        #[cfg(feature = "metrics")]
+                        active: receive.active,
+                    },
+                    send_channel: SendChannel {
+                        channel: send.channel,
+                        format: send_format(send.format),
+                        closed: send.closed,
+                        buffer: send.buffer,
+                        tap: send.tap,
+                        poisoned: send.poisoned,
+                        rekey: send.rekey,
+                        padding: send.padding,
+                        channel_binding: send.channel_binding,
+                        #[cfg(feature = "metrics")]
+                        active: send.active,
+                    },
+                    keepalive: chan.keepalive,
+                })
+            }
+        }
+    }
+    /// turn the channel into a bipartite channel, so that its halves can be used independently
+    fn bipartite(&mut self) -> &mut BipartiteChannel<R, W> {
+        take_mut::take(self, |this| match this {
+            Channel::Unified(chan) => {
+                let (send, receive) = chan.split();
+                Channel::Bipartite(BipartiteChannel {
+                    receive_channel: receive,
+                    send_channel: send,
+                    keepalive: None,
+                })
+            }
+            chan => chan,
+        });
+        match self {
+            Channel::Bipartite(chan) => chan,
+            Channel::Unified(_) => unreachable!("channel was just made bipartite"),
+        }
+    }
+}
+
+impl Channel<Format, Format> {
+    /// Ask the peer to use `format` in both directions,
+    /// the peer answers with `accept_format`.
+    /// Fails with an `UnsupportedFormat` listing the formats of the peer if it rejects it.
+    ///
+    /// The negotiation doesn't depend on the current format of either side,
+    /// so it works between peers built with different default formats.
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// use canary::serialization::formats::Format;
+    ///
+    /// chan.request_format(Format::Bincode).await?;
+    /// chan.send("Hello world!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn request_format(&mut self, format: Format) -> Result<()> {
+        self.send_frame(&frame::message(vec![format as u8])).await?;
+        let answer = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        negotiation::check_answer(format, frame::message_payload(&answer)?)?;
+        self.set_format(format);
+        Ok(())
+    }
+    /// Answer the format requested by the peer with `request_format`,
+    /// switching to it if it is one of `supported`.
+    /// Otherwise the peer is told which formats are supported,
+    /// and both sides fail with an `UnsupportedFormat`.
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// use canary::serialization::formats::Format;
+    ///
+    /// let format = chan.accept_format(&[Format::Bincode]).await?;
+    /// let string: String = chan.receive().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn accept_format(&mut self, supported: &[Format]) -> Result<Format> {
+        let request = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        let requested = negotiation::requested(frame::message_payload(&request)?)?;
+        let (answer, format) = negotiation::answer(requested, supported);
+        self.send_frame(&frame::message(answer)).await?;
+        let format = format?;
+        self.set_format(format);
+        Ok(format)
+    }
+    /// Negotiate a compression algorithm with the peer and compress every message with it.
+    /// Each side sends the algorithms it supports, and both pick the preferred one they have
+    /// in common, falling back to `Compression::None` instead of failing.
+    /// Both peers need to call it, `compression` returns the negotiated algorithm.
+    /// ```no_run
+    /// # async fn example(chan: canary::Channel) -> canary::Result<()> {
+    /// use canary::serialization::compressed::Compression;
+    ///
+    /// let mut chan = chan.compressed(Compression::SUPPORTED).await?;
+    /// println!("compressing with {:?}", chan.compression());
+    /// chan.send("Hello world!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn compressed(
+        mut self,
+        supported: &[Compression],
+    ) -> Result<Channel<Compressed, Compressed>> {
+        let ours = supported
+            .iter()
+            .map(|compression| *compression as u8)
+            .collect();
+        self.send_frame(&frame::message(ours)).await?;
+        let theirs = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        let compression = match frame::message_payload(&theirs) {
+            Ok(theirs) => Compression::negotiate(supported, theirs),
+            Err(_) => Compression::None,
+        };
+        Ok(self.map_formats(
+            |format| Compressed::new(format, compression),
+            |format| Compressed::new(format, compression),
+        ))
+    }
+    /// use the format in both directions
+    fn set_format(&mut self, format: Format) {
+        match self {
+            Channel::Unified(chan) => {
+                chan.receive_format = format;
+                chan.send_format = format;
+            }
+            Channel::Bipartite(chan) => {
+                chan.receive_channel.format = format;
+                chan.send_channel.format = format;
+            }
+        }
+    }
+    #[cfg(all(feature = "json_ser", not(target_arch = "wasm32")))]
+    /// Turn the channel into an `NdjsonChannel`, which sends every message
+    /// as a line of JSON without any framing. Both sides need to do this.
+    ///
+    /// Fails if `format` isn't `Format::Json`, or if the channel is encrypted,
+    /// checksummed, split or a websocket, since those can't be read as plain lines
+    /// ```no_run
+    /// # async fn example(chan: canary::Channel) -> canary::Result<()> {
+    /// use canary::serialization::formats::Format;
+    ///
+    /// let mut chan = chan.ndjson(Format::Json)?;
+    /// chan.send("Hello world!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn ndjson(self, format: Format) -> Result<NdjsonChannel> {
+        match self {
+            Channel::Unified(UnifiedChannel {
+                channel: UnformattedUnifiedChannel::Raw(chan),
+                ..
+            }) => NdjsonChannel::new(chan, format),
+            _ => err!((
+                unsupported,
+                "only raw channels that haven't been split can send ndjson"
+            )),
+        }
+    }
+    /// Prefix every message with a header carrying the format tag and a schema version.
+    /// Receiving a message sent with another version fails with a `VersionMismatch`,
+    /// use `receive_any` to handle older versions explicitly.
+    /// Both sides need to use versioned channels.
+    /// ```no_run
+    /// # async fn example(chan: canary::Channel) -> canary::Result<()> {
+    /// let mut chan = chan.versioned(2);
+    /// chan.send("Hello world!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn versioned(self, schema_version: u32) -> Channel<Versioned, Versioned> {
+        self.map_formats(
+            |format| Versioned::new(format, schema_version),
+            |format| Versioned::new(format, schema_version),
+        )
+    }
+}
+
+impl<W> Channel<Compressed, W> {
+    /// Algorithm negotiated by `Channel::compressed`
+    pub fn compression(&self) -> Compression {
+        match self {
+            Channel::Unified(chan) => chan.receive_format.compression,
+            Channel::Bipartite(chan) => chan.receive_channel.format.compression,
+        }
+    }
+}
+
+impl<W> Channel<Versioned, W> {
+    /// Receive the next message without checking its header or deserializing it,
+    /// returns the header and the serialized message.
+    /// ```no_run
+    /// # use canary::serialization::formats::Format;
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// let mut chan = chan.versioned(2);
+    /// let (header, bytes) = chan.receive_any().await?;
+    /// let name: String = match header.version {
+    ///     1 => Format::try_from(header.format)?.try_deserialize::<(String,)>(&bytes)?.0,
+    ///     _ => Format::try_from(header.format)?.try_deserialize(&bytes)?,
+    /// };
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_any(&mut self) -> Result<(Header, Vec<u8>)> {
+        let bytes = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        let (header, payload) = Header::decode(frame::message_payload(&bytes)?)?;
+        Ok((header, payload.to_vec()))
+    }
 }
 
 impl<'a> RefUnformattedBidirectionalChannel<'a> {
@@ -167,17 +1258,8 @@ impl<'a> RefUnformattedBidirectionalChannel<'a> {
         obj: T,
         format: &mut F,
     ) -> Result<usize> {
-        match self {
-            Self::Raw(chan) => chan.send(obj, format).await,
-            Self::Encrypted(chan, snow, nonce) => {
-                let ref mut snow = RefDividedSnow {
-                    transport: snow,
-                    nonce,
-                };
-                let mut with = WithCipher { snow, format };
-                chan.send(obj, &mut with).await
-            }
-        }
+        let bytes = frame::message(format.serialize(&obj)?);
+        self.send_bytes(&bytes).await
     }
     /// Receive an object sent through the channel with format
     /// ```no_run
@@ -187,15 +1269,57 @@ impl<'a> RefUnformattedBidirectionalChannel<'a> {
         &mut self,
         format: &mut F,
     ) -> Result<T> {
+        loop {
+            let bytes = self.receive_bytes().await?;
+            match frame::decode(&bytes)? {
+                (FrameKind::Message, payload) => return format.deserialize(payload),
+                (FrameKind::Chunk | FrameKind::End, _) => return Err(frame::unexpected_stream()),
+                (FrameKind::Result, _) => return Err(frame::unexpected_result()),
+                (FrameKind::Error, payload) => return Err(remote::from_frame(format, payload)),
+                (FrameKind::Ping, _) => {
+                    self.send_bytes(&frame::control(FrameKind::Pong)).await?;
+                }
+                (FrameKind::Close, _) => {
+                    self.send_bytes(&frame::control(FrameKind::CloseAck))
+                        .await?;
+                    return Err(frame::closed());
+                }
+                // the transport is borrowed, so it can't be rotated
+                (FrameKind::Rekey, _) => {
+                    return Err(err!(
+                        unsupported,
+                        "received a rekey through a borrowed channel"
+                    ))
+                }
+                (FrameKind::Pong | FrameKind::CloseAck, _) => {}
+            }
+        }
+    }
+    /// Send a buffer through the channel as a single frame, encrypting it if needed
+    pub async fn send_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
         match self {
-            Self::Raw(chan) => chan.receive(format).await,
+            Self::Raw(chan) => chan.send_bytes(bytes).await,
             Self::Encrypted(chan, snow, nonce) => {
-                let ref mut snow = RefDividedSnow {
+                let snow = &mut RefDividedSnow {
                     transport: snow,
                     nonce,
                 };
-                let mut with = WithCipher { snow, format };
-                chan.receive(&mut with).await
+                let bytes = snow.encrypt_packets(bytes)?;
+                chan.send_bytes(&bytes).await
+            }
+        }
+    }
+    /// Receive a single frame sent through the channel, decrypting it if needed
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        match self {
+            Self::Raw(chan) => chan.receive_bytes().await,
+            Self::Encrypted(chan, snow, nonce) => {
+                let snow = &mut RefDividedSnow {
+                    transport: snow,
+                    nonce,
+                };
+                let bytes = chan.receive_bytes().await?;
+                snow.decrypt(&bytes)
             }
         }
     }
@@ -246,3 +1370,13 @@ impl UnformattedBidirectionalChannel {
         }
     }
 }
+
+#[cfg(unix)]
+#[inline]
+/// error returned when passing file descriptors over a channel that was split
+fn fds_split() -> Error {
+    err!(
+        unsupported,
+        "file descriptors can only be passed over channels that haven't been split"
+    )
+}
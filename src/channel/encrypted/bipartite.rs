@@ -1,11 +1,16 @@
-use std::sync::Arc;
+use crate::async_snow::SharedTransport;
 
 use serde::{de::DeserializeOwned, Serialize};
-use snow::StatelessTransportState;
 
+use crate::channel::borrowed::Received;
 use crate::channel::channels::{ReceiveChannel, SendChannel};
+use crate::channel::frame::{self, FrameKind};
+use crate::channel::keepalive::Keepalive;
+use crate::channel::remote::{self, RemoteError};
+use crate::channel::stream;
+use crate::io::{Read, Write};
 use crate::serialization::formats::{Format, ReadFormat, SendFormat};
-use crate::Result;
+use crate::{Error, Result};
 
 use super::{receive_channel::UnformattedReceiveChannel, send_channel::UnformattedSendChannel};
 
@@ -23,6 +28,8 @@ pub struct BipartiteChannel<R = Format, W = Format> {
     pub receive_channel: ReceiveChannel<R>,
     /// Inner receive channel
     pub send_channel: SendChannel<W>,
+    /// Keepalive of the channel, if enabled
+    pub keepalive: Option<Keepalive>,
 }
 
 impl UnformattedBipartiteChannel {
@@ -34,7 +41,26 @@ impl UnformattedBipartiteChannel {
         &mut self,
         format: &mut F,
     ) -> Result<T> {
-        self.receive_channel.receive(format).await
+        loop {
+            let bytes = self.receive_channel.receive_bytes().await?;
+            match frame::decode(&bytes)? {
+                (FrameKind::Message, payload) => return format.deserialize(payload),
+                (FrameKind::Chunk | FrameKind::End, _) => return Err(frame::unexpected_stream()),
+                (FrameKind::Result, _) => return Err(frame::unexpected_result()),
+                (FrameKind::Error, payload) => return Err(remote::from_frame(format, payload)),
+                (FrameKind::Ping, _) => {
+                    let pong = frame::control(FrameKind::Pong);
+                    self.send_channel.send_bytes(&pong).await?;
+                }
+                (FrameKind::Close, _) => {
+                    let ack = frame::control(FrameKind::CloseAck);
+                    self.send_channel.send_bytes(&ack).await?;
+                    return Err(frame::closed());
+                }
+                (FrameKind::Rekey, _) => self.receive_channel.rekey_incoming()?,
+                (FrameKind::Pong | FrameKind::CloseAck, _) => {}
+            }
+        }
     }
 
     /// Send an object through the channel serialized with format
@@ -57,20 +83,17 @@ impl UnformattedBipartiteChannel {
 impl<R, W> BipartiteChannel<R, W> {
     /// Try to encrypt channel using the provided transport.
     /// Will return an error if channel is already encrypted.
-    /// To turn `Arc<StatelessTransportState>` into the inner transport state
-    /// use `Arc::try_unwrap(transport)`.
-    pub fn encrypt(
-        &mut self,
-        transport: Arc<StatelessTransportState>,
-    ) -> Result<(), Arc<StatelessTransportState>> {
+    /// To turn `SharedTransport` into the inner transport state
+    /// use `Arc::try_unwrap(transport)` followed by `RwLock::into_inner`.
+    pub fn encrypt(&mut self, transport: SharedTransport) -> Result<(), SharedTransport> {
         let mut state = Ok(());
         take_mut::take(self, |mut this| {
-            if let Err(_) = this.receive_channel.encrypt(transport.clone()) {
+            if this.receive_channel.encrypt(transport.clone()).is_err() {
                 state = Err(transport);
                 return this;
             }
 
-            if let Err(_) = this.receive_channel.encrypt(transport.clone()) {
+            if this.send_channel.encrypt(transport.clone()).is_err() {
                 state = Err(transport);
                 return this;
             }
@@ -86,7 +109,139 @@ impl<R, W> BipartiteChannel<R, W> {
     where
         R: ReadFormat,
     {
-        self.receive_channel.receive().await
+        self.try_receive().await?.ok_or_else(frame::closed)
+    }
+    /// Receive a result sent with `send_result`
+    pub async fn receive_result<T: DeserializeOwned, E: DeserializeOwned>(
+        &mut self,
+    ) -> Result<std::result::Result<T, E>>
+    where
+        R: ReadFormat,
+    {
+        let bytes = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        frame::result_payload(&mut self.receive_channel.format, &bytes)
+    }
+    #[cfg(feature = "json_ser")]
+    /// Receive the next message as a dynamic JSON value, see `Channel::receive_value`
+    pub async fn receive_value(&mut self) -> Result<serde_json::Value>
+    where
+        R: ReadFormat,
+    {
+        let bytes = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        let payload = frame::message_payload(&bytes)?;
+        self.receive_channel.format.deserialize_value(payload)
+    }
+    /// Receive the next message to deserialize borrowing from it,
+    /// see `Channel::receive_borrowed`
+    pub async fn receive_borrowed(&mut self) -> Result<Received<'_, R>>
+    where
+        R: ReadFormat,
+    {
+        let bytes = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        Received::new(&mut self.receive_channel.format, bytes)
+    }
+    /// Receive an object sent through the channel,
+    /// returns `None` if the peer closed the channel
+    /// ```no_run
+    /// # async fn example(mut chan: canary::channel::encrypted::bipartite::BipartiteChannel) -> canary::Result<()> {
+    /// while let Some(string) = chan.try_receive::<String>().await? {
+    ///     println!("{}", string);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn try_receive<T: DeserializeOwned>(&mut self) -> Result<Option<T>>
+    where
+        R: ReadFormat,
+    {
+        match self.try_receive_data().await? {
+            Some(bytes) => {
+                let payload = frame::message_payload(&bytes)?;
+                self.receive_channel.format.deserialize(payload).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+    /// Ping the peer and wait for its pong, see `Channel::ping`
+    pub async fn ping(&mut self) -> Result<()>
+    where
+        R: ReadFormat,
+    {
+        if self.send_channel.closed {
+            return Err(frame::send_closed());
+        }
+        if self.receive_channel.poisoned {
+            return Err(frame::poisoned());
+        }
+        let receive = &mut self.receive_channel;
+        let send = &mut self.send_channel;
+        send.send_frame(&frame::control(FrameKind::Ping)).await?;
+        while !receive.closed {
+            let bytes = receive.receive_frame().await?;
+            match frame::decode(&bytes)? {
+                (FrameKind::Pong, _) => return Ok(()),
+                (FrameKind::Message | FrameKind::Result | FrameKind::Chunk | FrameKind::End, _) => {
+                    return Err(frame::unexpected_data())
+                }
+                (FrameKind::Error, payload) => {
+                    return Err(remote::from_frame(&mut receive.format, payload))
+                }
+                (FrameKind::Ping, _) => {
+                    send.send_frame(&frame::control(FrameKind::Pong)).await?;
+                }
+                (FrameKind::Close, _) => {
+                    receive.closed = true;
+                    send.send_frame(&frame::control(FrameKind::CloseAck))
+                        .await?;
+                }
+                (FrameKind::Rekey, _) => receive.channel.rekey_incoming()?,
+                (FrameKind::CloseAck, _) => {}
+            }
+        }
+        Err(frame::closed())
+    }
+    /// receive the next message frame, answering control frames in the meantime
+    pub(crate) async fn try_receive_data(&mut self) -> Result<Option<Vec<u8>>>
+    where
+        R: ReadFormat,
+    {
+        if self.receive_channel.poisoned {
+            return Err(frame::poisoned());
+        }
+        self.next_data().await
+    }
+    /// receive the next frame carrying data, skipping control frames
+    async fn next_data(&mut self) -> Result<Option<Vec<u8>>>
+    where
+        R: ReadFormat,
+    {
+        let receive = &mut self.receive_channel;
+        let send = &mut self.send_channel;
+        while !receive.closed {
+            let bytes = match &mut self.keepalive {
+                Some(keepalive) => keepalive.receive_frame(receive, send).await?,
+                None => receive.receive_frame().await?,
+            };
+            match frame::decode(&bytes)? {
+                (FrameKind::Message | FrameKind::Result | FrameKind::Chunk | FrameKind::End, _) => {
+                    return Ok(Some(bytes))
+                }
+                (FrameKind::Error, payload) => {
+                    return Err(remote::from_frame(&mut receive.format, payload))
+                }
+                (FrameKind::Ping, _) => {
+                    send.send_frame(&frame::control(FrameKind::Pong)).await?;
+                }
+                (FrameKind::Close, _) => {
+                    receive.closed = true;
+                    send.send_frame(&frame::control(FrameKind::CloseAck))
+                        .await?;
+                }
+                (FrameKind::Rekey, _) => receive.channel.rekey_incoming()?,
+                (FrameKind::Pong | FrameKind::CloseAck, _) => {}
+            }
+        }
+        Ok(None)
     }
 
     /// Send an object through the channel
@@ -97,8 +252,151 @@ impl<R, W> BipartiteChannel<R, W> {
     where
         W: SendFormat,
     {
+        if let Some(keepalive) = &self.keepalive {
+            keepalive.check()?;
+        }
         self.send_channel.send(obj).await
     }
+    /// Send a result through the channel, received by the peer with `receive_result`
+    pub async fn send_result<T: Serialize, E: Serialize>(
+        &mut self,
+        result: std::result::Result<T, E>,
+    ) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        if let Some(keepalive) = &self.keepalive {
+            keepalive.check()?;
+        }
+        self.send_channel.send_result(result).await
+    }
+    /// Send an error to the peer, its `receive` will return it as a `RemoteError`
+    pub async fn send_error(&mut self, error: &Error) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        if let Some(keepalive) = &self.keepalive {
+            keepalive.check()?;
+        }
+        self.send_channel.send_error(error).await
+    }
+    /// Stream the contents of the reader to the peer in chunks,
+    /// see `SendChannel::send_reader`
+    pub async fn send_reader<Rd: Read + Unpin>(
+        &mut self,
+        reader: Rd,
+        len: Option<u64>,
+    ) -> Result<u64>
+    where
+        W: SendFormat,
+    {
+        if let Some(keepalive) = &self.keepalive {
+            keepalive.check()?;
+        }
+        self.send_channel.send_reader(reader, len).await
+    }
+    /// Write a stream sent by the peer into the writer,
+    /// see `ReceiveChannel::receive_writer`
+    pub async fn receive_writer<Wr: Write + Unpin>(&mut self, writer: Wr) -> Result<u64>
+    where
+        R: ReadFormat,
+    {
+        self.receive_stream(writer, None, |_| {}).await
+    }
+    /// Send the length of the stream followed by the stream itself,
+    /// see `SendChannel::send_chunked`
+    pub async fn send_chunked<Rd: Read + Unpin>(&mut self, reader: Rd, len: u64) -> Result<u64>
+    where
+        W: SendFormat,
+    {
+        self.send(len).await?;
+        self.send_reader(reader, Some(len)).await
+    }
+    /// Write a stream sent by the peer with `send_chunked` into the writer,
+    /// reporting the progress, see `ReceiveChannel::receive_chunked`
+    pub async fn receive_chunked<Wr: Write + Unpin>(
+        &mut self,
+        writer: Wr,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<u64>
+    where
+        R: ReadFormat,
+    {
+        let total: u64 = self.receive().await?;
+        self.receive_stream(writer, Some(total), |received| progress(received, total))
+            .await
+    }
+    /// write a stream to the writer, calling `progress` with the bytes received so far
+    async fn receive_stream<Wr: Write + Unpin>(
+        &mut self,
+        mut writer: Wr,
+        total: Option<u64>,
+        mut progress: impl FnMut(u64),
+    ) -> Result<u64>
+    where
+        R: ReadFormat,
+    {
+        if self.receive_channel.poisoned {
+            return Err(frame::poisoned());
+        }
+        let mut written = 0;
+        loop {
+            let bytes = match self.next_data().await {
+                Ok(bytes) => bytes.ok_or_else(frame::closed)?,
+                Err(e) => {
+                    // the peer aborted the stream
+                    let poisoned = &mut self.receive_channel.poisoned;
+                    *poisoned = *poisoned && RemoteError::of(&e).is_none();
+                    return Err(e);
+                }
+            };
+            self.receive_channel.poisoned = stream::is_chunk(&bytes);
+            match stream::write_frame(&mut writer, &mut written, total, &bytes).await? {
+                Some(len) => return Ok(len),
+                None => progress(written),
+            }
+        }
+    }
+    /// Tell the peer no more messages will be sent, the channel can still receive
+    pub async fn close_send(&mut self) -> Result<()> {
+        self.send_channel.close().await
+    }
+    /// Close the channel and wait for the peer to acknowledge it.
+    /// Messages received in the meantime are discarded
+    pub async fn close(mut self) -> Result<()> {
+        self.close_send().await?;
+        let receive = &mut self.receive_channel;
+        let send = &mut self.send_channel;
+        frame::close_ack(async {
+            loop {
+                let bytes = match receive.receive_frame().await {
+                    Ok(bytes) => bytes,
+                    // the peer may drop the channel right after acknowledging our close
+                    Err(_) if receive.closed => return Ok(()),
+                    Err(e) => return Err(e),
+                };
+                match frame::decode(&bytes)?.0 {
+                    FrameKind::CloseAck => return Ok(()),
+                    FrameKind::Ping => {
+                        send.send_frame(&frame::control(FrameKind::Pong)).await?;
+                    }
+                    FrameKind::Close => {
+                        receive.closed = true;
+                        send.send_frame(&frame::control(FrameKind::CloseAck))
+                            .await?;
+                    }
+                    FrameKind::Rekey => receive.channel.rekey_incoming()?,
+                    FrameKind::Message
+                    | FrameKind::Pong
+                    | FrameKind::Error
+                    | FrameKind::Chunk
+                    | FrameKind::End
+                    | FrameKind::Result => {}
+                }
+            }
+        })
+        .await
+    }
     #[must_use]
     /// Split channel into its send and receive components
     pub fn split(self) -> (SendChannel<W>, ReceiveChannel<R>) {
@@ -2,21 +2,30 @@ use std::sync::Arc;
 
 use derive_more::From;
 use serde::de::DeserializeOwned;
-use snow::StatelessTransportState;
 
 use crate::{
-    async_snow::RefDividedSnow,
+    async_snow::{self, Decrypt, RefDividedSnow, SharedTransport},
     channel::{
+        borrowed::Received,
         channels::SendChannel,
+        checksum,
+        frame::{self, FrameKind},
         raw::bipartite::receive_channel::{
             RefUnformattedRawReceiveChannel, UnformattedRawReceiveChannel,
         },
+        rekey,
+        remote::{self, RemoteError},
+        stream,
+        tap::{self, Direction, Tap},
     },
+    io::Write,
+    runtime::{self, CancelToken},
     serialization::formats::{Format, ReadFormat},
     Channel, Result,
 };
 
-use super::snowwith::WithCipher;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::providers::rate_limit::{MessageLimiter, Overflow, RateLimit};
 
 #[derive(From)]
 /// Reference unformatted receive channel, may be encrypted
@@ -26,8 +35,8 @@ pub enum RefUnformattedReceiveChannel<'a> {
     /// Encrypted channel
     Encrypted(
         RefUnformattedRawReceiveChannel<'a>,
-        &'a Arc<StatelessTransportState>,
-        &'a mut u32,
+        &'a SharedTransport,
+        &'a mut u64,
     ),
 }
 
@@ -37,11 +46,10 @@ pub enum UnformattedReceiveChannel {
     /// Unencrypted channel
     Raw(UnformattedRawReceiveChannel),
     /// Encrypted channel
-    Encrypted(
-        UnformattedRawReceiveChannel,
-        Arc<StatelessTransportState>,
-        u32,
-    ),
+    Encrypted(UnformattedRawReceiveChannel, SharedTransport, u64),
+    #[from(ignore)]
+    /// Unencrypted channel that checks the checksum of every frame
+    Checksummed(UnformattedRawReceiveChannel),
 }
 
 #[derive(From)]
@@ -53,13 +61,35 @@ pub struct RefReceiveChannel<'a, F = Format> {
     pub format: F,
 }
 
-#[derive(From)]
 /// Receive channel with format
 pub struct ReceiveChannel<F = Format> {
     /// Inner channel
     pub channel: UnformattedReceiveChannel,
     /// Inner format
     pub format: F,
+    /// Whether the peer closed the channel
+    pub closed: bool,
+    /// Hook shown every frame received through the channel
+    pub(crate) tap: Option<Tap>,
+    /// Whether a stream was interrupted while being received
+    pub(crate) poisoned: bool,
+    /// Hash of the handshake that encrypted the channel, see `Channel::channel_binding`
+    pub(crate) channel_binding: Option<Arc<[u8]>>,
+    /// Longest frame accepted on the wire, bounded while a handshake runs
+    pub(crate) frame_limit: Option<usize>,
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Token bucket limiting the messages received, see `Channel::rate_limit`
+    pub(crate) limiter: Option<MessageLimiter>,
+    #[cfg(feature = "metrics")]
+    /// Keeps the channel counted as active until its last half is dropped
+    pub(crate) active: Option<crate::metrics::Active>,
+}
+
+impl<F> From<(UnformattedReceiveChannel, F)> for ReceiveChannel<F> {
+    #[inline]
+    fn from((channel, format): (UnformattedReceiveChannel, F)) -> Self {
+        channel.to_formatted(format)
+    }
 }
 
 impl<'a, F> RefReceiveChannel<'a, F> {
@@ -78,12 +108,9 @@ impl<'a, F> RefReceiveChannel<'a, F> {
 impl<R> ReceiveChannel<R> {
     /// Try to encrypt channel using the provided transport.
     /// Will return an error if channel is already encrypted.
-    /// To turn `Arc<StatelessTransportState>` into the inner transport state
-    /// use `Arc::try_unwrap(transport)`.
-    pub fn encrypt(
-        &mut self,
-        transport: Arc<StatelessTransportState>,
-    ) -> Result<(), Arc<StatelessTransportState>> {
+    /// To turn `SharedTransport` into the inner transport state
+    /// use `Arc::try_unwrap(transport)` followed by `RwLock::into_inner`.
+    pub fn encrypt(&mut self, transport: SharedTransport) -> Result<(), SharedTransport> {
         self.channel.encrypt(transport)
     }
     /// Receive an object sent through the channel
@@ -94,7 +121,174 @@ impl<R> ReceiveChannel<R> {
     where
         R: ReadFormat,
     {
-        self.channel.receive(&mut self.format).await
+        self.try_receive().await?.ok_or_else(frame::closed)
+    }
+    /// Receive an object unless the token is cancelled first, see `Channel::receive_cancellable`
+    pub async fn receive_cancellable<T: DeserializeOwned>(
+        &mut self,
+        token: &CancelToken,
+    ) -> Result<T>
+    where
+        R: ReadFormat,
+    {
+        runtime::cancellable(token, self.receive()).await
+    }
+    /// Receive a result sent with `send_result`
+    pub async fn receive_result<T: DeserializeOwned, E: DeserializeOwned>(
+        &mut self,
+    ) -> Result<std::result::Result<T, E>>
+    where
+        R: ReadFormat,
+    {
+        let bytes = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        frame::result_payload(&mut self.format, &bytes)
+    }
+    #[cfg(feature = "json_ser")]
+    /// Receive the next message as a dynamic JSON value, see `Channel::receive_value`
+    pub async fn receive_value(&mut self) -> Result<serde_json::Value>
+    where
+        R: ReadFormat,
+    {
+        let bytes = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        self.format
+            .deserialize_value(frame::message_payload(&bytes)?)
+    }
+    /// Receive the next message to deserialize borrowing from it,
+    /// see `Channel::receive_borrowed`
+    pub async fn receive_borrowed(&mut self) -> Result<Received<'_, R>>
+    where
+        R: ReadFormat,
+    {
+        let bytes = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        Received::new(&mut self.format, bytes)
+    }
+    /// Receive an object sent through the channel,
+    /// returns `None` if the peer closed the channel
+    /// ```no_run
+    /// # async fn example(mut chan: canary::channel::channels::ReceiveChannel) -> canary::Result<()> {
+    /// while let Some(string) = chan.try_receive::<String>().await? {
+    ///     println!("{}", string);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn try_receive<T: DeserializeOwned>(&mut self) -> Result<Option<T>>
+    where
+        R: ReadFormat,
+    {
+        match self.try_receive_data().await? {
+            Some(bytes) => self
+                .format
+                .deserialize(frame::message_payload(&bytes)?)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+    /// receive the next message frame, skipping control frames
+    pub(crate) async fn try_receive_data(&mut self) -> Result<Option<Vec<u8>>>
+    where
+        R: ReadFormat,
+    {
+        if self.poisoned {
+            return Err(frame::poisoned());
+        }
+        self.next_data().await
+    }
+    /// receive the next frame carrying data, skipping control frames
+    async fn next_data(&mut self) -> Result<Option<Vec<u8>>>
+    where
+        R: ReadFormat,
+    {
+        while !self.closed {
+            let bytes = self.receive_frame().await?;
+            match frame::decode(&bytes)? {
+                (FrameKind::Message | FrameKind::Result | FrameKind::Chunk | FrameKind::End, _) => {
+                    return Ok(Some(bytes))
+                }
+                (FrameKind::Error, payload) => {
+                    return Err(remote::from_frame(&mut self.format, payload))
+                }
+                (FrameKind::Close, _) => self.closed = true,
+                (FrameKind::Rekey, _) => self.channel.rekey_incoming()?,
+                // a lone receive channel has no way of answering control frames
+                (FrameKind::Ping | FrameKind::Pong | FrameKind::CloseAck, _) => {}
+            }
+        }
+        Ok(None)
+    }
+    /// Receive a stream sent with `send_reader` and write it to the writer,
+    /// returns the length of the stream.
+    ///
+    /// If the future is dropped or writing fails midway the channel is poisoned
+    /// and every following receive fails, since the rest of the stream is still pending
+    pub async fn receive_writer<Wr: Write + Unpin>(&mut self, writer: Wr) -> Result<u64>
+    where
+        R: ReadFormat,
+    {
+        self.receive_stream(writer, None, |_| {}).await
+    }
+    /// Receive a stream sent with `SendChannel::send_chunked` and write it to the writer,
+    /// calling `progress` with the bytes received so far and the declared length
+    /// after every chunk. Returns the length of the stream.
+    ///
+    /// Fails if the stream goes past the declared length or ends short of it
+    pub async fn receive_chunked<Wr: Write + Unpin>(
+        &mut self,
+        writer: Wr,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<u64>
+    where
+        R: ReadFormat,
+    {
+        let total: u64 = self.receive().await?;
+        self.receive_stream(writer, Some(total), |received| progress(received, total))
+            .await
+    }
+    /// write a stream to the writer, calling `progress` with the bytes received so far
+    async fn receive_stream<Wr: Write + Unpin>(
+        &mut self,
+        mut writer: Wr,
+        total: Option<u64>,
+        mut progress: impl FnMut(u64),
+    ) -> Result<u64>
+    where
+        R: ReadFormat,
+    {
+        if self.poisoned {
+            return Err(frame::poisoned());
+        }
+        let mut written = 0;
+        loop {
+            let bytes = match self.next_data().await {
+                Ok(bytes) => bytes.ok_or_else(frame::closed)?,
+                Err(e) => {
+                    // the peer aborted the stream
+                    self.poisoned = self.poisoned && RemoteError::of(&e).is_none();
+                    return Err(e);
+                }
+            };
+            self.poisoned = stream::is_chunk(&bytes);
+            match stream::write_frame(&mut writer, &mut written, total, &bytes).await? {
+                Some(len) => return Ok(len),
+                None => progress(written),
+            }
+        }
+    }
+    /// receive a frame from the channel and show it to the tap
+    pub(crate) async fn receive_frame(&mut self) -> Result<Vec<u8>> {
+        let max = self.frame_limit.unwrap_or(usize::MAX);
+        let bytes = self.channel.receive_bytes_within(max).await?;
+        tap::show(&self.tap, Direction::Receive, &bytes);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(limiter) = &mut self.limiter {
+            limiter.admit(&bytes).await?;
+        }
+        Ok(bytes)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    /// limit the messages received from now on, see `Channel::rate_limit`
+    pub fn rate_limit(&mut self, limit: RateLimit, overflow: Overflow) {
+        self.limiter = Some(MessageLimiter::new(limit, overflow));
     }
     /// Join `Self` and a `SendChannel` into a bidirectional channel
     pub fn join<W>(self, send: SendChannel<W>) -> Channel<R, W> {
@@ -111,18 +305,46 @@ impl<'a> RefUnformattedReceiveChannel<'a> {
         &mut self,
         format: &mut F,
     ) -> Result<T> {
+        loop {
+            let bytes = self.receive_bytes().await?;
+            match frame::decode(&bytes)? {
+                (FrameKind::Message, payload) => return format.deserialize(payload),
+                (FrameKind::Chunk | FrameKind::End, _) => return Err(frame::unexpected_stream()),
+                (FrameKind::Result, _) => return Err(frame::unexpected_result()),
+                (FrameKind::Error, payload) => return Err(remote::from_frame(format, payload)),
+                (FrameKind::Close, _) => return Err(frame::closed()),
+                (FrameKind::Rekey, _) => self.rekey_incoming()?,
+                // a lone receive channel has no way of answering control frames
+                (FrameKind::Ping | FrameKind::Pong | FrameKind::CloseAck, _) => {}
+            }
+        }
+    }
+    /// Receive a single frame sent through the channel, decrypting it if needed
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
         match self {
-            Self::Raw(chan) => chan.receive(format).await,
+            Self::Raw(chan) => chan.receive_bytes().await,
             Self::Encrypted(chan, snow, nonce) => {
-                let ref mut snow = RefDividedSnow {
-                    transport: snow,
+                let bytes = chan.receive_bytes().await?;
+                RefDividedSnow {
+                    transport: &async_snow::read(snow),
                     nonce,
-                };
-                let mut with = WithCipher { snow, format };
-                chan.receive(&mut with).await
+                }
+                .decrypt(&bytes)
             }
         }
     }
+    /// Rotate the key used to decrypt the following frames,
+    /// done when the peer sends a rekey frame.
+    /// Fails if the channel isn't encrypted
+    pub fn rekey_incoming(&mut self) -> Result<()> {
+        match self {
+            Self::Encrypted(_, transport, _) => {
+                async_snow::write(transport).rekey_incoming();
+                Ok(())
+            }
+            Self::Raw(_) => Err(rekey::not_encrypted()),
+        }
+    }
 
     /// Returns `true` if the ref unformatted receive channel is [`Encrypted`].
     ///
@@ -136,15 +358,12 @@ impl<'a> RefUnformattedReceiveChannel<'a> {
 impl UnformattedReceiveChannel {
     /// Try to encrypt channel using the provided transport.
     /// Will return an error if channel is already encrypted.
-    /// To turn `Arc<StatelessTransportState>` into the inner transport state
-    /// use `Arc::try_unwrap(transport)`.
-    pub fn encrypt(
-        &mut self,
-        transport: Arc<StatelessTransportState>,
-    ) -> Result<(), Arc<StatelessTransportState>> {
+    /// To turn `SharedTransport` into the inner transport state
+    /// use `Arc::try_unwrap(transport)` followed by `RwLock::into_inner`.
+    pub fn encrypt(&mut self, transport: SharedTransport) -> Result<(), SharedTransport> {
         let mut state = Ok(());
         take_mut::take(self, |this| match this {
-            Self::Raw(chan) => Self::Encrypted(chan, transport, 0),
+            Self::Raw(chan) | Self::Checksummed(chan) => Self::Encrypted(chan, transport, 0),
             Self::Encrypted(..) => {
                 state = Err(transport);
                 this
@@ -152,6 +371,15 @@ impl UnformattedReceiveChannel {
         });
         state
     }
+    /// Check the CRC32C trailer of every frame received through an unencrypted channel.
+    /// Encrypted channels are left as is since they already check the integrity of frames
+    pub fn set_checksum(&mut self, enabled: bool) {
+        take_mut::take(self, |this| match this {
+            Self::Raw(chan) if enabled => Self::Checksummed(chan),
+            Self::Checksummed(chan) if !enabled => Self::Raw(chan),
+            this => this,
+        });
+    }
     #[inline]
     /// Format the channel
     /// ```no_run
@@ -161,6 +389,15 @@ impl UnformattedReceiveChannel {
         ReceiveChannel {
             channel: self,
             format,
+            closed: false,
+            tap: None,
+            poisoned: false,
+            channel_binding: None,
+            frame_limit: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            limiter: None,
+            #[cfg(feature = "metrics")]
+            active: None,
         }
     }
     /// Receive an object sent through the channel with format
@@ -171,16 +408,49 @@ impl UnformattedReceiveChannel {
         &mut self,
         format: &mut F,
     ) -> Result<T> {
+        loop {
+            let bytes = self.receive_bytes().await?;
+            match frame::decode(&bytes)? {
+                (FrameKind::Message, payload) => return format.deserialize(payload),
+                (FrameKind::Chunk | FrameKind::End, _) => return Err(frame::unexpected_stream()),
+                (FrameKind::Result, _) => return Err(frame::unexpected_result()),
+                (FrameKind::Error, payload) => return Err(remote::from_frame(format, payload)),
+                (FrameKind::Close, _) => return Err(frame::closed()),
+                (FrameKind::Rekey, _) => self.rekey_incoming()?,
+                // a lone receive channel has no way of answering control frames
+                (FrameKind::Ping | FrameKind::Pong | FrameKind::CloseAck, _) => {}
+            }
+        }
+    }
+    /// Receive a single frame sent through the channel, decrypting it if needed
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        self.receive_bytes_within(usize::MAX).await
+    }
+    /// Receive a single frame of at most `max` bytes on the wire, decrypting it if needed
+    pub(crate) async fn receive_bytes_within(&mut self, max: usize) -> Result<Vec<u8>> {
         match self {
-            Self::Raw(chan) => chan.receive(format).await,
+            Self::Raw(chan) => chan.receive_bytes_within(max).await,
             Self::Encrypted(chan, snow, nonce) => {
-                let ref mut snow = RefDividedSnow {
-                    transport: snow,
+                let bytes = chan.receive_bytes_within(max).await?;
+                RefDividedSnow {
+                    transport: &async_snow::read(snow),
                     nonce,
-                };
-                let mut with = WithCipher { snow, format };
-                chan.receive(&mut with).await
+                }
+                .decrypt(&bytes)
+            }
+            Self::Checksummed(chan) => checksum::verify(chan.receive_bytes_within(max).await?),
+        }
+    }
+    /// Rotate the key used to decrypt the following frames,
+    /// done when the peer sends a rekey frame.
+    /// Fails if the channel isn't encrypted
+    pub fn rekey_incoming(&mut self) -> Result<()> {
+        match self {
+            Self::Encrypted(_, transport, _) => {
+                async_snow::write(transport).rekey_incoming();
+                Ok(())
             }
+            Self::Raw(_) | Self::Checksummed(_) => Err(rekey::not_encrypted()),
         }
     }
 
@@ -2,20 +2,25 @@ use std::sync::Arc;
 
 use derive_more::From;
 use serde::Serialize;
-use snow::StatelessTransportState;
 
 use crate::{
-    async_snow::RefDividedSnow,
+    async_snow::{self, Encrypt, Padding, RefDividedSnow, SharedTransport},
     channel::{
         channels::ReceiveChannel,
+        checksum,
+        frame::{self, FrameKind},
         raw::bipartite::send_channel::{RefUnformattedRawSendChannel, UnformattedRawSendChannel},
+        rekey::{self, Rekey},
+        remote, stream,
+        tap::{self, Direction, Tap},
     },
+    err,
+    io::{Read, ReadExt},
+    runtime::{self, CancelToken},
     serialization::formats::{Format, SendFormat},
-    Channel, Result,
+    Channel, Error, Result,
 };
 
-use super::snowwith::WithCipher;
-
 #[derive(From)]
 /// Reference unformatted send channel that may be encrypted
 pub enum RefUnformattedSendChannel<'a> {
@@ -24,8 +29,8 @@ pub enum RefUnformattedSendChannel<'a> {
     /// Encrypted channel
     Encrypted(
         RefUnformattedRawSendChannel<'a>,
-        &'a Arc<StatelessTransportState>,
-        &'a mut u32,
+        &'a SharedTransport,
+        &'a mut u64,
     ),
 }
 
@@ -35,7 +40,10 @@ pub enum UnformattedSendChannel {
     /// Unencrypted channel
     Raw(UnformattedRawSendChannel),
     /// Encrypted channel
-    Encrypted(UnformattedRawSendChannel, Arc<StatelessTransportState>, u32),
+    Encrypted(UnformattedRawSendChannel, SharedTransport, u64),
+    #[from(ignore)]
+    /// Unencrypted channel that appends a checksum to every frame
+    Checksummed(UnformattedRawSendChannel),
 }
 
 /// Reference send channel with format
@@ -65,6 +73,23 @@ pub struct SendChannel<W = Format> {
     pub channel: UnformattedSendChannel,
     /// Inner format used to serialize objects
     pub format: W,
+    /// Whether the channel was closed
+    pub closed: bool,
+    /// Scratch buffer messages are serialized into, reused across sends
+    pub(crate) buffer: Vec<u8>,
+    /// Hook shown every frame sent through the channel
+    pub(crate) tap: Option<Tap>,
+    /// Whether a stream was interrupted while being sent
+    pub(crate) poisoned: bool,
+    /// Automatic rekeying of the channel
+    pub(crate) rekey: Rekey,
+    /// Padding hiding the length of the frames sent, see `Channel::pad_frames`
+    pub(crate) padding: Padding,
+    /// Hash of the handshake that encrypted the channel, see `Channel::channel_binding`
+    pub(crate) channel_binding: Option<Arc<[u8]>>,
+    #[cfg(feature = "metrics")]
+    /// Keeps the channel counted as active until its last half is dropped
+    pub(crate) active: Option<crate::metrics::Active>,
 }
 
 impl<W> SendChannel<W> {
@@ -82,12 +107,9 @@ impl<W> SendChannel<W> {
     }
     /// Try to encrypt channel using the provided transport.
     /// Will return an error if channel is already encrypted.
-    /// To turn `Arc<StatelessTransportState>` into the inner transport state
-    /// use `Arc::try_unwrap(transport)`.
-    pub fn encrypt(
-        &mut self,
-        transport: Arc<StatelessTransportState>,
-    ) -> Result<(), Arc<StatelessTransportState>> {
+    /// To turn `SharedTransport` into the inner transport state
+    /// use `Arc::try_unwrap(transport)` followed by `RwLock::into_inner`.
+    pub fn encrypt(&mut self, transport: SharedTransport) -> Result<(), SharedTransport> {
         self.channel.encrypt(transport)
     }
     /// Send an object through the channel
@@ -98,7 +120,175 @@ impl<W> SendChannel<W> {
     where
         W: SendFormat,
     {
-        self.channel.send(obj, &mut self.format).await
+        self.check()?;
+        frame::message_into(&mut self.format, &obj, &mut self.buffer)?;
+        self.rekey_if_due(self.buffer.len()).await?;
+        tap::show(&self.tap, Direction::Send, &self.buffer);
+        self.channel.send_sealed(&self.buffer, self.padding).await
+    }
+    /// Send an object unless the token is cancelled first, see `Channel::receive_cancellable`
+    pub async fn send_cancellable<T: Serialize>(
+        &mut self,
+        obj: T,
+        token: &CancelToken,
+    ) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        runtime::cancellable(token, self.send(obj)).await
+    }
+    /// Send a result through the channel, received by the peer with `receive_result`
+    pub async fn send_result<T: Serialize, E: Serialize>(
+        &mut self,
+        result: std::result::Result<T, E>,
+    ) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        self.check()?;
+        frame::result_into(&mut self.format, &result, &mut self.buffer)?;
+        self.rekey_if_due(self.buffer.len()).await?;
+        tap::show(&self.tap, Direction::Send, &self.buffer);
+        self.channel.send_sealed(&self.buffer, self.padding).await
+    }
+    /// Send an error to the peer, its `receive` will return it as a `RemoteError`
+    pub async fn send_error(&mut self, error: &Error) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        self.check()?;
+        let bytes = remote::to_frame(&mut self.format, error)?;
+        self.send_frame(&bytes).await
+    }
+    /// Tell the peer no more messages will be sent
+    pub async fn close(&mut self) -> Result<()> {
+        if !self.closed {
+            self.send_frame(&frame::control(FrameKind::Close)).await?;
+            self.closed = true;
+        }
+        Ok(())
+    }
+    /// Send everything the reader yields as a stream of chunks,
+    /// received by the peer with `receive_writer`. Returns the length of the stream.
+    ///
+    /// If `len` is set, at most `len` bytes are sent and the reader ending earlier is an error.
+    /// When the reader fails the peer receives the error and the channel stays usable,
+    /// but if the future is dropped midway the channel is poisoned
+    /// and every following send fails.
+    pub async fn send_reader<Rd: Read + Unpin>(
+        &mut self,
+        reader: Rd,
+        len: Option<u64>,
+    ) -> Result<u64>
+    where
+        W: SendFormat,
+    {
+        self.check()?;
+        self.poisoned = true;
+        let res = match len {
+            Some(len) => match self.send_chunks(reader.take(len)).await {
+                Ok(written) if written < len => err!((
+                    unexpected_eof,
+                    format!("reader ended after {} of {} bytes", written, len)
+                )),
+                res => res,
+            },
+            None => self.send_chunks(reader).await,
+        };
+        match res {
+            Ok(written) => {
+                self.send_frame(&stream::end(written)).await?;
+                self.poisoned = false;
+                Ok(written)
+            }
+            Err(e) => {
+                // let the peer know the stream won't be completed
+                let bytes = remote::to_frame(&mut self.format, &e)?;
+                self.send_frame(&bytes).await?;
+                self.poisoned = false;
+                Err(e)
+            }
+        }
+    }
+    /// Send the length of the stream as a message, then the stream itself,
+    /// so the peer's `receive_chunked` can report its progress against the total.
+    ///
+    /// The reader ending before `len` bytes is an error, like with `send_reader`
+    pub async fn send_chunked<Rd: Read + Unpin>(&mut self, reader: Rd, len: u64) -> Result<u64>
+    where
+        W: SendFormat,
+    {
+        self.send(len).await?;
+        self.send_reader(reader, Some(len)).await
+    }
+    /// send the chunks of a stream, without its end
+    async fn send_chunks<Rd: Read + Unpin>(&mut self, mut reader: Rd) -> Result<u64> {
+        let mut written = 0;
+        while stream::read_chunk(&mut reader, &mut self.buffer).await? {
+            self.rekey_if_due(self.buffer.len()).await?;
+            tap::show(&self.tap, Direction::Send, &self.buffer);
+            self.channel.send_sealed(&self.buffer, self.padding).await?;
+            written += self.buffer.len() as u64 - 1;
+        }
+        Ok(written)
+    }
+    /// fails if the channel can't send anymore
+    fn check(&self) -> Result<()> {
+        if self.closed {
+            return Err(frame::send_closed());
+        }
+        if self.poisoned {
+            return Err(frame::poisoned());
+        }
+        Ok(())
+    }
+    /// Rotate the key used to encrypt the channel.
+    /// A rekey frame is sent with the old key and the peer rotates its key when receiving it,
+    /// so frames already on their way are still decrypted. Fails if the channel isn't encrypted
+    pub async fn rekey(&mut self) -> Result<()> {
+        self.check()?;
+        self.rotate().await
+    }
+    /// Rotate the key every time `bytes` bytes have been sent,
+    /// `None` disables automatic rekeying
+    pub fn rekey_after(&mut self, bytes: Option<u64>) {
+        self.rekey.set(bytes);
+    }
+    /// pad the frames sent from now on to a multiple of 256 bytes to hide their length,
+    /// see `Channel::encrypt_length_prefix`
+    pub fn encrypt_length_prefix(&mut self, enabled: bool) {
+        self.padding = if enabled {
+            Padding::Bucket(256)
+        } else {
+            Padding::None
+        };
+    }
+    /// pad the frames sent from now on with the given scheme, see `Channel::pad_frames`
+    pub fn pad_frames(&mut self, padding: Padding) {
+        self.padding = padding;
+    }
+    /// send a rekey frame with the current key, then rotate it
+    async fn rotate(&mut self) -> Result<()> {
+        if !self.is_encrypted() {
+            return Err(rekey::not_encrypted());
+        }
+        let bytes = frame::control(FrameKind::Rekey);
+        tap::show(&self.tap, Direction::Send, &bytes);
+        self.channel.send_sealed(&bytes, self.padding).await?;
+        self.channel.rekey_outgoing()
+    }
+    /// rotate the key before sending a frame of `len` bytes if enough bytes were sent
+    async fn rekey_if_due(&mut self, len: usize) -> Result<()> {
+        if self.is_encrypted() && self.rekey.due(len) {
+            self.rotate().await?;
+        }
+        Ok(())
+    }
+    /// send a frame through the channel, showing it to the tap first
+    pub(crate) async fn send_frame(&mut self, bytes: &[u8]) -> Result<usize> {
+        self.rekey_if_due(bytes.len()).await?;
+        tap::show(&self.tap, Direction::Send, bytes);
+        self.channel.send_sealed(bytes, self.padding).await
     }
 }
 
@@ -112,15 +302,20 @@ impl<'a> RefUnformattedSendChannel<'a> {
         obj: T,
         format: &mut F,
     ) -> Result<usize> {
+        let bytes = frame::message(format.serialize(&obj)?);
+        self.send_bytes(&bytes).await
+    }
+    /// Send a buffer through the channel as a single frame, encrypting it if needed
+    pub async fn send_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
         match self {
-            Self::Raw(chan) => chan.send(obj, format).await,
+            Self::Raw(chan) => chan.send_bytes(bytes).await,
             Self::Encrypted(chan, snow, nonce) => {
-                let ref mut snow = RefDividedSnow {
-                    transport: snow,
+                let bytes = RefDividedSnow {
+                    transport: &async_snow::read(snow),
                     nonce,
-                };
-                let mut with = WithCipher { snow, format };
-                chan.send(obj, &mut with).await
+                }
+                .encrypt_packets(bytes)?;
+                chan.send_bytes(&bytes).await
             }
         }
     }
@@ -137,15 +332,12 @@ impl<'a> RefUnformattedSendChannel<'a> {
 impl UnformattedSendChannel {
     /// Try to encrypt channel using the provided transport.
     /// Will return an error if channel is already encrypted.
-    /// To turn `Arc<StatelessTransportState>` into the inner transport state
-    /// use `Arc::try_unwrap(transport)`.
-    pub fn encrypt(
-        &mut self,
-        transport: Arc<StatelessTransportState>,
-    ) -> Result<(), Arc<StatelessTransportState>> {
+    /// To turn `SharedTransport` into the inner transport state
+    /// use `Arc::try_unwrap(transport)` followed by `RwLock::into_inner`.
+    pub fn encrypt(&mut self, transport: SharedTransport) -> Result<(), SharedTransport> {
         let mut state = Ok(());
         take_mut::take(self, |this| match this {
-            Self::Raw(chan) => Self::Encrypted(chan, transport, 0),
+            Self::Raw(chan) | Self::Checksummed(chan) => Self::Encrypted(chan, transport, 0),
             Self::Encrypted(..) => {
                 state = Err(transport);
                 this
@@ -153,6 +345,15 @@ impl UnformattedSendChannel {
         });
         state
     }
+    /// Append a CRC32C trailer to every frame sent through an unencrypted channel.
+    /// Encrypted channels are left as is since they already check the integrity of frames
+    pub fn set_checksum(&mut self, enabled: bool) {
+        take_mut::take(self, |this| match this {
+            Self::Raw(chan) if enabled => Self::Checksummed(chan),
+            Self::Checksummed(chan) if !enabled => Self::Raw(chan),
+            this => this,
+        });
+    }
     #[inline]
     /// Format the channel
     /// ```no_run
@@ -162,6 +363,15 @@ impl UnformattedSendChannel {
         SendChannel {
             channel: self,
             format,
+            closed: false,
+            buffer: Vec::new(),
+            tap: None,
+            poisoned: false,
+            rekey: Rekey::default(),
+            padding: Padding::None,
+            channel_binding: None,
+            #[cfg(feature = "metrics")]
+            active: None,
         }
     }
     /// Send an object through the channel serialized with format
@@ -173,16 +383,38 @@ impl UnformattedSendChannel {
         obj: T,
         format: &mut F,
     ) -> Result<usize> {
+        let bytes = frame::message(format.serialize(&obj)?);
+        self.send_bytes(&bytes).await
+    }
+    /// Send a buffer through the channel as a single frame, encrypting it if needed
+    pub async fn send_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
+        self.send_sealed(bytes, Padding::None).await
+    }
+    /// send a frame, padding it inside the encryption with the given scheme to hide its length
+    pub(crate) async fn send_sealed(&mut self, bytes: &[u8], padding: Padding) -> Result<usize> {
         match self {
-            Self::Raw(chan) => chan.send(obj, format).await,
+            Self::Raw(chan) => chan.send_bytes(bytes).await,
             Self::Encrypted(chan, snow, nonce) => {
-                let ref mut snow = RefDividedSnow {
-                    transport: snow,
+                let bytes = RefDividedSnow {
+                    transport: &async_snow::read(snow),
                     nonce,
-                };
-                let mut with = WithCipher { snow, format };
-                chan.send(obj, &mut with).await
+                }
+                .seal(bytes, padding)?;
+                chan.send_bytes(&bytes).await
+            }
+            Self::Checksummed(chan) => chan.send_bytes(&checksum::append(bytes)).await,
+        }
+    }
+    /// Rotate the key used to encrypt the following frames,
+    /// the peer has to rotate its receive key at the same frame.
+    /// Fails if the channel isn't encrypted
+    pub fn rekey_outgoing(&mut self) -> Result<()> {
+        match self {
+            Self::Encrypted(_, transport, _) => {
+                async_snow::write(transport).rekey_outgoing();
+                Ok(())
             }
+            Self::Raw(_) | Self::Checksummed(_) => Err(rekey::not_encrypted()),
         }
     }
 
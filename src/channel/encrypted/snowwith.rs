@@ -19,7 +19,7 @@ pub struct WithCipher<'a, C, F> {
 impl<C: Encrypt, F: SendFormat> SendFormat for WithCipher<'_, C, F> {
     fn serialize<O: Serialize>(&mut self, obj: &O) -> Result<Vec<u8>> {
         let obj = self.format.serialize(obj)?;
-        self.snow.encrypt_packets(obj)
+        self.snow.encrypt_packets(&obj)
     }
 }
 
@@ -31,4 +31,9 @@ impl<C: Decrypt, F: ReadFormat> ReadFormat for WithCipher<'_, C, F> {
         let bytes = self.snow.decrypt(bytes)?;
         self.format.deserialize(&bytes)
     }
+    #[cfg(feature = "json_ser")]
+    fn deserialize_value(&mut self, bytes: &[u8]) -> crate::Result<serde_json::Value> {
+        let bytes = self.snow.decrypt(bytes)?;
+        self.format.deserialize_value(&bytes)
+    }
 }
@@ -1,22 +1,32 @@
-use std::sync::Arc;
+#[cfg(unix)]
+use std::os::fd::{BorrowedFd, OwnedFd};
+use std::sync::{Arc, RwLock};
 
 use serde::{de::DeserializeOwned, Serialize};
 use snow::StatelessTransportState;
 
 use crate::{
-    async_snow::RefDividedSnow,
+    async_snow::{Decrypt, Padding, RefDividedSnow},
     channel::{
+        borrowed::Received,
         channels::{ReceiveChannel, SendChannel},
+        checksum,
+        frame::{self, FrameKind},
         raw::unified::unformatted::UnformattedRawUnifiedChannel,
+        rekey::{self, Rekey},
+        remote,
+        tap::{self, Direction, Tap},
     },
     serialization::formats::{Format, ReadFormat, SendFormat},
-    Result,
+    Error, Result,
 };
 
-use super::{
-    receive_channel::UnformattedReceiveChannel, send_channel::UnformattedSendChannel,
-    snowwith::WithCipher,
-};
+#[cfg(unix)]
+use crate::err;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::providers::rate_limit::{MessageLimiter, Overflow, RateLimit};
+
+use super::{receive_channel::UnformattedReceiveChannel, send_channel::UnformattedSendChannel};
 
 /// Unformmated channel that has not been split.
 /// Can be encrypted or raw.
@@ -30,10 +40,12 @@ pub enum UnformattedUnifiedChannel {
         /// Inner transport state
         transport: StatelessTransportState,
         /// Inner send nonce
-        send_nonce: u32,
+        send_nonce: u64,
         /// Inner receive nonce
-        receive_nonce: u32,
+        receive_nonce: u64,
     },
+    /// Unencrypted channel that appends a checksum to every frame and checks it on receive
+    Checksummed(UnformattedRawUnifiedChannel),
 }
 
 /// Channel that has not been split with read and write formats
@@ -44,6 +56,31 @@ pub struct UnifiedChannel<R = Format, W = Format> {
     pub receive_format: R,
     /// Inner send format
     pub send_format: W,
+    /// Whether the peer closed the channel
+    pub receive_closed: bool,
+    /// Whether the send side of the channel was closed
+    pub send_closed: bool,
+    /// Scratch buffer messages are serialized into, reused across sends
+    pub(crate) buffer: Vec<u8>,
+    /// Hook shown every frame going through the channel
+    pub(crate) tap: Option<Tap>,
+    /// Automatic rekeying of the send side of the channel
+    pub(crate) rekey: Rekey,
+    /// Padding hiding the length of the frames sent, see `Channel::pad_frames`
+    pub(crate) padding: Padding,
+    /// Hash of the handshake that encrypted the channel, see `Channel::channel_binding`
+    pub(crate) channel_binding: Option<Arc<[u8]>>,
+    /// Longest frame accepted on the wire, bounded while a handshake runs
+    pub(crate) frame_limit: Option<usize>,
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Token bucket limiting the messages received, see `Channel::rate_limit`
+    pub(crate) limiter: Option<MessageLimiter>,
+    #[cfg(feature = "metrics")]
+    /// Keeps the channel counted as active until its last half is dropped
+    pub(crate) active: Option<crate::metrics::Active>,
+    #[cfg(unix)]
+    /// File descriptors received along with frames while `receive_fd` is waiting
+    pub(crate) received_fds: Option<Vec<OwnedFd>>,
 }
 
 impl<R, W> UnifiedChannel<R, W> {
@@ -63,7 +100,29 @@ impl<R, W> UnifiedChannel<R, W> {
     where
         W: SendFormat,
     {
-        self.channel.send(obj, &mut self.send_format).await
+        if self.send_closed {
+            return Err(frame::send_closed());
+        }
+        frame::message_into(&mut self.send_format, &obj, &mut self.buffer)?;
+        self.rekey_if_due(self.buffer.len()).await?;
+        tap::show(&self.tap, Direction::Send, &self.buffer);
+        self.channel.send_sealed(&self.buffer, self.padding).await
+    }
+    /// Send a result through the channel, received by the peer with `receive_result`
+    pub async fn send_result<T: Serialize, E: Serialize>(
+        &mut self,
+        result: std::result::Result<T, E>,
+    ) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        if self.send_closed {
+            return Err(frame::send_closed());
+        }
+        frame::result_into(&mut self.send_format, &result, &mut self.buffer)?;
+        self.rekey_if_due(self.buffer.len()).await?;
+        tap::show(&self.tap, Direction::Send, &self.buffer);
+        self.channel.send_sealed(&self.buffer, self.padding).await
     }
     /// Receive an object sent through the channel
     /// ```no_run
@@ -73,14 +132,307 @@ impl<R, W> UnifiedChannel<R, W> {
     where
         R: ReadFormat,
     {
-        self.channel.receive(&mut self.receive_format).await
+        self.try_receive().await?.ok_or_else(frame::closed)
+    }
+    /// Receive a result sent with `send_result`
+    pub async fn receive_result<T: DeserializeOwned, E: DeserializeOwned>(
+        &mut self,
+    ) -> Result<std::result::Result<T, E>>
+    where
+        R: ReadFormat,
+    {
+        let bytes = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        frame::result_payload(&mut self.receive_format, &bytes)
+    }
+    #[cfg(feature = "json_ser")]
+    /// Receive the next message as a dynamic JSON value, see `Channel::receive_value`
+    pub async fn receive_value(&mut self) -> Result<serde_json::Value>
+    where
+        R: ReadFormat,
+    {
+        let bytes = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        self.receive_format
+            .deserialize_value(frame::message_payload(&bytes)?)
+    }
+    /// Receive the next message to deserialize borrowing from it,
+    /// see `Channel::receive_borrowed`
+    pub async fn receive_borrowed(&mut self) -> Result<Received<'_, R>>
+    where
+        R: ReadFormat,
+    {
+        let bytes = self.try_receive_data().await?.ok_or_else(frame::closed)?;
+        Received::new(&mut self.receive_format, bytes)
+    }
+    /// Receive an object sent through the channel,
+    /// returns `None` if the peer closed the channel
+    /// ```no_run
+    /// # async fn example(mut chan: canary::channel::encrypted::unified::UnifiedChannel) -> canary::Result<()> {
+    /// while let Some(string) = chan.try_receive::<String>().await? {
+    ///     println!("{}", string);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn try_receive<T: DeserializeOwned>(&mut self) -> Result<Option<T>>
+    where
+        R: ReadFormat,
+    {
+        match self.try_receive_data().await? {
+            Some(bytes) => self
+                .receive_format
+                .deserialize(frame::message_payload(&bytes)?)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+    /// Rotate the key used to encrypt the send side of the channel.
+    /// A rekey frame is sent with the old key and the peer rotates its key when receiving it,
+    /// so frames already on their way are still decrypted. Fails if the channel isn't encrypted
+    pub async fn rekey(&mut self) -> Result<()> {
+        if self.send_closed {
+            return Err(frame::send_closed());
+        }
+        self.rotate().await
+    }
+    /// Rotate the send key every time `bytes` bytes have been sent,
+    /// `None` disables automatic rekeying
+    pub fn rekey_after(&mut self, bytes: Option<u64>) {
+        self.rekey.set(bytes);
+    }
+    /// pad the frames sent from now on to a multiple of 256 bytes to hide their length,
+    /// see `Channel::encrypt_length_prefix`
+    pub fn encrypt_length_prefix(&mut self, enabled: bool) {
+        self.padding = if enabled {
+            Padding::Bucket(256)
+        } else {
+            Padding::None
+        };
+    }
+    /// pad the frames sent from now on with the given scheme, see `Channel::pad_frames`
+    pub fn pad_frames(&mut self, padding: Padding) {
+        self.padding = padding;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    /// limit the messages received from now on, see `Channel::rate_limit`
+    pub fn rate_limit(&mut self, limit: RateLimit, overflow: Overflow) {
+        self.limiter = Some(MessageLimiter::new(limit, overflow));
+    }
+    /// send a rekey frame with the current key, then rotate it
+    async fn rotate(&mut self) -> Result<()> {
+        if !self.channel.is_encrypted() {
+            return Err(rekey::not_encrypted());
+        }
+        let bytes = frame::control(FrameKind::Rekey);
+        tap::show(&self.tap, Direction::Send, &bytes);
+        self.channel.send_sealed(&bytes, self.padding).await?;
+        self.channel.rekey_outgoing()
+    }
+    /// rotate the key before sending a frame of `len` bytes if enough bytes were sent
+    async fn rekey_if_due(&mut self, len: usize) -> Result<()> {
+        if self.channel.is_encrypted() && self.rekey.due(len) {
+            self.rotate().await?;
+        }
+        Ok(())
+    }
+    /// send a frame through the channel, showing it to the tap first
+    pub(crate) async fn send_frame(&mut self, bytes: &[u8]) -> Result<usize> {
+        self.rekey_if_due(bytes.len()).await?;
+        tap::show(&self.tap, Direction::Send, bytes);
+        self.channel.send_sealed(bytes, self.padding).await
+    }
+    /// receive a frame from the channel and show it to the tap
+    async fn receive_frame(&mut self) -> Result<Vec<u8>> {
+        let max = self.frame_limit.unwrap_or(usize::MAX);
+        #[cfg(unix)]
+        let bytes = match &mut self.received_fds {
+            Some(fds) => self.channel.receive_bytes_with_fds(fds, max).await?,
+            None => self.channel.receive_bytes_within(max).await?,
+        };
+        #[cfg(not(unix))]
+        let bytes = self.channel.receive_bytes_within(max).await?;
+        tap::show(&self.tap, Direction::Receive, &bytes);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(limiter) = &mut self.limiter {
+            limiter.admit(&bytes).await?;
+        }
+        Ok(bytes)
+    }
+    /// Ping the peer and wait for its pong, see `Channel::ping`
+    pub async fn ping(&mut self) -> Result<()>
+    where
+        R: ReadFormat,
+    {
+        if self.send_closed {
+            return Err(frame::send_closed());
+        }
+        self.send_frame(&frame::control(FrameKind::Ping)).await?;
+        while !self.receive_closed {
+            let bytes = self.receive_frame().await?;
+            match frame::decode(&bytes)? {
+                (FrameKind::Pong, _) => return Ok(()),
+                (FrameKind::Message | FrameKind::Result | FrameKind::Chunk | FrameKind::End, _) => {
+                    return Err(frame::unexpected_data())
+                }
+                (FrameKind::Error, payload) => {
+                    return Err(remote::from_frame(&mut self.receive_format, payload))
+                }
+                (FrameKind::Ping, _) => {
+                    let pong = frame::control(FrameKind::Pong);
+                    self.send_frame(&pong).await?;
+                }
+                (FrameKind::Close, _) => {
+                    self.receive_closed = true;
+                    let ack = frame::control(FrameKind::CloseAck);
+                    self.send_frame(&ack).await?;
+                }
+                (FrameKind::Rekey, _) => self.channel.rekey_incoming()?,
+                (FrameKind::CloseAck, _) => {}
+            }
+        }
+        Err(frame::closed())
+    }
+    /// receive the next message frame, answering control frames in the meantime
+    pub(crate) async fn try_receive_data(&mut self) -> Result<Option<Vec<u8>>>
+    where
+        R: ReadFormat,
+    {
+        while !self.receive_closed {
+            let bytes = self.receive_frame().await?;
+            match frame::decode(&bytes)? {
+                (FrameKind::Message | FrameKind::Result | FrameKind::Chunk | FrameKind::End, _) => {
+                    return Ok(Some(bytes))
+                }
+                (FrameKind::Error, payload) => {
+                    return Err(remote::from_frame(&mut self.receive_format, payload))
+                }
+                (FrameKind::Ping, _) => {
+                    let pong = frame::control(FrameKind::Pong);
+                    self.send_frame(&pong).await?;
+                }
+                (FrameKind::Close, _) => {
+                    self.receive_closed = true;
+                    let ack = frame::control(FrameKind::CloseAck);
+                    self.send_frame(&ack).await?;
+                }
+                (FrameKind::Rekey, _) => self.channel.rekey_incoming()?,
+                (FrameKind::Pong | FrameKind::CloseAck, _) => {}
+            }
+        }
+        Ok(None)
+    }
+    #[cfg(unix)]
+    /// Pass a file descriptor to the peer, which has to receive it with `receive_fd`.
+    /// Only channels over unix sockets can pass file descriptors
+    pub async fn send_fd(&mut self, fd: BorrowedFd<'_>) -> Result<usize> {
+        if self.send_closed {
+            return Err(frame::send_closed());
+        }
+        // the descriptor travels with an empty message frame
+        let bytes = frame::message(Vec::new());
+        self.rekey_if_due(bytes.len()).await?;
+        tap::show(&self.tap, Direction::Send, &bytes);
+        self.channel
+            .send_bytes_with_fds(&bytes, &[fd], self.padding)
+            .await
+    }
+    #[cfg(unix)]
+    /// Receive a file descriptor passed by the peer with `send_fd`.
+    /// Fails if the next message isn't a file descriptor
+    pub async fn receive_fd(&mut self) -> Result<OwnedFd>
+    where
+        R: ReadFormat,
+    {
+        self.received_fds = Some(Vec::new());
+        let received = self.try_receive_data().await;
+        let mut fds = self.received_fds.take().unwrap_or_default();
+        let bytes = received?.ok_or_else(frame::closed)?;
+        match (frame::decode(&bytes)?, fds.len()) {
+            ((FrameKind::Message, []), 1) => Ok(fds.remove(0)),
+            _ => err!((
+                invalid_data,
+                "expected a single file descriptor, received a message"
+            )),
+        }
+    }
+    /// Send an error to the peer, its `receive` will return it as a `RemoteError`
+    pub async fn send_error(&mut self, error: &Error) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        if self.send_closed {
+            return Err(frame::send_closed());
+        }
+        let bytes = remote::to_frame(&mut self.send_format, error)?;
+        self.send_frame(&bytes).await
+    }
+    /// Tell the peer no more messages will be sent, the channel can still receive
+    pub async fn close_send(&mut self) -> Result<()> {
+        if !self.send_closed {
+            self.send_frame(&frame::control(FrameKind::Close)).await?;
+            self.send_closed = true;
+        }
+        Ok(())
+    }
+    /// Close the channel and wait for the peer to acknowledge it.
+    /// Messages received in the meantime are discarded
+    pub async fn close(mut self) -> Result<()> {
+        self.close_send().await?;
+        frame::close_ack(async {
+            loop {
+                let bytes = match self.receive_frame().await {
+                    Ok(bytes) => bytes,
+                    // the peer may drop the channel right after acknowledging our close
+                    Err(_) if self.receive_closed => return Ok(()),
+                    Err(e) => return Err(e),
+                };
+                match frame::decode(&bytes)?.0 {
+                    FrameKind::CloseAck => return Ok(()),
+                    FrameKind::Ping => {
+                        let pong = frame::control(FrameKind::Pong);
+                        self.send_frame(&pong).await?;
+                    }
+                    FrameKind::Close => {
+                        self.receive_closed = true;
+                        let ack = frame::control(FrameKind::CloseAck);
+                        self.send_frame(&ack).await?;
+                    }
+                    FrameKind::Rekey => self.channel.rekey_incoming()?,
+                    FrameKind::Message
+                    | FrameKind::Pong
+                    | FrameKind::Error
+                    | FrameKind::Chunk
+                    | FrameKind::End
+                    | FrameKind::Result => {}
+                }
+            }
+        })
+        .await
     }
     #[must_use]
     /// Split channel into its send and receive components
     pub fn split(self) -> (SendChannel<W>, ReceiveChannel<R>) {
         let (send, receive) = self.channel.split();
-        let send = send.to_formatted(self.send_format);
-        let receive = receive.to_formatted(self.receive_format);
+        let mut send = send.to_formatted(self.send_format);
+        let mut receive = receive.to_formatted(self.receive_format);
+        send.closed = self.send_closed;
+        send.buffer = self.buffer;
+        send.tap = self.tap.clone();
+        send.rekey = self.rekey;
+        send.padding = self.padding;
+        send.channel_binding = self.channel_binding.clone();
+        receive.closed = self.receive_closed;
+        receive.tap = self.tap;
+        receive.channel_binding = self.channel_binding;
+        receive.frame_limit = self.frame_limit;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            receive.limiter = self.limiter;
+        }
+        #[cfg(feature = "metrics")]
+        {
+            send.active = self.active.clone();
+            receive.active = self.active;
+        }
         (send, receive)
     }
 }
@@ -94,12 +446,14 @@ impl UnformattedUnifiedChannel {
     ) -> Result<(), StatelessTransportState> {
         let mut state = Ok(());
         take_mut::take(self, |this| match this {
-            UnformattedUnifiedChannel::Raw(chan) => UnformattedUnifiedChannel::Encrypted {
-                chan,
-                transport,
-                send_nonce: 0,
-                receive_nonce: 0,
-            },
+            UnformattedUnifiedChannel::Raw(chan) | UnformattedUnifiedChannel::Checksummed(chan) => {
+                UnformattedUnifiedChannel::Encrypted {
+                    chan,
+                    transport,
+                    send_nonce: 0,
+                    receive_nonce: 0,
+                }
+            }
             UnformattedUnifiedChannel::Encrypted { .. } => {
                 state = Err(transport);
                 this
@@ -107,6 +461,16 @@ impl UnformattedUnifiedChannel {
         });
         state
     }
+    /// Append a CRC32C trailer to every frame sent through an unencrypted channel
+    /// and check it on receive.
+    /// Encrypted channels are left as is since they already check the integrity of frames
+    pub fn set_checksum(&mut self, enabled: bool) {
+        take_mut::take(self, |this| match this {
+            Self::Raw(chan) if enabled => Self::Checksummed(chan),
+            Self::Checksummed(chan) if !enabled => Self::Raw(chan),
+            this => this,
+        });
+    }
     /// Send an object through the channel serialized with format
     /// ```no_run
     /// chan.send("Hello world!", &mut Format::Bincode).await?;
@@ -116,48 +480,173 @@ impl UnformattedUnifiedChannel {
         obj: T,
         format: &mut F,
     ) -> Result<usize> {
+        let bytes = frame::message(format.serialize(&obj)?);
+        self.send_bytes(&bytes).await
+    }
+    /// Receive an object sent through the channel with format
+    /// ```no_run
+    /// let string: String = chan.receive(&mut Format::Bincode).await?;
+    /// ```
+    pub async fn receive<T: DeserializeOwned, F: ReadFormat>(
+        &mut self,
+        format: &mut F,
+    ) -> Result<T> {
+        loop {
+            let bytes = self.receive_bytes().await?;
+            match frame::decode(&bytes)? {
+                (FrameKind::Message, payload) => return format.deserialize(payload),
+                (FrameKind::Chunk | FrameKind::End, _) => return Err(frame::unexpected_stream()),
+                (FrameKind::Result, _) => return Err(frame::unexpected_result()),
+                (FrameKind::Error, payload) => return Err(remote::from_frame(format, payload)),
+                (FrameKind::Ping, _) => {
+                    self.send_bytes(&frame::control(FrameKind::Pong)).await?;
+                }
+                (FrameKind::Close, _) => {
+                    self.send_bytes(&frame::control(FrameKind::CloseAck))
+                        .await?;
+                    return Err(frame::closed());
+                }
+                (FrameKind::Rekey, _) => self.rekey_incoming()?,
+                (FrameKind::Pong | FrameKind::CloseAck, _) => {}
+            }
+        }
+    }
+    /// Send a buffer through the channel as a single frame, encrypting it if needed
+    pub async fn send_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
+        self.send_sealed(bytes, Padding::None).await
+    }
+    /// send a frame, padding it inside the encryption with the given scheme to hide its length
+    pub(crate) async fn send_sealed(&mut self, bytes: &[u8], padding: Padding) -> Result<usize> {
         match self {
-            Self::Raw(chan) => chan.send(obj, format).await,
+            Self::Raw(chan) => chan.send_bytes(bytes).await,
             Self::Encrypted {
                 chan,
                 transport,
                 send_nonce,
                 ..
             } => {
-                let ref mut snow = RefDividedSnow {
+                let snow = &mut RefDividedSnow {
                     transport,
                     nonce: send_nonce,
                 };
-                let mut with = WithCipher { snow, format };
-                chan.send(obj, &mut with).await
+                let bytes = snow.seal(bytes, padding)?;
+                chan.send_bytes(&bytes).await
             }
+            Self::Checksummed(chan) => chan.send_bytes(&checksum::append(bytes)).await,
         }
     }
-    /// Receive an object sent through the channel with format
-    /// ```no_run
-    /// let string: String = chan.receive(&mut Format::Bincode).await?;
-    /// ```
-    pub async fn receive<T: DeserializeOwned, F: ReadFormat>(
+    /// Receive a single frame sent through the channel, decrypting it if needed
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        self.receive_bytes_within(usize::MAX).await
+    }
+    /// Receive a single frame of at most `max` bytes on the wire, decrypting it if needed
+    pub(crate) async fn receive_bytes_within(&mut self, max: usize) -> Result<Vec<u8>> {
+        match self {
+            Self::Raw(chan) => chan.receive_bytes_within(max).await,
+            Self::Encrypted {
+                chan,
+                transport,
+                receive_nonce,
+                ..
+            } => {
+                let snow = &mut RefDividedSnow {
+                    transport,
+                    nonce: receive_nonce,
+                };
+                let bytes = chan.receive_bytes_within(max).await?;
+                snow.decrypt(&bytes)
+            }
+            Self::Checksummed(chan) => checksum::verify(chan.receive_bytes_within(max).await?),
+        }
+    }
+    #[cfg(unix)]
+    /// Send a buffer as a single frame along with file descriptors, encrypting it if needed
+    pub(crate) async fn send_bytes_with_fds(
         &mut self,
-        format: &mut F,
-    ) -> Result<T> {
+        bytes: &[u8],
+        fds: &[BorrowedFd<'_>],
+        padding: Padding,
+    ) -> Result<usize> {
+        match self {
+            Self::Raw(chan) => chan.send_bytes_with_fds(bytes, fds).await,
+            Self::Encrypted {
+                chan,
+                transport,
+                send_nonce,
+                ..
+            } => {
+                let snow = &mut RefDividedSnow {
+                    transport,
+                    nonce: send_nonce,
+                };
+                let bytes = snow.seal(bytes, padding)?;
+                chan.send_bytes_with_fds(&bytes, fds).await
+            }
+            Self::Checksummed(chan) => {
+                chan.send_bytes_with_fds(&checksum::append(bytes), fds)
+                    .await
+            }
+        }
+    }
+    #[cfg(unix)]
+    /// Receive a single frame of at most `max` bytes on the wire along with
+    /// the file descriptors sent with it, decrypting it if needed
+    pub(crate) async fn receive_bytes_with_fds(
+        &mut self,
+        fds: &mut Vec<OwnedFd>,
+        max: usize,
+    ) -> Result<Vec<u8>> {
         match self {
-            Self::Raw(chan) => chan.receive(format).await,
+            Self::Raw(chan) => chan.receive_bytes_with_fds(fds, max).await,
             Self::Encrypted {
                 chan,
                 transport,
                 receive_nonce,
                 ..
             } => {
-                let ref mut snow = RefDividedSnow {
+                let snow = &mut RefDividedSnow {
                     transport,
                     nonce: receive_nonce,
                 };
-                let mut with = WithCipher { snow, format };
-                chan.receive(&mut with).await
+                let bytes = chan.receive_bytes_with_fds(fds, max).await?;
+                snow.decrypt(&bytes)
+            }
+            Self::Checksummed(chan) => {
+                checksum::verify(chan.receive_bytes_with_fds(fds, max).await?)
+            }
+        }
+    }
+    /// Rotate the key used to encrypt the following frames,
+    /// the peer has to rotate its receive key at the same frame.
+    /// Fails if the channel isn't encrypted
+    pub fn rekey_outgoing(&mut self) -> Result<()> {
+        match self {
+            Self::Encrypted { transport, .. } => {
+                transport.rekey_outgoing();
+                Ok(())
             }
+            Self::Raw(_) | Self::Checksummed(_) => Err(rekey::not_encrypted()),
         }
     }
+    /// Rotate the key used to decrypt the following frames,
+    /// done when the peer sends a rekey frame.
+    /// Fails if the channel isn't encrypted
+    pub fn rekey_incoming(&mut self) -> Result<()> {
+        match self {
+            Self::Encrypted { transport, .. } => {
+                transport.rekey_incoming();
+                Ok(())
+            }
+            Self::Raw(_) | Self::Checksummed(_) => Err(rekey::not_encrypted()),
+        }
+    }
+    /// Returns `true` if the unformatted unified channel is [`Encrypted`].
+    ///
+    /// [`Encrypted`]: UnformattedUnifiedChannel::Encrypted
+    #[must_use]
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self, Self::Encrypted { .. })
+    }
     #[must_use]
     /// Split channel into its send and receive components
     pub fn split(self) -> (UnformattedSendChannel, UnformattedReceiveChannel) {
@@ -176,12 +665,18 @@ impl UnformattedUnifiedChannel {
             } => {
                 let (send, receive) = chan.split();
 
-                let transport = Arc::new(transport);
+                let transport = Arc::new(RwLock::new(transport));
                 let send = UnformattedSendChannel::Encrypted(send, transport.clone(), send_nonce);
                 let receive =
                     UnformattedReceiveChannel::Encrypted(receive, transport, receive_nonce);
                 (send, receive)
             }
+            Self::Checksummed(chan) => {
+                let (send, receive) = chan.split();
+                let send = UnformattedSendChannel::Checksummed(send);
+                let receive = UnformattedReceiveChannel::Checksummed(receive);
+                (send, receive)
+            }
         }
     }
 }
@@ -0,0 +1,220 @@
+use std::{future::Future, time::Duration};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    err,
+    error::ChannelClosed,
+    serialization::formats::{ReadFormat, SendFormat},
+    Result,
+};
+
+/// time a close waits for the peer to acknowledge it
+pub(crate) const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// capacity above which the scratch buffer of a channel is released instead of reused,
+/// so a single large message doesn't pin its memory for the lifetime of the channel
+const SCRATCH_CAPACITY: usize = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+/// Kind of a frame sent through a channel.
+/// Every frame starts with this byte (inside the encryption if the channel is encrypted),
+/// which lets control frames travel alongside messages without being seen by `receive`.
+///
+/// The kind byte changes the wire format: canary 0.3.3 and earlier send frames without it,
+/// and their handshake has no hello to tell versions apart, so they can't talk to peers
+/// using frame kinds. Raw channels between them misread every frame, and encrypted ones
+/// fail during the handshake. Both sides of a connection have to be upgraded together.
+pub enum FrameKind {
+    /// Frame carries a serialized message
+    Message = 0,
+    /// Keepalive ping, answered with a pong
+    Ping = 1,
+    /// Answer to a keepalive ping
+    Pong = 2,
+    /// Peer will not send any more messages
+    Close = 3,
+    /// Answer to a close frame
+    CloseAck = 4,
+    /// Frame carries an error that happened on the peer
+    Error = 5,
+    /// Frame carries a chunk of a stream, see `Channel::send_reader`
+    Chunk = 6,
+    /// Last frame of a stream, carries the length of the stream
+    End = 7,
+    /// Sender rotated its key, every following frame is encrypted with the new one
+    Rekey = 8,
+    /// Frame carries a result, see `Channel::send_result`
+    Result = 9,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = crate::Error;
+
+    #[inline]
+    fn try_from(kind: u8) -> Result<Self> {
+        Ok(match kind {
+            0 => FrameKind::Message,
+            1 => FrameKind::Ping,
+            2 => FrameKind::Pong,
+            3 => FrameKind::Close,
+            4 => FrameKind::CloseAck,
+            5 => FrameKind::Error,
+            6 => FrameKind::Chunk,
+            7 => FrameKind::End,
+            8 => FrameKind::Rekey,
+            9 => FrameKind::Result,
+            kind => err!((invalid_data, format!("unknown frame kind {}", kind)))?,
+        })
+    }
+}
+
+#[inline]
+/// build a frame of the given kind carrying a payload
+pub(crate) fn encode(kind: FrameKind, mut payload: Vec<u8>) -> Vec<u8> {
+    payload.insert(0, kind as u8);
+    payload
+}
+
+#[inline]
+/// build a frame carrying a serialized message
+pub(crate) fn message(payload: Vec<u8>) -> Vec<u8> {
+    encode(FrameKind::Message, payload)
+}
+
+#[inline]
+/// build a frame carrying a serialized message in the scratch buffer of a channel
+pub(crate) fn message_into<O: Serialize, F: SendFormat>(
+    format: &mut F,
+    obj: &O,
+    buffer: &mut Vec<u8>,
+) -> Result<()> {
+    if buffer.capacity() > SCRATCH_CAPACITY {
+        *buffer = Vec::new();
+    }
+    buffer.clear();
+    buffer.push(FrameKind::Message as u8);
+    format.serialize_into(obj, buffer)?;
+    Ok(())
+}
+
+/// Build a frame carrying a result in the scratch buffer of a channel.
+/// The payload is a byte telling the arms apart, `0` for `Ok` and `1` for `Err`,
+/// followed by the value of the arm serialized on its own
+pub(crate) fn result_into<T: Serialize, E: Serialize, F: SendFormat>(
+    format: &mut F,
+    result: &std::result::Result<T, E>,
+    buffer: &mut Vec<u8>,
+) -> Result<()> {
+    if buffer.capacity() > SCRATCH_CAPACITY {
+        *buffer = Vec::new();
+    }
+    buffer.clear();
+    buffer.push(FrameKind::Result as u8);
+    match result {
+        Ok(value) => {
+            buffer.push(0);
+            format.serialize_into(value, buffer)?;
+        }
+        Err(error) => {
+            buffer.push(1);
+            format.serialize_into(error, buffer)?;
+        }
+    }
+    Ok(())
+}
+
+/// read the result carried by a frame returned by `try_receive_data`
+pub(crate) fn result_payload<T: DeserializeOwned, E: DeserializeOwned, F: ReadFormat>(
+    format: &mut F,
+    frame: &[u8],
+) -> Result<std::result::Result<T, E>> {
+    let payload = match decode(frame)? {
+        (FrameKind::Result, payload) => payload,
+        (FrameKind::Message, _) => err!((invalid_data, "expected a result, received a message"))?,
+        _ => return Err(unexpected_stream()),
+    };
+    match payload.split_first() {
+        Some((0, value)) => format.deserialize(value).map(Ok),
+        Some((1, error)) => format.deserialize(error).map(Err),
+        _ => err!((invalid_data, "malformed result")),
+    }
+}
+
+#[inline]
+/// build a control frame without a payload
+pub(crate) fn control(kind: FrameKind) -> [u8; 1] {
+    [kind as u8]
+}
+
+#[inline]
+/// payload of a frame returned by `try_receive_data`, which has to carry a message
+pub(crate) fn message_payload(frame: &[u8]) -> Result<&[u8]> {
+    match decode(frame)? {
+        (FrameKind::Message, payload) => Ok(payload),
+        (FrameKind::Result, _) => Err(unexpected_result()),
+        _ => Err(unexpected_stream()),
+    }
+}
+
+#[inline]
+/// error returned when receiving a result sent with `send_result` while expecting a message
+pub(crate) fn unexpected_result() -> crate::Error {
+    err!(
+        invalid_data,
+        "received a result while expecting a message, use `receive_result`"
+    )
+}
+
+#[inline]
+/// error returned by `ping` when data arrives before the pong
+pub(crate) fn unexpected_data() -> crate::Error {
+    err!(
+        invalid_data,
+        "received data while waiting for a pong, the channel was still in use"
+    )
+}
+
+#[inline]
+/// error returned when receiving part of a stream while expecting a message
+pub(crate) fn unexpected_stream() -> crate::Error {
+    err!(
+        invalid_data,
+        "received part of a stream while expecting a message"
+    )
+}
+
+#[inline]
+/// split a frame into its kind and its payload
+pub(crate) fn decode(frame: &[u8]) -> Result<(FrameKind, &[u8])> {
+    let (kind, payload) = frame
+        .split_first()
+        .ok_or(err!(invalid_data, "received an empty frame"))?;
+    Ok((FrameKind::try_from(*kind)?, payload))
+}
+
+#[inline]
+/// error returned when receiving from a channel closed by the peer
+pub(crate) fn closed() -> crate::Error {
+    err!(not_connected, ChannelClosed { by_peer: true })
+}
+
+#[inline]
+/// error returned when using a side of a channel left in the middle of a stream
+pub(crate) fn poisoned() -> crate::Error {
+    err!(other, "channel is poisoned by an interrupted stream")
+}
+
+#[inline]
+/// error returned when sending through a channel whose send side was closed
+pub(crate) fn send_closed() -> crate::Error {
+    err!(broken_pipe, ChannelClosed { by_peer: false })
+}
+
+/// wait for the acknowledgement of a close frame, up to `CLOSE_TIMEOUT`
+pub(crate) async fn close_ack(ack: impl Future<Output = Result<()>>) -> Result<()> {
+    crate::runtime::timeout(CLOSE_TIMEOUT, ack)
+        .await
+        .unwrap_or_else(|_| err!((timeout, "peer did not acknowledge close")))
+}
@@ -1,17 +1,85 @@
-use derive_more::From;
+use std::time::Duration;
 
-use crate::{err, Channel, Result};
+use tracing::Instrument;
+
+use crate::{
+    async_snow::{self, StaticKeypair, HANDSHAKE_TIMEOUT},
+    err, Channel, Result,
+};
 
-#[derive(From)]
-#[repr(transparent)]
 /// Helper struct that represents a channel that may become encrypted
-pub struct Handshake(Channel);
+pub struct Handshake {
+    channel: Channel,
+    peer: Option<String>,
+}
+
+impl From<Channel> for Handshake {
+    #[inline]
+    fn from(channel: Channel) -> Self {
+        Handshake {
+            channel,
+            peer: None,
+        }
+    }
+}
 
 impl Handshake {
-    /// Get an encrypted channel
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    /// address of the peer the channel was accepted from, shown in the span of the handshake
+    pub(crate) fn accepted_from(mut self, peer: impl std::fmt::Display) -> Self {
+        self.peer = Some(peer.to_string());
+        self
+    }
+
+    #[inline]
+    /// Address of the peer, for channels accepted by providers that know it
+    pub fn peer_addr(&self) -> Option<&str> {
+        self.peer.as_deref()
+    }
+
+    /// span the handshake runs in, carrying the address of the peer if it is known
+    fn span(&self) -> tracing::Span {
+        match &self.peer {
+            Some(peer) => tracing::debug_span!("accepted", peer = %peer),
+            None => tracing::Span::none(),
+        }
+    }
+
+    /// Get an encrypted channel.
+    /// Fails if the peer doesn't complete the handshake within `HANDSHAKE_TIMEOUT`
     pub async fn encrypted(self) -> Result<Channel> {
-        let mut stream = self.0;
-        let snow = crate::async_snow::new(&mut stream).await?;
+        self.encrypted_with_timeout(HANDSHAKE_TIMEOUT).await
+    }
+
+    /// Get an encrypted channel, failing with a `TimedOut` error
+    /// if the peer doesn't complete the handshake within `timeout`.
+    /// The channel is dropped, and thus closed, if the handshake fails
+    pub async fn encrypted_with_timeout(self, timeout: Duration) -> Result<Channel> {
+        let span = self.span();
+        let mut stream = self.channel;
+        let snow = async_snow::new_with_timeout(&mut stream, timeout)
+            .instrument(span)
+            .await?;
+        stream
+            .encrypt(snow)
+            .map_err(|_| err!("channel already encrypted"))?;
+        Ok(stream)
+    }
+
+    /// Get a channel encrypted with the XX pattern, keeping it only if the verifier
+    /// accepts the static key of the peer. Otherwise the channel is dropped and
+    /// a `PermissionDenied` error is returned, see `async_snow::new_verified`
+    pub async fn verified(
+        self,
+        local_static: &StaticKeypair,
+        verifier: impl Fn(&[u8]) -> bool,
+    ) -> Result<Channel> {
+        let span = self.span();
+        let mut stream = self.channel;
+        let snow = async_snow::new_verified(&mut stream, local_static, verifier)
+            .instrument(span)
+            .await?;
         stream
             .encrypt(snow)
             .map_err(|_| err!("channel already encrypted"))?;
@@ -20,6 +88,6 @@ impl Handshake {
 
     /// Get the raw, unencrypted channel
     pub fn raw(self) -> Channel {
-        self.0
+        self.channel
     }
 }
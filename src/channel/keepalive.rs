@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use futures::{pin_mut, select, FutureExt};
+
+use crate::{err, Result};
+
+use super::channels::{ReceiveChannel, SendChannel};
+use super::frame::{self, FrameKind};
+
+#[derive(Clone, Copy, Debug)]
+/// Keepalive of a channel.
+///
+/// While a `receive` is pending and nothing has arrived for `interval`, a ping is sent
+/// to the peer. If nothing arrives within `timeout` after the ping, the peer is
+/// considered dead: the pending `receive` and every following `send` fail with
+/// a `TimedOut` error.
+///
+/// Pings are answered from within the peer's own `receive` or `send`,
+/// so `timeout` should be larger than the time the peer may spend without using the channel.
+pub struct Keepalive {
+    interval: Duration,
+    timeout: Duration,
+    dead: bool,
+}
+
+impl Keepalive {
+    #[inline]
+    /// Create a keepalive that pings after `interval` of silence and
+    /// waits `timeout` for an answer
+    pub fn new(interval: Duration, timeout: Duration) -> Self {
+        Keepalive {
+            interval,
+            timeout,
+            dead: false,
+        }
+    }
+
+    #[inline]
+    /// Returns an error if the peer stopped answering pings
+    pub fn check(&self) -> Result<()> {
+        if self.dead {
+            err!((timeout, "peer did not answer keepalive ping"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// receive a frame, pinging the peer whenever the channel stays silent for too long
+    pub(crate) async fn receive_frame<R, W>(
+        &mut self,
+        receive: &mut ReceiveChannel<R>,
+        send: &mut SendChannel<W>,
+    ) -> Result<Vec<u8>> {
+        self.check()?;
+        let bytes = receive.receive_frame().fuse();
+        pin_mut!(bytes);
+        let mut pinged = false;
+        loop {
+            let wait = if pinged { self.timeout } else { self.interval };
+            let timer = crate::runtime::sleep(wait).fuse();
+            pin_mut!(timer);
+            select! {
+                bytes = bytes => return bytes,
+                _ = timer => {
+                    if pinged {
+                        self.dead = true;
+                        self.check()?;
+                    }
+                    if let Err(e) = send.send_frame(&frame::control(FrameKind::Ping)).await {
+                        self.dead = true;
+                        return Err(e);
+                    }
+                    pinged = true;
+                }
+            }
+        }
+    }
+}
@@ -1,8 +1,33 @@
+/// contains the guard of messages deserialized without copying them
+pub mod borrowed;
 /// contains utility channels
 pub mod channels;
+/// contains the checksums of unencrypted frames
+pub mod checksum;
+/// contains the guard that closes channels when dropped
+pub mod close_on_drop;
 /// contains encrypted channels
 pub mod encrypted;
+/// contains the frame kinds used by channels
+pub mod frame;
 /// contains the handshake struct
 pub mod handshake;
+/// contains the keepalive of channels
+pub mod keepalive;
+/// contains channels that send messages as newline-delimited JSON
+pub mod ndjson;
+/// contains the negotiation of the format of channels
+pub mod negotiation;
 /// contains unencrypted channels
 pub mod raw;
+/// contains the rekeying of encrypted channels
+pub(crate) mod rekey;
+/// contains errors sent by peers
+pub mod remote;
+/// contains the chunking of streams sent through channels
+pub(crate) mod stream;
+/// contains the taps that see the frames of channels
+pub mod tap;
+/// contains the adapter that serves channels with tower services
+#[cfg(feature = "tower")]
+pub mod tower;
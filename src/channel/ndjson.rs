@@ -0,0 +1,130 @@
+#![cfg(all(feature = "json_ser", not(target_arch = "wasm32")))]
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{
+    channel::{frame, raw::unified::unformatted::UnformattedRawUnifiedChannel},
+    err,
+    io::{Read, Write, WriteExt},
+    serialization::formats::{Format, Json, ReadFormat, SendFormat},
+    Result,
+};
+
+/// Channel that sends every message as a line of JSON, without any framing.
+///
+/// The stream can be read directly by tools that understand newline-delimited JSON
+/// such as `jq` or log collectors. Messages are serialized with `Json::compact()`,
+/// which escapes the newlines inside strings, so every message is a single line.
+///
+/// There are no control frames, so the channel can't be encrypted, closed gracefully
+/// or kept alive, and both sides have to use NDJSON channels.
+/// ```no_run
+/// # async fn example(chan: canary::Channel) -> canary::Result<()> {
+/// use canary::serialization::formats::Format;
+///
+/// let mut chan = chan.ndjson(Format::Json)?;
+/// chan.send(&("request", 42)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct NdjsonChannel {
+    reader: BufReader<Box<dyn Read + Unpin + Send>>,
+    writer: Box<dyn Write + Unpin + Send>,
+    /// Scratch buffer lines are read into and serialized into, reused across messages
+    line: Vec<u8>,
+}
+
+impl NdjsonChannel {
+    /// Use the raw stream to send and receive lines of JSON.
+    /// Fails if the format isn't `Format::Json`, or if the stream is a websocket,
+    /// which is message based
+    pub(crate) fn new(chan: UnformattedRawUnifiedChannel, format: Format) -> Result<Self> {
+        if format != Format::Json {
+            return err!((
+                invalid_input,
+                "ndjson channels can only use the `Json` format"
+            ));
+        }
+        let (reader, writer): (Box<dyn Read + Unpin + Send>, Box<dyn Write + Unpin + Send>) =
+            match chan {
+                UnformattedRawUnifiedChannel::Tcp(stream) => {
+                    let (read, write) = stream.into_split();
+                    (Box::new(read), Box::new(write))
+                }
+                #[cfg(unix)]
+                UnformattedRawUnifiedChannel::Unix(stream) => {
+                    let (read, write) = stream.into_split();
+                    (Box::new(read), Box::new(write))
+                }
+                UnformattedRawUnifiedChannel::Memory(stream) => {
+                    let (read, write) = crate::io::split(stream);
+                    (Box::new(read), Box::new(write))
+                }
+                #[cfg(feature = "quic")]
+                UnformattedRawUnifiedChannel::Quic(write, read) => {
+                    (Box::new(read), Box::new(write))
+                }
+                #[cfg(feature = "tls")]
+                UnformattedRawUnifiedChannel::Tls(stream) => {
+                    let (read, write) = crate::io::split(*stream);
+                    (Box::new(read), Box::new(write))
+                }
+                UnformattedRawUnifiedChannel::Wss(_) => {
+                    return err!((
+                        unsupported,
+                        "ndjson channels need a byte stream, websockets are message based"
+                    ))
+                }
+            };
+        Ok(NdjsonChannel {
+            reader: BufReader::new(reader),
+            writer,
+            line: Vec::new(),
+        })
+    }
+    /// Send an object through the channel as a single line
+    /// ```no_run
+    /// # async fn example(mut chan: canary::channel::ndjson::NdjsonChannel) -> canary::Result<()> {
+    /// chan.send("Hello world!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send<T: Serialize>(&mut self, obj: T) -> Result<usize> {
+        self.line.clear();
+        Json::compact().serialize_into(&obj, &mut self.line)?;
+        self.line.push(b'\n');
+        self.writer.write_all(&self.line).await?;
+        self.writer.flush().await?;
+        Ok(self.line.len())
+    }
+    /// Receive an object sent through the channel.
+    /// Fails with a `NotConnected` error once the peer closes the stream
+    /// ```no_run
+    /// # async fn example(mut chan: canary::channel::ndjson::NdjsonChannel) -> canary::Result<()> {
+    /// let string: String = chan.receive().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive<T: DeserializeOwned>(&mut self) -> Result<T> {
+        self.try_receive().await?.ok_or_else(frame::closed)
+    }
+    /// Receive an object sent through the channel,
+    /// returns `None` once the peer closes the stream.
+    /// The last line may omit its trailing newline
+    /// ```no_run
+    /// # async fn example(mut chan: canary::channel::ndjson::NdjsonChannel) -> canary::Result<()> {
+    /// while let Some(string) = chan.try_receive::<String>().await? {
+    ///     println!("{}", string);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn try_receive<T: DeserializeOwned>(&mut self) -> Result<Option<T>> {
+        self.line.clear();
+        if self.reader.read_until(b'\n', &mut self.line).await? == 0 {
+            return Ok(None);
+        }
+        Json::compact().deserialize(&self.line).map(Some)
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt::Display;
+
+use crate::{err, serialization::formats::Format, Error, Result};
+
+/// capability bit of peers that append a checksum to their frames
+const CHECKSUM: u8 = 1;
+
+/// first byte of the answer of a peer that rejects the requested format, no format uses it
+const REJECTED: u8 = 0;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+/// Error returned when the peer doesn't support the requested format.
+///
+/// Both sides of the negotiation return it as an `InvalidData` error,
+/// use `UnsupportedFormat::of` to find it.
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+/// use canary::channel::negotiation::UnsupportedFormat;
+/// use canary::serialization::formats::Format;
+///
+/// if let Err(e) = chan.request_format(Format::Bincode).await {
+///     if let Some(unsupported) = UnsupportedFormat::of(&e) {
+///         println!("peer only supports {:?}", unsupported.supported);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct UnsupportedFormat {
+    /// tag of the requested format
+    pub requested: u8,
+    /// formats supported by the side that rejected the request,
+    /// without the ones this side wasn't compiled with
+    pub supported: Vec<Format>,
+}
+
+impl UnsupportedFormat {
+    #[inline]
+    /// Returns the unsupported format error carried by the error, if any
+    pub fn of(error: &Error) -> Option<&UnsupportedFormat> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl Display for UnsupportedFormat {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "format tag {} is not supported, supported formats are {:?}",
+            self.requested, self.supported
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedFormat {}
+
+#[inline]
+/// read the format tag requested by the peer
+pub(crate) fn requested(payload: &[u8]) -> Result<u8> {
+    match payload {
+        [tag] if *tag != REJECTED => Ok(*tag),
+        _ => err!((invalid_data, "malformed format request")),
+    }
+}
+
+#[inline]
+/// answer a request, accepting it if the format is supported
+pub(crate) fn answer(requested: u8, supported: &[Format]) -> (Vec<u8>, Result<Format>) {
+    match supported.iter().find(|format| **format as u8 == requested) {
+        Some(format) => (vec![requested], Ok(*format)),
+        None => {
+            let mut answer = vec![REJECTED];
+            answer.extend(supported.iter().map(|format| *format as u8));
+            let unsupported = UnsupportedFormat {
+                requested,
+                supported: supported.to_vec(),
+            };
+            (answer, err!((invalid_data, unsupported)))
+        }
+    }
+}
+
+#[inline]
+/// check the answer of the peer to a request of `format`
+pub(crate) fn check_answer(format: Format, answer: &[u8]) -> Result<()> {
+    match answer {
+        [tag] if *tag == format as u8 => Ok(()),
+        [REJECTED, supported @ ..] => {
+            let unsupported = UnsupportedFormat {
+                requested: format as u8,
+                supported: supported
+                    .iter()
+                    .filter_map(|tag| Format::try_from(*tag).ok())
+                    .collect(),
+            };
+            err!((invalid_data, unsupported))
+        }
+        _ => err!((invalid_data, "malformed answer to a format request")),
+    }
+}
+
+#[inline]
+/// capability byte sent by a side of a channel
+pub(crate) fn capabilities(checksum: bool) -> u8 {
+    if checksum {
+        CHECKSUM
+    } else {
+        0
+    }
+}
+
+#[inline]
+/// check the capability byte sent by the peer against ours
+pub(crate) fn check_capabilities(ours: u8, payload: &[u8]) -> Result<()> {
+    let theirs = match payload {
+        [theirs] => *theirs,
+        _ => err!((invalid_data, "malformed capabilities"))?,
+    };
+    match (ours & CHECKSUM, theirs & CHECKSUM) {
+        (0, CHECKSUM) => err!((
+            invalid_data,
+            "peer checksums its frames but this side doesn't"
+        )),
+        (CHECKSUM, 0) => err!((invalid_data, "peer doesn't checksum its frames")),
+        _ => Ok(()),
+    }
+}
@@ -49,6 +49,14 @@ impl UnformattedRawBidirectionalChannel {
     ) -> Result<T> {
         self.receive_chan.receive(format).await
     }
+    /// Send a buffer through the channel as a single frame
+    pub async fn send_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
+        self.send_chan.send_bytes(bytes).await
+    }
+    /// Receive a single frame sent through the channel
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        self.receive_chan.receive_bytes().await
+    }
 }
 
 #[derive(From)]
@@ -96,6 +104,14 @@ impl<'a> RefUnformattedRawBidirectionalChannel<'a> {
     ) -> Result<T> {
         self.receive_chan.receive(format).await
     }
+    /// Send a buffer through the channel as a single frame
+    pub async fn send_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
+        self.send_chan.send_bytes(bytes).await
+    }
+    /// Receive a single frame sent through the channel
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        self.receive_chan.receive_bytes().await
+    }
     /// Get a formatted channel with the specified format
     /// ```no_run
     /// unformatted.send("Hi!", &mut Format::Bincode).await?;
@@ -160,7 +176,7 @@ impl RawBidirectionalChannel {
     /// Split channel into its send and receive components
     pub fn split(self) -> (RawSendChannel, RawReceiveChannel) {
         let (send, receive) = self.chan.split();
-        let send = send.to_formatted(self.format.clone());
+        let send = send.to_formatted(self.format);
         let receive = receive.to_formatted(self.format);
         (send, receive)
     }
@@ -15,11 +15,17 @@ pub enum RefUnformattedRawReceiveChannel<'a> {
     #[cfg(unix)]
     /// unencrypted unix backend
     Unix(&'a mut tokio::net::unix::OwnedReadHalf),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// unencrypted in-memory backend
+    Memory(&'a mut crate::io::ReadHalf<crate::io::DuplexStream>),
     /// unencrypted wss backend
     WSS(&'a mut SplitStream<Box<Wss>>),
     #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
     /// unencrypted quic backend
     Quic(&'a mut quinn::RecvStream),
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// unencrypted tls backend
+    Tls(&'a mut crate::io::ReadHalf<crate::io::TlsStream>),
 }
 
 #[derive(From)]
@@ -31,6 +37,9 @@ pub enum UnformattedRawReceiveChannel {
     #[cfg(unix)]
     /// Unencrypted unix backend
     Unix(tokio::net::unix::OwnedReadHalf),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Unencrypted in-memory backend
+    Memory(crate::io::ReadHalf<crate::io::DuplexStream>),
     /// Unencrypted wss backend
     WSS(SplitStream<Box<Wss>>),
 
@@ -38,6 +47,9 @@ pub enum UnformattedRawReceiveChannel {
     #[cfg(feature = "quic")]
     /// Unencrypted quic backend
     Quic(quinn::RecvStream),
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// Unencrypted tls backend
+    Tls(crate::io::ReadHalf<crate::io::TlsStream>),
 }
 
 #[derive(From)]
@@ -67,16 +79,55 @@ impl<'a> RefUnformattedRawReceiveChannel<'a> {
         &mut self,
         format: &mut F,
     ) -> Result<T> {
+        let bytes = self.receive_bytes().await?;
+        format.deserialize(&bytes)
+    }
+    /// Receive a single frame sent through the channel
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        self.receive_bytes_within(usize::MAX).await
+    }
+    /// Receive a single frame sent through the channel, failing if it is longer than `max`.
+    /// Stream transports fail on the length prefix, before allocating anything for the frame
+    pub(crate) async fn receive_bytes_within(&mut self, max: usize) -> Result<Vec<u8>> {
         #[allow(unused)]
-        use crate::serialization::{rx, wss_rx};
+        use crate::serialization::{framing, rx_bytes_within, wss_rx_bytes};
+        let received = match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            RefUnformattedRawReceiveChannel::Tcp(st) => rx_bytes_within(st, max).await,
+            #[cfg(unix)]
+            RefUnformattedRawReceiveChannel::Unix(st) => rx_bytes_within(st, max).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            RefUnformattedRawReceiveChannel::Memory(st) => rx_bytes_within(st, max).await,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+            RefUnformattedRawReceiveChannel::Quic(st) => rx_bytes_within(st, max).await,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            RefUnformattedRawReceiveChannel::Tls(st) => rx_bytes_within(st, max).await,
+            // websockets bound messages by themselves, see `WebSocket::max_message_size`
+            RefUnformattedRawReceiveChannel::WSS(st) => wss_rx_bytes(st)
+                .await
+                .and_then(|bytes| framing::check_len(bytes.len(), max).map(|_| bytes)),
+        };
+        #[cfg(feature = "metrics")]
+        if let Ok(bytes) = &received {
+            crate::metrics::received(self.transport(), bytes.len());
+        }
+        received
+    }
+    #[cfg(feature = "metrics")]
+    /// name of the transport the channel runs over, used to label its metrics
+    pub(crate) fn transport(&self) -> &'static str {
         match self {
             #[cfg(not(target_arch = "wasm32"))]
-            RefUnformattedRawReceiveChannel::Tcp(st) => rx(st, format).await,
+            RefUnformattedRawReceiveChannel::Tcp(_) => "tcp",
             #[cfg(unix)]
-            RefUnformattedRawReceiveChannel::Unix(st) => rx(st, format).await,
+            RefUnformattedRawReceiveChannel::Unix(_) => "unix",
+            #[cfg(not(target_arch = "wasm32"))]
+            RefUnformattedRawReceiveChannel::Memory(_) => "memory",
             #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
-            RefUnformattedRawReceiveChannel::Quic(st) => rx(st, format).await,
-            RefUnformattedRawReceiveChannel::WSS(st) => wss_rx(st, format).await,
+            RefUnformattedRawReceiveChannel::Quic(_) => "quic",
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            RefUnformattedRawReceiveChannel::Tls(_) => "tls",
+            RefUnformattedRawReceiveChannel::WSS(_) => "wss",
         }
     }
     /// Get a formatted channel with the specified format
@@ -101,10 +152,14 @@ impl<'a> From<&'a mut UnformattedRawReceiveChannel> for RefUnformattedRawReceive
             UnformattedRawReceiveChannel::Tcp(ref mut chan) => chan.into(),
             #[cfg(unix)]
             UnformattedRawReceiveChannel::Unix(ref mut chan) => chan.into(),
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawReceiveChannel::Memory(ref mut chan) => chan.into(),
             UnformattedRawReceiveChannel::WSS(ref mut chan) => chan.into(),
             #[cfg(not(target_arch = "wasm32"))]
             #[cfg(feature = "quic")]
             UnformattedRawReceiveChannel::Quic(ref mut chan) => chan.into(),
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            UnformattedRawReceiveChannel::Tls(ref mut chan) => chan.into(),
         }
     }
 }
@@ -122,6 +177,18 @@ impl UnformattedRawReceiveChannel {
             .receive(format)
             .await
     }
+    /// Receive a single frame sent through the channel
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        RefUnformattedRawReceiveChannel::from(self)
+            .receive_bytes()
+            .await
+    }
+    /// Receive a single frame of at most `max` bytes, see `receive_bytes_within` of the borrowed channel
+    pub(crate) async fn receive_bytes_within(&mut self, max: usize) -> Result<Vec<u8>> {
+        RefUnformattedRawReceiveChannel::from(self)
+            .receive_bytes_within(max)
+            .await
+    }
     #[inline]
     /// Format the channel
     /// ```no_run
@@ -1,16 +1,15 @@
 use crate::io::Message;
 use crate::{
-    err,
     io::Wss,
     serialization::formats::{Format, SendFormat},
     Result,
 };
 use derive_more::From;
-use futures::{stream::SplitSink, SinkExt};
+use futures::stream::SplitSink;
 use serde::Serialize;
 
-///
 #[derive(From)]
+/// Reference unformatted unencrypted send channel
 pub enum RefUnformattedRawSendChannel<'a> {
     #[cfg(not(target_arch = "wasm32"))]
     /// tcp backend
@@ -18,11 +17,17 @@ pub enum RefUnformattedRawSendChannel<'a> {
     #[cfg(unix)]
     /// unix backend
     Unix(&'a mut tokio::net::unix::OwnedWriteHalf),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// in-memory backend
+    Memory(&'a mut crate::io::WriteHalf<crate::io::DuplexStream>),
     /// wss backend
     WSS(&'a mut SplitSink<Box<Wss>, Message>),
     #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
     /// quic backend
     Quic(&'a mut quinn::SendStream),
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// tls backend
+    Tls(&'a mut crate::io::WriteHalf<crate::io::TlsStream>),
 }
 
 #[derive(From)]
@@ -34,11 +39,17 @@ pub enum UnformattedRawSendChannel {
     #[cfg(unix)]
     /// unix backend
     Unix(tokio::net::unix::OwnedWriteHalf),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// in-memory backend
+    Memory(crate::io::WriteHalf<crate::io::DuplexStream>),
     /// wss backend
     WSS(SplitSink<Box<Wss>, Message>),
     #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
     /// quic backend
     Quic(quinn::SendStream),
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// tls backend
+    Tls(crate::io::WriteHalf<crate::io::TlsStream>),
 }
 
 #[derive(From)]
@@ -65,9 +76,13 @@ impl<'a> From<&'a mut UnformattedRawSendChannel> for RefUnformattedRawSendChanne
             UnformattedRawSendChannel::Tcp(ref mut chan) => chan.into(),
             #[cfg(unix)]
             UnformattedRawSendChannel::Unix(ref mut chan) => chan.into(),
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawSendChannel::Memory(ref mut chan) => chan.into(),
             UnformattedRawSendChannel::WSS(ref mut chan) => chan.into(),
             #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
             UnformattedRawSendChannel::Quic(ref mut chan) => chan.into(),
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            UnformattedRawSendChannel::Tls(ref mut chan) => chan.into(),
         }
     }
 }
@@ -78,33 +93,47 @@ impl<'a> RefUnformattedRawSendChannel<'a> {
     /// chan.send("Hello world!", &mut Format::Bincode).await?;
     /// ```
     pub async fn send<T: Serialize, F: SendFormat>(&mut self, obj: T, f: &mut F) -> Result<usize> {
+        let bytes = f.serialize(&obj)?;
+        self.send_bytes(&bytes).await
+    }
+    /// Send a buffer through the channel as a single frame
+    pub async fn send_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
         #[allow(unused)]
-        use crate::serialization::tx;
+        use crate::serialization::{tx_bytes, wss_tx_bytes};
+        let sent = match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            RefUnformattedRawSendChannel::Tcp(st) => tx_bytes(st, bytes).await,
+            #[cfg(unix)]
+            RefUnformattedRawSendChannel::Unix(st) => tx_bytes(st, bytes).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            RefUnformattedRawSendChannel::Memory(st) => tx_bytes(st, bytes).await,
+            RefUnformattedRawSendChannel::WSS(st) => wss_tx_bytes(st, bytes.to_vec()).await,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+            RefUnformattedRawSendChannel::Quic(st) => tx_bytes(st, bytes).await,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            RefUnformattedRawSendChannel::Tls(st) => tx_bytes(st, bytes).await,
+        };
+        #[cfg(feature = "metrics")]
+        if let Ok(len) = sent {
+            crate::metrics::sent(self.transport(), len);
+        }
+        sent
+    }
+    #[cfg(feature = "metrics")]
+    /// name of the transport the channel runs over, used to label its metrics
+    pub(crate) fn transport(&self) -> &'static str {
         match self {
             #[cfg(not(target_arch = "wasm32"))]
-            RefUnformattedRawSendChannel::Tcp(st) => tx(st, obj, f).await,
+            RefUnformattedRawSendChannel::Tcp(_) => "tcp",
             #[cfg(unix)]
-            RefUnformattedRawSendChannel::Unix(st) => tx(st, obj, f).await,
-            RefUnformattedRawSendChannel::WSS(st) => {
-                let buf = f.serialize(&obj).map_err(err!(@invalid_data))?;
-                let len = buf.len();
-
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    let item = Message::Binary(buf);
-                    st.send(item).await.map_err(err!(@other))?;
-                }
-
-                #[cfg(target_arch = "wasm32")]
-                {
-                    let item = Message::Bytes(buf);
-                    st.send(item).await.map_err(|e| err!(e.to_string()))?;
-                }
-
-                Ok(len)
-            }
+            RefUnformattedRawSendChannel::Unix(_) => "unix",
+            #[cfg(not(target_arch = "wasm32"))]
+            RefUnformattedRawSendChannel::Memory(_) => "memory",
+            RefUnformattedRawSendChannel::WSS(_) => "wss",
             #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
-            RefUnformattedRawSendChannel::Quic(st) => tx(st, obj, f).await,
+            RefUnformattedRawSendChannel::Quic(_) => "quic",
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            RefUnformattedRawSendChannel::Tls(_) => "tls",
         }
     }
     /// Get a formatted channel with the specified format
@@ -129,6 +158,12 @@ impl UnformattedRawSendChannel {
     pub async fn send<T: Serialize, F: SendFormat>(&mut self, obj: T, f: &mut F) -> Result<usize> {
         RefUnformattedRawSendChannel::from(self).send(obj, f).await
     }
+    /// Send a buffer through the channel as a single frame
+    pub async fn send_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
+        RefUnformattedRawSendChannel::from(self)
+            .send_bytes(bytes)
+            .await
+    }
     #[inline]
     /// Format the channel
     /// ```no_run
@@ -146,6 +146,20 @@ impl<'a> RefUnformattedRawChannel<'a> {
             Self::Bipartite(chan) => chan.receive(format).await,
         }
     }
+    /// Send a buffer through the channel as a single frame
+    pub async fn send_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
+        match self {
+            Self::Unified(chan) => chan.send_bytes(bytes).await,
+            Self::Bipartite(chan) => chan.send_bytes(bytes).await,
+        }
+    }
+    /// Receive a single frame sent through the channel
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        match self {
+            Self::Unified(chan) => chan.receive_bytes().await,
+            Self::Bipartite(chan) => chan.receive_bytes().await,
+        }
+    }
     #[inline]
     /// Format the channel
     /// ```no_run
@@ -187,6 +201,20 @@ impl UnformattedRawChannel {
             Self::Bipartite(chan) => chan.receive(format).await,
         }
     }
+    /// Send a buffer through the channel as a single frame
+    pub async fn send_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
+        match self {
+            Self::Unified(chan) => chan.send_bytes(bytes).await,
+            Self::Bipartite(chan) => chan.send_bytes(bytes).await,
+        }
+    }
+    /// Receive a single frame sent through the channel
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        match self {
+            Self::Unified(chan) => chan.receive_bytes().await,
+            Self::Bipartite(chan) => chan.receive_bytes().await,
+        }
+    }
     #[must_use]
     /// Split channel into its send and receive components
     pub fn split(self) -> (UnformattedRawSendChannel, UnformattedRawReceiveChannel) {
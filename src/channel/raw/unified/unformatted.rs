@@ -1,15 +1,21 @@
+#[cfg(unix)]
+use std::os::fd::{BorrowedFd, OwnedFd};
+
 use derive_more::From;
-use futures::{SinkExt, StreamExt};
+use futures::StreamExt;
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::channel::raw::bipartite::receive_channel::UnformattedRawReceiveChannel;
 use crate::channel::raw::bipartite::send_channel::UnformattedRawSendChannel;
-use crate::io::Message;
-#[cfg(not(target_arch = "wasm32"))]
-use crate::io::TcpStream;
+#[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+use crate::io::TlsStream;
 #[cfg(unix)]
 use crate::io::UnixStream;
-use crate::{err, Result};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::io::{DuplexStream, TcpStream};
+use crate::Result;
+#[cfg(unix)]
+use crate::{err, serialization::fds};
 use crate::{
     io::Wss,
     serialization::formats::{ReadFormat, SendFormat},
@@ -27,11 +33,17 @@ pub enum RefUnformattedRawUnifiedChannel<'a> {
     #[cfg(unix)]
     /// unix backend
     Unix(&'a mut UnixStream),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// in-memory backend
+    Memory(&'a mut DuplexStream),
     /// wss backend
     Wss(&'a mut Wss),
     #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
     /// quic backend
     Quic(&'a mut quinn::SendStream, &'a mut quinn::RecvStream),
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// tls backend
+    Tls(&'a mut TlsStream),
 }
 
 #[derive(From)]
@@ -44,11 +56,17 @@ pub enum UnformattedRawUnifiedChannel {
     #[cfg(unix)]
     /// Unix backend
     Unix(UnixStream),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// In-memory backend
+    Memory(DuplexStream),
     /// WebSocket backend
     Wss(Box<Wss>), // boxed since it's heavy and would weigh down other variants
     #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
     /// Quic backend
     Quic(quinn::SendStream, quinn::RecvStream),
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// Tls backend
+    Tls(Box<TlsStream>), // boxed for the same reason as websockets
 }
 
 impl UnformattedRawUnifiedChannel {
@@ -70,6 +88,11 @@ impl UnformattedRawUnifiedChannel {
                 let (read, write) = stream.into_split();
                 (From::from(write), From::from(read))
             }
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawUnifiedChannel::Memory(stream) => {
+                let (read, write) = crate::io::split(stream);
+                (From::from(write), From::from(read))
+            }
             UnformattedRawUnifiedChannel::Wss(stream) => {
                 let (write, read) = stream.split();
                 (From::from(write), From::from(read))
@@ -78,6 +101,11 @@ impl UnformattedRawUnifiedChannel {
             UnformattedRawUnifiedChannel::Quic(write, read) => {
                 (From::from(write), From::from(read))
             }
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            UnformattedRawUnifiedChannel::Tls(stream) => {
+                let (read, write) = crate::io::split(*stream);
+                (From::from(write), From::from(read))
+            }
         }
     }
     /// Send an object through the channel serialized with format
@@ -105,6 +133,77 @@ impl UnformattedRawUnifiedChannel {
             .receive(format)
             .await
     }
+    /// Send a buffer through the channel as a single frame
+    pub async fn send_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
+        RefUnformattedRawUnifiedChannel::from(self)
+            .send_bytes(bytes)
+            .await
+    }
+    /// Receive a single frame sent through the channel
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        RefUnformattedRawUnifiedChannel::from(self)
+            .receive_bytes()
+            .await
+    }
+    /// Receive a single frame of at most `max` bytes, see `receive_bytes_within` of the borrowed channel
+    pub(crate) async fn receive_bytes_within(&mut self, max: usize) -> Result<Vec<u8>> {
+        RefUnformattedRawUnifiedChannel::from(self)
+            .receive_bytes_within(max)
+            .await
+    }
+    #[cfg(feature = "metrics")]
+    /// name of the transport the channel runs over, used to label its metrics
+    pub(crate) fn transport(&self) -> &'static str {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Tcp(_) => "tcp",
+            #[cfg(unix)]
+            Self::Unix(_) => "unix",
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Memory(_) => "memory",
+            Self::Wss(_) => "wss",
+            #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+            Self::Quic(..) => "quic",
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            Self::Tls(_) => "tls",
+        }
+    }
+    #[cfg(unix)]
+    /// Send a buffer through the channel as a single frame along with file descriptors.
+    /// Only unix sockets can pass file descriptors
+    pub(crate) async fn send_bytes_with_fds(
+        &mut self,
+        bytes: &[u8],
+        fds: &[BorrowedFd<'_>],
+    ) -> Result<usize> {
+        let sent = match self {
+            Self::Unix(st) => fds::tx_bytes_with_fds(st, bytes, fds).await,
+            _ => Err(fds_unsupported()),
+        };
+        #[cfg(feature = "metrics")]
+        if sent.is_ok() {
+            crate::metrics::sent("unix", bytes.len());
+        }
+        sent
+    }
+    #[cfg(unix)]
+    /// Receive a single frame of at most `max` bytes sent through the channel,
+    /// collecting the file descriptors sent along with it into `fds`
+    pub(crate) async fn receive_bytes_with_fds(
+        &mut self,
+        fds: &mut Vec<OwnedFd>,
+        max: usize,
+    ) -> Result<Vec<u8>> {
+        let received = match self {
+            Self::Unix(st) => fds::rx_bytes_with_fds(st, fds, max).await,
+            _ => Err(fds_unsupported()),
+        };
+        #[cfg(feature = "metrics")]
+        if let Ok(bytes) = &received {
+            crate::metrics::received("unix", bytes.len());
+        }
+        received
+    }
 }
 
 impl<'a> From<&'a mut UnformattedRawUnifiedChannel> for RefUnformattedRawUnifiedChannel<'a> {
@@ -115,11 +214,17 @@ impl<'a> From<&'a mut UnformattedRawUnifiedChannel> for RefUnformattedRawUnified
             UnformattedRawUnifiedChannel::Tcp(ref mut chan) => chan.into(),
             #[cfg(unix)]
             UnformattedRawUnifiedChannel::Unix(ref mut chan) => chan.into(),
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawUnifiedChannel::Memory(ref mut chan) => chan.into(),
             UnformattedRawUnifiedChannel::Wss(ref mut chan) => {
                 RefUnformattedRawUnifiedChannel::Wss(chan)
             }
             #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
             UnformattedRawUnifiedChannel::Quic(ref mut tx, ref mut rx) => From::from((tx, rx)),
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            UnformattedRawUnifiedChannel::Tls(ref mut chan) => {
+                RefUnformattedRawUnifiedChannel::Tls(chan)
+            }
         }
     }
 }
@@ -134,35 +239,8 @@ impl<'a> RefUnformattedRawUnifiedChannel<'a> {
         obj: T,
         format: &mut F,
     ) -> Result<usize> {
-        #[allow(unused)]
-        use crate::serialization::tx;
-        match self {
-            #[cfg(not(target_arch = "wasm32"))]
-            Self::Tcp(st) => tx(st, obj, format).await,
-            #[cfg(unix)]
-            Self::Unix(st) => tx(st, obj, format).await,
-            #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
-            Self::Quic(st, _) => tx(st, obj, format).await,
-            Self::Wss(st) => {
-                let buf = format.serialize(&obj).map_err(err!(@invalid_data))?;
-                let len = buf.len();
-
-                // use tungstenite for native
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    let item = Message::Binary(buf);
-                    st.send(item).await.map_err(err!(@other))?;
-                };
-
-                // use reqwasm for wasm
-                #[cfg(target_arch = "wasm32")]
-                {
-                    let item = Message::Bytes(buf);
-                    st.send(item).await.map_err(|e| err!(e.to_string()))?;
-                };
-                Ok(len)
-            }
-        }
+        let bytes = format.serialize(&obj)?;
+        self.send_bytes(&bytes).await
     }
     /// Receive an object sent through the channel with format
     /// ```no_run
@@ -172,16 +250,78 @@ impl<'a> RefUnformattedRawUnifiedChannel<'a> {
         &mut self,
         format: &mut F,
     ) -> Result<T> {
+        let bytes = self.receive_bytes().await?;
+        format.deserialize(&bytes)
+    }
+    /// Send a buffer through the channel as a single frame
+    pub async fn send_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
         #[allow(unused)]
-        use crate::serialization::{rx, wss_rx};
+        use crate::serialization::{tx_bytes, wss_tx_bytes};
+        let sent = match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Tcp(st) => tx_bytes(st, bytes).await,
+            #[cfg(unix)]
+            Self::Unix(st) => tx_bytes(st, bytes).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Memory(st) => tx_bytes(st, bytes).await,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+            Self::Quic(st, _) => tx_bytes(st, bytes).await,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            Self::Tls(st) => tx_bytes(st, bytes).await,
+            Self::Wss(st) => wss_tx_bytes(st, bytes.to_vec()).await,
+        };
+        #[cfg(feature = "metrics")]
+        if let Ok(len) = sent {
+            crate::metrics::sent(self.transport(), len);
+        }
+        sent
+    }
+    /// Receive a single frame sent through the channel
+    pub async fn receive_bytes(&mut self) -> Result<Vec<u8>> {
+        self.receive_bytes_within(usize::MAX).await
+    }
+    /// Receive a single frame sent through the channel, failing if it is longer than `max`.
+    /// Stream transports fail on the length prefix, before allocating anything for the frame
+    pub(crate) async fn receive_bytes_within(&mut self, max: usize) -> Result<Vec<u8>> {
+        #[allow(unused)]
+        use crate::serialization::{framing, rx_bytes_within, wss_rx_bytes};
+        let received = match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Tcp(st) => rx_bytes_within(st, max).await,
+            #[cfg(unix)]
+            Self::Unix(st) => rx_bytes_within(st, max).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Memory(st) => rx_bytes_within(st, max).await,
+            // websockets bound messages by themselves, see `WebSocket::max_message_size`
+            Self::Wss(st) => wss_rx_bytes(st)
+                .await
+                .and_then(|bytes| framing::check_len(bytes.len(), max).map(|_| bytes)),
+            #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+            Self::Quic(_, st) => rx_bytes_within(st, max).await,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            Self::Tls(st) => rx_bytes_within(st, max).await,
+        };
+        #[cfg(feature = "metrics")]
+        if let Ok(bytes) = &received {
+            crate::metrics::received(self.transport(), bytes.len());
+        }
+        received
+    }
+    #[cfg(feature = "metrics")]
+    /// name of the transport the channel runs over, used to label its metrics
+    pub(crate) fn transport(&self) -> &'static str {
         match self {
             #[cfg(not(target_arch = "wasm32"))]
-            Self::Tcp(st) => rx(st, format).await,
+            Self::Tcp(_) => "tcp",
             #[cfg(unix)]
-            Self::Unix(st) => rx(st, format).await,
-            Self::Wss(st) => wss_rx(st, format).await,
+            Self::Unix(_) => "unix",
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Memory(_) => "memory",
+            Self::Wss(_) => "wss",
             #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
-            Self::Quic(_, st) => rx(st, format).await,
+            Self::Quic(..) => "quic",
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            Self::Tls(_) => "tls",
         }
     }
     /// Get a formatted channel with the specified format
@@ -197,3 +337,13 @@ impl<'a> RefUnformattedRawUnifiedChannel<'a> {
         }
     }
 }
+
+#[cfg(unix)]
+#[inline]
+/// error returned when passing file descriptors over a stream that isn't a unix socket
+fn fds_unsupported() -> crate::Error {
+    err!(
+        unsupported,
+        "file descriptors can only be passed over unix sockets"
+    )
+}
@@ -0,0 +1,38 @@
+use crate::{err, Error};
+
+#[derive(Clone, Copy, Default, Debug)]
+/// Automatic rekeying of the send side of an encrypted channel.
+///
+/// Counts the bytes sent since the last rekey, once `after` bytes have been sent
+/// the key is rotated before the next frame.
+pub(crate) struct Rekey {
+    after: Option<u64>,
+    sent: u64,
+}
+
+impl Rekey {
+    #[inline]
+    /// rotate the key every `after` bytes, never if `None`
+    pub(crate) fn set(&mut self, after: Option<u64>) {
+        self.after = after;
+        self.sent = 0;
+    }
+
+    #[inline]
+    /// Count a frame of `len` bytes about to be sent,
+    /// returns whether the key has to be rotated before sending it
+    pub(crate) fn due(&mut self, len: usize) -> bool {
+        let due = matches!(self.after, Some(after) if self.sent >= after);
+        if due {
+            self.sent = 0;
+        }
+        self.sent += len as u64;
+        due
+    }
+}
+
+#[inline]
+/// error returned when rekeying a channel that isn't encrypted
+pub(crate) fn not_encrypted() -> Error {
+    err!(invalid_input, "only encrypted channels can be rekeyed")
+}
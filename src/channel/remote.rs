@@ -0,0 +1,93 @@
+use std::{fmt::Display, sync::RwLock};
+
+use crate::{
+    channel::frame::{self, FrameKind},
+    serialization::formats::{ReadFormat, SendFormat},
+    Error, Result,
+};
+
+/// hook that builds the message of errors sent to peers
+pub type Redaction = fn(&Error) -> String;
+
+static REDACTION: RwLock<Option<Redaction>> = RwLock::new(None);
+
+#[derive(Clone, Debug)]
+/// Error that happened on the peer and was sent through the channel.
+///
+/// `receive` returns it as an error with the same kind as the original one,
+/// use `RemoteError::of` to find out if an error came from the peer.
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+/// use canary::channel::remote::RemoteError;
+///
+/// match chan.request::<_, String>(&"Hello!").await {
+///     Ok(res) => println!("{}", res),
+///     Err(e) => match RemoteError::of(&e) {
+///         Some(remote) => println!("peer failed: {}", remote),
+///         None => return Err(e),
+///     },
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct RemoteError {
+    message: String,
+}
+
+impl RemoteError {
+    #[inline]
+    /// Returns the remote error carried by the error, if it was sent by the peer
+    pub fn of(error: &Error) -> Option<&RemoteError> {
+        error.get_ref()?.downcast_ref()
+    }
+    #[inline]
+    /// Message of the error, as sent by the peer
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Display for RemoteError {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "remote error: {}", self.message)
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+/// Set the hook used to build the message of errors sent to peers.
+/// By default the message of the error is sent as is, which may leak internal details.
+/// ```no_run
+/// canary::channel::remote::redact_with(|e| format!("{:?}", e.kind()));
+/// ```
+pub fn redact_with(hook: Redaction) {
+    *REDACTION.write().unwrap_or_else(|e| e.into_inner()) = Some(hook);
+}
+
+/// error that gets serialized into error frames
+pub(crate) fn redact(error: &Error) -> Error {
+    let hook = *REDACTION.read().unwrap_or_else(|e| e.into_inner());
+    let message = match hook {
+        Some(hook) => hook(error),
+        None => error.to_string(),
+    };
+    Error::new(std::io::Error::new(error.kind(), message))
+}
+
+/// turn the payload of an error frame into the error returned by `receive`
+pub(crate) fn from_frame<F: ReadFormat>(format: &mut F, payload: &[u8]) -> Error {
+    let error: Error = match format.deserialize(payload) {
+        Ok(error) => error,
+        Err(e) => return e,
+    };
+    let message = error.to_string();
+    Error::new(std::io::Error::new(error.kind(), RemoteError { message }))
+}
+
+#[inline]
+/// build an error frame
+pub(crate) fn to_frame<F: SendFormat>(format: &mut F, error: &Error) -> Result<Vec<u8>> {
+    let payload = format.serialize(&redact(error))?;
+    Ok(frame::encode(FrameKind::Error, payload))
+}
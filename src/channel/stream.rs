@@ -0,0 +1,93 @@
+use crate::{
+    async_snow::PACKET_LEN,
+    err,
+    io::{Read, ReadExt, Write, WriteExt},
+    Result,
+};
+
+use super::frame::{self, FrameKind};
+
+/// largest payload of a chunk frame, so that every chunk fits in a single noise packet
+const CHUNK_LEN: usize = PACKET_LEN as usize - 1;
+
+/// Fill the buffer with a chunk frame read from the reader,
+/// returns `false` once the reader is exhausted
+pub(crate) async fn read_chunk<Rd: Read + Unpin>(
+    reader: &mut Rd,
+    buffer: &mut Vec<u8>,
+) -> Result<bool> {
+    buffer.clear();
+    buffer.push(FrameKind::Chunk as u8);
+    buffer.resize(1 + CHUNK_LEN, 0);
+    let mut filled = 1;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]).await? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    buffer.truncate(filled);
+    Ok(filled > 1)
+}
+
+#[inline]
+/// whether the frame is a chunk, which means more of the stream is pending
+pub(crate) fn is_chunk(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&(FrameKind::Chunk as u8))
+}
+
+#[inline]
+/// build the frame that ends a stream of `len` bytes
+pub(crate) fn end(len: u64) -> Vec<u8> {
+    frame::encode(FrameKind::End, len.to_be_bytes().to_vec())
+}
+
+/// Write a frame of a stream to the writer,
+/// returns the length of the stream once its end has been received.
+///
+/// If the sender declared the length of the stream beforehand as `total`,
+/// a stream going past it or ending short of it is an error
+pub(crate) async fn write_frame<Wr: Write + Unpin>(
+    writer: &mut Wr,
+    written: &mut u64,
+    total: Option<u64>,
+    bytes: &[u8],
+) -> Result<Option<u64>> {
+    match frame::decode(bytes)? {
+        (FrameKind::Chunk, chunk) => {
+            let received = *written + chunk.len() as u64;
+            if let Some(total) = total.filter(|total| received > *total) {
+                err!((
+                    invalid_data,
+                    format!("stream declared as {} bytes sent {} bytes", total, received)
+                ))?
+            }
+            writer.write_all(chunk).await?;
+            *written = received;
+            Ok(None)
+        }
+        (FrameKind::End, len) => {
+            let len = <[u8; 8]>::try_from(len)
+                .map_err(|_| err!(invalid_data, "malformed end of stream"))?;
+            let len = u64::from_be_bytes(len);
+            if len != *written {
+                err!((
+                    invalid_data,
+                    format!("stream of {} bytes ended after {} bytes", len, written)
+                ))?
+            }
+            if let Some(total) = total.filter(|total| len != *total) {
+                err!((
+                    unexpected_eof,
+                    format!(
+                        "stream declared as {} bytes ended after {} bytes",
+                        total, len
+                    )
+                ))?
+            }
+            writer.flush().await?;
+            Ok(Some(len))
+        }
+        _ => err!((invalid_data, "received a message in the middle of a stream")),
+    }
+}
@@ -0,0 +1,21 @@
+use std::sync::Arc;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Direction of a frame seen by a tap
+pub enum Direction {
+    /// Frame sent to the peer
+    Send,
+    /// Frame received from the peer
+    Receive,
+}
+
+/// Hook called with every frame going through a channel, see `Channel::tap`
+pub type Tap = Arc<dyn Fn(Direction, &[u8]) + Send + Sync>;
+
+#[inline]
+/// show a frame to the tap of a channel, if it has one
+pub(crate) fn show(tap: &Option<Tap>, direction: Direction, frame: &[u8]) {
+    if let Some(tap) = tap {
+        tap(direction, frame)
+    }
+}
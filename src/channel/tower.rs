@@ -0,0 +1,194 @@
+use std::{
+    any::Any,
+    fmt::Display,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::RwLock,
+};
+
+use futures::{future::poll_fn, FutureExt};
+use serde::{de::DeserializeOwned, Serialize};
+use tower_service::Service;
+use tracing::Instrument;
+
+use crate::{
+    err,
+    serialization::formats::{ReadFormat, SendFormat},
+    Channel, Error, Result,
+};
+
+/// error type returned by most tower middleware
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// hook called when a service served with `Channel::serve` panics
+pub type PanicHook = Box<dyn Fn(&ServicePanic) + Send + Sync>;
+
+static PANIC_HOOK: RwLock<Option<PanicHook>> = RwLock::new(None);
+
+#[derive(Clone, Debug)]
+/// Error returned by `Channel::serve` when the service panicked.
+///
+/// The peer only receives an error saying the service panicked,
+/// the message of the panic stays on this side.
+/// Use `ServicePanic::of` to find it
+pub struct ServicePanic {
+    service: &'static str,
+    message: String,
+}
+
+impl ServicePanic {
+    #[inline]
+    /// Returns the panic carried by the error, if a service panicked
+    pub fn of(error: &Error) -> Option<&ServicePanic> {
+        error.get_ref()?.downcast_ref()
+    }
+    #[inline]
+    /// Type name of the service that panicked
+    pub fn service(&self) -> &'static str {
+        self.service
+    }
+    #[inline]
+    /// Message the service panicked with
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+    fn new<S>(payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "Box<dyn Any>".into(),
+            },
+        };
+        let service = std::any::type_name::<S>();
+        ServicePanic { service, message }
+    }
+}
+
+impl Display for ServicePanic {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "service `{}` panicked: {}", self.service, self.message)
+    }
+}
+
+impl std::error::Error for ServicePanic {}
+
+/// Set the hook called when a service served with `Channel::serve` panics,
+/// such as to alert someone. It runs on the task that served the channel,
+/// after the panic was logged and before the peer is told
+/// ```no_run
+/// canary::set_panic_hook(|panic| {
+///     eprintln!("paging on-call: {} panicked", panic.service());
+/// });
+/// ```
+pub fn set_panic_hook(hook: impl Fn(&ServicePanic) + Send + Sync + 'static) {
+    *PANIC_HOOK.write().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(hook));
+}
+
+/// turn an error returned by a service into a canary error, keeping its kind if it has one
+fn into_error(error: BoxError) -> Error {
+    match error.downcast::<Error>() {
+        Ok(error) => *error,
+        Err(error) => match error.downcast::<std::io::Error>() {
+            Ok(error) => Error::new(*error),
+            Err(error) => err!(other, error),
+        },
+    }
+}
+
+impl<R, W> Channel<R, W> {
+    /// Answer every request received through the channel with the response of the service,
+    /// until the peer closes the channel.
+    ///
+    /// Every message is a request, so tower layers such as timeouts or rate limits
+    /// apply to each of them. Errors returned by the service are sent to the peer,
+    /// which receives them as `RemoteError`s, and the channel keeps serving.
+    /// An error while waiting for the service to be ready stops serving.
+    ///
+    /// If the service panics, the panic is logged with the name of the service,
+    /// the hook set with `canary::set_panic_hook` is called, the peer receives an error
+    /// and serving stops with a `ServicePanic` error, since the service may be left broken.
+    /// ```no_run
+    /// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+    /// struct Greeter;
+    ///
+    /// impl tower_service::Service<String> for Greeter {
+    ///     type Response = String;
+    ///     type Error = canary::Error;
+    ///     type Future = std::future::Ready<canary::Result<String>>;
+    ///
+    ///     fn poll_ready(
+    ///         &mut self,
+    ///         _: &mut std::task::Context<'_>,
+    ///     ) -> std::task::Poll<canary::Result<()>> {
+    ///         std::task::Poll::Ready(Ok(()))
+    ///     }
+    ///     fn call(&mut self, name: String) -> Self::Future {
+    ///         std::future::ready(Ok(format!("Hello {}!", name)))
+    ///     }
+    /// }
+    ///
+    /// chan.serve(Greeter).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn serve<S, Req>(&mut self, mut service: S) -> Result<()>
+    where
+        R: ReadFormat,
+        W: SendFormat,
+        S: Service<Req>,
+        S::Response: Serialize,
+        S::Error: Into<BoxError>,
+        Req: DeserializeOwned,
+    {
+        while let Some(req) = self.try_receive().await? {
+            poll_fn(|cx| service.poll_ready(cx))
+                .await
+                .map_err(|e| into_error(e.into()))?;
+            let span = tracing::debug_span!("request", service = std::any::type_name::<S>());
+            #[cfg(all(feature = "metrics", not(target_arch = "wasm32")))]
+            let started = std::time::Instant::now();
+            let call = span.in_scope(|| catch_unwind(AssertUnwindSafe(|| service.call(req))));
+            let result = match call {
+                Ok(fut) => AssertUnwindSafe(fut).catch_unwind().instrument(span).await,
+                Err(payload) => Err(payload),
+            };
+            #[cfg(all(feature = "metrics", not(target_arch = "wasm32")))]
+            crate::metrics::served(std::any::type_name::<S>(), started.elapsed());
+            match result {
+                Ok(Ok(resp)) => self.send(resp).await?,
+                Ok(Err(e)) => {
+                    let error = into_error(e.into());
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if error.kind() == std::io::ErrorKind::PermissionDenied {
+                        crate::audit::emit(crate::audit::AuditKind::AccessDenied {
+                            peer_key: None,
+                            service: Some(std::any::type_name::<S>().into()),
+                            reason: error.to_string(),
+                        });
+                    }
+                    self.send_error(&error).await?
+                }
+                Err(payload) => return Err(self.panicked(ServicePanic::new::<S>(payload)).await),
+            };
+        }
+        Ok(())
+    }
+
+    /// report a panic of the served service, telling the peer if the channel still works
+    async fn panicked(&mut self, panic: ServicePanic) -> Error
+    where
+        W: SendFormat,
+    {
+        tracing::error!("{}", panic);
+        #[cfg(feature = "metrics")]
+        crate::metrics::panicked(panic.service);
+        if let Some(hook) = &*PANIC_HOOK.read().unwrap_or_else(|e| e.into_inner()) {
+            hook(&panic);
+        }
+        self.send_error(&err!(other, "the service panicked"))
+            .await
+            .ok();
+        Error::new(std::io::Error::other(panic))
+    }
+}
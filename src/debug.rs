@@ -0,0 +1,184 @@
+#![cfg(all(feature = "capture", not(target_arch = "wasm32")))]
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::channel::frame::FrameKind;
+use crate::channel::tap::{Direction, Tap};
+use crate::{err, Result};
+
+/// environment variable that must be set for `record_to` to start recording
+pub const CAPTURE_ENV: &str = "CANARY_CAPTURE";
+
+/// first bytes of every capture file
+const MAGIC: &[u8; 8] = b"CNRYCAP1";
+/// direction, timestamp, channel and length preceding every recorded frame
+const HEADER_LEN: usize = 1 + 8 + 8 + 4;
+
+static RECORDING: RwLock<Option<Arc<Recording>>> = RwLock::new(None);
+static NEXT_CHANNEL: AtomicU64 = AtomicU64::new(0);
+
+struct Recording {
+    file: Mutex<Option<File>>,
+}
+
+impl Recording {
+    fn write(&self, channel: u64, direction: Direction, frame: &[u8]) {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(writer) = &mut *file else {
+            return;
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let mut record = Vec::with_capacity(HEADER_LEN + frame.len());
+        record.push(match direction {
+            Direction::Send => 0,
+            Direction::Receive => 1,
+        });
+        record.extend_from_slice(&timestamp.to_le_bytes());
+        record.extend_from_slice(&channel.to_le_bytes());
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        record.extend_from_slice(frame);
+        if let Err(e) = writer.write_all(&record) {
+            tracing::warn!("stopped recording frames: {}", e);
+            *file = None;
+        }
+    }
+}
+
+/// Record every frame of the channels opened from now on to the file at `path`,
+/// replacing it if it exists. Frames are recorded as `Channel::tap` sees them:
+/// serialized and before encryption, so captures stay readable over TLS or noise.
+///
+/// Recording only starts if the `CANARY_CAPTURE` environment variable is set,
+/// otherwise a `PermissionDenied` error is returned, so builds shipping
+/// the `capture` feature can't record by accident.
+/// Setting a tap on a channel replaces its recording
+/// ```no_run
+/// # fn example() -> canary::Result<()> {
+/// canary::debug::record_to("session.capture")?;
+/// // ... reproduce the bug
+/// canary::debug::stop();
+/// for frame in canary::debug::replay("session.capture")? {
+///     println!("{:?} {:?} {:?}", frame.channel, frame.direction, frame.kind());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn record_to(path: impl AsRef<Path>) -> Result<()> {
+    if std::env::var_os(CAPTURE_ENV).is_none() {
+        return err!((
+            permission_denied,
+            format!("set {CAPTURE_ENV} to record frames")
+        ));
+    }
+    let mut file = File::create(path)?;
+    file.write_all(MAGIC)?;
+    let recording = Arc::new(Recording {
+        file: Mutex::new(Some(file)),
+    });
+    if let Some(previous) = RECORDING
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .replace(recording)
+    {
+        previous
+            .file
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+    }
+    Ok(())
+}
+
+/// Stop recording, channels that were being recorded stop as well
+pub fn stop() {
+    if let Some(recording) = RECORDING.write().unwrap_or_else(|e| e.into_inner()).take() {
+        recording
+            .file
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+    }
+}
+
+/// tap recording the frames of a new channel, if recording
+pub(crate) fn tap() -> Option<Tap> {
+    let recording = RECORDING
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()?;
+    let channel = NEXT_CHANNEL.fetch_add(1, Ordering::Relaxed);
+    Some(Arc::new(move |direction, frame: &[u8]| {
+        recording.write(channel, direction, frame)
+    }))
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+/// Frame read back from a capture file by `replay`
+pub struct RecordedFrame {
+    /// Whether the frame was sent or received
+    pub direction: Direction,
+    /// When the frame went through the channel
+    pub timestamp: SystemTime,
+    /// Number of the channel the frame went through,
+    /// in the order the channels were opened since the process started
+    pub channel: u64,
+    /// The frame, starting with its `FrameKind`
+    pub frame: Vec<u8>,
+}
+
+impl RecordedFrame {
+    /// Kind of the frame, `None` if the first byte isn't a known kind
+    pub fn kind(&self) -> Option<FrameKind> {
+        FrameKind::try_from(*self.frame.first()?).ok()
+    }
+    /// The serialized message or control payload following the kind
+    pub fn payload(&self) -> &[u8] {
+        self.frame.get(1..).unwrap_or_default()
+    }
+}
+
+/// Read back the frames recorded by `record_to`, in the order they were recorded.
+/// Fails with an `InvalidData` error if the file isn't a capture or is truncated,
+/// as happens when the process dies while recording a frame
+pub fn replay(path: impl AsRef<Path>) -> Result<impl Iterator<Item = RecordedFrame>> {
+    let bytes = std::fs::read(path)?;
+    let mut rest = match bytes.strip_prefix(MAGIC) {
+        Some(rest) => rest,
+        None => return err!((invalid_data, "not a capture file")),
+    };
+    let mut frames = Vec::new();
+    while !rest.is_empty() {
+        if rest.len() < HEADER_LEN {
+            return err!((invalid_data, "capture file is truncated"));
+        }
+        let (header, body) = rest.split_at(HEADER_LEN);
+        let direction = match header[0] {
+            0 => Direction::Send,
+            1 => Direction::Receive,
+            _ => return err!((invalid_data, "capture file is corrupted")),
+        };
+        let timestamp = u64::from_le_bytes(header[1..9].try_into().unwrap());
+        let channel = u64::from_le_bytes(header[9..17].try_into().unwrap());
+        let len = u32::from_le_bytes(header[17..21].try_into().unwrap()) as usize;
+        if body.len() < len {
+            return err!((invalid_data, "capture file is truncated"));
+        }
+        let (frame, next) = body.split_at(len);
+        frames.push(RecordedFrame {
+            direction,
+            timestamp: UNIX_EPOCH + Duration::from_micros(timestamp),
+            channel,
+            frame: frame.to_vec(),
+        });
+        rest = next;
+    }
+    Ok(frames.into_iter())
+}
@@ -0,0 +1,227 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::async_snow::DowngradeDetected;
+use crate::runtime::Cancelled;
+use crate::serialization::formats::Format;
+use crate::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+/// Category of an error returned by the crate, found with `ErrorKind::of`.
+///
+/// Errors stay `canary::Error`, which is a `std::io::Error` underneath, so code matching
+/// on `kind()` keeps working. This tells apart what the io kinds can't, such as a channel
+/// closed by the peer or a serialization failure, without looking at messages
+/// ```no_run
+/// # async fn example(addr: canary::providers::Addr) -> canary::Result<()> {
+/// use canary::error::ErrorKind;
+///
+/// let chan = loop {
+///     match addr.connect().await {
+///         Ok(chan) => break chan,
+///         Err(e) if ErrorKind::of(&e).is_transient() => continue,
+///         Err(e) => return Err(e),
+///     }
+/// };
+/// # Ok(())
+/// # }
+/// ```
+pub enum ErrorKind {
+    /// what was asked for doesn't exist, such as an unknown address
+    NotFound,
+    /// the address is already bound by another provider
+    InUse,
+    /// the peer sent something malformed or unexpected
+    InvalidData,
+    /// the arguments of the call are invalid
+    InvalidInput,
+    /// the peer or the build doesn't support what was asked
+    Unsupported,
+    /// the peer or a policy refused access
+    PermissionDenied,
+    /// an operation didn't finish in time
+    TimedOut,
+    /// the channel was closed, by the peer or on this side
+    Closed,
+    /// the operation was stopped by a `CancelToken`
+    Cancelled,
+    /// the peer failed the handshake, or it was tampered with, see `HandshakeError`
+    Handshake,
+    /// an object could not be serialized or deserialized, see `SerializationError`
+    Serialization,
+    /// the transport failed, such as a refused or reset connection
+    Io,
+    /// any other error
+    Other,
+}
+
+impl ErrorKind {
+    /// Category of the error
+    pub fn of(error: &Error) -> ErrorKind {
+        if let Some(payload) = error.get_ref() {
+            if payload.is::<ChannelClosed>() {
+                return ErrorKind::Closed;
+            }
+            if payload.is::<Cancelled>() {
+                return ErrorKind::Cancelled;
+            }
+            if payload.is::<HandshakeError>() || payload.is::<DowngradeDetected>() {
+                return ErrorKind::Handshake;
+            }
+            if payload.is::<SerializationError>() {
+                return ErrorKind::Serialization;
+            }
+        }
+        use std::io::ErrorKind as Io;
+        match error.kind() {
+            Io::NotFound => ErrorKind::NotFound,
+            Io::AddrInUse => ErrorKind::InUse,
+            Io::InvalidData => ErrorKind::InvalidData,
+            Io::InvalidInput => ErrorKind::InvalidInput,
+            Io::Unsupported => ErrorKind::Unsupported,
+            Io::PermissionDenied => ErrorKind::PermissionDenied,
+            Io::TimedOut => ErrorKind::TimedOut,
+            Io::Other => ErrorKind::Other,
+            _ => ErrorKind::Io,
+        }
+    }
+
+    #[inline]
+    /// Whether trying again may succeed: timeouts, closed channels and transport failures.
+    /// Errors of the peer or of the call itself, such as a failed handshake, are permanent
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::TimedOut | ErrorKind::Closed | ErrorKind::Io
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Error returned when using a channel that was closed.
+///
+/// Receiving from a channel closed by the peer returns it as a `NotConnected` error,
+/// sending through a closed send side as a `BrokenPipe` error.
+/// Use `ChannelClosed::of` to find it
+pub struct ChannelClosed {
+    /// whether the peer closed the channel, rather than this side
+    pub by_peer: bool,
+}
+
+impl ChannelClosed {
+    #[inline]
+    /// Returns the closed channel error carried by the error, if any
+    pub fn of(error: &Error) -> Option<&ChannelClosed> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl Display for ChannelClosed {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.by_peer {
+            true => write!(f, "channel closed by peer"),
+            false => write!(f, "send side of the channel is closed"),
+        }
+    }
+}
+
+impl std::error::Error for ChannelClosed {}
+
+#[derive(Debug)]
+/// Error returned when the peer fails a handshake, such as when it holds other keys,
+/// uses other primitives or sent a malformed message.
+///
+/// It keeps the kind of the error that failed the handshake and the error itself
+/// as its `source`. A handshake interrupted by a closed channel, a transport error or
+/// a timeout returns those errors as they are instead, since they may be transient.
+/// Use `HandshakeError::of` to find it
+pub struct HandshakeError {
+    source: Error,
+}
+
+impl HandshakeError {
+    #[inline]
+    /// Returns the handshake error carried by the error, if any
+    pub fn of(error: &Error) -> Option<&HandshakeError> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    /// Mark an error failing a handshake, leaving transient errors
+    /// and the ones already marked, such as `DowngradeDetected`, as they are
+    pub(crate) fn wrap(source: Error) -> Error {
+        let category = ErrorKind::of(&source);
+        if category.is_transient() || category == ErrorKind::Handshake {
+            return source;
+        }
+        let kind = source.kind();
+        Error::new(std::io::Error::new(kind, HandshakeError { source }))
+    }
+}
+
+impl Display for HandshakeError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "handshake failed: {}", self.source)
+    }
+}
+
+impl std::error::Error for HandshakeError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+#[derive(Debug)]
+/// Error returned when an object can't be serialized or deserialized,
+/// such as a message that doesn't match the type it is received as.
+///
+/// Returned as an `InvalidData` error, with the error of the serializer as its `source`.
+/// Use `SerializationError::of` to find it
+pub struct SerializationError {
+    /// format that failed, `None` for formats registered at runtime
+    pub format: Option<Format>,
+    source: Box<dyn std::error::Error + Send + Sync>,
+}
+
+impl SerializationError {
+    #[inline]
+    /// Returns the serialization error carried by the error, if any
+    pub fn of(error: &Error) -> Option<&SerializationError> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    #[inline]
+    /// Wrap the errors of a serializer of the format
+    pub(crate) fn with<E>(format: impl Into<Option<Format>>) -> impl FnOnce(E) -> Error
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let format = format.into();
+        move |source| {
+            let error = SerializationError {
+                format,
+                source: source.into(),
+            };
+            Error::new(std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+        }
+    }
+}
+
+impl Display for SerializationError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.format {
+            Some(format) => write!(f, "{:?}: {}", format, self.source),
+            None => write!(f, "{}", self.source),
+        }
+    }
+}
+
+impl std::error::Error for SerializationError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.source)
+    }
+}
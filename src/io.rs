@@ -14,6 +14,7 @@ cfg_if! {
         pub(crate) use tokio::io::WriteHalf;
         pub(crate) use tokio::io::ReadHalf;
         pub(crate) use tokio::io::split;
+        pub(crate) use tokio::io::{duplex, DuplexStream};
 
         pub(crate) use tokio::net::ToSocketAddrs;
 
@@ -21,9 +22,25 @@ cfg_if! {
         pub(crate) use async_tungstenite as wss;
 
         pub(crate) type Wss = crate::io::wss::WebSocketStream<
-            async_tungstenite::tokio::TokioAdapter<TcpStream>
+            async_tungstenite::tokio::TokioAdapter<WssStream>
         >;
         pub(crate) type Message = tungstenite::Message;
+        #[cfg(feature = "tls")]
+        pub(crate) type TlsStream = tokio_rustls::TlsStream<TcpStream>;
+
+        /// run the future in the background on the current runtime,
+        /// returns `false` if there is no runtime to run it on
+        pub(crate) fn spawn_detached(
+            fut: impl std::future::Future<Output = ()> + Send + 'static,
+        ) -> bool {
+            match tokio::runtime::Handle::try_current() {
+                Ok(runtime) => {
+                    runtime.spawn(fut);
+                    true
+                }
+                Err(_) => false,
+            }
+        }
     } else if #[cfg(target_arch = "wasm32")] {
         pub(crate) use futures::io::AsyncRead as Read;
         pub(crate) use futures::io::AsyncReadExt as ReadExt;
@@ -31,5 +48,77 @@ cfg_if! {
         pub(crate) use futures::io::AsyncWriteExt as WriteExt;
         pub(crate) type Wss = reqwasm::websocket::futures::WebSocket;
         pub(crate) type Message = reqwasm::websocket::Message;
+
+        pub(crate) async fn sleep(duration: std::time::Duration) {
+            async_timer::timed(std::future::pending::<()>(), duration)
+                .await
+                .ok();
+        }
+
+        /// run the future in the background on the event loop of the browser, which is always there
+        pub(crate) fn spawn_detached(fut: impl std::future::Future<Output = ()> + 'static) -> bool {
+            wasm_bindgen_futures::spawn_local(fut);
+            true
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Stream websockets run over, `wss://` ones run over TLS
+pub enum WssStream {
+    /// plain websocket
+    Tcp(TcpStream),
+    #[cfg(feature = "tls")]
+    /// websocket over tls
+    Tls(Box<TlsStream>),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Read for WssStream {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            WssStream::Tcp(stream) => std::pin::Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            WssStream::Tls(stream) => std::pin::Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Write for WssStream {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            WssStream::Tcp(stream) => std::pin::Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            WssStream::Tls(stream) => std::pin::Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            WssStream::Tcp(stream) => std::pin::Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            WssStream::Tls(stream) => std::pin::Pin::new(stream).poll_flush(cx),
+        }
+    }
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            WssStream::Tcp(stream) => std::pin::Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            WssStream::Tls(stream) => std::pin::Pin::new(stream).poll_shutdown(cx),
+        }
     }
 }
@@ -20,9 +20,20 @@
 
 /// Contains encrypted stream
 pub mod async_snow;
+#[cfg(not(target_arch = "wasm32"))]
+/// Contains the hook recording security-relevant events, such as refused peers
+pub mod audit;
 /// Contains channels and constructs associated with them
 pub mod channel;
+#[cfg(all(feature = "capture", not(target_arch = "wasm32")))]
+/// Contains the recorder capturing the frames of channels to a file, to debug protocols
+pub mod debug;
+/// Contains the categories and typed payloads of the errors returned by the crate
+pub mod error;
 mod io;
+#[cfg(feature = "metrics")]
+/// Contains the names of the metrics recorded through the `metrics` facade
+pub mod metrics;
 /// Contains common imports
 pub mod prelude;
 /// Contains providers and address
@@ -32,6 +43,13 @@ pub mod providers;
 /// and formats
 pub mod serialization;
 
+/// Contains the timers and tasks of whichever runtime the crate is built for
+pub mod runtime;
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Contains the controller shutting the process down cleanly
+pub mod shutdown;
+
 /// Contains types that allow compile-time checking of message order.
 /// It can help debug complex systems.
 pub mod type_iter;
@@ -39,3 +57,6 @@ pub mod type_iter;
 pub use channel::channels::Channel;
 
 pub use io_err::{err, Error, Result};
+
+#[cfg(feature = "tower")]
+pub use channel::tower::set_panic_hook;
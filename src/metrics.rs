@@ -0,0 +1,105 @@
+#![cfg(feature = "metrics")]
+
+use std::sync::Arc;
+#[cfg(all(not(target_arch = "wasm32"), feature = "tower"))]
+use std::time::Duration;
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "tower"))]
+use ::metrics::histogram;
+use ::metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, Unit};
+
+/// bytes written to the wire, labelled by `transport`
+pub const BYTES_SENT: &str = "canary_bytes_sent_total";
+/// bytes read from the wire, labelled by `transport`
+pub const BYTES_RECEIVED: &str = "canary_bytes_received_total";
+/// frames written to the wire, labelled by `transport`
+pub const FRAMES_SENT: &str = "canary_frames_sent_total";
+/// frames read from the wire, labelled by `transport`
+pub const FRAMES_RECEIVED: &str = "canary_frames_received_total";
+/// channels currently open, labelled by `transport`
+pub const CHANNELS_ACTIVE: &str = "canary_channels_active";
+/// noise handshakes that failed
+pub const HANDSHAKE_FAILURES: &str = "canary_handshake_failures_total";
+/// time spent serving a request, labelled by `service`
+pub const REQUEST_DURATION: &str = "canary_request_duration_seconds";
+/// requests whose service panicked, labelled by `service`
+pub const SERVICE_PANICS: &str = "canary_service_panics_total";
+
+/// Register the units and descriptions of every metric with the installed recorder.
+///
+/// Metrics are recorded whether or not this is called,
+/// it only makes the exported ones self-describing
+/// ```no_run
+/// // install a recorder first, such as `metrics_exporter_prometheus`
+/// canary::metrics::describe();
+/// ```
+pub fn describe() {
+    describe_counter!(BYTES_SENT, Unit::Bytes, "bytes written to the wire");
+    describe_counter!(BYTES_RECEIVED, Unit::Bytes, "bytes read from the wire");
+    describe_counter!(FRAMES_SENT, Unit::Count, "frames written to the wire");
+    describe_counter!(FRAMES_RECEIVED, Unit::Count, "frames read from the wire");
+    describe_gauge!(CHANNELS_ACTIVE, Unit::Count, "channels currently open");
+    describe_counter!(
+        HANDSHAKE_FAILURES,
+        Unit::Count,
+        "noise handshakes that failed"
+    );
+    describe_histogram!(
+        REQUEST_DURATION,
+        Unit::Seconds,
+        "time spent serving a request"
+    );
+    describe_counter!(
+        SERVICE_PANICS,
+        Unit::Count,
+        "requests whose service panicked"
+    );
+}
+
+/// record a frame of `bytes` written to the wire
+pub(crate) fn sent(transport: &'static str, bytes: usize) {
+    counter!(BYTES_SENT, "transport" => transport).increment(bytes as u64);
+    counter!(FRAMES_SENT, "transport" => transport).increment(1);
+}
+
+/// record a frame of `bytes` read from the wire
+pub(crate) fn received(transport: &'static str, bytes: usize) {
+    counter!(BYTES_RECEIVED, "transport" => transport).increment(bytes as u64);
+    counter!(FRAMES_RECEIVED, "transport" => transport).increment(1);
+}
+
+/// record a failed noise handshake
+pub(crate) fn handshake_failed() {
+    counter!(HANDSHAKE_FAILURES).increment(1);
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "tower"))]
+/// record a request served by `service`
+pub(crate) fn served(service: &'static str, elapsed: Duration) {
+    histogram!(REQUEST_DURATION, "service" => service).record(elapsed);
+}
+
+#[cfg(feature = "tower")]
+/// record a request whose service panicked
+pub(crate) fn panicked(service: &'static str) {
+    counter!(SERVICE_PANICS, "service" => service).increment(1);
+}
+
+#[derive(Clone)]
+/// Keeps a channel counted in `canary_channels_active` until the last of its halves is dropped
+pub(crate) struct Active(#[allow(unused)] Arc<Counted>);
+
+struct Counted(&'static str);
+
+impl Active {
+    pub(crate) fn new(transport: &'static str) -> Self {
+        gauge!(CHANNELS_ACTIVE, "transport" => transport).increment(1.0);
+        Active(Arc::new(Counted(transport)))
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        gauge!(CHANNELS_ACTIVE, "transport" => self.0).decrement(1.0);
+    }
+}
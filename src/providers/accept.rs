@@ -0,0 +1,123 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::io::ErrorKind;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+
+use crate::{err, Error, Result};
+
+/// time to wait before accepting again when the process or the system ran out of resources
+const RESOURCES_BACKOFF: Duration = Duration::from_millis(100);
+/// least time between two warnings about accept errors
+const WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// what an error returned by `accept` means for the listener
+enum Severity {
+    /// only the connection being accepted failed
+    Connection,
+    /// the process or the system ran out of resources, such as file descriptors,
+    /// which free up over time
+    Resources,
+    /// the listener itself is broken
+    Fatal,
+}
+
+fn severity(e: &std::io::Error) -> Severity {
+    match e.kind() {
+        ErrorKind::ConnectionAborted
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionRefused
+        | ErrorKind::Interrupted
+        | ErrorKind::WouldBlock
+        | ErrorKind::TimedOut
+        | ErrorKind::PermissionDenied
+        | ErrorKind::HostUnreachable
+        | ErrorKind::NetworkUnreachable
+        | ErrorKind::NetworkDown => return Severity::Connection,
+        ErrorKind::OutOfMemory => return Severity::Resources,
+        _ => (),
+    }
+    #[cfg(unix)]
+    {
+        use rustix::io::Errno;
+        match Errno::from_io_error(e) {
+            // linux reports network errors pending on the new socket through accept
+            Some(Errno::PROTO | Errno::NOPROTOOPT | Errno::OPNOTSUPP) => Severity::Connection,
+            Some(Errno::MFILE | Errno::NFILE | Errno::NOBUFS | Errno::NOMEM) => Severity::Resources,
+            _ => Severity::Fatal,
+        }
+    }
+    #[cfg(windows)]
+    {
+        // WSAEMFILE and WSAENOBUFS
+        match e.raw_os_error() {
+            Some(10024 | 10055) => Severity::Resources,
+            _ => Severity::Fatal,
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    Severity::Fatal
+}
+
+/// Keeps providers accepting through transient errors,
+/// and tells `closed` about the ones that broke the listener
+pub(crate) struct AcceptErrors {
+    last_warning: Mutex<Option<Instant>>,
+    fatal: watch::Sender<Option<(ErrorKind, String)>>,
+}
+
+impl Default for AcceptErrors {
+    #[inline]
+    fn default() -> Self {
+        AcceptErrors {
+            last_warning: Mutex::new(None),
+            fatal: watch::channel(None).0,
+        }
+    }
+}
+
+impl AcceptErrors {
+    /// Handle an error returned by `accept`. Returns once the provider can accept again
+    /// if the error is transient, or the error if it broke the listener
+    pub(crate) async fn handle(&self, e: std::io::Error) -> Result<()> {
+        match severity(&e) {
+            Severity::Connection => {
+                tracing::debug!("accepting a connection failed: {}", e);
+                Ok(())
+            }
+            Severity::Resources => {
+                self.warn(&e);
+                crate::runtime::sleep(RESOURCES_BACKOFF).await;
+                Ok(())
+            }
+            Severity::Fatal => {
+                tracing::error!("listener failed: {}", e);
+                self.fatal.send_replace(Some((e.kind(), e.to_string())));
+                Err(e.into())
+            }
+        }
+    }
+
+    /// warn about running out of resources, at most once every `WARNING_INTERVAL`
+    fn warn(&self, e: &std::io::Error) {
+        let mut last_warning = self.last_warning.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if last_warning.is_none_or(|last| now - last >= WARNING_INTERVAL) {
+            *last_warning = Some(now);
+            tracing::warn!("accepting a connection failed, retrying: {}", e);
+        }
+    }
+
+    /// resolves with the error that broke the listener
+    pub(crate) async fn closed(&self) -> Error {
+        let mut fatal = self.fatal.subscribe();
+        let fatal = fatal.wait_for(Option::is_some).await;
+        match fatal.as_deref() {
+            Ok(Some((kind, message))) => Error::new(std::io::Error::new(*kind, message.clone())),
+            // the sender lives as long as `self`, so the wait doesn't fail
+            _ => err!(other, "listener closed"),
+        }
+    }
+}
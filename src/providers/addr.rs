@@ -7,14 +7,16 @@ use serde::ser::SerializeSeq;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use std::fmt::Display;
+use std::future::Future;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use tracing::Instrument;
 
-#[cfg(not(target_arch = "wasm32"))]
-use super::AnyProvider;
 use super::WebSocket;
+#[cfg(not(target_arch = "wasm32"))]
+use super::{AnyProvider, ConnectOptions};
 
 cfg_if! {
     if #[cfg(not(target_arch = "wasm32"))] {
@@ -31,6 +33,7 @@ cfg_if! {
 /// let unix = "unix@mysocket.sock".parse::<Addr>()?;
 /// let insecure_tcp = "itcp@127.0.0.1:8080".parse::<Addr>()?;
 /// let insecure_unix = "iunix@mysocket.sock".parse::<Addr>()?;
+/// let abstract_unix = "unix@@my-service".parse::<Addr>()?; // linux only
 ///
 /// tcp.bind().await?; // bind all addresses to the global route
 /// unix.bind().await?;
@@ -153,33 +156,31 @@ impl<'de> Deserialize<'de> for Addr {
                             ))?;
                     use AddressType::*;
                     Ok(match addr_ty {
-                        Tcp => seq
-                            .next_element()?
-                            .and_then(|addr| Some(Addr::Tcp(addr)))
-                            .ok_or(serde::de::Error::custom(
-                                "expected SocketAddr, found nothing",
-                            ))?,
-                        InsecureTcp => seq
-                            .next_element()?
-                            .and_then(|addr| Some(Addr::InsecureTcp(addr)))
-                            .ok_or(serde::de::Error::custom(
-                                "expected SocketAddr, found nothing",
-                            ))?,
+                        Tcp => {
+                            seq.next_element()?
+                                .map(Addr::Tcp)
+                                .ok_or(serde::de::Error::custom(
+                                    "expected SocketAddr, found nothing",
+                                ))?
+                        }
+                        InsecureTcp => seq.next_element()?.map(Addr::InsecureTcp).ok_or(
+                            serde::de::Error::custom("expected SocketAddr, found nothing"),
+                        )?,
                         Unix => seq
                             .next_element()?
-                            .and_then(|addr| Some(Addr::Unix(addr)))
+                            .map(Addr::Unix)
                             .ok_or(serde::de::Error::custom("expected Path, found nothing"))?,
                         InsecureUnix => seq
                             .next_element()?
-                            .and_then(|addr| Some(Addr::InsecureUnix(addr)))
+                            .map(Addr::InsecureUnix)
                             .ok_or(serde::de::Error::custom("expected Path, found nothing"))?,
                         Wss => seq
                             .next_element()?
-                            .and_then(|addr| Some(Addr::Wss(addr)))
+                            .map(Addr::Wss)
                             .ok_or(serde::de::Error::custom("expected String, found nothing"))?,
                         InsecureWss => seq
                             .next_element()?
-                            .and_then(|addr| Some(Addr::InsecureWss(addr)))
+                            .map(Addr::InsecureWss)
                             .ok_or(serde::de::Error::custom("expected String, found nothing"))?,
                     })
                 }
@@ -189,6 +190,20 @@ impl<'de> Deserialize<'de> for Addr {
     }
 }
 
+/// Run the connection in a span carrying the address, which the span of the handshake
+/// is nested in, and log how it ended.
+/// Callers box the connection, its future nests deep enough otherwise to overflow
+/// the query depth of the compiler in futures awaiting it, such as `connect_any`
+async fn traced(addr: &Addr, connect: impl Future<Output = Result<Channel>>) -> Result<Channel> {
+    let span = tracing::debug_span!("connect", addr = %addr);
+    let result = connect.instrument(span.clone()).await;
+    span.in_scope(|| match &result {
+        Ok(_) => tracing::debug!("connected"),
+        Err(e) => tracing::debug!("connecting failed: {}", e),
+    });
+    result
+}
+
 impl Addr {
     #[inline]
     /// create a new address from a string
@@ -199,6 +214,11 @@ impl Addr {
     #[inline]
     /// connect to the address
     pub async fn connect(&self) -> Result<Channel> {
+        traced(self, Box::pin(self.open())).await
+    }
+
+    /// open a channel to the address, see `connect`
+    async fn open(&self) -> Result<Channel> {
         cfg_if! {
             if #[cfg(target_arch = "wasm32")] {
                 match self {
@@ -238,6 +258,102 @@ impl Addr {
         }
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    /// connect to the address, retrying failed attempts as set by the options
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::{Addr, ConnectOptions};
+    /// let addr: Addr = "tcp@127.0.0.1:8080".parse()?;
+    /// let chan = addr.connect_with(ConnectOptions::default().retries(3)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with(&self, options: ConnectOptions) -> Result<Channel> {
+        traced(self, Box::pin(self.open_with(options))).await
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// open a channel to the address, see `connect_with`
+    async fn open_with(&self, options: ConnectOptions) -> Result<Channel> {
+        match self {
+            Addr::Tcp(addrs) => {
+                Tcp::connect_with(addrs.as_ref(), options)
+                    .await?
+                    .encrypted()
+                    .await
+            }
+            Addr::InsecureTcp(addrs) => Ok(Tcp::connect_with(addrs.as_ref(), options).await?.raw()),
+            #[cfg(unix)]
+            Addr::Unix(addrs) => {
+                Unix::connect_with(addrs.as_ref(), options)
+                    .await?
+                    .encrypted()
+                    .await
+            }
+            #[cfg(unix)]
+            Addr::InsecureUnix(addrs) => {
+                Ok(Unix::connect_with(addrs.as_ref(), options).await?.raw())
+            }
+            Addr::Wss(addrs) => {
+                WebSocket::connect_with(addrs.as_str(), options)
+                    .await?
+                    .encrypted()
+                    .await
+            }
+            Addr::InsecureWss(addrs) => Ok(WebSocket::connect_with(addrs.as_str(), options)
+                .await?
+                .raw()),
+            #[cfg(not(unix))]
+            Addr::Unix(_) | Addr::InsecureUnix(_) => err!((
+                unsupported,
+                "connecting to unix providers is not supported on non-unix platforms"
+            )),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Connect to the first address that works, trying them in order,
+    /// and return it along with the channel. Useful for a primary and its failovers.
+    ///
+    /// Each address gets a single attempt, which includes the handshake of encrypted
+    /// addresses and is bounded by the timeout of the options, before the next one is tried.
+    /// Once every address failed, the whole list is retried as set by the options
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::{Addr, ConnectOptions};
+    /// let addrs: Vec<Addr> = vec![
+    ///     "tcp@10.0.0.1:8080".parse()?,
+    ///     "tcp@10.0.0.2:8080".parse()?,
+    /// ];
+    /// let (addr, chan) = Addr::connect_any(&addrs, ConnectOptions::default()).await?;
+    /// println!("connected to {}", addr);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_any(addrs: &[Addr], options: ConnectOptions) -> Result<(Addr, Channel)> {
+        let once = options.clone().retries(0).timeout(None);
+        options
+            .clone()
+            .timeout(None)
+            .retry(&addrs, || async {
+                let mut last_err = None;
+                for addr in addrs {
+                    match options.attempt(addr.connect_with(once.clone())).await {
+                        Ok(chan) => return Ok((addr.clone(), chan)),
+                        Err(e) => {
+                            tracing::debug!("connecting to `{}` failed: {}", addr, e);
+                            last_err = Some(e);
+                        }
+                    }
+                }
+                match last_err {
+                    Some(e) => Err(e),
+                    None => err!((invalid_input, "no address to connect to")),
+                }
+            })
+            .await
+    }
+
     #[inline]
     #[cfg(not(target_arch = "wasm32"))]
     /// connect to the address
@@ -276,9 +392,10 @@ impl FromStr for Addr {
     /// tcp@127.0.0.1:8092
     /// tcp@127.0.0.1:8092
     /// unix@folder/address.sock
+    /// unix@@abstract-address
     fn from_str(addr: &str) -> Result<Self> {
         let (protocol, addr) = addr
-            .rsplit_once('@')
+            .split_once('@')
             .ok_or(err!(invalid_input, "malformed address"))?;
         let address_ty = protocol.parse::<AddressType>()?;
         Ok(match address_ty {
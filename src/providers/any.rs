@@ -1,6 +1,7 @@
 use std::future::Future;
 use std::pin::Pin;
 
+use futures::future::{select_all, BoxFuture};
 use futures::StreamExt;
 use futures::{pin_mut, select, stream::FuturesUnordered, FutureExt};
 
@@ -10,9 +11,9 @@ use super::Tcp;
 use super::Unix;
 use crate::channel::handshake::Handshake;
 use crate::Channel;
-use crate::Result;
+use crate::{err, Error, Result};
 
-use super::WebSocket;
+use super::{Addr, WebSocket};
 
 /// abstraction over any provider
 pub enum AnyProvider {
@@ -32,9 +33,59 @@ pub enum AnyProvider {
     Wss(WebSocket),
     /// encapsulates the websocket provider without any encryption
     InsecureWss(WebSocket),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// encapsulates several providers, channels come from whichever accepts one first.
+    /// Useful to serve the same thing over several transports at once,
+    /// dropping it closes every listener
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// use canary::providers::{Addr, AnyProvider};
+    ///
+    /// let tcp: Addr = "tcp@127.0.0.1:8080".parse()?;
+    /// let wss: Addr = "wss@127.0.0.1:8081".parse()?;
+    /// let provider = AnyProvider::Many(vec![tcp.bind().await?, wss.bind().await?]);
+    /// let mut channels = provider.channels();
+    /// while let Ok(mut chan) = channels.next().await {
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    Many(Vec<AnyProvider>),
 }
 
 impl AnyProvider {
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Bind to every address and accept channels from all of them,
+    /// see `AnyProvider::Many`. Fails if any of them can't be bound,
+    /// in which case the listeners already bound are closed.
+    ///
+    /// On most systems a listener on `[::]` accepts IPv4 connections too,
+    /// so dual-stack serving only needs that address.
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// use canary::providers::{Addr, AnyProvider};
+    ///
+    /// let provider = AnyProvider::bind_all([
+    ///     "tcp@[::]:8080".parse::<Addr>()?,
+    ///     "unix@/tmp/service.sock".parse()?,
+    /// ])
+    /// .await?;
+    /// let mut channels = provider.channels();
+    /// while let Ok(mut chan) = channels.next().await {
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind_all(addrs: impl IntoIterator<Item = Addr>) -> Result<AnyProvider> {
+        let mut providers = vec![];
+        for addr in addrs {
+            providers.push(addr.bind().await?);
+        }
+        Ok(AnyProvider::Many(providers))
+    }
+
     #[inline]
     #[cfg(not(target_arch = "wasm32"))]
     /// get the next handshake
@@ -56,12 +107,32 @@ impl AnyProvider {
             AnyProvider::InsecureUnix(provider) => provider.next().await,
             AnyProvider::Wss(provider) => provider.next().await,
             AnyProvider::InsecureWss(provider) => provider.next().await,
+            AnyProvider::Many(_) => Ok(self.accept().await?.0),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// get the next handshake along with the encryption of the provider it came from
+    fn accept(&self) -> BoxFuture<'_, Result<(Handshake, bool)>> {
+        match self {
+            AnyProvider::Many(providers) if providers.is_empty() => {
+                Box::pin(async { err!((invalid_input, "no providers to accept channels from")) })
+            }
+            // the handshakes of the providers are cancel-safe, so the ones that lose are dropped
+            AnyProvider::Many(providers) => {
+                Box::pin(async move { select_all(providers.iter().map(Self::accept)).await.0 })
+            }
+            provider => Box::pin(async move {
+                let hs = provider.next_handshake().await?;
+                Ok((hs, provider.encrypted()))
+            }),
         }
     }
 
     #[inline]
     #[cfg(not(target_arch = "wasm32"))]
-    /// get the encryption of the provider
+    /// get the encryption of the provider,
+    /// several providers are encrypted only if all of them are
     pub fn encrypted(&self) -> bool {
         match self {
             AnyProvider::Tcp(_) => true,
@@ -72,6 +143,31 @@ impl AnyProvider {
             AnyProvider::InsecureUnix(_) => false,
             AnyProvider::Wss(_) => true,
             AnyProvider::InsecureWss(_) => false,
+            AnyProvider::Many(providers) => providers.iter().all(AnyProvider::encrypted),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Resolves once a listener is broken and can't accept anymore, with the error that broke it.
+    /// Several providers resolve as soon as one of them does, while the others keep accepting
+    pub fn closed(&self) -> BoxFuture<'_, Error> {
+        match self {
+            AnyProvider::Tcp(provider) | AnyProvider::InsecureTcp(provider) => {
+                Box::pin(provider.closed())
+            }
+            #[cfg(unix)]
+            AnyProvider::Unix(provider) | AnyProvider::InsecureUnix(provider) => {
+                Box::pin(provider.closed())
+            }
+            AnyProvider::Wss(provider) | AnyProvider::InsecureWss(provider) => {
+                Box::pin(provider.closed())
+            }
+            AnyProvider::Many(providers) if providers.is_empty() => {
+                Box::pin(async { err!(invalid_input, "no providers to accept channels from") })
+            }
+            AnyProvider::Many(providers) => {
+                Box::pin(async move { select_all(providers.iter().map(Self::closed)).await.0 })
+            }
         }
     }
 
@@ -103,20 +199,16 @@ pub struct ChannelIter {
 impl ChannelIter {
     /// get the next channel from the provider
     pub async fn next(&mut self) -> Result<Channel> {
-        let hs = self.listener.next_handshake().fuse();
+        let hs = self.listener.accept().fuse();
         pin_mut!(hs);
 
         loop {
             let chan = select! {
-                chan = self.futures.next().fuse() => {
-                    match chan {
-                        Some(chan) => chan,
-                        None => continue,
-                    }
-                },
+                // skipped while no handshake is pending instead of yielding `None` in a loop
+                chan = self.futures.select_next_some() => chan,
                 res = hs => {
-                    let hs: Handshake = res?;
-                    if self.listener.encrypted() {
+                    let (hs, encrypted) = res?;
+                    if encrypted {
                         let fut = hs.encrypted();
                         self.futures.push(Box::pin(fut));
                         continue;
@@ -0,0 +1,189 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use rand::Rng;
+
+use super::proxy::Proxy;
+use crate::{err, Error, Result};
+
+#[derive(Clone, Copy, Debug)]
+/// Delay between two connection attempts
+pub enum Backoff {
+    /// wait the same time after every failed attempt
+    Constant(Duration),
+    /// wait `base` after the first failed attempt and double it after every other one,
+    /// up to `max`
+    Exponential {
+        /// delay after the first failed attempt
+        base: Duration,
+        /// longest delay between two attempts
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// delay after the failed attempt number `attempt`, starting at 1
+    fn delay(&self, attempt: u32) -> Duration {
+        match *self {
+            Backoff::Constant(delay) => delay,
+            Backoff::Exponential { base, max } => {
+                let factor = 1u32.checked_shl(attempt - 1).unwrap_or(u32::MAX);
+                base.saturating_mul(factor).min(max)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// How clients connect to a provider: how long an attempt may take,
+/// how many times it is retried and how long to wait in between.
+///
+/// By default an attempt times out after 10 seconds and is retried 5 times,
+/// waiting 100 milliseconds after the first failure and doubling the wait up to 10 seconds,
+/// with jitter.
+///
+/// Dropping the connect future at any point closes the socket of the attempt in flight,
+/// and nothing is left behind while waiting between attempts.
+/// ```no_run
+/// # async fn example() -> canary::Result<()> {
+/// # use canary::providers::{Backoff, ConnectOptions, Tcp};
+/// # use std::time::Duration;
+/// let options = ConnectOptions::default()
+///     .timeout(Some(Duration::from_secs(3)))
+///     .retries(10)
+///     .backoff(Backoff::Exponential {
+///         base: Duration::from_millis(50),
+///         max: Duration::from_secs(5),
+///     });
+/// let chan = Tcp::connect_with("127.0.0.1:8080", options).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ConnectOptions {
+    timeout: Option<Duration>,
+    retries: u32,
+    backoff: Backoff,
+    jitter: bool,
+    proxy: Option<Proxy>,
+}
+
+impl Default for ConnectOptions {
+    #[inline]
+    fn default() -> Self {
+        ConnectOptions {
+            timeout: Some(Duration::from_secs(10)),
+            retries: 5,
+            backoff: Backoff::Exponential {
+                base: Duration::from_millis(100),
+                max: Duration::from_secs(10),
+            },
+            jitter: true,
+            proxy: None,
+        }
+    }
+}
+
+impl ConnectOptions {
+    #[inline]
+    /// fail an attempt that takes longer than `timeout`, attempts never time out if `None`
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    #[inline]
+    /// retry a failed connection `retries` times, so it is attempted `retries + 1` times
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+    #[inline]
+    /// set how long to wait between attempts
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+    #[inline]
+    /// wait a random time between half of the backoff and the whole backoff,
+    /// so clients that failed together don't retry together
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+    #[inline]
+    /// Connect through the proxy, see `Proxy`.
+    /// Applies to TCP and websocket connections, unix sockets are local and connect directly
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    #[inline]
+    /// proxy connections go through, if any
+    pub(super) fn proxied(&self) -> Option<&Proxy> {
+        self.proxy.as_ref()
+    }
+
+    /// delay after the failed attempt number `attempt`, starting at 1
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.backoff.delay(attempt);
+        if self.jitter {
+            delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+        } else {
+            delay
+        }
+    }
+
+    /// run a single attempt, failing it if it takes longer than the timeout
+    pub(crate) async fn attempt<T>(&self, connect: impl Future<Output = Result<T>>) -> Result<T> {
+        match self.timeout {
+            Some(timeout) => crate::runtime::timeout(timeout, connect)
+                .await
+                .unwrap_or_else(|_| {
+                    err!((timeout, format!("attempt timed out after {:?}", timeout)))
+                }),
+            None => connect.await,
+        }
+    }
+
+    /// Run `connect` until it succeeds or every attempt failed.
+    /// Errors retrying can't fix, `Unsupported`, `InvalidInput` and `PermissionDenied`
+    /// such as rejected proxy credentials, are returned right away
+    pub(crate) async fn retry<A, T, F, Fut>(&self, addrs: &A, mut connect: F) -> Result<T>
+    where
+        A: Debug + ?Sized,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let e = match self.attempt(connect()).await {
+                Ok(connected) => return Ok(connected),
+                Err(e) => e,
+            };
+            let permanent = matches!(
+                e.kind(),
+                ErrorKind::Unsupported | ErrorKind::InvalidInput | ErrorKind::PermissionDenied
+            );
+            if permanent || attempt > self.retries {
+                let message = format!(
+                    "connecting to `{:?}` failed after {} attempts: {}",
+                    addrs, attempt, e
+                );
+                return Err(Error::new(std::io::Error::new(e.kind(), message)));
+            }
+            tracing::debug!(
+                "connecting to `{:?}` failed, attempt {} of {}: {}",
+                addrs,
+                attempt,
+                self.retries + 1,
+                e
+            );
+            crate::runtime::sleep(self.delay(attempt)).await;
+        }
+    }
+}
@@ -0,0 +1,172 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::channel::handshake::Handshake;
+use crate::err;
+use crate::io::{duplex, DuplexStream};
+use crate::Channel;
+use crate::Result;
+
+/// size of the in-process buffer of each direction of a memory channel
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// providers currently bound, by name
+static LISTENERS: Mutex<BTreeMap<String, UnboundedSender<DuplexStream>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Exposes routes in-process, without any sockets.
+/// Useful for tests, since channels go through the same framing as over the network
+/// and snow is optional.
+pub struct Memory {
+    name: String,
+    listener: tokio::sync::Mutex<UnboundedReceiver<DuplexStream>>,
+}
+
+impl Memory {
+    #[inline]
+    /// Bind to this name. Fails if another provider is bound to it in this process
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// use canary::providers::Memory;
+    ///
+    /// let memory = Memory::bind("my-service").await?;
+    /// while let Ok(chan) = memory.next().await {
+    ///     let mut chan = chan.raw();
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind(name: impl Into<String>) -> Result<Self> {
+        let name = name.into();
+        let mut listeners = LISTENERS.lock().unwrap_or_else(|e| e.into_inner());
+        if listeners.contains_key(&name) {
+            err!((in_use, format!("memory address `{}` already in use", name)))?
+        }
+        let (sender, receiver) = unbounded_channel();
+        listeners.insert(name.clone(), sender);
+        Ok(Memory {
+            name,
+            listener: tokio::sync::Mutex::new(receiver),
+        })
+    }
+    #[inline]
+    /// get the next channel
+    /// ```no_run
+    /// # async fn example(memory: canary::providers::Memory) -> canary::Result<()> {
+    /// while let Ok(chan) = memory.next().await {
+    ///     let mut chan = chan.encrypted().await?;
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn next(&self) -> Result<Handshake> {
+        crate::shutdown::accepting(self.accept()).await
+    }
+
+    /// accept the next channel, see `next`
+    async fn accept(&self) -> Result<Handshake> {
+        let raw = self.listener.lock().await.recv().await;
+        // the sender lives in the registry until this provider is dropped
+        let raw = raw.ok_or_else(|| err!(not_connected, "memory provider unbound"))?;
+        Ok(Handshake::from(Channel::from_raw(
+            raw,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+    #[inline]
+    /// connect to the provider bound to this name
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// use canary::providers::Memory;
+    ///
+    /// let mut chan = Memory::connect("my-service").await?.raw();
+    /// let greeting: String = chan.receive().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect(name: &str) -> Result<Handshake> {
+        Self::connect_with_capacity(name, BUFFER_SIZE).await
+    }
+    /// Connect to the provider bound to this name, buffering at most `capacity` bytes
+    /// in each direction. Sends wait while the buffer is full, so a small capacity
+    /// reproduces slow consumers. The capacity is at least one byte
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// use canary::providers::Memory;
+    ///
+    /// let mut chan = Memory::connect_with_capacity("my-service", 16).await?.raw();
+    /// // waits until the provider reads the first bytes
+    /// chan.send("a message longer than sixteen bytes").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with_capacity(name: &str, capacity: usize) -> Result<Handshake> {
+        let sender = LISTENERS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(name)
+            .cloned();
+        let sender = sender.ok_or_else(|| {
+            err!(
+                conn_refused,
+                format!("no memory provider bound to `{}`", name)
+            )
+        })?;
+        let (client, server) = duplex(capacity.max(1));
+        sender
+            .send(server)
+            .map_err(|_| err!(conn_refused, "memory provider unbound"))?;
+        Ok(Handshake::from(Channel::from_raw(
+            client,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+    #[inline]
+    #[must_use]
+    /// Two channels connected to each other, without binding a name.
+    /// They go through the same framing as over the network,
+    /// so the handshakes of one side have to be matched by the other
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// use canary::providers::Memory;
+    ///
+    /// let (a, b) = Memory::pair();
+    /// let (a, b) = tokio::try_join!(a.encrypted(), b.encrypted())?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn pair() -> (Handshake, Handshake) {
+        Self::pair_with_capacity(BUFFER_SIZE)
+    }
+    #[must_use]
+    /// Two channels connected to each other, buffering at most `capacity` bytes
+    /// in each direction, see `Memory::connect_with_capacity`
+    pub fn pair_with_capacity(capacity: usize) -> (Handshake, Handshake) {
+        let (a, b) = duplex(capacity.max(1));
+        let a = Channel::from_raw(a, Default::default(), Default::default());
+        let b = Channel::from_raw(b, Default::default(), Default::default());
+        (Handshake::from(a), Handshake::from(b))
+    }
+    #[inline]
+    /// name this provider is bound to
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        LISTENERS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.name);
+    }
+}
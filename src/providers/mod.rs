@@ -1,7 +1,16 @@
+pub(crate) mod accept;
 pub(crate) mod addr;
 #[cfg(not(target_arch = "wasm32"))]
 mod any;
+mod connect;
+mod memory;
+mod policy;
+mod pool;
+mod proxy;
+mod quic;
+pub(crate) mod rate_limit;
 mod tcp;
+mod tls;
 mod unix;
 mod wss;
 
@@ -11,8 +20,42 @@ pub use wss::*;
 #[cfg(not(target_arch = "wasm32"))]
 pub use any::*;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use connect::{Backoff, ConnectOptions};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use memory::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use policy::{AcceptFilter, AcceptPolicy, AcceptStats, Cidr, Verdict};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use pool::{Pool, PoolConfig, PoolMetrics, PoolStats, PooledChannel};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use proxy::{Destination, Proxy, ProxyAuth};
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+pub use quic::{Quic, QuicConnection};
+#[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+/// quinn, to build the configs of the quic provider
+pub use quinn;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use rate_limit::{Overflow, RateLimit};
+
 #[cfg(not(target_arch = "wasm32"))]
 pub use tcp::*;
 
+#[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+pub use tls::{ClientTlsConfig, ServerTlsConfig, Tls};
+#[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+/// rustls, to build the certificates and roots of the tls configs
+pub use tokio_rustls::rustls;
+
 #[cfg(unix)]
 pub use unix::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+/// tungstenite, to read and answer the upgrade requests of websockets
+pub use async_tungstenite::tungstenite;
@@ -0,0 +1,237 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::fmt::{self, Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::{err, Error, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Decision of an `AcceptPolicy` filter on a connection
+pub enum Verdict {
+    /// let the connection through
+    Accept,
+    /// close the connection before anything is read from it
+    Reject,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// Range of IP addresses in CIDR notation, such as `10.0.0.0/8` or `2001:db8::/32`.
+/// An address without a prefix length covers only itself
+/// ```no_run
+/// # use canary::providers::Cidr;
+/// let cidr: Cidr = "192.168.0.0/16".parse()?;
+/// assert!(cidr.contains("192.168.1.7".parse()?));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Range of the addresses sharing the first `prefix` bits with `addr`.
+    /// Fails if the prefix is longer than the address
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix > max {
+            err!((
+                invalid_input,
+                format!("prefix of `{}` is {} bits at most", addr, max)
+            ))?
+        }
+        Ok(Cidr { addr, prefix })
+    }
+
+    /// Whether the address is in the range.
+    /// IPv4 addresses mapped to IPv6 are matched as IPv4 addresses
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => same_prefix(
+                u32::from(net).into(),
+                u32::from(addr).into(),
+                32,
+                self.prefix,
+            ),
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                same_prefix(net.into(), addr.into(), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// whether the first `prefix` of the `bits` low bits of both addresses are the same
+fn same_prefix(net: u128, addr: u128, bits: u8, prefix: u8) -> bool {
+    let shift = u32::from(bits - prefix);
+    net.checked_shr(shift).unwrap_or(0) == addr.checked_shr(shift).unwrap_or(0)
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || err!(invalid_input, format!("`{}` is not a valid CIDR range", s));
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        Cidr::new(addr, prefix)
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Filter called with the address of every connection, see `AcceptPolicy::filter`
+pub type AcceptFilter = Arc<dyn Fn(&SocketAddr) -> Verdict + Send + Sync>;
+
+#[derive(Clone, Default)]
+/// Rules deciding which peers a provider accepts connections from.
+///
+/// They run right after the connection is accepted, before anything is read from it
+/// or any handshake starts. Rejected connections are closed at once:
+/// - a peer in a denied range is rejected
+/// - if any range is allowed, a peer outside all of them is rejected
+/// - the filter, if set, decides on the peers left
+///
+/// The default policy accepts everyone
+/// ```no_run
+/// # async fn example() -> canary::Result<()> {
+/// use canary::providers::{AcceptPolicy, Tcp, Verdict};
+///
+/// let policy = AcceptPolicy::default()
+///     .allow("10.0.0.0/8".parse()?)
+///     .deny("10.13.0.0/16".parse()?)
+///     .filter(|addr| match addr.port() {
+///         0..=1023 => Verdict::Reject,
+///         _ => Verdict::Accept,
+///     });
+/// let tcp = Tcp::bind_with("0.0.0.0:8080", policy).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AcceptPolicy {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+    filter: Option<AcceptFilter>,
+}
+
+impl AcceptPolicy {
+    #[inline]
+    /// only accept peers in the given ranges, along with the ranges allowed before
+    pub fn allow(mut self, range: Cidr) -> Self {
+        self.allow.push(range);
+        self
+    }
+    #[inline]
+    /// reject peers in the range, even if they are in an allowed one
+    pub fn deny(mut self, range: Cidr) -> Self {
+        self.deny.push(range);
+        self
+    }
+    #[inline]
+    /// Call `filter` with the address of every peer the ranges let through.
+    /// It runs on the task accepting connections, so it has to return quickly
+    pub fn filter(
+        mut self,
+        filter: impl Fn(&SocketAddr) -> Verdict + Send + Sync + 'static,
+    ) -> Self {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// decide on a connection from `addr`
+    pub fn check(&self, addr: &SocketAddr) -> Verdict {
+        let ip = addr.ip();
+        if self.deny.iter().any(|range| range.contains(ip)) {
+            return Verdict::Reject;
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|range| range.contains(ip)) {
+            return Verdict::Reject;
+        }
+        match &self.filter {
+            Some(filter) => filter(addr),
+            None => Verdict::Accept,
+        }
+    }
+}
+
+impl fmt::Debug for AcceptPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AcceptPolicy")
+            .field("allow", &self.allow)
+            .field("deny", &self.deny)
+            .field("filter", &self.filter.is_some())
+            .finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+/// Connections seen by a provider since it was bound
+pub struct AcceptStats {
+    /// connections handed out by `next`
+    pub accepted: u64,
+    /// connections closed because the accept policy rejected them
+    pub rejected: u64,
+    /// connections dropped because the peer went over its rate limit
+    pub rate_limited: u64,
+}
+
+/// Accept policy of a provider, swappable while it accepts, along with its counters
+#[derive(Default)]
+pub(crate) struct Gate {
+    policy: Mutex<Arc<AcceptPolicy>>,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+    rate_limited: AtomicU64,
+}
+
+impl Gate {
+    pub(crate) fn set_policy(&self, policy: AcceptPolicy) {
+        *self.policy.lock().unwrap_or_else(|e| e.into_inner()) = Arc::new(policy);
+    }
+    /// Decide on a connection from `addr` and count it if rejected.
+    /// The filter runs without the lock held, so it can swap the policy itself
+    pub(crate) fn check(&self, addr: &SocketAddr) -> Verdict {
+        let policy = self
+            .policy
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let verdict = policy.check(addr);
+        if verdict == Verdict::Reject {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+        verdict
+    }
+    #[inline]
+    pub(crate) fn count_accepted(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+    #[inline]
+    pub(crate) fn count_rate_limited(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn stats(&self) -> AcceptStats {
+        AcceptStats {
+            accepted: self.accepted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,422 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::{Addr, ConnectOptions};
+use crate::channel::{frame, remote::RemoteError};
+use crate::{err, io, Channel, Result};
+
+/// time an idle channel has to answer the ping sent on checkout
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug)]
+/// Sizes of the pool of an address, given to the metrics hook on every checkout
+pub struct PoolStats {
+    /// channels open to the address, idle or checked out, including the one checked out
+    pub open: usize,
+    /// idle channels left in the pool of the address
+    pub idle: usize,
+    /// time the checkout waited for a channel to the address to be returned
+    pub wait: Duration,
+    /// whether the checked out channel was reused instead of connected
+    pub reused: bool,
+}
+
+/// Hook called with the address and the sizes of its pool on every checkout,
+/// see `PoolConfig::metrics`
+pub type PoolMetrics = Arc<dyn Fn(&Addr, PoolStats) + Send + Sync>;
+
+#[derive(Clone)]
+/// Configuration of a `Pool`.
+///
+/// By default a pool keeps up to 8 channels per address, closes channels idle
+/// for 90 seconds, never closes channels for being too old, and pings channels
+/// idle for more than 30 seconds before handing them out again
+/// ```no_run
+/// # async fn example() -> canary::Result<()> {
+/// use canary::providers::{Pool, PoolConfig};
+/// use std::time::Duration;
+///
+/// let pool = Pool::new(
+///     PoolConfig::default()
+///         .max_per_addr(16)
+///         .idle_timeout(Some(Duration::from_secs(30)))
+///         .max_lifetime(Some(Duration::from_secs(600))),
+/// );
+/// # Ok(())
+/// # }
+/// ```
+pub struct PoolConfig {
+    max_per_addr: usize,
+    idle_timeout: Option<Duration>,
+    max_lifetime: Option<Duration>,
+    ping_after: Duration,
+    connect: ConnectOptions,
+    metrics: Option<PoolMetrics>,
+}
+
+impl Default for PoolConfig {
+    #[inline]
+    fn default() -> Self {
+        PoolConfig {
+            max_per_addr: 8,
+            idle_timeout: Some(Duration::from_secs(90)),
+            max_lifetime: None,
+            ping_after: Duration::from_secs(30),
+            connect: ConnectOptions::default(),
+            metrics: None,
+        }
+    }
+}
+
+impl PoolConfig {
+    #[inline]
+    /// Open at most `max` channels to a single address, checkouts over the limit
+    /// wait for a channel to be returned. At least 1
+    pub fn max_per_addr(mut self, max: usize) -> Self {
+        self.max_per_addr = max.max(1);
+        self
+    }
+    #[inline]
+    /// close channels left idle for longer than `timeout`, `None` keeps them forever
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+    #[inline]
+    /// close channels once they have been open for longer than `lifetime`,
+    /// so connections move to new servers behind the address. `None` never closes them
+    pub fn max_lifetime(mut self, lifetime: Option<Duration>) -> Self {
+        self.max_lifetime = lifetime;
+        self
+    }
+    #[inline]
+    /// ping channels idle for longer than `after` before handing them out,
+    /// so a connection that died while idle is replaced instead of failing the caller
+    pub fn ping_after(mut self, after: Duration) -> Self {
+        self.ping_after = after;
+        self
+    }
+    #[inline]
+    /// set how new channels are connected, see `ConnectOptions`
+    pub fn connect(mut self, options: ConnectOptions) -> Self {
+        self.connect = options;
+        self
+    }
+    #[inline]
+    /// call `metrics` with the address and the sizes of its pool on every checkout
+    /// ```no_run
+    /// # use canary::providers::PoolConfig;
+    /// let config = PoolConfig::default().metrics(|addr, stats| {
+    ///     tracing::info!("{} open to {}, waited {:?}", stats.open, addr, stats.wait);
+    /// });
+    /// ```
+    pub fn metrics(mut self, metrics: impl Fn(&Addr, PoolStats) + Send + Sync + 'static) -> Self {
+        self.metrics = Some(Arc::new(metrics));
+        self
+    }
+}
+
+/// channel waiting in the pool to be checked out again
+struct Idle {
+    chan: Channel,
+    opened: Instant,
+    since: Instant,
+}
+
+/// channels of a single address
+struct Host {
+    /// one permit per channel that can be checked out, idle channels don't hold any
+    permits: Arc<Semaphore>,
+    /// idle channels, the most recently returned last
+    idle: Vec<Idle>,
+}
+
+struct Shared {
+    config: PoolConfig,
+    hosts: Mutex<HashMap<Addr, Host>>,
+}
+
+impl Shared {
+    fn hosts(&self) -> std::sync::MutexGuard<'_, HashMap<Addr, Host>> {
+        self.hosts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    /// whether a channel opened at `opened` is too old to be handed out again
+    fn expired(&self, opened: Instant) -> bool {
+        self.config
+            .max_lifetime
+            .is_some_and(|lifetime| opened.elapsed() >= lifetime)
+    }
+}
+
+#[derive(Clone)]
+/// Pool of channels keyed by address, so clients talking to the same providers
+/// over and over don't pay for a connection and a handshake every time.
+///
+/// `get` hands out an idle channel to the address if there is one,
+/// or connects a new one as long as the address has less than `max_per_addr` channels.
+///
+/// A checked out channel is used by a single caller at a time, and goes back to the pool
+/// when dropped only if its last exchange completed: every request sent with
+/// `PooledChannel::send` was answered, and no send or receive failed or was dropped midway.
+/// Otherwise the next caller could receive the reply meant for the previous one,
+/// so the channel is closed instead. Clones share the same channels
+/// ```no_run
+/// # async fn example() -> canary::Result<()> {
+/// use canary::providers::{Addr, Pool, PoolConfig};
+///
+/// let pool = Pool::new(PoolConfig::default());
+/// let addr: Addr = "tcp@127.0.0.1:8080".parse()?;
+/// for _ in 0..3 {
+///     let mut chan = pool.get(&addr).await?;
+///     chan.send("hello!").await?;
+///     let reply: String = chan.receive().await?;
+///     // `chan` goes back to the pool here, and is reused by the next iteration
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Pool {
+    shared: Arc<Shared>,
+}
+
+impl Pool {
+    #[inline]
+    /// create an empty pool
+    pub fn new(config: PoolConfig) -> Self {
+        Pool {
+            shared: Arc::new(Shared {
+                config,
+                hosts: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Check out a channel to the address, reusing an idle one if possible.
+    /// Waits if the address already has `max_per_addr` channels checked out
+    pub async fn get(&self, addr: &Addr) -> Result<PooledChannel> {
+        let start = Instant::now();
+        let permits = {
+            let mut hosts = self.shared.hosts();
+            let max = self.shared.config.max_per_addr;
+            let host = hosts.entry(addr.clone()).or_insert_with(|| Host {
+                permits: Arc::new(Semaphore::new(max)),
+                idle: vec![],
+            });
+            host.permits.clone()
+        };
+        // the semaphore is never closed
+        let permit = permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| err!(other, e.to_string()))?;
+        let wait = start.elapsed();
+
+        let (chan, opened, reused) = loop {
+            match self.checkout_idle(addr) {
+                Some(mut idle) => {
+                    if idle.since.elapsed() >= self.shared.config.ping_after {
+                        let ping = crate::runtime::timeout(PING_TIMEOUT, idle.chan.ping()).await;
+                        if !matches!(ping, Ok(Ok(()))) {
+                            tracing::debug!("dropping pooled channel to `{}`, ping failed", addr);
+                            continue;
+                        }
+                    }
+                    break (idle.chan, idle.opened, true);
+                }
+                None => {
+                    let chan = addr
+                        .connect_with(self.shared.config.connect.clone())
+                        .await?;
+                    break (chan, Instant::now(), false);
+                }
+            }
+        };
+
+        if let Some(metrics) = &self.shared.config.metrics {
+            let idle = self
+                .shared
+                .hosts()
+                .get(addr)
+                .map_or(0, |host| host.idle.len());
+            let stats = PoolStats {
+                open: permits_used(&permits, self.shared.config.max_per_addr) + idle,
+                idle,
+                wait,
+                reused,
+            };
+            metrics(addr, stats);
+        }
+        Ok(PooledChannel {
+            chan: Some(chan),
+            addr: addr.clone(),
+            opened,
+            shared: self.shared.clone(),
+            poisoned: false,
+            unanswered: 0,
+            _permit: permit,
+        })
+    }
+
+    /// take the most recently returned idle channel that is still usable,
+    /// closing the ones that idled or lived for too long on the way
+    fn checkout_idle(&self, addr: &Addr) -> Option<Idle> {
+        let mut hosts = self.shared.hosts();
+        let host = hosts.get_mut(addr)?;
+        while let Some(idle) = host.idle.pop() {
+            let idled = self
+                .shared
+                .config
+                .idle_timeout
+                .is_some_and(|timeout| idle.since.elapsed() >= timeout);
+            if idled || self.shared.expired(idle.opened) {
+                close_detached(idle.chan);
+                continue;
+            }
+            return Some(idle);
+        }
+        None
+    }
+
+    /// Close every idle channel, checked out channels still go back to the pool
+    /// when dropped. Useful after the providers behind the addresses restarted
+    pub fn clear(&self) {
+        for host in self.shared.hosts().values_mut() {
+            for idle in host.idle.drain(..) {
+                close_detached(idle.chan);
+            }
+        }
+    }
+}
+
+/// channels checked out of a semaphore of `max` permits
+fn permits_used(permits: &Semaphore, max: usize) -> usize {
+    max.saturating_sub(permits.available_permits())
+}
+
+/// close a channel in the background, nobody is left to report a failed close to
+fn close_detached(chan: Channel) {
+    io::spawn_detached(async move {
+        chan.close().await.ok();
+    });
+}
+
+/// Channel checked out of a `Pool`, returned to it when dropped
+/// if neither side closed it and its last exchange completed.
+///
+/// `send`, `try_receive` and `receive` keep track of the exchanges, every reply received
+/// answering a request sent before it. Any other use that needs the channel mutably,
+/// such as `send_result` or `split`, goes through `DerefMut` and keeps the channel
+/// out of the pool, since the pool can't tell whether it was left in sync
+pub struct PooledChannel {
+    chan: Option<Channel>,
+    addr: Addr,
+    opened: Instant,
+    shared: Arc<Shared>,
+    /// set while a send or receive is running, and left set if it failed or was dropped
+    poisoned: bool,
+    /// requests sent whose reply wasn't received yet
+    unanswered: usize,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledChannel {
+    #[inline]
+    /// address the channel is connected to
+    pub fn addr(&self) -> &Addr {
+        &self.addr
+    }
+    #[inline]
+    /// Drop the channel instead of returning it to the pool
+    pub fn discard(mut self) {
+        self.chan.take();
+    }
+    /// Send a request through the channel, which has to be answered
+    /// before the channel can go back to the pool
+    pub async fn send<T: Serialize>(&mut self, obj: T) -> Result<usize> {
+        self.poisoned = true;
+        let sent = self.chan().send(obj).await?;
+        self.poisoned = false;
+        self.unanswered += 1;
+        Ok(sent)
+    }
+    /// Receive the reply to a request, returns `None` if the peer closed the channel.
+    /// An error sent by the peer answers the request like any other reply
+    pub async fn try_receive<T: DeserializeOwned>(&mut self) -> Result<Option<T>> {
+        self.poisoned = true;
+        let received = self.chan().try_receive().await;
+        let answered = match &received {
+            Ok(reply) => reply.is_some(),
+            Err(e) => RemoteError::of(e).is_some(),
+        };
+        if answered {
+            self.poisoned = false;
+            self.unanswered = self.unanswered.saturating_sub(1);
+        }
+        received
+    }
+    /// Receive the reply to a request, see `try_receive`
+    pub async fn receive<T: DeserializeOwned>(&mut self) -> Result<T> {
+        self.try_receive().await?.ok_or_else(frame::closed)
+    }
+    #[inline]
+    fn chan(&mut self) -> &mut Channel {
+        self.chan.as_mut().expect("channel is only taken on drop")
+    }
+    #[inline]
+    /// Take the channel out of the pool for good,
+    /// freeing its place for a new channel to the address
+    pub fn into_inner(mut self) -> Channel {
+        self.chan.take().expect("channel is only taken on drop")
+    }
+}
+
+impl Deref for PooledChannel {
+    type Target = Channel;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.chan.as_ref().expect("channel is only taken on drop")
+    }
+}
+
+impl DerefMut for PooledChannel {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // the pool can't follow what is done with the channel from here
+        self.poisoned = true;
+        self.chan()
+    }
+}
+
+impl Drop for PooledChannel {
+    fn drop(&mut self) {
+        let chan = match self.chan.take() {
+            Some(chan) => chan,
+            None => return,
+        };
+        // a channel dropped midway may be in the middle of a frame, so it isn't closed cleanly
+        if !chan.is_open() || self.poisoned {
+            return;
+        }
+        if self.unanswered > 0 || self.shared.expired(self.opened) {
+            close_detached(chan);
+            return;
+        }
+        // the permit is released after the channel is back, so a waiting checkout finds it
+        if let Some(host) = self.shared.hosts().get_mut(&self.addr) {
+            host.idle.push(Idle {
+                chan,
+                opened: self.opened,
+                since: Instant::now(),
+            });
+        }
+    }
+}
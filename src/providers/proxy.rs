@@ -0,0 +1,378 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+
+use super::tcp::TcpOptions;
+use crate::io::{ReadExt, TcpStream, ToSocketAddrs, WriteExt};
+use crate::{err, Error, Result};
+
+/// longest response to a `CONNECT` request read before giving up on the proxy
+const MAX_RESPONSE_LEN: usize = 8192;
+
+#[derive(Clone, Debug)]
+/// Proxy outbound connections go through, see `ConnectOptions::proxy`.
+///
+/// The tunnel is set up before anything else runs over the connection,
+/// so channels, handshakes and websocket upgrades go through it unchanged.
+/// Destinations named by host are resolved by the proxy, so names only it can resolve
+/// work and nothing leaks to the local resolver. Only IP addresses are sent as such.
+///
+/// Errors tell the proxy and the destination apart: a proxy rejecting the credentials
+/// fails with `PermissionDenied`, while a destination the proxy can't reach fails with
+/// `ConnectionRefused`, `HostUnreachable`, `NetworkUnreachable` or `TimedOut`
+/// ```no_run
+/// # async fn example() -> canary::Result<()> {
+/// use canary::providers::{ConnectOptions, Proxy, ProxyAuth, Tcp};
+///
+/// let proxy = Proxy::HttpConnect {
+///     addr: "proxy.corp.example:3128".into(),
+///     auth: Some(ProxyAuth::new("user", "password")),
+/// };
+/// let chan = Tcp::connect_with("db.internal:5432", ConnectOptions::default().proxy(proxy)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub enum Proxy {
+    /// SOCKS5 proxy (RFC 1928), authenticating with a username and password (RFC 1929)
+    /// if `auth` is set
+    Socks5 {
+        /// address of the proxy
+        addr: String,
+        /// credentials of the proxy, if it requires them
+        auth: Option<ProxyAuth>,
+    },
+    /// HTTP proxy tunnelling connections with `CONNECT`,
+    /// authenticating with `Proxy-Authorization: Basic` if `auth` is set
+    HttpConnect {
+        /// address of the proxy
+        addr: String,
+        /// credentials of the proxy, if it requires them
+        auth: Option<ProxyAuth>,
+    },
+}
+
+#[derive(Clone)]
+/// Username and password a proxy is authenticated with.
+/// The password is left out of `Debug`, so options can be logged
+pub struct ProxyAuth {
+    /// username
+    pub username: String,
+    /// password
+    pub password: String,
+}
+
+impl ProxyAuth {
+    #[inline]
+    /// authenticate with the username and password
+    pub fn new(username: &str, password: &str) -> Self {
+        ProxyAuth {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        }
+    }
+}
+
+impl Debug for ProxyAuth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyAuth")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Address a connection is opened to, implemented for the same types as `ToSocketAddrs`.
+///
+/// Connecting through a `Proxy` hands host names to the proxy as they are, instead of
+/// resolving them locally
+pub trait Destination: ToSocketAddrs {
+    /// host name and port of the destination, unless it is already an IP address
+    fn host(&self) -> Option<(&str, u16)>;
+}
+
+/// host name and port of `host`, unless it is an IP address
+fn named(host: &str, port: u16) -> Option<(&str, u16)> {
+    match host.parse::<IpAddr>() {
+        Ok(_) => None,
+        Err(_) => Some((host, port)),
+    }
+}
+
+impl Destination for str {
+    fn host(&self) -> Option<(&str, u16)> {
+        let (host, port) = self.rsplit_once(':')?;
+        // bracketed IPv6 addresses are resolved like any other address
+        if host.starts_with('[') {
+            return None;
+        }
+        named(host, port.parse().ok()?)
+    }
+}
+
+impl Destination for String {
+    #[inline]
+    fn host(&self) -> Option<(&str, u16)> {
+        self.as_str().host()
+    }
+}
+
+impl Destination for (&str, u16) {
+    #[inline]
+    fn host(&self) -> Option<(&str, u16)> {
+        named(self.0, self.1)
+    }
+}
+
+impl Destination for (String, u16) {
+    #[inline]
+    fn host(&self) -> Option<(&str, u16)> {
+        named(&self.0, self.1)
+    }
+}
+
+impl<T: Destination + ?Sized> Destination for &T {
+    #[inline]
+    fn host(&self) -> Option<(&str, u16)> {
+        (**self).host()
+    }
+}
+
+macro_rules! addresses {
+    ($($addr: ty),*) => {$(
+        impl Destination for $addr {
+            #[inline]
+            fn host(&self) -> Option<(&str, u16)> {
+                None
+            }
+        }
+    )*};
+}
+
+addresses!(
+    SocketAddr,
+    SocketAddrV4,
+    SocketAddrV6,
+    (IpAddr, u16),
+    (Ipv4Addr, u16),
+    (Ipv6Addr, u16),
+    &[SocketAddr]
+);
+
+#[derive(Clone, Debug)]
+/// destination of a tunnel, as given to the proxy
+pub(crate) enum Target {
+    Addr(SocketAddr),
+    Host(String, u16),
+}
+
+impl Target {
+    #[inline]
+    pub(crate) fn port(&self) -> u16 {
+        match self {
+            Target::Addr(addr) => addr.port(),
+            Target::Host(_, port) => *port,
+        }
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Addr(addr) => write!(f, "{}", addr),
+            Target::Host(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+impl Proxy {
+    /// Open a tunnel to the destination. A host name is passed on to the proxy,
+    /// while addresses are tried in turn until the proxy reaches one.
+    /// Returns the stream along with the destination, since the peer of the stream is the proxy
+    pub(crate) async fn connect(
+        &self,
+        tcp: &TcpOptions,
+        addrs: impl Destination,
+    ) -> Result<(TcpStream, Target)> {
+        if let Some((host, port)) = addrs.host() {
+            let target = Target::Host(host.to_owned(), port);
+            return Ok((self.tunnel(tcp, &target).await?, target));
+        }
+        let mut last_err = None;
+        for addr in tokio::net::lookup_host(addrs).await? {
+            let target = Target::Addr(addr);
+            match self.tunnel(tcp, &target).await {
+                Ok(stream) => return Ok((stream, target)),
+                // other destinations go through the same proxy with the same credentials
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => return Err(e),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        match last_err {
+            Some(e) => Err(e),
+            None => err!((invalid_input, "could not resolve to any address")),
+        }
+    }
+
+    /// open a tunnel to `target`
+    async fn tunnel(&self, tcp: &TcpOptions, target: &Target) -> Result<TcpStream> {
+        let (addr, auth) = match self {
+            Proxy::Socks5 { addr, auth } | Proxy::HttpConnect { addr, auth } => (addr, auth),
+        };
+        let mut stream = tcp.connect(addr.as_str()).await.map_err(|e| {
+            let message = format!("connecting to proxy `{}` failed: {}", addr, e);
+            Error::new(std::io::Error::new(e.kind(), message))
+        })?;
+        match self {
+            Proxy::Socks5 { .. } => socks5(&mut stream, target, auth.as_ref()).await?,
+            Proxy::HttpConnect { .. } => http_connect(&mut stream, target, auth.as_ref()).await?,
+        }
+        tracing::debug!("tunnel to `{}` open through proxy `{}`", target, addr);
+        Ok(stream)
+    }
+}
+
+/// negotiate a SOCKS5 tunnel to `target`
+async fn socks5(stream: &mut TcpStream, target: &Target, auth: Option<&ProxyAuth>) -> Result<()> {
+    // no authentication, or username and password
+    let greeting: &[u8] = match auth {
+        Some(_) => &[5, 2, 0, 2],
+        None => &[5, 1, 0],
+    };
+    stream.write_all(greeting).await?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    match choice {
+        [5, 0] => (),
+        [5, 2] => match auth {
+            Some(auth) => socks5_auth(stream, auth).await?,
+            None => err!((
+                invalid_data,
+                "socks5 proxy picked a method that wasn't offered"
+            ))?,
+        },
+        [5, 0xff] => err!((
+            permission_denied,
+            "socks5 proxy requires an authentication method that wasn't offered"
+        ))?,
+        _ => err!((invalid_data, "malformed socks5 method selection"))?,
+    }
+
+    let mut request = vec![5, 1, 0];
+    match target {
+        Target::Addr(SocketAddr::V4(addr)) => {
+            request.push(1);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        Target::Addr(SocketAddr::V6(addr)) => {
+            request.push(4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        Target::Host(host, _) => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| err!(invalid_input, "socks5 host names are 255 bytes at most"))?;
+            request.extend_from_slice(&[3, len]);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 5 {
+        err!((invalid_data, "malformed socks5 reply"))?
+    }
+    let failure =
+        |reason: &str| format!("socks5 proxy could not connect to `{}`: {}", target, reason);
+    match reply[1] {
+        0 => (),
+        1 => err!((other, failure("general failure")))?,
+        2 => err!((permission_denied, failure("not allowed by its rules")))?,
+        3 => err!((net_unreachable, failure("network unreachable")))?,
+        4 => err!((host_unreachable, failure("host unreachable")))?,
+        5 => err!((conn_refused, failure("connection refused")))?,
+        6 => err!((timeout, failure("timed out")))?,
+        7 | 8 => err!((unsupported, failure("not supported by the proxy")))?,
+        code => err!((other, failure(&format!("error {}", code))))?,
+    }
+    // the address the proxy bound for the tunnel isn't needed
+    let bound_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => stream.read_u8().await? as usize,
+        _ => err!((invalid_data, "malformed socks5 reply"))?,
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// authenticate with a username and password, as described by RFC 1929
+async fn socks5_auth(stream: &mut TcpStream, auth: &ProxyAuth) -> Result<()> {
+    let username = u8::try_from(auth.username.len())
+        .map_err(|_| err!(invalid_input, "socks5 usernames are 255 bytes at most"))?;
+    let password = u8::try_from(auth.password.len())
+        .map_err(|_| err!(invalid_input, "socks5 passwords are 255 bytes at most"))?;
+    let mut request = vec![1, username];
+    request.extend_from_slice(auth.username.as_bytes());
+    request.push(password);
+    request.extend_from_slice(auth.password.as_bytes());
+    stream.write_all(&request).await?;
+    let mut status = [0u8; 2];
+    stream.read_exact(&mut status).await?;
+    match status {
+        [1, 0] => Ok(()),
+        [1, _] => err!((permission_denied, "socks5 proxy rejected the credentials")),
+        _ => err!((invalid_data, "malformed socks5 authentication reply")),
+    }
+}
+
+/// open a tunnel to `target` with an HTTP `CONNECT` request
+async fn http_connect(
+    stream: &mut TcpStream,
+    target: &Target,
+    auth: Option<&ProxyAuth>,
+) -> Result<()> {
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some(auth) = auth {
+        let credentials = format!("{}:{}", auth.username, auth.password);
+        request += &format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            BASE64_STANDARD.encode(credentials)
+        );
+    }
+    request += "\r\n";
+    stream.write_all(request.as_bytes()).await?;
+
+    // read byte by byte, so nothing sent through the tunnel is read along with the response
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_RESPONSE_LEN {
+            err!((invalid_data, "response of the http proxy is too long"))?
+        }
+        response.push(stream.read_u8().await?);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| err!(invalid_data, "malformed response from the http proxy"))?;
+    let failure = format!(
+        "http proxy could not connect to `{}`: {}",
+        target, status_line
+    );
+    match status {
+        200..=299 => Ok(()),
+        407 if auth.is_some() => err!((permission_denied, "http proxy rejected the credentials")),
+        407 => err!((permission_denied, "http proxy requires credentials")),
+        403 => err!((permission_denied, failure)),
+        504 => err!((timeout, failure)),
+        _ => err!((conn_refused, failure)),
+    }
+}
@@ -0,0 +1,231 @@
+#![cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+
+use std::net::SocketAddr;
+
+use futures::StreamExt;
+use quinn::{
+    ClientConfig, Connection, ConnectionError, Endpoint, Incoming, IncomingBiStreams,
+    NewConnection, RecvStream, SendStream, ServerConfig, VarInt,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+
+use crate::async_snow::HANDSHAKE_TIMEOUT;
+use crate::channel::handshake::Handshake;
+use crate::err;
+use crate::io::ToSocketAddrs;
+use crate::{Channel, Result};
+
+/// Exposes routes over QUIC, every bidirectional stream opened by a peer is a channel.
+///
+/// A single connection can carry many channels at once, and since QUIC streams
+/// are independent, a slow channel doesn't hold back the others like channels
+/// multiplexed over a single TCP stream do.
+/// Streams of every connection are accepted concurrently and yielded by `next`
+/// in the order they are opened.
+///
+/// QUIC connections are already encrypted with TLS, so `raw` is enough.
+/// ```no_run
+/// # async fn example(config: canary::providers::quinn::ServerConfig) -> canary::Result<()> {
+/// use canary::providers::Quic;
+///
+/// let quic = Quic::bind("127.0.0.1:4433", config).await?;
+/// while let Ok(chan) = quic.next().await {
+///     let mut chan = chan.raw();
+///     chan.send("hello!").await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Quic {
+    endpoint: Endpoint,
+    streams: tokio::sync::Mutex<UnboundedReceiver<(SendStream, RecvStream)>>,
+    accept: JoinHandle<()>,
+}
+
+impl Quic {
+    /// Bind to this address, serving the certificate of the config.
+    /// Binds to the first address that works
+    pub async fn bind(addrs: impl ToSocketAddrs, config: ServerConfig) -> Result<Self> {
+        let (endpoint, mut incoming) = bind_endpoint(addrs, config).await?;
+        let (sender, receiver) = unbounded_channel();
+        let accept = tokio::spawn(async move {
+            while let Some(connecting) = incoming.next().await {
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    let addr = connecting.remote_address();
+                    match crate::runtime::timeout(HANDSHAKE_TIMEOUT, connecting).await {
+                        Ok(Ok(new)) => accept_streams(new.bi_streams, sender).await,
+                        Ok(Err(e)) => {
+                            tracing::debug!("quic handshake with `{}` failed: {}", addr, e)
+                        }
+                        Err(_) => tracing::debug!("quic handshake with `{}` timed out", addr),
+                    }
+                });
+            }
+        });
+        Ok(Quic {
+            endpoint,
+            streams: tokio::sync::Mutex::new(receiver),
+            accept,
+        })
+    }
+    #[inline]
+    /// get the next channel, opened by any of the connected peers
+    pub async fn next(&self) -> Result<Handshake> {
+        crate::shutdown::accepting(self.accept()).await
+    }
+
+    /// accept the next channel, see `next`
+    async fn accept(&self) -> Result<Handshake> {
+        let stream = self.streams.lock().await.recv().await;
+        // the sender lives in the accept task until this provider is dropped
+        let stream = stream.ok_or_else(|| err!(not_connected, "quic provider unbound"))?;
+        Ok(Handshake::from(Channel::from_raw(
+            stream,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+    #[inline]
+    /// local address of the endpoint, useful when binding to port 0
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
+    /// Connect to the following address, verifying the server with the config.
+    /// `server_name` is the name the certificate of the server has to be valid for.
+    ///
+    /// Channels are opened on the returned connection with `QuicConnection::open`
+    /// ```no_run
+    /// # async fn example(config: canary::providers::quinn::ClientConfig) -> canary::Result<()> {
+    /// use canary::providers::Quic;
+    ///
+    /// let conn = Quic::connect("example.com:4433", "example.com", config).await?;
+    /// let mut users = conn.open().await?.raw();
+    /// let mut orders = conn.open().await?.raw();
+    /// users.send("hello!").await?;
+    /// orders.send("hello!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect(
+        addrs: impl ToSocketAddrs,
+        server_name: &str,
+        config: ClientConfig,
+    ) -> Result<QuicConnection> {
+        let mut last_err = None;
+        for addr in tokio::net::lookup_host(addrs).await? {
+            let local: SocketAddr = if addr.is_ipv4() {
+                ([0, 0, 0, 0], 0).into()
+            } else {
+                ([0u16; 8], 0).into()
+            };
+            let endpoint = Endpoint::client(local)?;
+            let connecting = endpoint
+                .connect_with(config.clone(), addr, server_name)
+                .map_err(|e| err!(invalid_input, e.to_string()))?;
+            match connecting.await {
+                Ok(NewConnection { connection, .. }) => {
+                    return Ok(QuicConnection {
+                        connection,
+                        endpoint,
+                    })
+                }
+                Err(e) => last_err = Some(connection_err(e)),
+            }
+        }
+        match last_err {
+            Some(e) => Err(e),
+            None => err!((invalid_input, "could not resolve to any address")),
+        }
+    }
+}
+
+/// bind an endpoint to the first address that can be bound
+async fn bind_endpoint(
+    addrs: impl ToSocketAddrs,
+    config: ServerConfig,
+) -> Result<(Endpoint, Incoming)> {
+    let mut last_err = None;
+    for addr in tokio::net::lookup_host(addrs).await? {
+        match Endpoint::server(config.clone(), addr) {
+            Ok(bound) => return Ok(bound),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    match last_err {
+        Some(e) => Err(e.into()),
+        None => err!((invalid_input, "could not resolve to any address")),
+    }
+}
+
+impl Drop for Quic {
+    fn drop(&mut self) {
+        // connections already accepted keep running, so channels in use aren't cut off
+        self.accept.abort();
+    }
+}
+
+/// forward every bidirectional stream of a connection to the provider,
+/// until the connection closes or the provider is dropped
+async fn accept_streams(
+    mut streams: IncomingBiStreams,
+    sender: UnboundedSender<(SendStream, RecvStream)>,
+) {
+    while let Some(stream) = streams.next().await {
+        match stream {
+            Ok(stream) => {
+                if sender.send(stream).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                tracing::debug!("quic connection closed: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// QUIC connection to a provider, channels are opened on it with `open`.
+/// Dropping it closes the connection once every channel opened on it is dropped
+pub struct QuicConnection {
+    connection: Connection,
+    endpoint: Endpoint,
+}
+
+impl QuicConnection {
+    /// Open a new channel on this connection, which the provider gets with `next`.
+    /// Opening a channel doesn't need a round trip, so the provider only gets it
+    /// once something is sent on it. Waits if the peer doesn't allow more concurrent streams
+    pub async fn open(&self) -> Result<Handshake> {
+        let stream = self.connection.open_bi().await.map_err(connection_err)?;
+        Ok(Handshake::from(Channel::from_raw(
+            stream,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+    #[inline]
+    /// address of the provider
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.connection.remote_address()
+    }
+    /// Close the connection right away along with every channel opened on it,
+    /// then wait for the peer to be notified
+    pub async fn close(self) {
+        self.connection.close(VarInt::from_u32(0), b"");
+        self.endpoint.wait_idle().await;
+    }
+}
+
+fn connection_err(e: ConnectionError) -> crate::Error {
+    match e {
+        ConnectionError::TimedOut => err!(timeout, e.to_string()),
+        ConnectionError::Reset => err!(conn_reset, e.to_string()),
+        ConnectionError::ApplicationClosed(_)
+        | ConnectionError::ConnectionClosed(_)
+        | ConnectionError::LocallyClosed => err!(conn_aborted, e.to_string()),
+        _ => err!(invalid_data, e.to_string()),
+    }
+}
@@ -0,0 +1,175 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::channel::frame::FrameKind;
+use crate::{err, Result};
+
+/// buckets are only pruned once this many peers are tracked
+const PRUNE_AT: usize = 1024;
+
+#[derive(Clone, Copy, Debug)]
+/// Limit on how many connections a single peer can open per second.
+///
+/// Every peer gets a token bucket that holds up to `burst` connections and refills at
+/// `per_second` connections per second. Connections accepted while the bucket
+/// of the peer is empty are dropped before any handshake runs.
+/// Peers are keyed by IP address for TCP and by user id for unix sockets.
+///
+/// The same limit caps the messages a single channel receives, see `Channel::rate_limit`
+/// ```no_run
+/// # async fn example() -> canary::Result<()> {
+/// # use canary::providers::{RateLimit, Tcp};
+/// let tcp = Tcp::bind("127.0.0.1:8080")
+///     .await?
+///     .rate_limit(RateLimit::per_second(5).burst(20));
+/// # Ok(())
+/// # }
+/// ```
+pub struct RateLimit {
+    per_second: f64,
+    burst: f64,
+}
+
+impl RateLimit {
+    #[inline]
+    /// allow `rate` connections or messages per second, with a burst of the same size
+    pub fn per_second(rate: u32) -> Self {
+        RateLimit {
+            per_second: rate as f64,
+            burst: rate.max(1) as f64,
+        }
+    }
+    #[inline]
+    /// set how many connections or messages are allowed at once, at least one
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1) as f64;
+        self
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    #[inline]
+    fn refill(&mut self, now: Instant, limit: &RateLimit) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst);
+        self.updated = now;
+    }
+}
+
+/// Token buckets of the peers seen by a provider
+pub(crate) struct Limiter<K> {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K: Hash + Eq> Limiter<K> {
+    #[inline]
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Limiter {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token from the bucket of the peer,
+    /// returns false if the connection has to be dropped
+    pub(crate) fn allow(&self, peer: K) -> bool {
+        let now = Instant::now();
+        let limit = &self.limit;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= PRUNE_AT {
+            // a full bucket behaves like a new one, so forgetting it changes nothing
+            buckets.retain(|_, bucket| {
+                bucket.refill(now, limit);
+                bucket.tokens < limit.burst
+            });
+        }
+        let bucket = buckets.entry(peer).or_insert(Bucket {
+            tokens: limit.burst,
+            updated: now,
+        });
+        bucket.refill(now, limit);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// What a rate limited channel does with the messages received over its limit,
+/// see `Channel::rate_limit`
+pub enum Overflow {
+    #[default]
+    /// Hold the message back until it fits the limit. Nothing is read meanwhile,
+    /// so the transport pushes back on the peer and slows it down
+    Delay,
+    /// Drop the message and fail the receive with a `PermissionDenied` error,
+    /// the channel can keep being used
+    Reject,
+}
+
+/// Token bucket limiting the messages received through a channel
+pub(crate) struct MessageLimiter {
+    limit: RateLimit,
+    overflow: Overflow,
+    bucket: Bucket,
+}
+
+impl MessageLimiter {
+    #[inline]
+    pub(crate) fn new(limit: RateLimit, overflow: Overflow) -> Self {
+        MessageLimiter {
+            limit,
+            overflow,
+            bucket: Bucket {
+                tokens: limit.burst,
+                updated: Instant::now(),
+            },
+        }
+    }
+
+    /// Take a token for a received frame, waiting for one or failing if the bucket is empty.
+    /// Only frames carrying messages count, control frames such as pings go through
+    pub(crate) async fn admit(&mut self, frame: &[u8]) -> Result<()> {
+        let kind = frame
+            .first()
+            .and_then(|kind| FrameKind::try_from(*kind).ok());
+        if !matches!(
+            kind,
+            Some(FrameKind::Message | FrameKind::Result | FrameKind::Error)
+        ) {
+            return Ok(());
+        }
+        self.bucket.refill(Instant::now(), &self.limit);
+        if self.bucket.tokens < 1.0 {
+            match self.overflow {
+                Overflow::Reject => {
+                    return err!((
+                        permission_denied,
+                        "the peer went over the rate limit of the channel"
+                    ))
+                }
+                Overflow::Delay => {
+                    let missing = (1.0 - self.bucket.tokens) / self.limit.per_second;
+                    let wait = Duration::try_from_secs_f64(missing).unwrap_or(Duration::MAX);
+                    crate::runtime::sleep(wait).await;
+                    self.bucket.refill(Instant::now(), &self.limit);
+                }
+            }
+        }
+        self.bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
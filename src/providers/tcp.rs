@@ -1,20 +1,317 @@
 #![cfg(not(target_arch = "wasm32"))]
 
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use super::accept::AcceptErrors;
+use super::connect::ConnectOptions;
+use super::policy::{AcceptPolicy, AcceptStats, Gate, Verdict};
+use super::proxy::Destination;
+use super::rate_limit::{Limiter, RateLimit};
 use crate::channel::handshake::Handshake;
+use crate::err;
 use crate::io::TcpListener;
 use crate::io::TcpStream;
 use crate::io::ToSocketAddrs;
 use crate::Channel;
+use crate::Error;
 use crate::Result;
 
 use backoff::ExponentialBackoff;
-use derive_more::{From, Into};
+use futures::stream::{FuturesUnordered, StreamExt};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpSocket;
+
+/// how long a connection attempt runs alone before the next address is tried alongside it,
+/// the value recommended by RFC 8305
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
 
-#[derive(From, Into)]
-#[into(owned, ref, ref_mut)]
-#[repr(transparent)]
 /// Exposes routes over TCP
-pub struct Tcp(TcpListener);
+pub struct Tcp {
+    listener: TcpListener,
+    options: TcpOptions,
+    limiter: Option<Limiter<IpAddr>>,
+    accept_errors: AcceptErrors,
+    gate: Gate,
+}
+
+impl From<TcpListener> for Tcp {
+    #[inline]
+    fn from(listener: TcpListener) -> Self {
+        Tcp {
+            listener,
+            options: TcpOptions::default(),
+            limiter: None,
+            accept_errors: AcceptErrors::default(),
+            gate: Gate::default(),
+        }
+    }
+}
+
+impl From<Tcp> for TcpListener {
+    #[inline]
+    fn from(tcp: Tcp) -> Self {
+        tcp.listener
+    }
+}
+
+impl<'a> From<&'a Tcp> for &'a TcpListener {
+    #[inline]
+    fn from(tcp: &'a Tcp) -> Self {
+        &tcp.listener
+    }
+}
+
+impl<'a> From<&'a mut Tcp> for &'a mut TcpListener {
+    #[inline]
+    fn from(tcp: &'a mut Tcp) -> Self {
+        &mut tcp.listener
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Socket options applied to TCP streams.
+///
+/// `TCP_NODELAY` is enabled by default since channels send whole messages
+/// and Nagle's algorithm only adds latency to them,
+/// use `TcpOptions::default().nodelay(false)` to restore the system default.
+/// ```no_run
+/// # async fn example() -> canary::Result<()> {
+/// # use canary::providers::{Tcp, TcpOptions};
+/// # use std::time::Duration;
+/// let options = TcpOptions::default()
+///     .recv_buffer_size(1 << 20)
+///     .keepalive(Some(Duration::from_secs(60)))
+///     .backlog(4096);
+/// let tcp = Tcp::bind_with_options("127.0.0.1:8080", options).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TcpOptions {
+    nodelay: bool,
+    recv_buffer_size: Option<usize>,
+    send_buffer_size: Option<usize>,
+    keepalive: Option<Duration>,
+    backlog: u32,
+    dual_stack: Option<bool>,
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    reuse_port: bool,
+    #[cfg(target_os = "linux")]
+    device: Option<String>,
+}
+
+impl Default for TcpOptions {
+    #[inline]
+    fn default() -> Self {
+        TcpOptions {
+            nodelay: true,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            keepalive: None,
+            backlog: 1024,
+            dual_stack: None,
+            #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+            reuse_port: false,
+            #[cfg(target_os = "linux")]
+            device: None,
+        }
+    }
+}
+
+impl TcpOptions {
+    #[inline]
+    /// set `TCP_NODELAY`, enabled by default
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+    #[inline]
+    /// set the size of the receive buffer (`SO_RCVBUF`), uses the system default if not set
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+    #[inline]
+    /// set the size of the send buffer (`SO_SNDBUF`), uses the system default if not set
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+    #[inline]
+    /// enable TCP keepalive probes after the connection has been idle for the given time,
+    /// disabled by default
+    pub fn keepalive(mut self, idle: Option<Duration>) -> Self {
+        self.keepalive = idle;
+        self
+    }
+    #[inline]
+    /// set how many connections can wait to be accepted by a listener, 1024 by default
+    pub fn backlog(mut self, backlog: u32) -> Self {
+        self.backlog = backlog;
+        self
+    }
+    #[inline]
+    /// Let listeners bound to an IPv6 address also accept IPv4 clients, by clearing
+    /// `IPV6_V6ONLY`, or restrict them to IPv6 by setting it. IPv4 clients then show up
+    /// with mapped addresses such as `::ffff:10.0.0.1`, which accept policies match
+    /// against IPv4 ranges too.
+    ///
+    /// Not set by default, leaving it to the system: Linux accepts both families,
+    /// Windows and the BSDs only IPv6. Where the system doesn't support dual-stack
+    /// sockets, such as OpenBSD, the listener falls back to IPv6 only with a warning,
+    /// bind an IPv4 address separately there. Listeners bound to IPv4 addresses ignore it
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::{Tcp, TcpOptions};
+    /// let options = TcpOptions::default().dual_stack(true);
+    /// let tcp = Tcp::bind_with_options("[::]:8080", options).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn dual_stack(mut self, dual_stack: bool) -> Self {
+        self.dual_stack = Some(dual_stack);
+        self
+    }
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    #[inline]
+    /// set `SO_REUSEPORT` on listeners, which lets several of them bind the same address
+    /// and share its connections, disabled by default
+    pub fn reuse_port(mut self, reuse_port: bool) -> Self {
+        self.reuse_port = reuse_port;
+        self
+    }
+    #[cfg(target_os = "linux")]
+    #[inline]
+    /// only send and receive through the given network interface (`SO_BINDTODEVICE`),
+    /// usually requires `CAP_NET_RAW`
+    pub fn bind_device(mut self, interface: &str) -> Self {
+        self.device = Some(interface.to_owned());
+        self
+    }
+
+    /// buffer sizes need to be set before listening or connecting to affect the tcp window
+    fn apply_to_socket(&self, socket: &TcpSocket) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(device) = &self.device {
+            SockRef::from(socket).bind_device(Some(device.as_bytes()))?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size.try_into().map_err(err!(@invalid_input))?)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size.try_into().map_err(err!(@invalid_input))?)?;
+        }
+        Ok(())
+    }
+
+    fn apply_to_stream(&self, stream: &TcpStream) -> Result<()> {
+        let socket = SockRef::from(stream);
+        socket.set_tcp_nodelay(self.nodelay)?;
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(idle) = self.keepalive {
+            socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+        }
+        Ok(())
+    }
+
+    /// Connect to the first address that accepts the connection.
+    ///
+    /// Addresses are tried with happy eyeballs (RFC 8305): families alternate
+    /// starting with the first one the resolver returned, and the next address
+    /// is tried alongside the pending ones when an attempt fails or takes longer
+    /// than `CONNECTION_ATTEMPT_DELAY`. The first connection wins and the others are dropped
+    pub(crate) async fn connect(&self, addrs: impl ToSocketAddrs) -> Result<TcpStream> {
+        let mut addrs = interleave_families(tokio::net::lookup_host(addrs).await?).into_iter();
+        let mut attempts = FuturesUnordered::new();
+        let mut last_err = None;
+        loop {
+            if attempts.is_empty() {
+                match addrs.next() {
+                    Some(addr) => attempts.push(self.connect_addr(addr)),
+                    None => break,
+                }
+            }
+            tokio::select! {
+                Some(attempt) = attempts.next() => match attempt {
+                    Ok(stream) => return Ok(stream),
+                    Err(e) => {
+                        last_err = Some(e);
+                        // don't wait for the delay, the failed attempt freed its slot
+                        if let Some(addr) = addrs.next() {
+                            attempts.push(self.connect_addr(addr));
+                        }
+                    }
+                },
+                _ = crate::runtime::sleep(CONNECTION_ATTEMPT_DELAY), if addrs.len() > 0 => {
+                    if let Some(addr) = addrs.next() {
+                        attempts.push(self.connect_addr(addr));
+                    }
+                }
+            }
+        }
+        match last_err {
+            Some(e) => Err(e),
+            None => err!((invalid_input, "could not resolve to any address")),
+        }
+    }
+
+    async fn connect_addr(&self, addr: SocketAddr) -> Result<TcpStream> {
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        self.apply_to_socket(&socket)?;
+        let stream = socket.connect(addr).await?;
+        self.apply_to_stream(&stream)?;
+        tracing::debug!("connected to `{}`", addr);
+        Ok(stream)
+    }
+
+    /// bind to the first address that can be bound
+    async fn bind(&self, addrs: impl ToSocketAddrs) -> Result<TcpListener> {
+        let mut last_err = None;
+        for addr in tokio::net::lookup_host(addrs).await? {
+            let socket = if addr.is_ipv4() {
+                TcpSocket::new_v4()?
+            } else {
+                TcpSocket::new_v6()?
+            };
+            // same as `TcpListener::bind`
+            #[cfg(unix)]
+            socket.set_reuseaddr(true)?;
+            #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+            SockRef::from(&socket).set_reuse_port(self.reuse_port)?;
+            match self.dual_stack {
+                Some(true) if addr.is_ipv6() => {
+                    if let Err(e) = SockRef::from(&socket).set_only_v6(false) {
+                        tracing::warn!(
+                            "dual-stack sockets are not supported, `{}` only accepts IPv6: {}",
+                            addr,
+                            e
+                        );
+                    }
+                }
+                Some(false) if addr.is_ipv6() => SockRef::from(&socket).set_only_v6(true)?,
+                _ => {}
+            }
+            self.apply_to_socket(&socket)?;
+            match socket.bind(addr).and_then(|_| socket.listen(self.backlog)) {
+                Ok(listener) => return Ok(listener),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        match last_err {
+            Some(e) => Err(e.into()),
+            None => err!((invalid_input, "could not resolve to any address")),
+        }
+    }
+}
 
 impl Tcp {
     #[inline]
@@ -27,8 +324,118 @@ impl Tcp {
     /// }
     /// ```
     pub async fn bind(addrs: impl ToSocketAddrs) -> Result<Self> {
-        let listener = TcpListener::bind(addrs).await?;
-        Ok(Tcp(listener))
+        Self::bind_with_options(addrs, TcpOptions::default()).await
+    }
+
+    #[inline]
+    /// Bind to this address, applying the options to the listener and every accepted stream
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::{Tcp, TcpOptions};
+    /// let options = TcpOptions::default().nodelay(false);
+    /// let tcp = Tcp::bind_with_options("127.0.0.1:8080", options).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind_with_options(addrs: impl ToSocketAddrs, options: TcpOptions) -> Result<Self> {
+        let listener = options.bind(addrs).await?;
+        Ok(Tcp {
+            listener,
+            options,
+            limiter: None,
+            accept_errors: AcceptErrors::default(),
+            gate: Gate::default(),
+        })
+    }
+
+    #[inline]
+    /// Bind to this address, only accepting connections the policy lets through
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::{AcceptPolicy, Tcp};
+    /// let policy = AcceptPolicy::default().allow("10.0.0.0/8".parse()?);
+    /// let tcp = Tcp::bind_with("0.0.0.0:8080", policy).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind_with(addrs: impl ToSocketAddrs, policy: AcceptPolicy) -> Result<Self> {
+        let tcp = Self::bind(addrs).await?;
+        tcp.set_policy(policy);
+        Ok(tcp)
+    }
+
+    #[inline]
+    /// Replace the accept policy, connections accepted from now on are checked against
+    /// the new one. Works while other tasks are waiting on `next`
+    /// ```no_run
+    /// # async fn example(tcp: canary::providers::Tcp) -> canary::Result<()> {
+    /// # use canary::providers::AcceptPolicy;
+    /// tcp.set_policy(AcceptPolicy::default().deny("203.0.113.0/24".parse()?));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_policy(&self, policy: AcceptPolicy) {
+        self.gate.set_policy(policy);
+    }
+
+    #[inline]
+    /// connections accepted, rejected by the accept policy and dropped by the rate limit
+    /// since the provider was bound
+    pub fn stats(&self) -> AcceptStats {
+        self.gate.stats()
+    }
+
+    #[inline]
+    /// Limit how many connections a single IP address can open per second,
+    /// connections over the limit are dropped before the handshake starts
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::{RateLimit, Tcp};
+    /// let tcp = Tcp::bind("127.0.0.1:8080")
+    ///     .await?
+    ///     .rate_limit(RateLimit::per_second(5).burst(20));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.limiter = Some(Limiter::new(limit));
+        self
+    }
+
+    #[inline]
+    /// Address the listener is bound to,
+    /// which tells the port picked by the system when binding to port 0
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::Tcp;
+    /// let tcp = Tcp::bind("127.0.0.1:0").await?;
+    /// let addr = tcp.local_addr()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    #[inline]
+    /// Resolves once the listener is broken and `next` can't accept anymore,
+    /// with the error that broke it.
+    ///
+    /// Errors that only concern the connection being accepted, or running out of
+    /// file descriptors for a moment, are skipped by `next` and don't close the provider
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::Tcp;
+    /// let tcp = Tcp::bind("127.0.0.1:8080").await?;
+    /// tokio::select! {
+    ///     _ = async { while let Ok(chan) = tcp.next().await { /* ... */ } } => {}
+    ///     e = tcp.closed() => tracing::error!("provider closed: {}", e),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn closed(&self) -> Error {
+        self.accept_errors.closed().await
     }
 
     #[inline]
@@ -40,18 +447,42 @@ impl Tcp {
     /// }
     /// ```
     pub async fn next(&self) -> Result<Handshake> {
-        let (stream, _) = self.0.accept().await?;
-        Ok(Handshake::from(Channel::from_raw(
-            stream,
-            Default::default(),
-            Default::default(),
-        )))
+        crate::shutdown::accepting(self.accept()).await
+    }
+
+    /// accept the next channel, see `next`
+    async fn accept(&self) -> Result<Handshake> {
+        let (stream, addr) = loop {
+            let (stream, addr) = match self.listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    self.accept_errors.handle(e).await?;
+                    continue;
+                }
+            };
+            if self.gate.check(&addr) == Verdict::Reject {
+                tracing::debug!("closing connection from `{}`, rejected by policy", addr);
+                continue;
+            }
+            let ip = addr.ip().to_canonical();
+            match &self.limiter {
+                Some(limiter) if !limiter.allow(ip) => {
+                    self.gate.count_rate_limited();
+                    tracing::debug!("dropping connection from `{}`, rate limit exceeded", ip);
+                }
+                _ => break (stream, addr),
+            }
+        };
+        self.gate.count_accepted();
+        self.options.apply_to_stream(&stream)?;
+        let chan = Channel::from_raw(stream, Default::default(), Default::default());
+        Ok(Handshake::from(chan).accepted_from(addr))
     }
     /// connect to address without any backoff strategy
     pub async fn connect_no_backoff(
         addrs: impl ToSocketAddrs + std::fmt::Debug,
     ) -> Result<Handshake> {
-        let stream = TcpStream::connect(&addrs).await?;
+        let stream = TcpOptions::default().connect(&addrs).await?;
         Ok(Handshake::from(Channel::from_raw(
             stream,
             Default::default(),
@@ -61,8 +492,26 @@ impl Tcp {
     #[inline]
     /// Connect to the following address with the given id and retry in case of failure
     pub async fn connect(addrs: impl ToSocketAddrs + std::fmt::Debug) -> Result<Handshake> {
+        Self::connect_with_options(addrs, TcpOptions::default()).await
+    }
+    #[inline]
+    /// Connect to the following address applying the options to the stream,
+    /// and retry in case of failure
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::{Tcp, TcpOptions};
+    /// # use std::time::Duration;
+    /// let options = TcpOptions::default().keepalive(Some(Duration::from_secs(60)));
+    /// let chan = Tcp::connect_with_options("127.0.0.1:8080", options).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with_options(
+        addrs: impl ToSocketAddrs + std::fmt::Debug,
+        options: TcpOptions,
+    ) -> Result<Handshake> {
         let hs = backoff::future::retry(ExponentialBackoff::default(), || async {
-            let stream = TcpStream::connect(&addrs).await?;
+            let stream = options.connect(&addrs).await?;
             Ok(Handshake::from(Channel::from_raw(
                 stream,
                 Default::default(),
@@ -72,4 +521,49 @@ impl Tcp {
         .await?;
         Ok(hs)
     }
+    /// Connect to the following address, retrying failed attempts as set by the options
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::{ConnectOptions, Tcp};
+    /// let chan = Tcp::connect_with("127.0.0.1:8080", ConnectOptions::default().retries(3)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with(
+        addrs: impl Destination + std::fmt::Debug,
+        options: ConnectOptions,
+    ) -> Result<Handshake> {
+        let tcp_options = TcpOptions::default();
+        let stream = options
+            .retry(&addrs, || async {
+                match options.proxied() {
+                    Some(proxy) => Ok(proxy.connect(&tcp_options, &addrs).await?.0),
+                    None => tcp_options.connect(&addrs).await,
+                }
+            })
+            .await?;
+        Ok(Handshake::from(Channel::from_raw(
+            stream,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+}
+
+/// order addresses alternating between IPv6 and IPv4,
+/// starting with the family of the first address
+fn interleave_families(addrs: impl Iterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+    let addrs: Vec<_> = addrs.collect();
+    let first_is_ipv6 = addrs.first().is_some_and(SocketAddr::is_ipv6);
+    let (first, second): (Vec<_>, Vec<_>) = addrs
+        .iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+    let mut second = second.into_iter();
+    let mut ordered = Vec::with_capacity(addrs.len());
+    for addr in first {
+        ordered.push(addr);
+        ordered.extend(second.next());
+    }
+    ordered.extend(second);
+    ordered
 }
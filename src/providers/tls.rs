@@ -0,0 +1,253 @@
+#![cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::{
+    self,
+    crypto::{ring, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+    server::WebPkiClientVerifier,
+    ClientConfig, RootCertStore, ServerConfig,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use super::accept::AcceptErrors;
+use super::tcp::TcpOptions;
+use crate::async_snow::HANDSHAKE_TIMEOUT;
+use crate::channel::handshake::Handshake;
+use crate::err;
+use crate::io::{TcpListener, TlsStream, ToSocketAddrs};
+use crate::{Channel, Error, Result};
+
+/// Certificate chain and key of a TLS server, and optionally the roots
+/// client certificates have to be signed by
+/// ```no_run
+/// # fn example(
+/// #     cert_chain: Vec<canary::providers::rustls::pki_types::CertificateDer<'static>>,
+/// #     key: canary::providers::rustls::pki_types::PrivateKeyDer<'static>,
+/// #     client_roots: canary::providers::rustls::RootCertStore,
+/// # ) {
+/// use canary::providers::ServerTlsConfig;
+///
+/// let config = ServerTlsConfig::new(cert_chain, key).client_auth(client_roots);
+/// # }
+/// ```
+pub struct ServerTlsConfig {
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    client_roots: Option<RootCertStore>,
+}
+
+impl ServerTlsConfig {
+    #[inline]
+    /// serve the certificate chain, the first certificate being the one of the server
+    pub fn new(cert_chain: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> Self {
+        ServerTlsConfig {
+            cert_chain,
+            key,
+            client_roots: None,
+        }
+    }
+    #[inline]
+    /// require clients to present a certificate signed by one of the roots
+    pub fn client_auth(mut self, roots: RootCertStore) -> Self {
+        self.client_roots = Some(roots);
+        self
+    }
+
+    pub(super) fn build(self) -> Result<ServerConfig> {
+        let provider = provider();
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_err)?;
+        let builder = match self.client_roots {
+            Some(roots) => {
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()
+                        .map_err(|e| err!(invalid_input, e.to_string()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        builder
+            .with_single_cert(self.cert_chain, self.key)
+            .map_err(tls_err)
+    }
+}
+
+/// Roots a TLS client trusts, the name of the server it expects and optionally
+/// the certificate chain and key it authenticates with
+/// ```no_run
+/// # fn example(roots: canary::providers::rustls::RootCertStore) -> canary::Result<()> {
+/// use canary::providers::ClientTlsConfig;
+///
+/// let config = ClientTlsConfig::new(roots, "example.com")?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ClientTlsConfig {
+    roots: RootCertStore,
+    sni: ServerName<'static>,
+    client_auth: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+}
+
+impl ClientTlsConfig {
+    /// trust the roots and expect the server to present a certificate for `sni`,
+    /// a DNS name or an IP address. Fails if `sni` is neither
+    pub fn new(roots: RootCertStore, sni: &str) -> Result<Self> {
+        let sni = ServerName::try_from(sni)
+            .map_err(|e| err!(invalid_input, e.to_string()))?
+            .to_owned();
+        Ok(ClientTlsConfig {
+            roots,
+            sni,
+            client_auth: None,
+        })
+    }
+    #[inline]
+    /// authenticate with the certificate chain, for servers that require client certificates
+    pub fn client_auth(
+        mut self,
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Self {
+        self.client_auth = Some((cert_chain, key));
+        self
+    }
+
+    #[inline]
+    /// name the server is expected to present a certificate for
+    pub(super) fn sni(&self) -> &ServerName<'static> {
+        &self.sni
+    }
+
+    pub(super) fn build(self) -> Result<ClientConfig> {
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_err)?
+            .with_root_certificates(self.roots);
+        match self.client_auth {
+            Some((cert_chain, key)) => builder
+                .with_client_auth_cert(cert_chain, key)
+                .map_err(tls_err),
+            None => Ok(builder.with_no_client_auth()),
+        }
+    }
+}
+
+/// Exposes routes over TLS, for peers that can't run the Noise handshake
+/// but can use their existing PKI.
+///
+/// Channels carry the same frames as over TCP inside the TLS stream, and are already
+/// encrypted, so `raw` is enough. TLS handshakes run when accepting
+/// and time out after `HANDSHAKE_TIMEOUT`, failed ones are dropped.
+/// ```no_run
+/// # async fn example(config: canary::providers::ServerTlsConfig) -> canary::Result<()> {
+/// use canary::providers::Tls;
+///
+/// let tls = Tls::bind("127.0.0.1:8443", config).await?;
+/// while let Ok(chan) = tls.next().await {
+///     let mut chan = chan.raw();
+///     chan.send("hello!").await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Tls {
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    accept_errors: AcceptErrors,
+}
+
+impl Tls {
+    /// Bind to this address, serving the certificate of the config
+    pub async fn bind(addrs: impl ToSocketAddrs, config: ServerTlsConfig) -> Result<Self> {
+        let acceptor = TlsAcceptor::from(Arc::new(config.build()?));
+        let listener = TcpListener::bind(addrs).await?;
+        Ok(Tls {
+            listener,
+            acceptor,
+            accept_errors: AcceptErrors::default(),
+        })
+    }
+    #[inline]
+    /// Address the listener is bound to,
+    /// which tells the port picked by the system when binding to port 0
+    /// ```no_run
+    /// # async fn example(config: canary::providers::ServerTlsConfig) -> canary::Result<()> {
+    /// # use canary::providers::Tls;
+    /// let tls = Tls::bind("127.0.0.1:0", config).await?;
+    /// let addr = tls.local_addr()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+    #[inline]
+    /// Resolves once the listener is broken and `next` can't accept anymore,
+    /// with the error that broke it. Transient errors are skipped by `next`
+    pub async fn closed(&self) -> Error {
+        self.accept_errors.closed().await
+    }
+    /// get the next channel
+    pub async fn next(&self) -> Result<Handshake> {
+        crate::shutdown::accepting(self.accept()).await
+    }
+
+    /// accept the next channel, see `next`
+    async fn accept(&self) -> Result<Handshake> {
+        loop {
+            let (stream, addr) = match self.listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    self.accept_errors.handle(e).await?;
+                    continue;
+                }
+            };
+            let accepted = crate::runtime::timeout(HANDSHAKE_TIMEOUT, self.acceptor.accept(stream));
+            match accepted.await {
+                Ok(Ok(stream)) => {
+                    let stream = Box::new(TlsStream::from(stream));
+                    let chan = Channel::from_raw(stream, Default::default(), Default::default());
+                    return Ok(Handshake::from(chan).accepted_from(addr));
+                }
+                Ok(Err(e)) => tracing::debug!("tls handshake with `{}` failed: {}", addr, e),
+                Err(_) => tracing::debug!("tls handshake with `{}` timed out", addr),
+            }
+        }
+    }
+    /// Connect to the following address, verifying the server with the config
+    /// ```no_run
+    /// # async fn example(config: canary::providers::ClientTlsConfig) -> canary::Result<()> {
+    /// use canary::providers::Tls;
+    ///
+    /// let mut chan = Tls::connect("example.com:8443", config).await?.raw();
+    /// chan.send("hello!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect(addrs: impl ToSocketAddrs, config: ClientTlsConfig) -> Result<Handshake> {
+        let sni = config.sni.clone();
+        let connector = TlsConnector::from(Arc::new(config.build()?));
+        let stream = TcpOptions::default().connect(addrs).await?;
+        let stream = connector.connect(sni, stream).await?;
+        let stream = Box::new(TlsStream::from(stream));
+        Ok(Handshake::from(Channel::from_raw(
+            stream,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+}
+
+/// every config uses the ring provider, so the process-wide default doesn't have to be set
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn tls_err(e: rustls::Error) -> crate::Error {
+    err!(invalid_input, e.to_string())
+}
@@ -1,34 +1,253 @@
 #![cfg(unix)]
 #![cfg(not(target_arch = "wasm32"))]
 
-use std::path::Path;
+use std::fs::Permissions;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 
+use super::accept::AcceptErrors;
+use super::connect::ConnectOptions;
+use super::rate_limit::{Limiter, RateLimit};
 use crate::channel::handshake::Handshake;
 use crate::err;
 use crate::io::UnixListener;
 use crate::io::UnixStream;
 use crate::Channel;
+use crate::Error;
 use crate::Result;
 
-use derive_more::{From, Into};
-#[derive(From, Into)]
-#[into(owned, ref, ref_mut)]
 /// Exposes routes over TCP
-pub struct Unix(UnixListener);
+pub struct Unix {
+    listener: UnixListener,
+    limiter: Option<Limiter<u32>>,
+    socket_file: Option<SocketFile>,
+    accept_errors: AcceptErrors,
+}
+
+impl From<UnixListener> for Unix {
+    #[inline]
+    fn from(listener: UnixListener) -> Self {
+        Unix {
+            listener,
+            limiter: None,
+            socket_file: None,
+            accept_errors: AcceptErrors::default(),
+        }
+    }
+}
+
+impl From<Unix> for UnixListener {
+    #[inline]
+    fn from(unix: Unix) -> Self {
+        // the listener still uses the socket file, so it's left in place
+        if let Some(socket_file) = unix.socket_file {
+            socket_file.keep();
+        }
+        unix.listener
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// Options applied when binding a unix socket to a path.
+///
+/// They are ignored for abstract addresses, which have no file.
+/// ```no_run
+/// # async fn example() -> canary::Result<()> {
+/// # use canary::providers::{Unix, UnixOptions};
+/// let options = UnixOptions::default()
+///     .mode(0o660)
+///     .unlink_on_bind(true)
+///     .unlink_on_drop(true);
+/// let unix = Unix::bind_with_options("/tmp/service.sock", options).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct UnixOptions {
+    mode: Option<u32>,
+    unlink_on_bind: bool,
+    unlink_on_drop: bool,
+}
+
+impl UnixOptions {
+    #[inline]
+    /// set the permissions of the socket file, which decide who can connect to it.
+    /// Uses the process umask if not set
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+    #[inline]
+    /// remove a stale socket file left behind by a process that didn't shut down cleanly,
+    /// disabled by default. The file is only removed if nothing is listening on it
+    pub fn unlink_on_bind(mut self, unlink: bool) -> Self {
+        self.unlink_on_bind = unlink;
+        self
+    }
+    #[inline]
+    /// remove the socket file when the provider is dropped, disabled by default
+    pub fn unlink_on_drop(mut self, unlink: bool) -> Self {
+        self.unlink_on_drop = unlink;
+        self
+    }
+
+    /// bind to the path, applying the options to the socket file
+    fn bind(&self, path: &Path) -> Result<(UnixListener, Option<SocketFile>)> {
+        if self.unlink_on_bind {
+            unlink_stale(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        if let Some(mode) = self.mode {
+            std::fs::set_permissions(path, Permissions::from_mode(mode))?;
+        }
+        let socket_file = match self.unlink_on_drop {
+            true => Some(SocketFile::new(path)?),
+            false => None,
+        };
+        Ok((listener, socket_file))
+    }
+}
+
+/// remove the socket file at `path` if no listener is accepting on it anymore
+fn unlink_stale(path: &Path) -> Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {}
+        // binding reports anything else that is in the way
+        _ => return Ok(()),
+    }
+    // connecting to a local socket doesn't block, so this is fine inside async code
+    match std::os::unix::net::UnixStream::connect(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            tracing::debug!("removing stale socket file `{:?}`", path);
+            std::fs::remove_file(path)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Socket file bound by a provider, removed when dropped
+/// unless another socket has been bound to the path since
+struct SocketFile {
+    path: PathBuf,
+    dev: u64,
+    ino: u64,
+}
+
+impl SocketFile {
+    fn new(path: &Path) -> Result<Self> {
+        let metadata = std::fs::symlink_metadata(path)?;
+        Ok(SocketFile {
+            path: path.to_owned(),
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        })
+    }
+
+    /// leave the file in place when dropped
+    fn keep(mut self) {
+        self.path = PathBuf::new();
+    }
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if self.path.as_os_str().is_empty() {
+            return;
+        }
+        match std::fs::symlink_metadata(&self.path) {
+            Ok(metadata) if metadata.dev() == self.dev && metadata.ino() == self.ino => {
+                std::fs::remove_file(&self.path).ok();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a> From<&'a Unix> for &'a UnixListener {
+    #[inline]
+    fn from(unix: &'a Unix) -> Self {
+        &unix.listener
+    }
+}
+
+impl<'a> From<&'a mut Unix> for &'a mut UnixListener {
+    #[inline]
+    fn from(unix: &'a mut Unix) -> Self {
+        &mut unix.listener
+    }
+}
 
 impl Unix {
     #[inline]
-    /// Bind to this address
+    /// Bind to this address.
+    /// On Linux, addresses of the form `@name` or `\0name` bind to the abstract namespace
     /// ```no_run
     /// let unix = Unix::bind("127.0.0.1:8080").await?;
+    /// let abstract_unix = Unix::bind("@my-service").await?;
     /// while let Ok(chan) = unix.next().await {
     ///     let mut chan = chan.encrypted().await?;
     ///     chan.send("hello!").await?;
     /// }
     /// ```
     pub async fn bind(addrs: impl AsRef<Path>) -> Result<Self> {
-        let listener = UnixListener::bind(addrs)?;
-        Ok(Unix(listener))
+        Self::bind_with_options(addrs, UnixOptions::default()).await
+    }
+    #[inline]
+    /// Bind to this address, applying the options to the socket file
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::{Unix, UnixOptions};
+    /// let options = UnixOptions::default().mode(0o600).unlink_on_bind(true);
+    /// let unix = Unix::bind_with_options("/tmp/service.sock", options).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind_with_options(addrs: impl AsRef<Path>, options: UnixOptions) -> Result<Self> {
+        let addrs = addrs.as_ref();
+        let (listener, socket_file) = match abstract_name(addrs) {
+            Some(name) => (bind_abstract(name)?, None),
+            None => options.bind(addrs)?,
+        };
+        Ok(Unix {
+            listener,
+            limiter: None,
+            socket_file,
+            accept_errors: AcceptErrors::default(),
+        })
+    }
+    #[inline]
+    /// Limit how many connections processes of a single user can open per second,
+    /// connections over the limit are dropped before the handshake starts
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::{RateLimit, Unix};
+    /// let unix = Unix::bind("/tmp/service.sock")
+    ///     .await?
+    ///     .rate_limit(RateLimit::per_second(5).burst(20));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.limiter = Some(Limiter::new(limit));
+        self
+    }
+    #[inline]
+    /// Resolves once the listener is broken and `next` can't accept anymore,
+    /// with the error that broke it. Transient errors are skipped by `next`
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::Unix;
+    /// let unix = Unix::bind("/tmp/canary.sock").await?;
+    /// tokio::select! {
+    ///     _ = async { while let Ok(chan) = unix.next().await { /* ... */ } } => {}
+    ///     e = unix.closed() => tracing::error!("provider closed: {}", e),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn closed(&self) -> Error {
+        self.accept_errors.closed().await
     }
     #[inline]
     /// get the next channel
@@ -39,7 +258,36 @@ impl Unix {
     /// }
     /// ```
     pub async fn next(&self) -> Result<Handshake> {
-        let (raw, _) = self.0.accept().await?;
+        crate::shutdown::accepting(self.accept()).await
+    }
+
+    /// accept the next channel, see `next`
+    async fn accept(&self) -> Result<Handshake> {
+        let raw = loop {
+            let (raw, _) = match self.listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    self.accept_errors.handle(e).await?;
+                    continue;
+                }
+            };
+            let limiter = match &self.limiter {
+                Some(limiter) => limiter,
+                None => break raw,
+            };
+            let uid = match raw.peer_cred() {
+                Ok(cred) => cred.uid(),
+                // the peer may be gone already, which only concerns this connection
+                Err(e) => {
+                    tracing::debug!("dropping connection, could not get its credentials: {}", e);
+                    continue;
+                }
+            };
+            if limiter.allow(uid) {
+                break raw;
+            }
+            tracing::debug!("dropping connection from uid {}, rate limit exceeded", uid);
+        };
         Ok(Handshake::from(Channel::from_raw(
             raw,
             Default::default(),
@@ -61,15 +309,16 @@ impl Unix {
         let addrs = &addrs;
         let mut attempt = 0;
         let raw = loop {
-            match UnixStream::connect(&addrs).await {
+            match connect_stream(addrs.as_ref()).await {
                 Ok(s) => break s,
+                Err(e) if e.kind() == std::io::ErrorKind::Unsupported => return Err(e),
                 Err(e) => {
                     tracing::error!(
                         "connecting to address `{:?}` failed, attempt {} starting",
                         addrs,
                         attempt
                     );
-                    crate::io::sleep(std::time::Duration::from_millis(time_to_retry)).await;
+                    crate::runtime::sleep(std::time::Duration::from_millis(time_to_retry)).await;
                     attempt += 1;
                     if attempt == retries {
                         err!((e))?
@@ -84,4 +333,77 @@ impl Unix {
             Default::default(),
         )))
     }
+    /// Connect to the following address, retrying failed attempts as set by the options
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::{ConnectOptions, Unix};
+    /// let chan = Unix::connect_with("/tmp/canary.sock", ConnectOptions::default()).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with(
+        addrs: impl AsRef<Path> + std::fmt::Debug,
+        options: ConnectOptions,
+    ) -> Result<Handshake> {
+        let raw = options
+            .retry(&addrs, || connect_stream(addrs.as_ref()))
+            .await?;
+        Ok(Handshake::from(Channel::from_raw(
+            raw,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+}
+
+#[inline]
+/// name of the abstract socket if the address is of the form `@name` or `\0name`
+fn abstract_name(addrs: &Path) -> Option<&[u8]> {
+    let addrs = addrs.as_os_str().as_bytes();
+    addrs
+        .strip_prefix(b"@")
+        .or_else(|| addrs.strip_prefix(b"\0"))
+}
+
+#[cfg(target_os = "linux")]
+fn bind_abstract(name: &[u8]) -> Result<UnixListener> {
+    use std::os::linux::net::SocketAddrExt;
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    let listener = std::os::unix::net::UnixListener::bind_addr(&addr)?;
+    listener.set_nonblocking(true)?;
+    Ok(UnixListener::from_std(listener)?)
+}
+
+#[cfg(target_os = "linux")]
+fn connect_abstract(name: &[u8]) -> Result<UnixStream> {
+    use std::os::linux::net::SocketAddrExt;
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    // connecting to a local socket doesn't block, so this is fine inside async code
+    let stream = std::os::unix::net::UnixStream::connect_addr(&addr)?;
+    stream.set_nonblocking(true)?;
+    Ok(UnixStream::from_std(stream)?)
+}
+
+/// connect to a path, or to an abstract address of the form `@name` or `\0name`
+async fn connect_stream(addrs: &Path) -> Result<UnixStream> {
+    match abstract_name(addrs) {
+        Some(name) => connect_abstract(name),
+        None => Ok(UnixStream::connect(addrs).await?),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_abstract(_: &[u8]) -> Result<UnixListener> {
+    err!((
+        unsupported,
+        "abstract unix socket addresses are only supported on linux"
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_abstract(_: &[u8]) -> Result<UnixStream> {
+    err!((
+        unsupported,
+        "abstract unix socket addresses are only supported on linux"
+    ))
 }
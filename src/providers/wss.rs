@@ -8,23 +8,272 @@ use cfg_if::cfg_if;
 
 cfg_if! {
     if #[cfg(not(target_arch = "wasm32"))] {
-        use crate::io::{TcpListener, ToSocketAddrs};
+        use std::sync::Arc;
+
+        use crate::async_snow::HANDSHAKE_TIMEOUT;
+        use crate::io::{TcpListener, TcpStream, ToSocketAddrs, Wss, WssStream};
         use crate::io::wss;
         use backoff::ExponentialBackoff;
+        use super::accept::AcceptErrors;
+        use super::connect::ConnectOptions;
+        use super::proxy::{Destination, Proxy, Target};
+        use crate::Error;
+        use super::tcp::TcpOptions;
+        use wss::tungstenite::client::IntoClientRequest;
+        use wss::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+        use wss::tungstenite::http::{HeaderName, HeaderValue, StatusCode};
     } else {
         use crate::io::Wss;
     }
 }
 
+#[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+use super::tls::{ClientTlsConfig, ServerTlsConfig};
+#[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+use tokio_rustls::{rustls::pki_types::ServerName, TlsAcceptor, TlsConnector};
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Largest websocket message, and so frame, a channel accepts by default: 64MB.
+/// Messages are buffered whole before they're read, even before the noise handshake,
+/// so the limit keeps peers from filling the memory of the process.
+/// Set another one with `WebSocket::max_message_size` or `WssConnectOptions::max_message_size`
+pub const WSS_MAX_MESSAGE_SIZE: usize = 64 << 20;
+
 #[cfg(not(target_arch = "wasm32"))]
-#[derive(derive_more::From, derive_more::Into)]
-#[into(owned, ref, ref_mut)]
-/// Websocket Provider
-pub struct WebSocket(TcpListener);
+/// called with every upgrade request, see `WebSocket::accept_hook`
+type AcceptHook =
+    dyn Fn(&Request, Response) -> std::result::Result<Response, ErrorResponse> + Send + Sync;
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Websocket Provider.
+///
+/// Upgrades run when accepting and time out after `HANDSHAKE_TIMEOUT`,
+/// failed and rejected ones are dropped.
+pub struct WebSocket {
+    listener: TcpListener,
+    hook: Option<Arc<AcceptHook>>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+    accept_errors: AcceptErrors,
+    max_message_size: usize,
+}
 
 #[cfg(target_arch = "wasm32")]
+/// Websocket Provider, which only connects on wasm
 pub struct WebSocket;
 
+#[cfg(not(target_arch = "wasm32"))]
+impl From<TcpListener> for WebSocket {
+    #[inline]
+    fn from(listener: TcpListener) -> Self {
+        WebSocket {
+            listener,
+            hook: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            accept_errors: AcceptErrors::default(),
+            max_message_size: WSS_MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<WebSocket> for TcpListener {
+    #[inline]
+    fn from(wss: WebSocket) -> Self {
+        wss.listener
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a> From<&'a WebSocket> for &'a TcpListener {
+    #[inline]
+    fn from(wss: &'a WebSocket) -> Self {
+        &wss.listener
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a> From<&'a mut WebSocket> for &'a mut TcpListener {
+    #[inline]
+    fn from(wss: &'a mut WebSocket) -> Self {
+        &mut wss.listener
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+/// How clients upgrade to websockets: the path and headers of the upgrade request,
+/// TLS for `wss://` servers and how failed attempts are retried
+/// ```no_run
+/// # async fn example() -> canary::Result<()> {
+/// # use canary::providers::{WebSocket, WssConnectOptions};
+/// let options = WssConnectOptions::default()
+///     .path("/chat")
+///     .header("authorization", "Bearer my-token")
+///     .header("sec-websocket-protocol", "canary");
+/// let chan = WebSocket::connect_with_options("127.0.0.1:8080", options).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct WssConnectOptions {
+    path: Option<String>,
+    headers: Vec<(String, String)>,
+    retry: ConnectOptions,
+    #[cfg(feature = "tls")]
+    tls: Option<ClientTlsConfig>,
+    max_message_size: Option<usize>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl WssConnectOptions {
+    #[inline]
+    /// path of the upgrade request, `/` by default
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_owned());
+        self
+    }
+    #[inline]
+    /// add a header to the upgrade request, such as an auth token or `Sec-WebSocket-Protocol`.
+    /// Connecting fails if the name or value isn't a valid header
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+    #[inline]
+    /// set how failed attempts are retried, see `ConnectOptions`
+    pub fn retry(mut self, retry: ConnectOptions) -> Self {
+        self.retry = retry;
+        self
+    }
+    #[inline]
+    /// largest message accepted from the server, `WSS_MAX_MESSAGE_SIZE` by default.
+    /// Larger messages fail the receive with an `InvalidData` error
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = Some(size);
+        self
+    }
+    #[cfg(feature = "tls")]
+    #[inline]
+    /// connect to a `wss://` server, verifying it with the config.
+    /// The name the config expects is also the host of the upgrade request
+    /// ```no_run
+    /// # async fn example(tls: canary::providers::ClientTlsConfig) -> canary::Result<()> {
+    /// # use canary::providers::{WebSocket, WssConnectOptions};
+    /// let options = WssConnectOptions::default().tls(tls);
+    /// let chan = WebSocket::connect_with_options("example.com:443", options).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn tls(mut self, tls: ClientTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+/// upgrade request of a client, checked once before any attempt
+struct Upgrade {
+    path: String,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    #[cfg(feature = "tls")]
+    tls: Option<(TlsConnector, ServerName<'static>)>,
+    proxy: Option<Proxy>,
+    max_message_size: Option<usize>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Upgrade {
+    fn new(options: WssConnectOptions) -> Result<Self> {
+        let path = options.path.unwrap_or_else(|| "/".to_owned());
+        if !path.starts_with('/') {
+            err!((invalid_input, "websocket path must start with `/`"))?
+        }
+        let headers = options
+            .headers
+            .into_iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|e| err!(invalid_input, e.to_string()))?;
+                let value = HeaderValue::from_str(&value)
+                    .map_err(|e| err!(invalid_input, e.to_string()))?;
+                Ok((name, value))
+            })
+            .collect::<Result<_>>()?;
+        #[cfg(feature = "tls")]
+        let tls = match options.tls {
+            Some(config) => {
+                let sni = config.sni().clone();
+                Some((TlsConnector::from(Arc::new(config.build()?)), sni))
+            }
+            None => None,
+        };
+        Ok(Upgrade {
+            path,
+            headers,
+            #[cfg(feature = "tls")]
+            tls,
+            proxy: options.retry.proxied().cloned(),
+            max_message_size: options.max_message_size,
+        })
+    }
+
+    /// connect to the first address that accepts the connection and upgrade it
+    async fn connect(&self, addrs: impl Destination) -> Result<Box<Wss>> {
+        let tcp = TcpOptions::default();
+        // through a proxy the peer of the stream is the proxy, so the url names the destination
+        let (stream, peer) = match &self.proxy {
+            Some(proxy) => proxy.connect(&tcp, addrs).await?,
+            None => {
+                let stream = tcp.connect(addrs).await?;
+                let peer = Target::Addr(stream.peer_addr()?);
+                (stream, peer)
+            }
+        };
+        let (stream, url) = self.wrap(stream, peer).await?;
+        let mut request = url
+            .into_client_request()
+            .map_err(|e| err!(invalid_input, e.to_string()))?;
+        for (name, value) in &self.headers {
+            request.headers_mut().append(name.clone(), value.clone());
+        }
+        let max_message_size = self.max_message_size.unwrap_or(WSS_MAX_MESSAGE_SIZE);
+        let (raw, _) =
+            wss::tokio::client_async_with_config(request, stream, config(max_message_size))
+                .await
+                .map_err(upgrade_err)?;
+        Ok(Box::new(raw))
+    }
+
+    #[cfg(feature = "tls")]
+    async fn wrap(&self, stream: TcpStream, peer: Target) -> Result<(WssStream, String)> {
+        match &self.tls {
+            Some((connector, sni)) => {
+                let host = match sni {
+                    ServerName::DnsName(name) => format!("{}:{}", name.as_ref(), peer.port()),
+                    _ => peer.to_string(),
+                };
+                let stream = connector.connect(sni.clone(), stream).await?;
+                let stream = WssStream::Tls(Box::new(stream.into()));
+                Ok((stream, format!("wss://{}{}", host, self.path)))
+            }
+            None => Ok((
+                WssStream::Tcp(stream),
+                format!("ws://{}{}", peer, self.path),
+            )),
+        }
+    }
+
+    #[cfg(not(feature = "tls"))]
+    async fn wrap(&self, stream: TcpStream, peer: Target) -> Result<(WssStream, String)> {
+        Ok((
+            WssStream::Tcp(stream),
+            format!("ws://{}{}", peer, self.path),
+        ))
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 impl WebSocket {
     #[inline]
@@ -38,7 +287,79 @@ impl WebSocket {
     /// ```
     pub async fn bind(addrs: impl ToSocketAddrs) -> Result<Self> {
         let listener = TcpListener::bind(addrs).await?;
-        Ok(WebSocket(listener))
+        Ok(WebSocket::from(listener))
+    }
+    #[cfg(feature = "tls")]
+    /// Bind to this address and serve `wss://`, presenting the certificate of the config
+    /// ```no_run
+    /// # async fn example(config: canary::providers::ServerTlsConfig) -> canary::Result<()> {
+    /// use canary::providers::WebSocket;
+    ///
+    /// let wss = WebSocket::bind_tls("127.0.0.1:8443", config).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind_tls(addrs: impl ToSocketAddrs, config: ServerTlsConfig) -> Result<Self> {
+        let acceptor = TlsAcceptor::from(Arc::new(config.build()?));
+        let mut wss = Self::bind(addrs).await?;
+        wss.tls = Some(acceptor);
+        Ok(wss)
+    }
+    #[inline]
+    /// Call the hook with every upgrade request. It can read the headers of the request,
+    /// such as an auth token, and add headers to the response, such as
+    /// the `Sec-WebSocket-Protocol` it picked.
+    /// Returning an error rejects the upgrade, answering with its status and body
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// use canary::providers::{tungstenite::http, WebSocket};
+    ///
+    /// let wss = WebSocket::bind("127.0.0.1:8080")
+    ///     .await?
+    ///     .accept_hook(|request, response| {
+    ///         match request.headers().get("authorization") {
+    ///             Some(token) if token == "Bearer my-token" => Ok(response),
+    ///             _ => Err(http::Response::builder()
+    ///                 .status(http::StatusCode::UNAUTHORIZED)
+    ///                 .body(Some("invalid token".into()))
+    ///                 .unwrap()),
+    ///         }
+    ///     });
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn accept_hook(
+        mut self,
+        hook: impl Fn(&Request, Response) -> std::result::Result<Response, ErrorResponse>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+    #[inline]
+    /// largest message accepted from clients, `WSS_MAX_MESSAGE_SIZE` by default.
+    /// Larger messages fail the receive with an `InvalidData` error
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// use canary::providers::WebSocket;
+    ///
+    /// let wss = WebSocket::bind("127.0.0.1:8080")
+    ///     .await?
+    ///     .max_message_size(1 << 20);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+    #[inline]
+    /// Resolves once the listener is broken and `next` can't accept anymore,
+    /// with the error that broke it. Transient errors are skipped by `next`
+    pub async fn closed(&self) -> Error {
+        self.accept_errors.closed().await
     }
     #[inline]
     /// get the next channel
@@ -49,31 +370,61 @@ impl WebSocket {
     /// }
     /// ```
     pub async fn next(&self) -> Result<Handshake> {
-        let (chan, _) = self.0.accept().await?;
-        let raw = wss::tokio::accept_async(chan)
-            .await // this future doesn't suspend, hence why this await point is not delegated upwards.
-            .map_err(|e| err!(e))?;
-        let raw = Box::new(raw);
-        Ok(Handshake::from(Channel::from_raw(
-            raw,
-            Default::default(),
-            Default::default(),
-        )))
+        crate::shutdown::accepting(self.accept()).await
+    }
+
+    /// accept the next channel, see `next`
+    async fn accept(&self) -> Result<Handshake> {
+        loop {
+            let (stream, addr) = match self.listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    self.accept_errors.handle(e).await?;
+                    continue;
+                }
+            };
+            match crate::runtime::timeout(HANDSHAKE_TIMEOUT, self.upgrade(stream)).await {
+                Ok(Ok(raw)) => {
+                    let chan = Channel::from_raw(raw, Default::default(), Default::default());
+                    return Ok(Handshake::from(chan).accepted_from(addr));
+                }
+                Ok(Err(e)) => tracing::debug!("websocket upgrade from `{}` failed: {}", addr, e),
+                Err(_) => tracing::debug!("websocket upgrade from `{}` timed out", addr),
+            }
+        }
+    }
+
+    /// answer the upgrade request of an accepted stream
+    async fn upgrade(&self, stream: TcpStream) -> Result<Box<Wss>> {
+        #[cfg(feature = "tls")]
+        let stream = match &self.tls {
+            Some(acceptor) => WssStream::Tls(Box::new(acceptor.accept(stream).await?.into())),
+            None => WssStream::Tcp(stream),
+        };
+        #[cfg(not(feature = "tls"))]
+        let stream = WssStream::Tcp(stream);
+        let hook = self.hook.clone();
+        // the signature of the callback is the one tungstenite expects
+        #[allow(clippy::result_large_err)]
+        let callback = move |request: &Request, response: Response| match hook {
+            Some(hook) => hook(request, response),
+            None => Ok(response),
+        };
+        let raw = wss::tokio::accept_hdr_async_with_config(
+            stream,
+            callback,
+            config(self.max_message_size),
+        )
+        .await
+        .map_err(upgrade_err)?;
+        Ok(Box::new(raw))
     }
 
     /// connect to address without any backoff strategy
     pub async fn connect_no_backoff(
-        addrs: impl ToSocketAddrs + std::fmt::Debug,
+        addrs: impl Destination + std::fmt::Debug,
     ) -> Result<Handshake> {
-        let addrs = tokio::net::lookup_host(&addrs)
-            .await
-            .map_err(|e| err!(e))?
-            .next()
-            .ok_or(err!("no endpoint found"))?;
-        let (raw, _) = wss::tokio::connect_async(&format!("ws://{}", &addrs))
-            .await
-            .map_err(err!(@other))?;
-        let raw = Box::new(raw);
+        let raw = Upgrade::default().connect(&addrs).await?;
         Ok(Handshake::from(Channel::from_raw(
             raw,
             Default::default(),
@@ -82,27 +433,85 @@ impl WebSocket {
     }
     #[inline]
     /// Connect to the following address with the given id and retry in case of failure
-    pub async fn connect(addrs: impl ToSocketAddrs + std::fmt::Debug) -> Result<Handshake> {
-        let addrs = tokio::net::lookup_host(&addrs)
-            .await
-            .map_err(|e| err!(e))?
-            .next()
-            .ok_or(err!("no endpoint found"))?;
-        let hs = backoff::future::retry(ExponentialBackoff::default(), || async {
-            let (raw, _) = wss::tokio::connect_async(&format!("ws://{}", &addrs))
-                .await
-                .map_err(err!(@other))?;
-            let raw = Box::new(raw);
-            Ok(Handshake::from(Channel::from_raw(
-                raw,
-                Default::default(),
-                Default::default(),
-            )))
+    pub async fn connect(addrs: impl Destination + std::fmt::Debug) -> Result<Handshake> {
+        let upgrade = Upgrade::default();
+        let raw = backoff::future::retry(ExponentialBackoff::default(), || async {
+            Ok(upgrade.connect(&addrs).await?)
         })
         .await?;
-        Ok(hs)
+        Ok(Handshake::from(Channel::from_raw(
+            raw,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+    /// Connect to the following address, retrying failed attempts as set by the options
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::{ConnectOptions, WebSocket};
+    /// let chan = WebSocket::connect_with("127.0.0.1:8080", ConnectOptions::default()).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with(
+        addrs: impl Destination + std::fmt::Debug,
+        options: ConnectOptions,
+    ) -> Result<Handshake> {
+        Self::connect_with_options(addrs, WssConnectOptions::default().retry(options)).await
+    }
+    /// Connect to the following address, upgrading as set by the options
+    /// ```no_run
+    /// # async fn example() -> canary::Result<()> {
+    /// # use canary::providers::{WebSocket, WssConnectOptions};
+    /// let options = WssConnectOptions::default().path("/canary");
+    /// let chan = WebSocket::connect_with_options("example.com:80", options).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with_options(
+        addrs: impl Destination + std::fmt::Debug,
+        options: WssConnectOptions,
+    ) -> Result<Handshake> {
+        let retry = options.retry.clone();
+        let upgrade = Upgrade::new(options)?;
+        let raw = retry.retry(&addrs, || upgrade.connect(&addrs)).await?;
+        Ok(Handshake::from(Channel::from_raw(
+            raw,
+            Default::default(),
+            Default::default(),
+        )))
     }
 }
+
+#[cfg(not(target_arch = "wasm32"))]
+/// a rejected upgrade tells its status, other failures keep the kind of their io error
+fn upgrade_err(e: wss::tungstenite::Error) -> crate::Error {
+    match e {
+        wss::tungstenite::Error::Io(e) => e.into(),
+        wss::tungstenite::Error::Http(response) => {
+            let message = format!("websocket upgrade rejected with {}", response.status());
+            match response.status() {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                    err!(permission_denied, message)
+                }
+                _ => err!(conn_refused, message),
+            }
+        }
+        e => err!(other, e.to_string()),
+    }
+}
+
+/// Frames are sent as a single binary message each, and tungstenite sends every message
+/// as a single websocket frame, so both are capped at the same size
+#[cfg(not(target_arch = "wasm32"))]
+fn config(max_message_size: usize) -> Option<wss::tungstenite::protocol::WebSocketConfig> {
+    Some(wss::tungstenite::protocol::WebSocketConfig {
+        max_message_size: Some(max_message_size),
+        max_frame_size: Some(max_message_size),
+        ..Default::default()
+    })
+}
+
 #[cfg(target_arch = "wasm32")]
 impl WebSocket {
     #[inline]
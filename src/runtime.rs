@@ -0,0 +1,477 @@
+use std::fmt::{self, Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use futures::future::Shared;
+use futures::{pin_mut, select, FutureExt};
+
+#[cfg(not(target_arch = "wasm32"))]
+use futures::Stream;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+use crate::err;
+
+#[inline]
+/// Wait for the given duration, on the timer channels use for their own timeouts
+/// ```no_run
+/// # async fn example() {
+/// canary::runtime::sleep(std::time::Duration::from_secs(1)).await;
+/// # }
+/// ```
+pub async fn sleep(duration: Duration) {
+    crate::io::sleep(duration).await
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Error returned by `timeout` when the future didn't finish in time
+pub struct Elapsed(Duration);
+
+impl Elapsed {
+    #[inline]
+    /// time the future was given
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl Display for Elapsed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline of {:?} has elapsed", self.0)
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+impl From<Elapsed> for std::io::Error {
+    #[inline]
+    fn from(elapsed: Elapsed) -> Self {
+        std::io::Error::new(std::io::ErrorKind::TimedOut, elapsed)
+    }
+}
+
+impl From<Elapsed> for crate::Error {
+    #[inline]
+    fn from(elapsed: Elapsed) -> Self {
+        crate::Error::new(elapsed.into())
+    }
+}
+
+/// Run the future, failing with `Elapsed` if it doesn't finish within `duration`.
+/// The future is dropped once the time runs out.
+///
+/// `Elapsed` converts into a `TimedOut` error, so `?` works in functions returning
+/// `canary::Result`
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+/// use std::time::Duration;
+///
+/// let reply: String = canary::runtime::timeout(Duration::from_secs(5), chan.receive()).await??;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn timeout<F: Future>(duration: Duration, fut: F) -> Result<F::Output, Elapsed> {
+    let fut = fut.fuse();
+    let timer = sleep(duration).fuse();
+    pin_mut!(fut, timer);
+    select! {
+        output = fut => Ok(output),
+        _ = timer => Err(Elapsed(duration)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Error returned when a `CancelToken` was cancelled before the operation finished
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "operation was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl From<Cancelled> for std::io::Error {
+    #[inline]
+    fn from(cancelled: Cancelled) -> Self {
+        std::io::Error::other(cancelled)
+    }
+}
+
+impl From<Cancelled> for crate::Error {
+    #[inline]
+    fn from(cancelled: Cancelled) -> Self {
+        crate::Error::new(cancelled.into())
+    }
+}
+
+#[derive(Clone)]
+/// Token cancelling every operation racing against it, such as the pumps of a proxy
+/// between two channels: once one side fails or closes, the other stops waiting.
+///
+/// Clones share the same state. Children made with `child` are cancelled along with
+/// their parent, but cancelling a child leaves its parent alone
+/// ```no_run
+/// # async fn example(client: canary::Channel, upstream: canary::Channel) -> canary::Result<()> {
+/// use canary::runtime::CancelToken;
+///
+/// let token = CancelToken::new();
+/// let (mut client_send, mut client_receive) = client.split();
+/// let (mut upstream_send, mut upstream_receive) = upstream.split();
+/// let up = async {
+///     loop {
+///         let message: serde_json::Value = client_receive.receive_cancellable(&token).await?;
+///         upstream_send.send_cancellable(message, &token).await?;
+///     }
+/// };
+/// let down = async {
+///     loop {
+///         let message: serde_json::Value = upstream_receive.receive_cancellable(&token).await?;
+///         client_send.send_cancellable(message, &token).await?;
+///     }
+/// };
+/// // the first side to fail cancels the token, which stops the other one
+/// let (up, down): (canary::Result<()>, canary::Result<()>) = futures::join!(up, down);
+/// # Ok(())
+/// # }
+/// ```
+pub struct CancelToken(Arc<CancelState>);
+
+struct CancelState {
+    /// dropped once cancelled, which resolves `receiver`
+    sender: Mutex<Option<oneshot::Sender<()>>>,
+    receiver: Shared<oneshot::Receiver<()>>,
+    children: Mutex<Vec<Weak<CancelState>>>,
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        let (sender, receiver) = oneshot::channel();
+        CancelToken(Arc::new(CancelState {
+            sender: Mutex::new(Some(sender)),
+            receiver: receiver.shared(),
+            children: Mutex::new(Vec::new()),
+        }))
+    }
+}
+
+impl CancelToken {
+    #[inline]
+    /// token that isn't cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Token cancelled along with this one, already cancelled if this one is
+    pub fn child(&self) -> CancelToken {
+        let child = CancelToken::new();
+        let mut children = self.0.children.lock().unwrap_or_else(|e| e.into_inner());
+        if self.is_cancelled() {
+            child.cancel();
+        } else {
+            // forget the children that were dropped
+            children.retain(|child| child.strong_count() > 0);
+            children.push(Arc::downgrade(&child.0));
+        }
+        child
+    }
+    /// Cancel the token and its children, waking everything waiting on them
+    pub fn cancel(&self) {
+        Self::cancel_state(&self.0)
+    }
+    fn cancel_state(state: &CancelState) {
+        let children = {
+            let mut children = state.children.lock().unwrap_or_else(|e| e.into_inner());
+            state
+                .sender
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .take();
+            std::mem::take(&mut *children)
+        };
+        for child in children.iter().filter_map(Weak::upgrade) {
+            Self::cancel_state(&child);
+        }
+    }
+    #[inline]
+    /// whether the token was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0
+            .sender
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_none()
+    }
+    /// Wait until the token is cancelled
+    pub async fn cancelled(&self) {
+        // the sender is never used, so this only resolves once it's dropped
+        self.0.receiver.clone().await.ok();
+    }
+    /// Run the future, failing with `Cancelled` if the token is cancelled first.
+    /// The future is dropped once the token is cancelled.
+    ///
+    /// `Cancelled` converts into a `canary::Error`, so `?` works in functions returning
+    /// `canary::Result`
+    pub async fn run_until_cancelled<F: Future>(&self, fut: F) -> Result<F::Output, Cancelled> {
+        if self.is_cancelled() {
+            return Err(Cancelled);
+        }
+        let fut = fut.fuse();
+        let cancelled = self.cancelled().fuse();
+        pin_mut!(fut, cancelled);
+        select! {
+            output = fut => Ok(output),
+            _ = cancelled => Err(Cancelled),
+        }
+    }
+    #[inline]
+    /// Guard cancelling the token when dropped, such as when the task
+    /// owning it returns or panics
+    pub fn drop_guard(self) -> CancelGuard {
+        CancelGuard(self)
+    }
+}
+
+/// Cancels its token when dropped, see `CancelToken::drop_guard`
+pub struct CancelGuard(CancelToken);
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        self.0.cancel()
+    }
+}
+
+/// Run the operation of a channel until the token is cancelled,
+/// and cancel the token if the channel failed, since it can't be used anymore.
+/// Objects that fail to (de)serialize and errors sent by the peer leave the channel usable
+pub(crate) async fn cancellable<T>(
+    token: &CancelToken,
+    fut: impl Future<Output = crate::Result<T>>,
+) -> crate::Result<T> {
+    let res = token.run_until_cancelled(fut).await?;
+    if let Err(e) = &res {
+        let usable = crate::error::ErrorKind::of(e) == crate::error::ErrorKind::Serialization
+            || crate::channel::remote::RemoteError::of(e).is_some();
+        if !usable {
+            token.cancel();
+        }
+    }
+    res
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Stream yielding the instant of every tick, the first one right away
+/// and then every `period`. Ticks missed while the stream wasn't polled
+/// are yielded at once, so the average rate stays at one tick per `period`.
+///
+/// Not available on wasm, which has no `Instant`
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+/// use futures::StreamExt;
+/// use std::time::Duration;
+///
+/// let ticks = canary::runtime::interval(Duration::from_secs(1));
+/// futures::pin_mut!(ticks);
+/// while let Some(tick) = ticks.next().await {
+///     chan.send(format!("{:?}", tick)).await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn interval(period: Duration) -> impl Stream<Item = Instant> {
+    futures::stream::unfold(Instant::now(), move |deadline| async move {
+        sleep(deadline.saturating_duration_since(Instant::now())).await;
+        Some((deadline, deadline + period))
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Run the future in the background on the current tokio runtime.
+/// Has to be called within a runtime
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+/// let task = canary::runtime::spawn(async move { chan.receive::<String>().await });
+/// let message = task.await??;
+/// # Ok(())
+/// # }
+/// ```
+pub fn spawn<T: Send + 'static>(fut: impl Future<Output = T> + Send + 'static) -> TaskHandle<T> {
+    TaskHandle(tokio::spawn(fut))
+}
+
+#[cfg(target_arch = "wasm32")]
+/// Run the future in the background on the event loop of the browser.
+/// Futures don't need to be `Send` there, since everything runs on the same thread
+/// ```no_run
+/// # async fn example() -> canary::Result<()> {
+/// use canary::providers::Addr;
+///
+/// let addr: Addr = "wss@example.com:8080".parse()?;
+/// let task = canary::runtime::spawn(async move {
+///     let mut chan = addr.connect().await?;
+///     chan.send("hello from the browser").await?;
+///     chan.receive::<String>().await
+/// });
+/// let reply = task.await??;
+/// # Ok(())
+/// # }
+/// ```
+pub fn spawn<T: 'static>(fut: impl Future<Output = T> + 'static) -> TaskHandle<T> {
+    let (sender, receiver) = futures::channel::oneshot::channel();
+    wasm_bindgen_futures::spawn_local(async move {
+        sender.send(fut.await).ok();
+    });
+    TaskHandle(receiver)
+}
+
+#[cfg(target_arch = "wasm32")]
+/// Handle of a task started with `spawn`, resolving to what it returned.
+/// Dropping the handle lets the task run to completion
+pub struct TaskHandle<T>(futures::channel::oneshot::Receiver<T>);
+
+#[cfg(target_arch = "wasm32")]
+impl<T> Future for TaskHandle<T> {
+    type Output = crate::Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0)
+            .poll(cx)
+            .map(|res| res.map_err(|_| err!(other, "task panicked")))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Run `f` on the thread pool of the runtime set aside for blocking work,
+/// such as hashing passwords or reading files with `std::fs`.
+///
+/// This is the way to do CPU-heavy or blocking work in a task serving a channel:
+/// done inline, it stalls every other task of the executor thread, including
+/// the keepalive of other channels. Has to be called within a runtime,
+/// and the work can't be aborted once it started.
+///
+/// Not available on wasm, which has no threads
+/// ```no_run
+/// # async fn example(mut chan: canary::Channel) -> canary::Result<()> {
+/// let password: String = chan.receive().await?;
+/// let hash = canary::runtime::spawn_blocking(move || {
+///     // stand-in for an expensive hash such as argon2
+///     password.bytes().fold(0u64, |hash, byte| hash.rotate_left(5) ^ byte as u64)
+/// })
+/// .await?;
+/// chan.send(hash).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn spawn_blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> TaskHandle<T> {
+    TaskHandle(tokio::task::spawn_blocking(f))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Handle of a task started with `spawn` or `spawn_blocking`, resolving to what it returned.
+/// Fails if the task panicked. Dropping the handle lets the task run to completion
+pub struct TaskHandle<T>(tokio::task::JoinHandle<T>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T> TaskHandle<T> {
+    #[inline]
+    /// whether the task finished
+    pub fn is_finished(&self) -> bool {
+        self.0.is_finished()
+    }
+    #[inline]
+    /// Abort the task, which is dropped the next time it yields.
+    /// Awaiting the handle afterwards fails unless the task finished first.
+    /// Tasks of `spawn_blocking` can't be aborted once they started
+    pub fn abort(&self) {
+        self.0.abort()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T> Future for TaskHandle<T> {
+    type Output = crate::Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx).map(|res| {
+            res.map_err(|e| {
+                if e.is_panic() {
+                    err!(other, "task panicked")
+                } else {
+                    err!(other, "task was aborted, or the runtime is shutting down")
+                }
+            })
+        })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+/// Owner of background tasks tied to the lifetime of a handler, such as one serving a channel.
+///
+/// Tasks started with `Scope::spawn` are aborted when the scope is dropped, so a handler
+/// that returns because its channel closed or failed doesn't leave them running.
+/// Each task also gets a handle to await or abort it on its own.
+///
+/// Not available on wasm, where tasks can't be aborted
+/// ```no_run
+/// # async fn example(chan: canary::Channel) -> canary::Result<()> {
+/// use std::time::Duration;
+/// use futures::StreamExt;
+///
+/// let scope = canary::runtime::Scope::new();
+/// let (mut send, mut receive) = chan.split();
+/// // pushes every second until the handler returns
+/// scope.spawn(async move {
+///     let ticks = canary::runtime::interval(Duration::from_secs(1));
+///     futures::pin_mut!(ticks);
+///     while ticks.next().await.is_some() {
+///         send.send("tick").await?;
+///     }
+///     Ok::<_, canary::Error>(())
+/// });
+/// while let Some(message) = receive.try_receive::<String>().await? {
+///     println!("{}", message);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Scope {
+    tasks: std::sync::Mutex<Vec<tokio::task::AbortHandle>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Scope {
+    #[inline]
+    /// scope without any task
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the future in the background on the current tokio runtime,
+    /// until it finishes or the scope is dropped. Has to be called within a runtime
+    pub fn spawn<T: Send + 'static>(
+        &self,
+        fut: impl Future<Output = T> + Send + 'static,
+    ) -> TaskHandle<T> {
+        let handle = tokio::spawn(fut);
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        // forget the tasks that already finished
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(handle.abort_handle());
+        TaskHandle(handle)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let tasks = self.tasks.get_mut().unwrap_or_else(|e| e.into_inner());
+        for task in tasks.drain(..) {
+            task.abort();
+        }
+    }
+}
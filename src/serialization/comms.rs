@@ -12,7 +12,7 @@ use crate::io::wss::tungstenite::Message;
 use reqwasm::websocket::Message;
 
 use super::formats::{ReadFormat, SendFormat};
-use super::zc;
+use super::{framing, zc};
 
 /// send an item through the stream
 pub async fn tx<T, O, F: SendFormat>(st: &mut T, obj: O, f: &mut F) -> Result<usize>
@@ -21,11 +21,7 @@ where
     O: Serialize,
 {
     let serialized = f.serialize(&obj)?;
-    zc::send_u64(st, serialized.len() as _).await?;
-    // return length of object sent
-    st.write_all(&serialized).await?;
-    st.flush().await?;
-    Ok(serialized.len())
+    tx_bytes(st, &serialized).await
 }
 
 /// receive an item from the stream
@@ -34,16 +30,48 @@ where
     T: Read + Unpin,
     O: DeserializeOwned,
 {
-    let size = zc::read_u64(st).await?;
+    let buf = rx_bytes(st).await?;
+    f.deserialize(&buf)
+}
+
+/// send a buffer through the stream prefixed with its length
+pub async fn tx_bytes<T>(st: &mut T, bytes: &[u8]) -> Result<usize>
+where
+    T: Write + Unpin,
+{
+    st.write_all(&framing::encode_len(bytes.len())).await?;
+    // return length of object sent
+    st.write_all(bytes).await?;
+    st.flush().await?;
+    Ok(bytes.len())
+}
+
+/// receive a length-prefixed buffer from the stream
+pub async fn rx_bytes<T>(st: &mut T) -> Result<Vec<u8>>
+where
+    T: Read + Unpin,
+{
+    rx_bytes_within(st, usize::MAX).await
+}
+
+/// receive a length-prefixed buffer from the stream,
+/// failing on the prefix without allocating if the buffer is longer than `max`
+pub(crate) async fn rx_bytes_within<T>(st: &mut T, max: usize) -> Result<Vec<u8>>
+where
+    T: Read + Unpin,
+{
+    let mut prefix = [0; framing::LEN_PREFIX];
+    st.read_exact(&mut prefix).await?;
+    let size = framing::decode_len(prefix)?;
+    framing::check_len(size, max)?;
     // this is done for fallibility, we don't want people sending in usize::MAX
     // as the len unexpectedly crashing the program
-    let mut buf = zc::try_vec(size as usize)?;
+    let mut buf = zc::try_vec(size)?;
     // read message into buffer
     st.read_exact(&mut buf).await?;
-    f.deserialize(&buf)
+    Ok(buf)
 }
 
-#[cfg(not(target_arch = "wasm32"))]
 /// send a message from a websocket stream
 pub async fn wss_tx<T, O, F: SendFormat>(st: &mut T, obj: O, f: &mut F) -> Result<usize>
 where
@@ -52,24 +80,32 @@ where
     <T as futures::prelude::Sink<Message>>::Error: ToString,
 {
     let serialized = f.serialize(&obj)?;
-    let len = serialized.len();
-    let msg = Message::Binary(serialized.to_vec());
+    wss_tx_bytes(st, serialized).await
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// send a buffer as a binary message through a websocket stream
+pub async fn wss_tx_bytes<T>(st: &mut T, bytes: Vec<u8>) -> Result<usize>
+where
+    T: futures::prelude::Sink<Message> + Unpin,
+    <T as futures::prelude::Sink<Message>>::Error: ToString,
+{
+    let len = bytes.len();
+    let msg = Message::Binary(bytes);
     st.feed(msg).await.map_err(|e| err!(e.to_string()))?;
     st.flush().await.map_err(|e| err!(e.to_string()))?;
     Ok(len)
 }
 
 #[cfg(target_arch = "wasm32")]
-/// send a message from a websocket stream
-pub async fn wss_tx<T, O, F: SendFormat>(st: &mut T, obj: O, f: &mut F) -> Result<usize>
+/// send a buffer as a binary message through a websocket stream
+pub async fn wss_tx_bytes<T>(st: &mut T, bytes: Vec<u8>) -> Result<usize>
 where
     T: futures::prelude::Sink<Message> + Unpin,
-    O: Serialize,
     <T as futures::prelude::Sink<Message>>::Error: ToString,
 {
-    let serialized = f.serialize(&obj)?;
-    let len = serialized.len();
-    let msg = Message::Bytes(serialized);
+    let len = bytes.len();
+    let msg = Message::Bytes(bytes);
     st.feed(msg).await.map_err(|e| err!(e.to_string()))?;
     st.flush().await.map_err(|e| err!(e.to_string()))?;
     Ok(len)
@@ -84,19 +120,41 @@ where
         > + Unpin,
     O: DeserializeOwned,
 {
-    let msg = st
-        .next()
-        .await
-        .ok_or(err!(broken_pipe, "websocket connection broke"))?
-        .map_err(|e| err!(broken_pipe, e))?;
+    let buf = wss_rx_bytes(st).await?;
+    f.deserialize(&buf)
+}
 
-    match msg {
-        Message::Binary(vec) => f.deserialize(&vec),
-        Message::Text(_) => err!((invalid_data, "expected binary message, found text message")),
-        Message::Ping(_) => err!((invalid_data, "expected binary message, found ping message")),
-        Message::Pong(_) => err!((invalid_data, "expected binary message, found pong message")),
-        Message::Close(_) => err!((invalid_data, "expected binary message, found close message")),
-        Message::Frame(_) => err!((invalid_data, "expected binary message, found frame")),
+#[cfg(not(target_arch = "wasm32"))]
+/// receive a binary message from a websocket stream
+pub async fn wss_rx_bytes<T>(st: &mut T) -> Result<Vec<u8>>
+where
+    T: futures::prelude::Stream<
+            Item = std::result::Result<Message, crate::io::wss::tungstenite::error::Error>,
+        > + Unpin,
+{
+    loop {
+        let msg = st
+            .next()
+            .await
+            .ok_or(err!(broken_pipe, "websocket connection broke"))?
+            .map_err(|e| match e {
+                crate::io::wss::tungstenite::Error::Capacity(e) => err!(invalid_data, e),
+                e => err!(broken_pipe, e),
+            })?;
+
+        match msg {
+            Message::Binary(vec) => return Ok(vec),
+            // tungstenite answers pings by itself, they only keep the connection alive
+            Message::Ping(_) | Message::Pong(_) => continue,
+            Message::Close(_) => return err!((broken_pipe, "websocket connection closed")),
+            Message::Text(_) => {
+                return err!((invalid_data, "expected binary message, found text message"))
+            }
+            // only yielded when reading raw frames, continuations are reassembled into messages
+            Message::Frame(_) => {
+                return err!((invalid_data, "expected binary message, found frame"))
+            }
+        }
     }
 }
 
@@ -108,6 +166,18 @@ where
             Item = std::result::Result<Message, reqwasm::websocket::WebSocketError>,
         > + Unpin,
     O: DeserializeOwned,
+{
+    let buf = wss_rx_bytes(st).await?;
+    f.deserialize(&buf)
+}
+
+#[cfg(target_arch = "wasm32")]
+/// receive a binary message from a websocket stream
+pub async fn wss_rx_bytes<T>(st: &mut T) -> Result<Vec<u8>>
+where
+    T: futures::prelude::Stream<
+            Item = std::result::Result<Message, reqwasm::websocket::WebSocketError>,
+        > + Unpin,
 {
     let msg = st
         .next()
@@ -116,7 +186,7 @@ where
         .map_err(|e| err!(broken_pipe, e.to_string()))?;
 
     match msg {
-        Message::Bytes(vec) => f.deserialize(&vec),
+        Message::Bytes(vec) => Ok(vec),
         Message::Text(_) => err!((invalid_data, "expected binary data, found text")),
     }
 }
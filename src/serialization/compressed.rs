@@ -0,0 +1,125 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+#[cfg(feature = "zstd_compression")]
+use crate::err;
+use crate::Result;
+
+use super::formats::{Format, ReadFormat, SendFormat};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[repr(u8)]
+/// compression algorithms allowed for channels,
+/// later variants are preferred during negotiation
+pub enum Compression {
+    /// messages are sent as serialized
+    #[default]
+    None = 0,
+    #[cfg(feature = "zstd_compression")]
+    /// the Zstandard compression algorithm
+    Zstd = 1,
+}
+
+impl Compression {
+    /// every algorithm this side was compiled with, in order of preference
+    pub const SUPPORTED: &'static [Compression] = &[
+        #[cfg(feature = "zstd_compression")]
+        Compression::Zstd,
+        Compression::None,
+    ];
+
+    #[inline]
+    /// Pick the algorithm used by both peers given the algorithms each supports,
+    /// falls back to `None` if they have none in common
+    pub fn negotiate(ours: &[Compression], theirs: &[u8]) -> Compression {
+        ours.iter()
+            .copied()
+            .filter(|compression| theirs.contains(&(*compression as u8)))
+            .max_by_key(|compression| *compression as u8)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy)]
+/// Format that compresses every message after serializing it with the inner format.
+///
+/// Channels get it through `Channel::compressed`,
+/// which negotiates the algorithm with the peer.
+pub struct Compressed {
+    /// inner format
+    pub format: Format,
+    /// algorithm used to compress messages
+    pub compression: Compression,
+}
+
+impl Compressed {
+    #[inline]
+    /// Compress the messages of a format with an algorithm
+    pub fn new(format: Format, compression: Compression) -> Self {
+        Compressed {
+            format,
+            compression,
+        }
+    }
+}
+
+impl SendFormat for Compressed {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        self.serialize_into(obj, &mut bytes)?;
+        Ok(bytes)
+    }
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> Result<usize> {
+        match self.compression {
+            Compression::None => SendFormat::serialize_into(&mut self.format, obj, buf),
+            #[cfg(feature = "zstd_compression")]
+            Compression::Zstd => {
+                let bytes = SendFormat::serialize(&mut self.format, obj)?;
+                let start = buf.len();
+                zstd::stream::copy_encode(&*bytes, &mut *buf, zstd::DEFAULT_COMPRESSION_LEVEL)
+                    .map_err(err!(@invalid_data))?;
+                Ok(buf.len() - start)
+            }
+        }
+    }
+}
+
+impl ReadFormat for Compressed {
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        match self.compression {
+            Compression::None => ReadFormat::deserialize(&mut self.format, bytes),
+            #[cfg(feature = "zstd_compression")]
+            Compression::Zstd => {
+                let bytes = zstd::stream::decode_all(bytes).map_err(err!(@invalid_data))?;
+                ReadFormat::deserialize(&mut self.format, &bytes)
+            }
+        }
+    }
+    #[cfg(feature = "json_ser")]
+    fn deserialize_value(&mut self, bytes: &[u8]) -> Result<serde_json::Value> {
+        match self.compression {
+            Compression::None => self.format.deserialize_value(bytes),
+            #[cfg(feature = "zstd_compression")]
+            Compression::Zstd => {
+                let bytes = zstd::stream::decode_all(bytes).map_err(err!(@invalid_data))?;
+                self.format.deserialize_value(&bytes)
+            }
+        }
+    }
+    fn deserialize_borrowed<'de, T>(&mut self, bytes: &'de [u8]) -> Result<T>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        match self.compression {
+            Compression::None => self.format.deserialize_borrowed(bytes),
+            #[cfg(feature = "zstd_compression")]
+            Compression::Zstd => err!((
+                unsupported,
+                "compressed messages are decompressed into a new buffer, objects can't borrow from them"
+            )),
+        }
+    }
+}
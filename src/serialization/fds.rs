@@ -0,0 +1,112 @@
+use std::io::{IoSlice, IoSliceMut};
+use std::mem::MaybeUninit;
+use std::os::fd::{BorrowedFd, OwnedFd};
+
+use rustix::net::{
+    recvmsg, sendmsg, RecvAncillaryBuffer, RecvAncillaryMessage, RecvFlags, ReturnFlags,
+    SendAncillaryBuffer, SendAncillaryMessage, SendFlags,
+};
+use tokio::io::Interest;
+
+use crate::io::{ReadExt, UnixStream, WriteExt};
+use crate::{err, Result};
+
+use super::{framing, zc};
+
+/// most file descriptors that can be passed along with a single frame
+const MAX_FDS: usize = 8;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+/// received file descriptors are closed on exec, like the ones opened by the standard library
+const RECV_FLAGS: RecvFlags = RecvFlags::CMSG_CLOEXEC;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const RECV_FLAGS: RecvFlags = RecvFlags::empty();
+
+/// send a length-prefixed buffer through a unix stream,
+/// passing the file descriptors along with the first bytes of the frame
+pub(crate) async fn tx_bytes_with_fds(
+    st: &mut UnixStream,
+    bytes: &[u8],
+    fds: &[BorrowedFd<'_>],
+) -> Result<usize> {
+    if fds.len() > MAX_FDS {
+        return err!((
+            invalid_input,
+            format!("at most {MAX_FDS} file descriptors can be sent with a frame")
+        ));
+    }
+    let prefix = framing::encode_len(bytes.len());
+    let sent = loop {
+        st.writable().await?;
+        let stream: &UnixStream = st;
+        let sent = stream.try_io(Interest::WRITABLE, || {
+            let mut space = [MaybeUninit::uninit(); rustix::cmsg_space!(ScmRights(MAX_FDS))];
+            let mut control = SendAncillaryBuffer::new(&mut space);
+            control.push(SendAncillaryMessage::ScmRights(fds));
+            let iov = [IoSlice::new(&prefix), IoSlice::new(bytes)];
+            Ok(sendmsg(stream, &iov, &mut control, SendFlags::empty())?)
+        });
+        match sent {
+            Ok(sent) => break sent,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e.into()),
+        }
+    };
+    // the file descriptors went out with the first bytes, the rest is written as usual
+    if sent < prefix.len() {
+        st.write_all(&prefix[sent..]).await?;
+        st.write_all(bytes).await?;
+    } else {
+        st.write_all(&bytes[sent - prefix.len()..]).await?;
+    }
+    st.flush().await?;
+    Ok(bytes.len())
+}
+
+/// receive a length-prefixed buffer of at most `max` bytes from a unix stream,
+/// collecting the file descriptors passed along with it into `fds`
+pub(crate) async fn rx_bytes_with_fds(
+    st: &mut UnixStream,
+    fds: &mut Vec<OwnedFd>,
+    max: usize,
+) -> Result<Vec<u8>> {
+    let mut prefix = [0; framing::LEN_PREFIX];
+    // the file descriptors arrive with the first bytes of the frame,
+    // so only those are read with `recvmsg`
+    let read = loop {
+        st.readable().await?;
+        let stream: &UnixStream = st;
+        let read = stream.try_io(Interest::READABLE, || {
+            let mut space = [MaybeUninit::uninit(); rustix::cmsg_space!(ScmRights(MAX_FDS))];
+            let mut control = RecvAncillaryBuffer::new(&mut space);
+            let mut iov = [IoSliceMut::new(&mut prefix)];
+            let msg = recvmsg(stream, &mut iov, &mut control, RECV_FLAGS)?;
+            for message in control.drain() {
+                if let RecvAncillaryMessage::ScmRights(received) = message {
+                    fds.extend(received);
+                }
+            }
+            Ok((msg.bytes, msg.flags))
+        });
+        match read {
+            Ok(read) => break read,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e.into()),
+        }
+    };
+    match read {
+        (_, flags) if flags.contains(ReturnFlags::CTRUNC) => {
+            return err!((
+                invalid_data,
+                "received more file descriptors than fit in a frame"
+            ))
+        }
+        (0, _) => return err!((unexpected_eof, "unix stream closed")),
+        (read, _) => st.read_exact(&mut prefix[read..]).await?,
+    };
+    let size = framing::decode_len(prefix)?;
+    framing::check_len(size, max)?;
+    let mut buf = zc::try_vec(size)?;
+    st.read_exact(&mut buf).await?;
+    Ok(buf)
+}
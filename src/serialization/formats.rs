@@ -1,13 +1,39 @@
+//! Serialization formats of channels.
+//!
+//! Every format is behind a feature, and the enabled ones make up the `Format` enum:
+//!
+//! | feature           | format                | dependency   | default |
+//! |-------------------|-----------------------|--------------|---------|
+//! | `bincode_ser`     | `Format::Bincode`     | `bincode`    | yes     |
+//! | `json_ser`        | `Format::Json`        | `serde_json` | yes     |
+//! | `bson_ser`        | `Format::Bson`        | `bson`       | yes     |
+//! | `postcard_ser`    | `Format::Postcard`    | `postcard`   | yes     |
+//! | `messagepack_ser` | `Format::MessagePack` | `rmp-serde`  | yes     |
+//! | `cbor_ser`        | `Format::Cbor`        | `ciborium`   | yes     |
+//!
+//! At least one of them has to be enabled, `Format::default()` is the first one in this table.
+//! A build with `default-features = false, features = ["postcard_ser"]` only pulls in postcard.
+//!
+//! Canary itself needs std, but the wire format doesn't: an embedded client that implements
+//! the length prefix of `framing` and serializes with postcard can talk to a canary
+//! server using `Format::Postcard` over an unencrypted channel.
+//! Every frame starts with its `FrameKind` byte, `0` for messages, followed by the message.
+
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+#[cfg(feature = "bincode_ser")]
 use bincode::Options;
 use serde::{de::DeserializeOwned, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
-use crate::err;
+use crate::{err, error::SerializationError};
 
-#[derive(Serialize_repr, Deserialize_repr, Clone, Copy)]
+#[derive(Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(u8)]
 /// formats allowed for channels
 pub enum Format {
+    #[cfg(feature = "bincode_ser")]
     /// the Bincode serialization format
     Bincode = 1,
     #[cfg(feature = "json_ser")]
@@ -22,26 +48,188 @@ pub enum Format {
     #[cfg(feature = "messagepack_ser")]
     /// the MessagePack serialization format
     MessagePack = 5,
+    #[cfg(feature = "cbor_ser")]
+    /// the CBOR serialization format
+    Cbor = 6,
+}
+
+#[cfg(not(any(
+    feature = "bincode_ser",
+    feature = "json_ser",
+    feature = "bson_ser",
+    feature = "postcard_ser",
+    feature = "messagepack_ser",
+    feature = "cbor_ser"
+)))]
+compile_error!("canary needs at least one of the format features, such as `postcard_ser`");
+
+impl Format {
+    /// every format this build was compiled with, in the order of their tags
+    pub const SUPPORTED: &'static [Format] = &[
+        #[cfg(feature = "bincode_ser")]
+        Format::Bincode,
+        #[cfg(feature = "json_ser")]
+        Format::Json,
+        #[cfg(feature = "bson_ser")]
+        Format::Bson,
+        #[cfg(feature = "postcard_ser")]
+        Format::Postcard,
+        #[cfg(feature = "messagepack_ser")]
+        Format::MessagePack,
+        #[cfg(feature = "cbor_ser")]
+        Format::Cbor,
+    ];
 }
 
 impl Default for Format {
+    #[inline]
+    /// the first supported format, which is bincode with the default features
     fn default() -> Self {
-        Format::Bincode
+        Format::SUPPORTED[0]
+    }
+}
+
+impl TryFrom<u8> for Format {
+    type Error = crate::Error;
+
+    #[inline]
+    fn try_from(tag: u8) -> crate::Result<Self> {
+        Ok(match tag {
+            #[cfg(feature = "bincode_ser")]
+            1 => Format::Bincode,
+            #[cfg(feature = "json_ser")]
+            2 => Format::Json,
+            #[cfg(feature = "bson_ser")]
+            3 => Format::Bson,
+            #[cfg(feature = "postcard_ser")]
+            4 => Format::Postcard,
+            #[cfg(feature = "messagepack_ser")]
+            5 => Format::MessagePack,
+            #[cfg(feature = "cbor_ser")]
+            6 => Format::Cbor,
+            tag => err!((invalid_data, format!("unknown format tag {}", tag)))?,
+        })
+    }
+}
+
+impl Format {
+    #[inline]
+    /// Deserialize an object from bytes in this format, without any channel involved.
+    ///
+    /// Arbitrary input returns an error instead of panicking, for every variant,
+    /// which makes this the entry point to fuzz the types a service receives,
+    /// as the `try_deserialize` target in `fuzz/` does with `cargo fuzz run try_deserialize`.
+    /// Deeply recursive types are the exception: a deep enough input may overflow the stack.
+    /// ```no_run
+    /// # use canary::serialization::formats::Format;
+    /// let bytes = [1, 2, 3];
+    /// let parsed: canary::Result<(u8, u8)> = Format::Bincode.try_deserialize(&bytes);
+    /// ```
+    pub fn try_deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> crate::Result<T> {
+        ReadFormat::deserialize(&mut { *self }, bytes)
+    }
+    /// Guess the format a serialized object is in, such as the payload of a message,
+    /// for inspection tools that don't know what their peers use.
+    ///
+    /// This is a heuristic and not authoritative, never use it to pick how to read a channel.
+    /// Only self-describing formats can be recognized, and only when the whole input
+    /// is a single valid value in them: JSON objects, arrays and strings,
+    /// BSON documents, CBOR and MessagePack values.
+    /// Bincode and Postcard carry no structure to recognize, so they return `None`,
+    /// as do inputs several formats accept, such as a small integer in CBOR and MessagePack
+    /// ```no_run
+    /// # use canary::serialization::formats::Format;
+    /// assert_eq!(Format::detect(br#"{"id": 1}"#), Some(Format::Json));
+    /// ```
+    pub fn detect(bytes: &[u8]) -> Option<Format> {
+        let checks: &[(Format, Check)] = &[
+            #[cfg(feature = "json_ser")]
+            (Format::Json, is_json),
+            #[cfg(feature = "bson_ser")]
+            (Format::Bson, is_bson),
+            #[cfg(feature = "messagepack_ser")]
+            (Format::MessagePack, is_messagepack),
+            #[cfg(feature = "cbor_ser")]
+            (Format::Cbor, is_cbor),
+        ];
+        let mut matching = checks
+            .iter()
+            .filter(|(_, check)| check(bytes))
+            .map(|(format, _)| *format);
+        match (matching.next(), matching.next()) {
+            (Some(format), None) => Some(format),
+            // valid in several formats, too ambiguous to guess
+            _ => None,
+        }
+    }
+}
+
+/// tells whether the input looks like a format, see `Format::detect`
+type Check = fn(&[u8]) -> bool;
+
+#[cfg(feature = "json_ser")]
+/// JSON is text, only documents starting like an object, an array or a string count,
+/// numbers and literals are too easily mistaken for binary formats
+fn is_json(bytes: &[u8]) -> bool {
+    let start = bytes.iter().find(|byte| !byte.is_ascii_whitespace());
+    matches!(start, Some(b'{' | b'[' | b'"'))
+        && serde_json::from_slice::<serde::de::IgnoredAny>(bytes).is_ok()
+}
+
+#[cfg(feature = "bson_ser")]
+/// BSON documents start with their own length and end with a zero byte
+fn is_bson(bytes: &[u8]) -> bool {
+    match bytes {
+        [a, b, c, d, .., 0] if i32::from_le_bytes([*a, *b, *c, *d]) as usize == bytes.len() => {
+            bson::Document::from_reader(bytes).is_ok()
+        }
+        _ => false,
     }
 }
 
+#[cfg(feature = "cbor_ser")]
+/// the whole input has to be a single CBOR value
+fn is_cbor(mut bytes: &[u8]) -> bool {
+    ciborium::de::from_reader::<serde::de::IgnoredAny, _>(&mut bytes).is_ok() && bytes.is_empty()
+}
+
+#[cfg(feature = "messagepack_ser")]
+/// the whole input has to be a single MessagePack value
+fn is_messagepack(mut bytes: &[u8]) -> bool {
+    rmp_serde::from_read::<_, serde::de::IgnoredAny>(&mut bytes).is_ok() && bytes.is_empty()
+}
+
 impl SendFormat for Format {
     fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
         match self {
+            #[cfg(feature = "bincode_ser")]
             Format::Bincode => Bincode.serialize(obj),
             #[cfg(feature = "json_ser")]
-            Format::Json => Json.serialize(obj),
+            Format::Json => Json::compact().serialize(obj),
             #[cfg(feature = "postcard_ser")]
             Format::Postcard => Postcard.serialize(obj),
             #[cfg(feature = "messagepack_ser")]
-            Format::MessagePack => MessagePack.serialize(obj),
+            Format::MessagePack => MessagePack::compact().serialize(obj),
             #[cfg(feature = "bson_ser")]
             Format::Bson => Bson.serialize(obj),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.serialize(obj),
+        }
+    }
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        match self {
+            #[cfg(feature = "bincode_ser")]
+            Format::Bincode => Bincode.serialize_into(obj, buf),
+            #[cfg(feature = "json_ser")]
+            Format::Json => Json::compact().serialize_into(obj, buf),
+            #[cfg(feature = "postcard_ser")]
+            Format::Postcard => Postcard.serialize_into(obj, buf),
+            #[cfg(feature = "messagepack_ser")]
+            Format::MessagePack => MessagePack::compact().serialize_into(obj, buf),
+            #[cfg(feature = "bson_ser")]
+            Format::Bson => Bson.serialize_into(obj, buf),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.serialize_into(obj, buf),
         }
     }
 }
@@ -52,15 +240,53 @@ impl ReadFormat for Format {
         T: DeserializeOwned,
     {
         match self {
+            #[cfg(feature = "bincode_ser")]
             Format::Bincode => Bincode.deserialize(bytes),
             #[cfg(feature = "json_ser")]
-            Format::Json => Json.deserialize(bytes),
+            Format::Json => Json::compact().deserialize(bytes),
             #[cfg(feature = "postcard_ser")]
             Format::Postcard => Postcard.deserialize(bytes),
             #[cfg(feature = "messagepack_ser")]
-            Format::MessagePack => MessagePack.deserialize(bytes),
+            Format::MessagePack => MessagePack::compact().deserialize(bytes),
             #[cfg(feature = "bson_ser")]
             Format::Bson => Bson.deserialize(bytes),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.deserialize(bytes),
+        }
+    }
+    #[cfg(feature = "json_ser")]
+    fn deserialize_value(&mut self, bytes: &[u8]) -> crate::Result<serde_json::Value> {
+        match self {
+            #[cfg(feature = "bincode_ser")]
+            Format::Bincode => Bincode.deserialize_value(bytes),
+            Format::Json => Json::compact().deserialize_value(bytes),
+            #[cfg(feature = "postcard_ser")]
+            Format::Postcard => Postcard.deserialize_value(bytes),
+            #[cfg(feature = "messagepack_ser")]
+            Format::MessagePack => MessagePack::compact().deserialize_value(bytes),
+            #[cfg(feature = "bson_ser")]
+            Format::Bson => Bson.deserialize_value(bytes),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.deserialize_value(bytes),
+        }
+    }
+    fn deserialize_borrowed<'de, T>(&mut self, bytes: &'de [u8]) -> crate::Result<T>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        match self {
+            #[cfg(feature = "bincode_ser")]
+            Format::Bincode => Bincode.deserialize_borrowed(bytes),
+            #[cfg(feature = "json_ser")]
+            Format::Json => Json::compact().deserialize_borrowed(bytes),
+            #[cfg(feature = "postcard_ser")]
+            Format::Postcard => Postcard.deserialize_borrowed(bytes),
+            #[cfg(feature = "messagepack_ser")]
+            Format::MessagePack => MessagePack::compact().deserialize_borrowed(bytes),
+            #[cfg(feature = "bson_ser")]
+            Format::Bson => Bson.deserialize_borrowed(bytes),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.deserialize_borrowed(bytes),
         }
     }
 }
@@ -68,15 +294,34 @@ impl ReadFormat for Format {
 impl SendFormat for &mut Format {
     fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
         match self {
+            #[cfg(feature = "bincode_ser")]
             Format::Bincode => Bincode.serialize(obj),
             #[cfg(feature = "json_ser")]
-            Format::Json => Json.serialize(obj),
+            Format::Json => Json::compact().serialize(obj),
             #[cfg(feature = "postcard_ser")]
             Format::Postcard => Postcard.serialize(obj),
             #[cfg(feature = "messagepack_ser")]
-            Format::MessagePack => MessagePack.serialize(obj),
+            Format::MessagePack => MessagePack::compact().serialize(obj),
             #[cfg(feature = "bson_ser")]
             Format::Bson => Bson.serialize(obj),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.serialize(obj),
+        }
+    }
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        match self {
+            #[cfg(feature = "bincode_ser")]
+            Format::Bincode => Bincode.serialize_into(obj, buf),
+            #[cfg(feature = "json_ser")]
+            Format::Json => Json::compact().serialize_into(obj, buf),
+            #[cfg(feature = "postcard_ser")]
+            Format::Postcard => Postcard.serialize_into(obj, buf),
+            #[cfg(feature = "messagepack_ser")]
+            Format::MessagePack => MessagePack::compact().serialize_into(obj, buf),
+            #[cfg(feature = "bson_ser")]
+            Format::Bson => Bson.serialize_into(obj, buf),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.serialize_into(obj, buf),
         }
     }
 }
@@ -87,41 +332,140 @@ impl ReadFormat for &mut Format {
         T: DeserializeOwned,
     {
         match self {
+            #[cfg(feature = "bincode_ser")]
             Format::Bincode => Bincode.deserialize(bytes),
             #[cfg(feature = "json_ser")]
-            Format::Json => Json.deserialize(bytes),
+            Format::Json => Json::compact().deserialize(bytes),
             #[cfg(feature = "postcard_ser")]
             Format::Postcard => Postcard.deserialize(bytes),
             #[cfg(feature = "messagepack_ser")]
-            Format::MessagePack => MessagePack.deserialize(bytes),
+            Format::MessagePack => MessagePack::compact().deserialize(bytes),
             #[cfg(feature = "bson_ser")]
             Format::Bson => Bson.deserialize(bytes),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.deserialize(bytes),
+        }
+    }
+    #[cfg(feature = "json_ser")]
+    fn deserialize_value(&mut self, bytes: &[u8]) -> crate::Result<serde_json::Value> {
+        match self {
+            #[cfg(feature = "bincode_ser")]
+            Format::Bincode => Bincode.deserialize_value(bytes),
+            Format::Json => Json::compact().deserialize_value(bytes),
+            #[cfg(feature = "postcard_ser")]
+            Format::Postcard => Postcard.deserialize_value(bytes),
+            #[cfg(feature = "messagepack_ser")]
+            Format::MessagePack => MessagePack::compact().deserialize_value(bytes),
+            #[cfg(feature = "bson_ser")]
+            Format::Bson => Bson.deserialize_value(bytes),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.deserialize_value(bytes),
+        }
+    }
+    fn deserialize_borrowed<'de, T>(&mut self, bytes: &'de [u8]) -> crate::Result<T>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        match self {
+            #[cfg(feature = "bincode_ser")]
+            Format::Bincode => Bincode.deserialize_borrowed(bytes),
+            #[cfg(feature = "json_ser")]
+            Format::Json => Json::compact().deserialize_borrowed(bytes),
+            #[cfg(feature = "postcard_ser")]
+            Format::Postcard => Postcard.deserialize_borrowed(bytes),
+            #[cfg(feature = "messagepack_ser")]
+            Format::MessagePack => MessagePack::compact().deserialize_borrowed(bytes),
+            #[cfg(feature = "bson_ser")]
+            Format::Bson => Bson.deserialize_borrowed(bytes),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.deserialize_borrowed(bytes),
         }
     }
 }
 
+#[cfg(feature = "bincode_ser")]
 /// bincode serialization format
 pub struct Bincode;
 
 #[cfg(feature = "json_ser")]
-/// JSON serialization format
-pub struct Json;
+#[derive(Clone, Copy, Default)]
+/// JSON serialization format.
+/// Messages are serialized without whitespace by default,
+/// use `Json::pretty()` to indent them, which is easier to read when debugging.
+/// Both read any JSON, so a pretty peer can talk to a compact one.
+/// Channels use it through `Channel::with_formats`
+pub struct Json {
+    pretty: bool,
+}
+
+#[cfg(feature = "json_ser")]
+impl Json {
+    #[inline]
+    /// serialize messages indented, over multiple lines
+    pub fn pretty() -> Self {
+        Json { pretty: true }
+    }
+    #[inline]
+    /// serialize messages without whitespace, this is the default
+    pub fn compact() -> Self {
+        Json { pretty: false }
+    }
+}
 #[cfg(feature = "bson_ser")]
-/// Postcard serialization format
+/// BSON serialization format.
+/// BSON can only hold documents at the top level, so other values
+/// are sent wrapped in a document with a single `v` key
 pub struct Bson;
 
+#[cfg(feature = "bson_ser")]
+/// key of the document non-document values are wrapped in
+const BSON_WRAPPER_KEY: &str = "v";
+
 #[cfg(feature = "postcard_ser")]
 /// Postcard serialization format
 pub struct Postcard;
 
 #[cfg(feature = "messagepack_ser")]
-/// Postcard serialization format
-pub struct MessagePack;
+#[derive(Clone, Copy, Default)]
+/// MessagePack serialization format.
+/// Structs are serialized as arrays by default,
+/// use `MessagePack::named()` to serialize them as maps with their field names
+pub struct MessagePack {
+    named: bool,
+}
+
+#[cfg(feature = "messagepack_ser")]
+impl MessagePack {
+    #[inline]
+    /// serialize structs as maps keyed by field name,
+    /// which dynamically-typed peers usually expect
+    pub fn named() -> Self {
+        MessagePack { named: true }
+    }
+    #[inline]
+    /// serialize structs as arrays of their fields, this is the default
+    pub fn compact() -> Self {
+        MessagePack { named: false }
+    }
+}
+
+#[cfg(feature = "cbor_ser")]
+/// CBOR serialization format
+pub struct Cbor;
 
 /// trait that represents the serialize side of a format
 pub trait SendFormat {
     /// serialize object in this format
     fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>>;
+    #[inline]
+    /// serialize object in this format at the end of the buffer,
+    /// returns the number of bytes written.
+    /// Lets callers reuse a buffer across messages instead of allocating one each time
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        let bytes = self.serialize(obj)?;
+        buf.extend_from_slice(&bytes);
+        Ok(bytes.len())
+    }
 }
 
 /// trait that represents the deserialize side of a format
@@ -130,21 +474,56 @@ pub trait ReadFormat {
     fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
     where
         T: serde::de::DeserializeOwned;
+    #[cfg(feature = "json_ser")]
+    #[inline]
+    /// deserialize any object in this format into a dynamic JSON value,
+    /// without knowing its type. Formats that don't describe their own types can't,
+    /// since the bytes alone don't say where a value ends or what it is
+    fn deserialize_value(&mut self, bytes: &[u8]) -> crate::Result<serde_json::Value> {
+        self.deserialize(bytes)
+    }
+    #[inline]
+    /// deserialize an object borrowing from `bytes` instead of copying out of them,
+    /// such as a `&str` field. Formats that only read owned objects, or that
+    /// decode the bytes into a new buffer first, fail with `Unsupported`
+    fn deserialize_borrowed<'de, T>(&mut self, _: &'de [u8]) -> crate::Result<T>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        err!((
+            unsupported,
+            "the format can't deserialize objects borrowing from the message"
+        ))
+    }
 }
 
 /// trait that represents a format that can serialize and deserialize
 pub trait CompleteFormat: SendFormat + ReadFormat {}
 
+#[cfg(feature = "bincode_ser")]
 impl SendFormat for Bincode {
     #[inline]
     fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
         let obj = bincode::DefaultOptions::new()
             .allow_trailing_bytes()
             .serialize(obj)
-            .map_err(err!(@invalid_data))?;
-        Ok(obj.into())
+            .map_err(SerializationError::with(Format::Bincode))?;
+        Ok(obj)
+    }
+    #[inline]
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        let options = bincode::DefaultOptions::new().allow_trailing_bytes();
+        let size = options
+            .serialized_size(obj)
+            .map_err(SerializationError::with(Format::Bincode))? as usize;
+        buf.reserve(size);
+        options
+            .serialize_into(&mut *buf, obj)
+            .map_err(SerializationError::with(Format::Bincode))?;
+        Ok(size)
     }
 }
+#[cfg(feature = "bincode_ser")]
 impl ReadFormat for Bincode {
     #[inline]
     fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
@@ -154,7 +533,25 @@ impl ReadFormat for Bincode {
         bincode::DefaultOptions::new()
             .allow_trailing_bytes()
             .deserialize(bytes)
-            .map_err(err!(@invalid_data))
+            .map_err(SerializationError::with(Format::Bincode))
+    }
+    #[cfg(feature = "json_ser")]
+    #[inline]
+    fn deserialize_value(&mut self, _: &[u8]) -> crate::Result<serde_json::Value> {
+        err!((
+            unsupported,
+            "bincode doesn't describe its types, messages can't be read without knowing them"
+        ))
+    }
+    #[inline]
+    fn deserialize_borrowed<'de, T>(&mut self, bytes: &'de [u8]) -> crate::Result<T>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        bincode::DefaultOptions::new()
+            .allow_trailing_bytes()
+            .deserialize(bytes)
+            .map_err(SerializationError::with(Format::Bincode))
     }
 }
 
@@ -162,7 +559,23 @@ impl ReadFormat for Bincode {
 impl SendFormat for Json {
     #[inline]
     fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
-        serde_json::to_vec(obj).map_err(err!(@invalid_data))
+        if self.pretty {
+            serde_json::to_vec_pretty(obj).map_err(SerializationError::with(Format::Json))
+        } else {
+            serde_json::to_vec(obj).map_err(SerializationError::with(Format::Json))
+        }
+    }
+    #[inline]
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        let start = buf.len();
+        if self.pretty {
+            serde_json::to_writer_pretty(&mut *buf, obj)
+                .map_err(SerializationError::with(Format::Json))?;
+        } else {
+            serde_json::to_writer(&mut *buf, obj)
+                .map_err(SerializationError::with(Format::Json))?;
+        }
+        Ok(buf.len() - start)
     }
 }
 
@@ -173,7 +586,14 @@ impl ReadFormat for Json {
     where
         T: serde::de::DeserializeOwned,
     {
-        serde_json::from_slice(bytes).map_err(err!(@invalid_data))
+        serde_json::from_slice(bytes).map_err(SerializationError::with(Format::Json))
+    }
+    #[inline]
+    fn deserialize_borrowed<'de, T>(&mut self, bytes: &'de [u8]) -> crate::Result<T>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        serde_json::from_slice(bytes).map_err(SerializationError::with(Format::Json))
     }
 }
 
@@ -181,7 +601,11 @@ impl ReadFormat for Json {
 impl SendFormat for Bson {
     #[inline]
     fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
-        bson::to_vec(obj).map_err(err!(@invalid_data))
+        let document = match bson::to_bson(obj).map_err(SerializationError::with(Format::Bson))? {
+            bson::Bson::Document(document) => document,
+            value => bson::doc! { BSON_WRAPPER_KEY: value },
+        };
+        bson::to_vec(&document).map_err(SerializationError::with(Format::Bson))
     }
 }
 
@@ -192,14 +616,63 @@ impl ReadFormat for Bson {
     where
         T: serde::de::DeserializeOwned,
     {
-        bson::from_slice(bytes).map_err(err!(@invalid_data))
+        let error = match bson::from_slice(bytes) {
+            Ok(obj) => return Ok(obj),
+            Err(error) => error,
+        };
+        // the value may have been wrapped since it isn't a document
+        let mut document =
+            bson::Document::from_reader(bytes).map_err(SerializationError::with(Format::Bson))?;
+        match document.remove(BSON_WRAPPER_KEY) {
+            Some(value) if document.is_empty() => {
+                bson::from_bson(value).map_err(SerializationError::with(Format::Bson))
+            }
+            _ => Err(SerializationError::with(Format::Bson)(error)),
+        }
+    }
+    #[cfg(feature = "json_ser")]
+    /// BSON types JSON lacks, such as object ids and dates,
+    /// come out in relaxed extended JSON, like `{"$oid": "..."}`
+    fn deserialize_value(&mut self, bytes: &[u8]) -> crate::Result<serde_json::Value> {
+        let mut document =
+            bson::Document::from_reader(bytes).map_err(SerializationError::with(Format::Bson))?;
+        let value = match document.remove(BSON_WRAPPER_KEY) {
+            Some(value) if document.is_empty() => value,
+            Some(value) => {
+                document.insert(BSON_WRAPPER_KEY, value);
+                bson::Bson::Document(document)
+            }
+            None => bson::Bson::Document(document),
+        };
+        Ok(value.into_relaxed_extjson())
+    }
+    fn deserialize_borrowed<'de, T>(&mut self, bytes: &'de [u8]) -> crate::Result<T>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        let error = match bson::from_slice(bytes) {
+            Ok(obj) => return Ok(obj),
+            Err(error) => error,
+        };
+        match bson::from_slice::<BsonWrapper<T>>(bytes) {
+            Ok(wrapper) => Ok(wrapper.v),
+            Err(_) => Err(SerializationError::with(Format::Bson)(error)),
+        }
     }
 }
+
+#[cfg(feature = "bson_ser")]
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+/// document a value that isn't a document is wrapped in, its field is `BSON_WRAPPER_KEY`
+struct BsonWrapper<T> {
+    v: T,
+}
 #[cfg(feature = "postcard_ser")]
 impl SendFormat for Postcard {
     #[inline]
     fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
-        postcard::to_allocvec(obj).map_err(err!(@invalid_data))
+        postcard::to_allocvec(obj).map_err(SerializationError::with(Format::Postcard))
     }
 }
 #[cfg(feature = "postcard_ser")]
@@ -209,7 +682,22 @@ impl ReadFormat for Postcard {
     where
         T: serde::de::DeserializeOwned,
     {
-        postcard::from_bytes(bytes).map_err(err!(@invalid_data))
+        postcard::from_bytes(bytes).map_err(SerializationError::with(Format::Postcard))
+    }
+    #[inline]
+    fn deserialize_borrowed<'de, T>(&mut self, bytes: &'de [u8]) -> crate::Result<T>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        postcard::from_bytes(bytes).map_err(SerializationError::with(Format::Postcard))
+    }
+    #[cfg(feature = "json_ser")]
+    #[inline]
+    fn deserialize_value(&mut self, _: &[u8]) -> crate::Result<serde_json::Value> {
+        err!((
+            unsupported,
+            "postcard doesn't describe its types, messages can't be read without knowing them"
+        ))
     }
 }
 
@@ -217,7 +705,23 @@ impl ReadFormat for Postcard {
 impl SendFormat for MessagePack {
     #[inline]
     fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
-        rmp_serde::to_vec(obj).map_err(err!(@invalid_data))
+        if self.named {
+            rmp_serde::to_vec_named(obj).map_err(SerializationError::with(Format::MessagePack))
+        } else {
+            rmp_serde::to_vec(obj).map_err(SerializationError::with(Format::MessagePack))
+        }
+    }
+    #[inline]
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        let start = buf.len();
+        if self.named {
+            rmp_serde::encode::write_named(buf, obj)
+                .map_err(SerializationError::with(Format::MessagePack))?;
+        } else {
+            rmp_serde::encode::write(buf, obj)
+                .map_err(SerializationError::with(Format::MessagePack))?;
+        }
+        Ok(buf.len() - start)
     }
 }
 #[cfg(feature = "messagepack_ser")]
@@ -227,6 +731,202 @@ impl ReadFormat for MessagePack {
     where
         T: serde::de::DeserializeOwned,
     {
-        rmp_serde::from_slice(bytes).map_err(err!(@invalid_data))
+        rmp_serde::from_slice(bytes).map_err(SerializationError::with(Format::MessagePack))
+    }
+    #[inline]
+    fn deserialize_borrowed<'de, T>(&mut self, bytes: &'de [u8]) -> crate::Result<T>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        rmp_serde::from_slice(bytes).map_err(SerializationError::with(Format::MessagePack))
+    }
+}
+
+#[cfg(feature = "cbor_ser")]
+impl SendFormat for Cbor {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(obj, &mut bytes)
+            .map_err(SerializationError::with(Format::Cbor))?;
+        Ok(bytes)
+    }
+    #[inline]
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        let start = buf.len();
+        ciborium::ser::into_writer(obj, &mut *buf)
+            .map_err(SerializationError::with(Format::Cbor))?;
+        Ok(buf.len() - start)
+    }
+}
+#[cfg(feature = "cbor_ser")]
+impl ReadFormat for Cbor {
+    #[inline]
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        ciborium::de::from_reader(bytes).map_err(SerializationError::with(Format::Cbor))
+    }
+}
+
+/// Callback given to `DynFormat::deserialize`, it has to be called once with a deserializer
+pub type Visit<'a> = &'a mut dyn FnMut(&mut dyn erased_serde::Deserializer) -> crate::Result<()>;
+
+/// Object-safe version of `SendFormat` and `ReadFormat`,
+/// implement it to make a format selectable at runtime with `register`.
+/// ```no_run
+/// # use canary::serialization::formats::{DynFormat, Visit};
+/// struct MyJson;
+///
+/// impl DynFormat for MyJson {
+///     fn serialize(&mut self, obj: &dyn erased_serde::Serialize) -> canary::Result<Vec<u8>> {
+///         let mut bytes = vec![];
+///         let ser = &mut serde_json::Serializer::new(&mut bytes);
+///         erased_serde::serialize(obj, ser).map_err(canary::err!(@invalid_data))?;
+///         Ok(bytes)
+///     }
+///     fn deserialize(&mut self, bytes: &[u8], visit: Visit) -> canary::Result<()> {
+///         let de = &mut serde_json::Deserializer::from_slice(bytes);
+///         visit(&mut <dyn erased_serde::Deserializer>::erase(de))
+///     }
+/// }
+/// ```
+pub trait DynFormat: Send {
+    /// serialize object in this format
+    fn serialize(&mut self, obj: &dyn erased_serde::Serialize) -> crate::Result<Vec<u8>>;
+    /// create a deserializer over the bytes and pass it to `visit`
+    fn deserialize(&mut self, bytes: &[u8], visit: Visit) -> crate::Result<()>;
+}
+
+/// Function that creates a registered format
+pub type FormatFactory = fn() -> Box<dyn DynFormat>;
+
+/// tags up to this one are reserved for built-in formats
+pub const RESERVED_TAGS: u8 = 64;
+
+static REGISTRY: RwLock<BTreeMap<u8, FormatFactory>> = RwLock::new(BTreeMap::new());
+
+/// Register a format under a tag so `DynamicFormat::from_tag` can create it.
+/// Tags up to 64 are reserved for built-in formats, and a tag can only be registered once.
+/// ```no_run
+/// # use canary::serialization::formats::{self, DynFormat, DynamicFormat};
+/// # fn example(my_format: fn() -> Box<dyn DynFormat>) -> canary::Result<()> {
+/// formats::register(65, my_format)?;
+/// let format = DynamicFormat::from_tag(65)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn register(tag: u8, factory: FormatFactory) -> crate::Result<()> {
+    if tag <= RESERVED_TAGS {
+        err!((
+            invalid_input,
+            format!("format tag {} is reserved for built-in formats", tag)
+        ))?
+    }
+    let mut registry = REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+    if registry.contains_key(&tag) {
+        err!((
+            already_exists,
+            format!("format tag {} is already registered", tag)
+        ))?
+    }
+    registry.insert(tag, factory);
+    Ok(())
+}
+
+/// Format selected at runtime from its tag,
+/// either one of the built-in formats or a registered one
+pub enum DynamicFormat {
+    /// built-in format
+    Builtin(Format),
+    /// format created by a registered factory
+    Registered(u8, Box<dyn DynFormat>),
+}
+
+impl Default for DynamicFormat {
+    #[inline]
+    fn default() -> Self {
+        DynamicFormat::Builtin(Format::default())
+    }
+}
+
+impl From<Format> for DynamicFormat {
+    #[inline]
+    fn from(format: Format) -> Self {
+        DynamicFormat::Builtin(format)
+    }
+}
+
+impl DynamicFormat {
+    /// Create the format with the given tag, fails with `InvalidData`
+    /// if the tag is neither a built-in format nor a registered one
+    pub fn from_tag(tag: u8) -> crate::Result<Self> {
+        if tag <= RESERVED_TAGS {
+            return Format::try_from(tag).map(DynamicFormat::Builtin);
+        }
+        let registry = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+        match registry.get(&tag) {
+            Some(factory) => Ok(DynamicFormat::Registered(tag, factory())),
+            None => err!((invalid_data, format!("unknown format tag {}", tag))),
+        }
+    }
+    #[inline]
+    /// Tag of the format
+    pub fn tag(&self) -> u8 {
+        match self {
+            DynamicFormat::Builtin(format) => *format as u8,
+            DynamicFormat::Registered(tag, _) => *tag,
+        }
+    }
+}
+
+impl SendFormat for DynamicFormat {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
+        match self {
+            DynamicFormat::Builtin(format) => SendFormat::serialize(format, obj),
+            DynamicFormat::Registered(_, format) => format.serialize(obj),
+        }
+    }
+}
+
+impl ReadFormat for DynamicFormat {
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        match self {
+            DynamicFormat::Builtin(format) => ReadFormat::deserialize(format, bytes),
+            DynamicFormat::Registered(_, format) => {
+                let mut obj = None;
+                format.deserialize(bytes, &mut |de| {
+                    obj = Some(
+                        erased_serde::deserialize(de).map_err(SerializationError::with(None))?,
+                    );
+                    Ok(())
+                })?;
+                obj.ok_or_else(|| err!(invalid_data, "format did not deserialize the object"))
+            }
+        }
+    }
+    #[cfg(feature = "json_ser")]
+    fn deserialize_value(&mut self, bytes: &[u8]) -> crate::Result<serde_json::Value> {
+        match self {
+            DynamicFormat::Builtin(format) => format.deserialize_value(bytes),
+            DynamicFormat::Registered(..) => self.deserialize(bytes),
+        }
+    }
+    fn deserialize_borrowed<'de, T>(&mut self, bytes: &'de [u8]) -> crate::Result<T>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        match self {
+            DynamicFormat::Builtin(format) => format.deserialize_borrowed(bytes),
+            DynamicFormat::Registered(..) => err!((
+                unsupported,
+                "registered formats can't deserialize objects borrowing from the message"
+            )),
+        }
     }
 }
@@ -0,0 +1,68 @@
+//! Length prefix of the frames sent over byte streams.
+//!
+//! On stream transports (tcp, unix, quic and in-memory channels) every frame is sent as
+//! its length, encoded as an unsigned 64-bit big-endian integer, followed by the frame itself:
+//! ```text
+//! +---------------------+------------------+
+//! | length (u64, BE)    | frame            |
+//! | 8 bytes             | `length` bytes   |
+//! +---------------------+------------------+
+//! ```
+//! The encoding doesn't depend on the platform, so clients in other languages can read
+//! and write it directly. Websockets are message based and send every frame as a single
+//! binary message without a prefix, of at most `WSS_MAX_MESSAGE_SIZE` bytes by default.
+//! Pings and pongs between frames are skipped, and text messages are rejected.
+//!
+//! The frame itself is the same on every transport. On encrypted channels it holds
+//! the Noise packets of the message back to back, so moving a service from one
+//! transport to another doesn't change what its peers have to decrypt.
+//! If the sender pads frames with `Channel::pad_frames`, the packets hold `0xff`,
+//! the length prefix of the frame, the frame and zeros up to the length of the scheme,
+//! such as the next multiple of 256 bytes, so the clear prefix only tells the padded length.
+//!
+//! Every frame starts with its kind byte, so an empty message, like `()` or an empty `Vec`
+//! serialized with a compact format, is a one-byte `Message` frame, and on encrypted channels
+//! a single packet holding that byte and its tag. A length of zero is never sent: a frame
+//! without its kind byte is a protocol error, which `frame::decode` rejects with
+//! "received an empty frame". A closed connection shows up as the stream ending.
+
+use crate::{err, Result};
+
+/// length of the prefix that precedes every frame
+pub const LEN_PREFIX: usize = 8;
+
+#[inline]
+/// Encode the length of a frame into its prefix
+/// ```no_run
+/// use canary::serialization::framing::encode_len;
+/// assert_eq!(encode_len(258), [0, 0, 0, 0, 0, 0, 1, 2]);
+/// ```
+pub fn encode_len(len: usize) -> [u8; LEN_PREFIX] {
+    (len as u64).to_be_bytes()
+}
+
+#[inline]
+/// Decode the length of a frame from its prefix.
+/// Fails if the length doesn't fit in memory on this platform
+/// ```no_run
+/// use canary::serialization::framing::decode_len;
+/// assert_eq!(decode_len([0, 0, 0, 0, 0, 0, 1, 2])?, 258);
+/// # Ok::<(), canary::Error>(())
+/// ```
+pub fn decode_len(prefix: [u8; LEN_PREFIX]) -> Result<usize> {
+    let len = u64::from_be_bytes(prefix);
+    usize::try_from(len)
+        .map_err(|_| err!(invalid_data, format!("frame of {} bytes is too large", len)))
+}
+
+/// Fail if a frame of `len` bytes is longer than `max`,
+/// checked on the prefix before anything is allocated for the frame
+pub(crate) fn check_len(len: usize, max: usize) -> Result<()> {
+    if len > max {
+        return err!((
+            invalid_data,
+            format!("frame of {} bytes is over the limit of {} bytes", len, max)
+        ));
+    }
+    Ok(())
+}
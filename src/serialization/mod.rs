@@ -1,6 +1,14 @@
 mod comms;
+/// contains the compression of messages
+pub mod compressed;
+#[cfg(unix)]
+pub(crate) mod fds;
 /// contains serialization formats
 pub mod formats;
+/// contains the length prefix of frames
+pub mod framing;
+/// contains the versioned envelope of messages
+pub mod versioned;
 /// contains zero-cost stream operations and more
 /// ```no_run
 /// zc::send_u64(&mut stream, 42).await?;
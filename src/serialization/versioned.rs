@@ -0,0 +1,181 @@
+use std::fmt::Display;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{err, Error, Result};
+
+use super::formats::{Format, ReadFormat, SendFormat};
+
+/// length of the header prefixed to versioned messages
+const HEADER_LEN: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Header prefixed to every message sent through a versioned channel
+pub struct Header {
+    /// tag of the format the message was serialized with
+    pub format: u8,
+    /// schema version of the sender
+    pub version: u32,
+}
+
+impl Header {
+    #[inline]
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.format);
+        buf.extend_from_slice(&self.version.to_le_bytes());
+    }
+    #[inline]
+    /// split a message into its header and its payload
+    pub(crate) fn decode(bytes: &[u8]) -> Result<(Header, &[u8])> {
+        if bytes.len() < HEADER_LEN {
+            err!((
+                invalid_data,
+                "message is too short to carry a version header"
+            ))?
+        }
+        let (header, payload) = bytes.split_at(HEADER_LEN);
+        let mut version = [0; 4];
+        version.copy_from_slice(&header[1..]);
+        let header = Header {
+            format: header[0],
+            version: u32::from_le_bytes(version),
+        };
+        Ok((header, payload))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Error returned when the peer sent a message with a different schema version.
+///
+/// `receive` returns it as an `InvalidData` error, use `VersionMismatch::of` to find it.
+/// ```no_run
+/// # use canary::serialization::versioned::Versioned;
+/// # async fn example(mut chan: canary::Channel<Versioned, Versioned>) -> canary::Result<()> {
+/// use canary::serialization::versioned::VersionMismatch;
+///
+/// if let Err(e) = chan.receive::<String>().await {
+///     if let Some(mismatch) = VersionMismatch::of(&e) {
+///         println!("peer is at version {}, we are at {}", mismatch.theirs, mismatch.ours);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct VersionMismatch {
+    /// schema version of this side of the channel
+    pub ours: u32,
+    /// schema version of the peer
+    pub theirs: u32,
+}
+
+impl VersionMismatch {
+    #[inline]
+    /// Returns the version mismatch carried by the error, if any
+    pub fn of(error: &Error) -> Option<&VersionMismatch> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl Display for VersionMismatch {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "schema version mismatch: ours is {}, theirs is {}",
+            self.ours, self.theirs
+        )
+    }
+}
+
+impl std::error::Error for VersionMismatch {}
+
+#[derive(Clone, Copy)]
+/// Format that prefixes every message with a `Header`
+/// carrying the tag of the inner format and a schema version.
+///
+/// Messages with a different version fail to deserialize with a `VersionMismatch`
+/// instead of a generic deserialization error.
+pub struct Versioned {
+    /// inner format
+    pub format: Format,
+    /// schema version of this side
+    pub version: u32,
+}
+
+impl Versioned {
+    #[inline]
+    /// Wrap a format with a schema version
+    pub fn new(format: Format, version: u32) -> Self {
+        Versioned { format, version }
+    }
+    #[inline]
+    /// header prefixed to the messages of this side
+    pub fn header(&self) -> Header {
+        Header {
+            format: self.format as u8,
+            version: self.version,
+        }
+    }
+    /// check the header of a message sent by the peer
+    fn check(&self, header: Header) -> Result<()> {
+        if header.version != self.version {
+            let mismatch = VersionMismatch {
+                ours: self.version,
+                theirs: header.version,
+            };
+            err!((invalid_data, mismatch))?
+        }
+        if header.format != self.format as u8 {
+            err!((
+                invalid_data,
+                format!(
+                    "peer serialized the message with format tag {}",
+                    header.format
+                )
+            ))?
+        }
+        Ok(())
+    }
+}
+
+impl SendFormat for Versioned {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        self.serialize_into(obj, &mut bytes)?;
+        Ok(bytes)
+    }
+    #[inline]
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> Result<usize> {
+        self.header().encode(buf);
+        Ok(HEADER_LEN + SendFormat::serialize_into(&mut self.format, obj, buf)?)
+    }
+}
+
+impl ReadFormat for Versioned {
+    #[inline]
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let (header, payload) = Header::decode(bytes)?;
+        self.check(header)?;
+        ReadFormat::deserialize(&mut self.format, payload)
+    }
+    #[cfg(feature = "json_ser")]
+    #[inline]
+    fn deserialize_value(&mut self, bytes: &[u8]) -> Result<serde_json::Value> {
+        let (header, payload) = Header::decode(bytes)?;
+        self.check(header)?;
+        self.format.deserialize_value(payload)
+    }
+    #[inline]
+    fn deserialize_borrowed<'de, T>(&mut self, bytes: &'de [u8]) -> Result<T>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        let (header, payload) = Header::decode(bytes)?;
+        self.check(header)?;
+        self.format.deserialize_borrowed(payload)
+    }
+}
@@ -7,13 +7,13 @@ use crate::{err, Result};
 #[inline]
 pub(crate) fn try_vec<T: Default + Clone>(size: usize) -> Result<Vec<T>> {
     let mut buf = Vec::new();
-    buf.try_reserve(size as usize).map_err(|e| {
+    buf.try_reserve(size).map_err(|e| {
         err!(
             out_of_memory,
             format!("failed to reserve {} bytes, error: {:?}", size, e)
         )
     })?;
-    buf.resize(size as usize, T::default());
+    buf.resize(size, T::default());
     Ok(buf)
 }
 
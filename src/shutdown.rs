@@ -0,0 +1,219 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::future::Future;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+use crate::audit::AuditKind;
+use crate::{err, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Running,
+    Draining,
+    Completed,
+}
+
+struct Inner {
+    phase: watch::Sender<Phase>,
+    tasks: Mutex<JoinSet<()>>,
+}
+
+/// abort the tasks and count the ones that were still running
+async fn abort(mut tasks: JoinSet<()>) -> usize {
+    tasks.abort_all();
+    let mut aborted = 0;
+    while let Some(res) = tasks.join_next().await {
+        // tasks finishing right as they were aborted aren't counted
+        if matches!(res, Err(e) if e.is_cancelled()) {
+            aborted += 1;
+        }
+    }
+    aborted
+}
+
+#[derive(Clone)]
+/// Controller shutting the process down cleanly, reachable from anywhere with `handle`.
+///
+/// Once `begin` is called every provider stops accepting: pending and later calls to
+/// `next` fail with a `NotConnected` error, so accept loops end on their own.
+/// Tasks started with `spawn`, such as the ones serving each channel, get the grace period
+/// to finish and are aborted once it runs out. Channels opened before the shutdown
+/// are left to those tasks, which can watch `begun` to wrap up early
+/// ```no_run
+/// # async fn example() -> canary::Result<()> {
+/// use canary::providers::Tcp;
+/// use canary::shutdown;
+/// use std::time::Duration;
+///
+/// let tcp = Tcp::bind("127.0.0.1:8080").await?;
+/// tokio::spawn(async {
+///     tokio::signal::ctrl_c().await.ok();
+///     shutdown::handle().begin(Duration::from_secs(30)).await;
+/// });
+/// while let Ok(chan) = tcp.next().await {
+///     shutdown::handle().spawn(async move {
+///         if let Ok(mut chan) = chan.encrypted().await {
+///             chan.send("hello!").await.ok();
+///         }
+///     });
+/// }
+/// shutdown::handle().completed().await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+/// Shutdown controller of the process, the one providers stop accepting for
+pub fn handle() -> Shutdown {
+    static HANDLE: OnceLock<Shutdown> = OnceLock::new();
+    HANDLE
+        .get_or_init(|| Shutdown {
+            inner: Arc::new(Inner {
+                phase: watch::channel(Phase::Running).0,
+                tasks: Mutex::new(JoinSet::new()),
+            }),
+        })
+        .clone()
+}
+
+impl Shutdown {
+    fn tasks(&self) -> std::sync::MutexGuard<'_, JoinSet<()>> {
+        self.inner.tasks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run the task on the current runtime, giving it the grace period
+    /// to finish once the shutdown begins
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let mut tasks = self.tasks();
+        // forget the tasks that already finished
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(task);
+    }
+
+    /// Stop every provider from accepting, wait up to `grace` for the tasks started
+    /// with `spawn` and abort the ones still running.
+    /// Resolves once the shutdown completed, calling it again only waits for that
+    pub async fn begin(&self, grace: Duration) {
+        let began = self.inner.phase.send_if_modified(|phase| {
+            let running = *phase == Phase::Running;
+            if running {
+                *phase = Phase::Draining;
+            }
+            running
+        });
+        if !began {
+            return self.completed().await;
+        }
+        tracing::info!("shutting down, waiting up to {:?} for running tasks", grace);
+        crate::audit::emit(AuditKind::ShutdownBegun { grace });
+        // kept out of the timed future, so the tasks it was waiting for
+        // can still be aborted once the grace period runs out
+        let mut draining = JoinSet::new();
+        let drained = crate::runtime::timeout(grace, async {
+            // tasks spawned while draining are waited for as well
+            loop {
+                draining = std::mem::take(&mut *self.tasks());
+                if draining.is_empty() {
+                    break;
+                }
+                while draining.join_next().await.is_some() {}
+            }
+        })
+        .await;
+        let mut aborted = 0;
+        if drained.is_err() {
+            let late = std::mem::take(&mut *self.tasks());
+            aborted = abort(draining).await + abort(late).await;
+            tracing::warn!(
+                "aborted {} tasks still running after the grace period",
+                aborted
+            );
+        }
+        self.inner.phase.send_replace(Phase::Completed);
+        tracing::info!("shutdown completed");
+        crate::audit::emit(AuditKind::ShutdownCompleted { aborted });
+    }
+
+    #[inline]
+    /// whether the shutdown began
+    pub fn is_begun(&self) -> bool {
+        *self.inner.phase.borrow() != Phase::Running
+    }
+
+    /// resolves once the shutdown begins
+    pub async fn begun(&self) {
+        // the sender lives in the handle, so the wait doesn't fail
+        let mut phase = self.inner.phase.subscribe();
+        phase.wait_for(|phase| *phase != Phase::Running).await.ok();
+    }
+
+    /// resolves once the shutdown completed and the remaining tasks were aborted
+    pub async fn completed(&self) {
+        let mut phase = self.inner.phase.subscribe();
+        phase
+            .wait_for(|phase| *phase == Phase::Completed)
+            .await
+            .ok();
+    }
+
+    #[cfg(feature = "signal")]
+    /// Begin the shutdown with the given grace period on ctrl-c, or on `SIGTERM` on unix.
+    /// Has to be called within a runtime
+    /// ```no_run
+    /// # async fn example() {
+    /// use std::time::Duration;
+    ///
+    /// canary::shutdown::handle().on_signal(Duration::from_secs(30));
+    /// canary::shutdown::handle().completed().await;
+    /// # }
+    /// ```
+    pub fn on_signal(&self, grace: Duration) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            #[cfg(unix)]
+            {
+                use tokio::signal::unix::{signal, SignalKind};
+                match signal(SignalKind::terminate()) {
+                    Ok(mut terminate) => {
+                        tokio::select! {
+                            _ = tokio::signal::ctrl_c() => (),
+                            _ = terminate.recv() => (),
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!("could not listen for SIGTERM: {}", e);
+                        tokio::signal::ctrl_c().await.ok();
+                    }
+                }
+            }
+            #[cfg(not(unix))]
+            tokio::signal::ctrl_c().await.ok();
+            shutdown.begin(grace).await;
+        });
+    }
+}
+
+/// Run the accept of a provider, failing once the shutdown begins
+pub(crate) async fn accepting<T>(accept: impl Future<Output = Result<T>>) -> Result<T> {
+    let shutdown = handle();
+    if shutdown.is_begun() {
+        return shutting_down();
+    }
+    tokio::select! {
+        res = accept => res,
+        _ = shutdown.begun() => shutting_down(),
+    }
+}
+
+fn shutting_down<T>() -> Result<T> {
+    err!((
+        not_connected,
+        "provider stopped accepting, the process is shutting down"
+    ))
+}
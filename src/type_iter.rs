@@ -1,6 +1,6 @@
 //! Here be dragons
 
-use std::marker::PhantomData;
+use std::{future::Future, marker::PhantomData};
 
 use serde::{de::DeserializeOwned, Serialize};
 
@@ -189,6 +189,24 @@ impl<T: TypeIterT> MainChannel<T> {
         let chan = MainChannel(PhantomData, self.1);
         Ok((res, chan))
     }
+    /// receive a request, answer it with the handler and iterate past both types
+    pub async fn respond<F, Fut>(
+        mut self,
+        handler: F,
+    ) -> crate::Result<MainChannel<<T::Next as TypeIterT>::Next>>
+    where
+        T::Type: Receive,
+        <T as TypeIterT>::Next: TypeIterT,
+        <<T as TypeIterT>::Next as TypeIterT>::Next: TypeIterT,
+        <<T as TypeIterT>::Next as TypeIterT>::Type: Transmit,
+        <T::Type as Receive>::Type: DeserializeOwned,
+        <<<T as TypeIterT>::Next as TypeIterT>::Type as Transmit>::Type: Serialize,
+        F: FnOnce(<T::Type as Receive>::Type) -> Fut,
+        Fut: Future<Output = <<<T as TypeIterT>::Next as TypeIterT>::Type as Transmit>::Type>,
+    {
+        self.1.respond(handler).await?;
+        Ok(MainChannel(PhantomData, self.1))
+    }
     /// coerce into a different kind of channel:
     pub fn coerce(self) -> Channel {
         self.1
@@ -256,6 +274,25 @@ impl<T: TypeIterT> PeerChannel<T> {
         let chan = PeerChannel(PhantomData, self.1);
         Ok((res, chan))
     }
+    /// send a request, wait for its response and iterate past both types
+    pub async fn request(
+        mut self,
+        req: &<T::Type as Receive>::Type,
+    ) -> crate::Result<(
+        <<<T as TypeIterT>::Next as TypeIterT>::Type as Transmit>::Type,
+        PeerChannel<<T::Next as TypeIterT>::Next>,
+    )>
+    where
+        T::Type: Receive,
+        <T as TypeIterT>::Next: TypeIterT,
+        <<T as TypeIterT>::Next as TypeIterT>::Next: TypeIterT,
+        <<T as TypeIterT>::Next as TypeIterT>::Type: Transmit,
+        <T::Type as Receive>::Type: Serialize,
+        <<<T as TypeIterT>::Next as TypeIterT>::Type as Transmit>::Type: DeserializeOwned,
+    {
+        let res = self.1.request(req).await?;
+        Ok((res, PeerChannel(PhantomData, self.1)))
+    }
     /// coerce into a different kind of channel:
     pub fn channel(self) -> Channel {
         self.1
@@ -0,0 +1,57 @@
+//! A listener that breaks ends `next` and resolves `closed` with the error that broke it.
+#![cfg(target_os = "linux")]
+
+use std::io::ErrorKind;
+use std::net::Shutdown;
+use std::time::Duration;
+
+use canary::providers::{AnyProvider, Tcp};
+use futures::FutureExt;
+use socket2::SockRef;
+use tokio::net::TcpListener;
+
+/// linux fails `accept` on a listening socket that was shut down with `EINVAL`
+fn break_listener(tcp: &Tcp) {
+    let listener: &TcpListener = tcp.into();
+    SockRef::from(listener).shutdown(Shutdown::Read).unwrap();
+}
+
+#[tokio::test]
+async fn healthy_listeners_stay_open() {
+    let tcp = Tcp::bind("127.0.0.1:0").await.unwrap();
+    let addr = tcp.local_addr().unwrap();
+    let _client = std::net::TcpStream::connect(addr).unwrap();
+    tcp.next().await.unwrap();
+    assert!(tcp.closed().now_or_never().is_none());
+}
+
+#[tokio::test]
+async fn broken_listeners_close_the_provider() {
+    let tcp = Tcp::bind("127.0.0.1:0").await.unwrap();
+    let closed = tcp.closed();
+    break_listener(&tcp);
+
+    let next = tokio::time::timeout(Duration::from_secs(5), tcp.next())
+        .await
+        .expect("next returns once the listener broke");
+    let error = next.err().expect("a broken listener doesn't accept");
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let closed = tokio::time::timeout(Duration::from_secs(5), closed)
+        .await
+        .expect("closed resolves once the listener broke");
+    assert_eq!(closed.kind(), error.kind());
+}
+
+#[tokio::test]
+async fn one_broken_listener_closes_many() {
+    let broken = Tcp::bind("127.0.0.1:0").await.unwrap();
+    break_listener(&broken);
+    assert!(broken.next().await.is_err());
+    let healthy = Tcp::bind("127.0.0.1:0").await.unwrap();
+
+    let providers = AnyProvider::Many(vec![AnyProvider::Tcp(healthy), AnyProvider::Tcp(broken)]);
+    let closed = tokio::time::timeout(Duration::from_secs(5), providers.closed())
+        .await
+        .expect("closed resolves with the broken listener");
+    assert_eq!(closed.kind(), ErrorKind::InvalidInput);
+}
@@ -0,0 +1,44 @@
+//! Running out of file descriptors pauses the accept loop instead of ending it.
+//! The only test of its file, since it lowers the file descriptor limit of the whole process.
+#![cfg(target_os = "linux")]
+
+use std::fs::File;
+use std::time::Duration;
+
+use canary::providers::Tcp;
+use futures::FutureExt;
+use rustix::process::{getrlimit, setrlimit, Resource, Rlimit};
+
+#[tokio::test]
+async fn accepting_survives_fd_exhaustion() {
+    let tcp = Tcp::bind("127.0.0.1:0").await.unwrap();
+    let addr = tcp.local_addr().unwrap();
+    let _client = std::net::TcpStream::connect(addr).unwrap();
+
+    let limit = getrlimit(Resource::Nofile);
+    setrlimit(
+        Resource::Nofile,
+        Rlimit {
+            current: Some(256),
+            maximum: limit.maximum,
+        },
+    )
+    .unwrap();
+    let mut files = vec![];
+    while let Ok(file) = File::open("/dev/null") {
+        files.push(file);
+    }
+
+    // `accept` fails with EMFILE, the provider keeps retrying
+    let next = tokio::time::timeout(Duration::from_millis(500), tcp.next()).await;
+    assert!(next.is_err(), "nothing can be accepted without descriptors");
+    assert!(tcp.closed().now_or_never().is_none());
+
+    drop(files);
+    setrlimit(Resource::Nofile, limit).unwrap();
+    let next = tokio::time::timeout(Duration::from_secs(5), tcp.next())
+        .await
+        .expect("the pending connection is accepted once descriptors free up");
+    assert!(next.is_ok());
+    assert!(tcp.closed().now_or_never().is_none());
+}
@@ -0,0 +1,84 @@
+//! Events passed to the audit sink.
+//!
+//! The sink and the shutdown controller are global,
+//! so the whole sequence runs in a single test.
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+use canary::async_snow::{self, StaticKeypair};
+use canary::audit::{AuditEvent, AuditKind};
+use canary::error::ErrorKind;
+use canary::providers::Memory;
+use canary::shutdown;
+
+/// the next `expected` events, waiting for the sink thread to pass them on
+fn events(received: &mpsc::Receiver<AuditEvent>, expected: usize) -> Vec<AuditKind> {
+    (0..expected)
+        .map_while(|_| received.recv_timeout(Duration::from_secs(5)).ok())
+        .map(|event| event.kind)
+        .collect()
+}
+
+#[tokio::test]
+async fn security_events_reach_the_sink() {
+    let (sink, received) = mpsc::channel();
+    canary::audit::set_sink(move |event| {
+        sink.send(event).ok();
+    })
+    .unwrap();
+
+    // a verified handshake refusing the key of the peer
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = (a.raw(), b.raw());
+    let server = StaticKeypair::generate().unwrap();
+    let client = StaticKeypair::generate().unwrap();
+    let client_key = canary_key(client.public());
+    let (refused, accepted) = tokio::join!(
+        async_snow::new_verified(&mut a, &server, |_| false),
+        async_snow::new_xx(&mut b, &client),
+    );
+    assert_eq!(
+        ErrorKind::of(&refused.unwrap_err()),
+        ErrorKind::PermissionDenied
+    );
+    accepted.unwrap();
+
+    // the sides race, so their events may come in any order
+    let handshakes = events(&received, 3);
+    assert!(handshakes.contains(&AuditKind::AccessDenied {
+        peer_key: Some(client_key.clone()),
+        service: None,
+        reason: "static key of the peer is not allowed".into(),
+    }));
+    // both sides finished the handshake before the key was checked
+    assert!(handshakes.contains(&AuditKind::HandshakeCompleted {
+        peer_key: Some(client_key),
+    }));
+    assert!(handshakes.contains(&AuditKind::HandshakeCompleted {
+        peer_key: Some(canary_key(server.public())),
+    }));
+
+    // a shutdown aborting two tasks
+    let handle = shutdown::handle();
+    handle.spawn(async {});
+    for _ in 0..2 {
+        handle.spawn(tokio::time::sleep(Duration::from_secs(3600)));
+    }
+    let grace = Duration::from_millis(50);
+    handle.begin(grace).await;
+    assert_eq!(
+        events(&received, 2),
+        [
+            AuditKind::ShutdownBegun { grace },
+            AuditKind::ShutdownCompleted { aborted: 2 },
+        ]
+    );
+    assert_eq!(canary::audit::dropped(), 0);
+}
+
+/// static key as it appears in events
+fn canary_key(key: &[u8]) -> String {
+    use base64::prelude::{Engine, BASE64_STANDARD};
+    BASE64_STANDARD.encode(key)
+}
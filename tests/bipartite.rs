@@ -0,0 +1,38 @@
+//! Channels converted to their bipartite form keep working, encrypted or not.
+
+use std::time::Duration;
+
+use canary::async_snow;
+use canary::providers::Memory;
+
+#[tokio::test]
+async fn converted_channels_encrypt_both_directions() {
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = (a.raw(), b.raw());
+    let (a_transport, b_transport) =
+        tokio::try_join!(async_snow::new(&mut a), async_snow::new(&mut b)).unwrap();
+    // keepalive turns the channels into their bipartite form
+    a.enable_keepalive(Duration::from_secs(60), Duration::from_secs(60));
+    b.enable_keepalive(Duration::from_secs(60), Duration::from_secs(60));
+    a.encrypt(a_transport).map_err(drop).unwrap();
+    b.encrypt(b_transport).map_err(drop).unwrap();
+
+    a.send("from a").await.unwrap();
+    assert_eq!(b.receive::<String>().await.unwrap(), "from a");
+    b.send("from b").await.unwrap();
+    assert_eq!(a.receive::<String>().await.unwrap(), "from b");
+}
+
+#[tokio::test]
+async fn converted_channels_dont_send_plaintext() {
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = (a.raw(), b.raw());
+    let (a_transport, _) =
+        tokio::try_join!(async_snow::new(&mut a), async_snow::new(&mut b)).unwrap();
+    a.enable_keepalive(Duration::from_secs(60), Duration::from_secs(60));
+    a.encrypt(a_transport).map_err(drop).unwrap();
+
+    // the peer doesn't decrypt, so it can't read the message
+    a.send("secret").await.unwrap();
+    assert!(b.receive::<String>().await.is_err());
+}
@@ -0,0 +1,50 @@
+//! Cancellable operations only cancel their token when the channel itself failed.
+
+use canary::channel::remote::RemoteError;
+use canary::error::SerializationError;
+use canary::providers::Memory;
+use canary::runtime::CancelToken;
+
+#[tokio::test]
+async fn remote_errors_leave_the_token_alone() {
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = (a.raw(), b.raw());
+    let token = CancelToken::new();
+
+    b.send_error(&canary::err!(not_found, "no such thing"))
+        .await
+        .unwrap();
+    let error = a.receive_cancellable::<String>(&token).await.unwrap_err();
+    assert!(RemoteError::of(&error).is_some());
+    assert!(!token.is_cancelled());
+
+    // the channel is still usable afterwards
+    b.send("next").await.unwrap();
+    let next: String = a.receive_cancellable(&token).await.unwrap();
+    assert_eq!(next, "next");
+}
+
+#[tokio::test]
+async fn serialization_errors_leave_the_token_alone() {
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = (a.raw(), b.raw());
+    let token = CancelToken::new();
+
+    // too short to hold the length of a string
+    b.send(1u8).await.unwrap();
+    let error = a.receive_cancellable::<String>(&token).await.unwrap_err();
+    assert!(SerializationError::of(&error).is_some());
+    assert!(!token.is_cancelled());
+}
+
+#[tokio::test]
+async fn closed_channels_cancel_the_token() {
+    let (a, b) = Memory::pair();
+    let (mut a, b) = (a.raw(), b.raw());
+    let token = CancelToken::new();
+
+    drop(b);
+    let error = a.receive_cancellable::<String>(&token).await.unwrap_err();
+    assert!(RemoteError::of(&error).is_none());
+    assert!(token.is_cancelled());
+}
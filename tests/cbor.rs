@@ -0,0 +1,122 @@
+#![cfg(feature = "cbor_ser")]
+//! CBOR works like every other format, and input in another format fails cleanly.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::ErrorKind;
+
+use canary::error::SerializationError;
+use canary::providers::Memory;
+use canary::serialization::formats::{Cbor, Format, ReadFormat, SendFormat};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+struct Reading {
+    sensor: String,
+    values: Vec<f64>,
+    calibration: Option<Calibration>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+struct Calibration {
+    offset: i32,
+    table: HashMap<u16, Vec<i8>>,
+}
+
+fn reading() -> Reading {
+    Reading {
+        sensor: "thermo-1".into(),
+        values: vec![21.5, -3.25, 0.0],
+        calibration: Some(Calibration {
+            offset: -7,
+            table: HashMap::from([(1, vec![-1, 2]), (40_000, vec![])]),
+        }),
+    }
+}
+
+/// serialize with CBOR, through the format and the unit struct alike, and read it back
+fn roundtrip<T>(value: &T) -> T
+where
+    T: Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+{
+    let bytes = SendFormat::serialize(&mut Format::Cbor, value).unwrap();
+    assert_eq!(Cbor.serialize(value).unwrap(), bytes);
+    let read: T = Cbor.deserialize(&bytes).unwrap();
+    assert_eq!(Format::Cbor.try_deserialize::<T>(&bytes).unwrap(), read);
+    read
+}
+
+#[test]
+fn nested_structs_roundtrip() {
+    assert_eq!(roundtrip(&reading()), reading());
+}
+
+#[test]
+fn maps_with_non_string_keys_roundtrip() {
+    let map = BTreeMap::from([((1u8, 2u8), "a".to_string()), ((3, 4), "b".to_string())]);
+    assert_eq!(roundtrip(&map), map);
+    // JSON can't hold these keys, BSON can't hold any key but strings
+    assert!(SendFormat::serialize(&mut Format::Json, &map).is_err());
+}
+
+#[test]
+fn bytes_roundtrip() {
+    let array = [0xa5u8; 32];
+    assert_eq!(roundtrip(&array), array);
+    let bytes: Vec<u8> = (0..=255).collect();
+    assert_eq!(roundtrip(&bytes), bytes);
+    assert_eq!(roundtrip(&Vec::<u8>::new()), Vec::<u8>::new());
+}
+
+#[test]
+fn other_formats_fail_with_invalid_data() {
+    // without the calibration table, which BSON can't hold
+    let reading = Reading {
+        calibration: None,
+        ..reading()
+    };
+    for format in Format::SUPPORTED {
+        if *format == Format::Cbor {
+            continue;
+        }
+        let bytes = SendFormat::serialize(&mut { *format }, &reading).unwrap();
+        let error = Cbor.deserialize::<Reading>(&bytes).unwrap_err();
+        assert_eq!(
+            error.kind(),
+            ErrorKind::InvalidData,
+            "{:?} read as CBOR",
+            format
+        );
+        let source = SerializationError::of(&error).expect("not a serialization error");
+        assert_eq!(source.format, Some(Format::Cbor));
+    }
+    let cbor = Cbor.serialize(&reading).unwrap();
+    for format in Format::SUPPORTED {
+        if *format != Format::Cbor {
+            let read = format.try_deserialize::<Reading>(&cbor);
+            assert!(read.is_err(), "CBOR read as {:?}", format);
+        }
+    }
+}
+
+#[test]
+fn tag_and_detection() {
+    assert_eq!(Format::try_from(Format::Cbor as u8).unwrap(), Format::Cbor);
+    assert!(Format::SUPPORTED.contains(&Format::Cbor));
+    let bytes = Cbor.serialize(&reading()).unwrap();
+    assert_eq!(Format::detect(&bytes), Some(Format::Cbor));
+}
+
+#[tokio::test]
+async fn channels_negotiate_cbor() {
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = (a.raw(), b.raw());
+    let (requested, accepted) = tokio::join!(
+        a.request_format(Format::Cbor),
+        b.accept_format(Format::SUPPORTED)
+    );
+    requested.unwrap();
+    assert_eq!(accepted.unwrap(), Format::Cbor);
+
+    a.send(reading()).await.unwrap();
+    assert_eq!(b.receive::<Reading>().await.unwrap(), reading());
+}
@@ -0,0 +1,65 @@
+//! Both ends of a handshake share its hash, and no two handshakes share one.
+
+use canary::async_snow::{self, HandshakePattern, SnowConfig, StaticKeypair};
+use canary::providers::Memory;
+use canary::Channel;
+
+/// both ends of an encrypted channel
+async fn pair() -> (Channel, Channel) {
+    let (a, b) = Memory::pair();
+    tokio::try_join!(a.encrypted(), b.encrypted()).unwrap()
+}
+
+#[tokio::test]
+async fn both_ends_agree() {
+    let (a, b) = pair().await;
+    let binding = a.channel_binding().expect("encrypted channels are bound");
+    assert_eq!(binding.len(), 32);
+    assert_eq!(Some(binding), b.channel_binding());
+}
+
+#[tokio::test]
+async fn handshakes_get_distinct_bindings() {
+    let (a, _) = pair().await;
+    let (c, _) = pair().await;
+    assert_ne!(a.channel_binding().unwrap(), c.channel_binding().unwrap());
+}
+
+#[tokio::test]
+async fn configured_handshakes_are_bound() {
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = (a.raw(), b.raw());
+    let config = |keypair| SnowConfig {
+        local_static: Some(keypair),
+        ..SnowConfig::new(HandshakePattern::XX)
+    };
+    let (a_config, b_config) = (
+        config(StaticKeypair::generate().unwrap()),
+        config(StaticKeypair::generate().unwrap()),
+    );
+    let (a_transport, b_transport) = tokio::try_join!(
+        async_snow::new_with_config(&mut a, &a_config),
+        async_snow::new_with_config(&mut b, &b_config)
+    )
+    .unwrap();
+    a.encrypt(a_transport).map_err(drop).unwrap();
+    b.encrypt(b_transport).map_err(drop).unwrap();
+    assert!(a.channel_binding().is_some());
+    assert_eq!(a.channel_binding(), b.channel_binding());
+}
+
+#[tokio::test]
+async fn bindings_survive_splitting() {
+    let (a, _b) = pair().await;
+    let binding = a.channel_binding().unwrap().to_vec();
+    let (send, receive) = a.split();
+    let a = Channel::join(send, receive);
+    assert_eq!(a.channel_binding(), Some(&binding[..]));
+}
+
+#[tokio::test]
+async fn raw_channels_are_unbound() {
+    let (a, b) = Memory::pair();
+    assert!(a.raw().channel_binding().is_none());
+    assert!(b.raw().channel_binding().is_none());
+}
@@ -0,0 +1,83 @@
+//! Empty messages are frames like any other, never mistaken for a closed channel.
+
+use std::fmt::Debug;
+
+use canary::async_snow::{self, Decrypt, Encrypt, RefDividedSnow};
+use canary::providers::Memory;
+use canary::Channel;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// send `value` both ways and check it arrives unchanged
+async fn assert_roundtrip<T>(a: &mut Channel, b: &mut Channel, value: T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    a.send(&value).await.unwrap();
+    assert_eq!(b.receive::<T>().await.unwrap(), value);
+    b.send(&value).await.unwrap();
+    assert_eq!(a.receive::<T>().await.unwrap(), value);
+}
+
+/// send every kind of empty message, then check the end of the channel still shows up as such
+async fn assert_empty_messages(mut a: Channel, mut b: Channel) {
+    assert_roundtrip(&mut a, &mut b, ()).await;
+    assert_roundtrip(&mut a, &mut b, Vec::<u8>::new()).await;
+    assert_roundtrip(&mut a, &mut b, String::new()).await;
+
+    a.send(()).await.unwrap();
+    a.close_send().await.unwrap();
+    assert_eq!(b.try_receive::<()>().await.unwrap(), Some(()));
+    assert_eq!(b.try_receive::<()>().await.unwrap(), None);
+
+    // a connection that goes away is an error, not an empty message
+    b.send(()).await.unwrap();
+    drop(b);
+    assert_eq!(a.try_receive::<()>().await.unwrap(), Some(()));
+    let error = a.try_receive::<()>().await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn empty_messages_roundtrip_raw() {
+    let (a, b) = Memory::pair();
+    assert_empty_messages(a.raw(), b.raw()).await;
+}
+
+#[tokio::test]
+async fn empty_messages_roundtrip_encrypted() {
+    let (a, b) = Memory::pair();
+    let (a, b) = tokio::try_join!(a.encrypted(), b.encrypted()).unwrap();
+    assert_empty_messages(a, b).await;
+}
+
+#[tokio::test]
+async fn empty_frames_are_authenticated() {
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = (a.raw(), b.raw());
+    let (a, b) = tokio::try_join!(async_snow::new(&mut a), async_snow::new(&mut b)).unwrap();
+    let (mut send, receive) = (0, 0);
+    let mut sealed = RefDividedSnow {
+        transport: &a,
+        nonce: &mut send,
+    }
+    .encrypt_packets(&[])
+    .unwrap();
+    // a single packet holding just its tag
+    assert_eq!(sealed.len(), 16);
+
+    // each attempt to open the packet starts from the same nonce
+    let open = |packet: &[u8]| {
+        let mut nonce = receive;
+        RefDividedSnow {
+            transport: &b,
+            nonce: &mut nonce,
+        }
+        .decrypt(packet)
+    };
+    // neither a frame without its packet nor a tampered tag goes through
+    assert!(open(&[]).is_err());
+    sealed[0] ^= 1;
+    assert!(open(&sealed).is_err());
+    sealed[0] ^= 1;
+    assert_eq!(open(&sealed).unwrap(), Vec::<u8>::new());
+}
@@ -0,0 +1,112 @@
+//! Every format takes any `Serialize` type at the top level, so switching formats
+//! doesn't change which messages a channel can send.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use canary::serialization::formats::{Format, ReadFormat, SendFormat};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+struct Unit;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+struct Newtype(u32);
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+struct Tuple(i8, String);
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+enum Shape {
+    Empty,
+    Circle(f64),
+    Rect { width: u16, height: u16 },
+    Path(Vec<(i32, i32)>),
+}
+
+/// struct with the field BSON wraps other values in, which has to be read as is
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+struct Wrapperlike {
+    v: u8,
+}
+
+/// check `value` round-trips through every format
+fn assert_conforms<T>(value: T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    for format in Format::SUPPORTED {
+        let bytes = SendFormat::serialize(&mut { *format }, &value)
+            .unwrap_or_else(|e| panic!("{:?} failed to serialize {:?}: {}", format, value, e));
+        let read: T = ReadFormat::deserialize(&mut { *format }, &bytes)
+            .unwrap_or_else(|e| panic!("{:?} failed to read {:?} back: {}", format, value, e));
+        assert_eq!(read, value, "{:?} changed the value", format);
+    }
+}
+
+#[test]
+fn scalars_conform() {
+    assert_conforms(());
+    assert_conforms(true);
+    assert_conforms(42u64);
+    assert_conforms(-7i32);
+    assert_conforms(1.5f64);
+    assert_conforms('c');
+    assert_conforms(String::from("bare string"));
+}
+
+#[test]
+fn structs_conform() {
+    assert_conforms(Unit);
+    assert_conforms(Newtype(7));
+    assert_conforms(Tuple(-1, "two".into()));
+    assert_conforms((1u8, "tuple".to_string(), false));
+    assert_conforms(Wrapperlike { v: 3 });
+}
+
+#[test]
+fn enums_conform() {
+    assert_conforms(Shape::Empty);
+    assert_conforms(Shape::Circle(2.5));
+    assert_conforms(Shape::Rect {
+        width: 3,
+        height: 4,
+    });
+    assert_conforms(Shape::Path(vec![(0, 0), (-1, 5)]));
+    assert_conforms(vec![Shape::Empty, Shape::Circle(1.0)]);
+}
+
+#[test]
+fn collections_conform() {
+    assert_conforms(BTreeMap::from([
+        ("a".to_string(), 1u32),
+        ("b".to_string(), 2),
+    ]));
+    assert_conforms(BTreeMap::<String, u32>::new());
+    assert_conforms(vec![1u64, 2, 3]);
+    assert_conforms(Vec::<u8>::new());
+    assert_conforms((0..=255).collect::<Vec<u8>>());
+    assert_conforms([9u8; 16]);
+    assert_conforms(Some(5u8));
+    assert_conforms(None::<String>);
+}
+
+#[cfg(feature = "bson_ser")]
+#[test]
+fn bson_wraps_only_non_documents() {
+    use canary::serialization::formats::Bson;
+
+    // a bare value is sent as a document holding it under `v`
+    let wrapped = Bson.serialize(&42u64).unwrap();
+    let document = bson::Document::from_reader(&wrapped[..]).unwrap();
+    assert_eq!(document, bson::doc! { "v": 42i64 });
+
+    // documents are sent as they are
+    let plain = Bson.serialize(&Wrapperlike { v: 3 }).unwrap();
+    let document = bson::Document::from_reader(&plain[..]).unwrap();
+    assert_eq!(document, bson::doc! { "v": 3i32 });
+    assert_eq!(
+        Bson.deserialize::<Wrapperlike>(&plain).unwrap(),
+        Wrapperlike { v: 3 }
+    );
+}
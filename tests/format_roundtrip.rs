@@ -0,0 +1,215 @@
+//! Round-trip property tests for every format the crate is built with.
+//!
+//! Values the formats can't represent are left out of the generated ones
+//! and pinned down by the tests at the end of the file instead:
+//! - JSON reads `Some(None)` back as `None`, and writes NaN and infinities as `null`
+//! - BSON can't hold integers above `i64::MAX`, nor maps whose keys aren't strings
+//! - Bincode and Postcard don't describe their types, so untagged enums can't be read back
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use canary::serialization::formats::{Format, ReadFormat, SendFormat};
+use proptest::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// serialize `value` with `format` and check it reads back unchanged
+fn assert_roundtrip<F, T>(mut format: F, value: T)
+where
+    F: SendFormat + ReadFormat,
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let bytes = format
+        .serialize(&value)
+        .unwrap_or_else(|e| panic!("failed to serialize {value:?}: {e}"));
+    let read: T = format
+        .deserialize(&bytes)
+        .unwrap_or_else(|e| panic!("failed to deserialize {value:?}: {e}"));
+    assert_eq!(read, value);
+}
+
+/// check `value` round-trips through every supported format
+fn assert_roundtrip_all<T>(value: T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug + Clone,
+{
+    for format in Format::SUPPORTED {
+        assert_roundtrip(*format, value.clone());
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+struct Scalars {
+    byte: u8,
+    small: u32,
+    large: u64,
+    signed: i64,
+    float: f64,
+    flag: bool,
+    letter: char,
+    text: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+enum Shape {
+    Empty,
+    Circle(f64),
+    Segment(i32, i32),
+    Rect { width: u32, height: u32 },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+struct Nested {
+    scalars: Scalars,
+    shapes: Vec<Shape>,
+    parent: Option<Box<Nested>>,
+    labels: HashMap<String, Option<String>>,
+    bytes: Vec<u8>,
+}
+
+fn float() -> impl Strategy<Value = f64> {
+    prop::num::f64::NORMAL | prop::num::f64::ZERO | prop::num::f64::SUBNORMAL
+}
+
+fn scalars() -> impl Strategy<Value = Scalars> {
+    (
+        any::<u8>(),
+        any::<u32>(),
+        0..=i64::MAX as u64,
+        any::<i64>(),
+        float(),
+        any::<bool>(),
+        any::<char>(),
+        any::<String>(),
+    )
+        .prop_map(
+            |(byte, small, large, signed, float, flag, letter, text)| Scalars {
+                byte,
+                small,
+                large,
+                signed,
+                float,
+                flag,
+                letter,
+                text,
+            },
+        )
+}
+
+fn shape() -> impl Strategy<Value = Shape> {
+    prop_oneof![
+        Just(Shape::Empty),
+        float().prop_map(Shape::Circle),
+        any::<(i32, i32)>().prop_map(|(a, b)| Shape::Segment(a, b)),
+        any::<(u32, u32)>().prop_map(|(width, height)| Shape::Rect { width, height }),
+    ]
+}
+
+fn nested() -> impl Strategy<Value = Nested> {
+    let leaf = (
+        scalars(),
+        prop::collection::vec(shape(), 0..8),
+        prop::collection::hash_map(any::<String>(), any::<Option<String>>(), 0..8),
+        prop::collection::vec(any::<u8>(), 0..64),
+    )
+        .prop_map(|(scalars, shapes, labels, bytes)| Nested {
+            scalars,
+            shapes,
+            parent: None,
+            labels,
+            bytes,
+        });
+    leaf.prop_recursive(3, 8, 1, |inner| {
+        (inner, scalars()).prop_map(|(parent, scalars)| Nested {
+            scalars,
+            shapes: Vec::new(),
+            parent: Some(Box::new(parent)),
+            labels: HashMap::new(),
+            bytes: Vec::new(),
+        })
+    })
+}
+
+proptest! {
+    #[test]
+    fn scalars_roundtrip(value in scalars()) {
+        assert_roundtrip_all(value);
+    }
+
+    #[test]
+    fn enums_roundtrip(value in shape()) {
+        assert_roundtrip_all(value);
+    }
+
+    #[test]
+    fn nested_roundtrip(value in nested()) {
+        assert_roundtrip_all(value);
+    }
+
+    #[test]
+    fn options_roundtrip(value in any::<Option<String>>()) {
+        assert_roundtrip_all(value);
+    }
+
+    #[test]
+    fn string_maps_roundtrip(value in prop::collection::btree_map(any::<String>(), any::<i64>(), 0..16)) {
+        assert_roundtrip_all(value);
+    }
+
+    #[test]
+    fn large_vecs_roundtrip(value in prop::collection::vec(any::<u32>(), 0..16 * 1024)) {
+        assert_roundtrip_all(value);
+    }
+}
+
+#[cfg(feature = "json_ser")]
+#[test]
+fn json_flattens_nested_options() {
+    let bytes = SendFormat::serialize(&mut Format::Json, &Some(None::<u8>)).unwrap();
+    let read: Option<Option<u8>> = Format::Json.deserialize(&bytes).unwrap();
+    assert_eq!(read, None);
+}
+
+#[cfg(feature = "json_ser")]
+#[test]
+fn json_writes_non_finite_floats_as_null() {
+    let bytes = SendFormat::serialize(&mut Format::Json, &f64::NAN).unwrap();
+    assert_eq!(bytes, b"null");
+}
+
+#[cfg(feature = "bson_ser")]
+#[test]
+fn bson_rejects_unsigned_integers_above_i64() {
+    assert!(SendFormat::serialize(&mut Format::Bson, &u64::MAX).is_err());
+}
+
+#[cfg(feature = "bson_ser")]
+#[test]
+fn bson_rejects_non_string_keys() {
+    let map = std::collections::BTreeMap::from([(1u32, "one".to_string())]);
+    assert!(SendFormat::serialize(&mut Format::Bson, &map).is_err());
+}
+
+#[cfg(any(feature = "bincode_ser", feature = "postcard_ser"))]
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(untagged)]
+enum Untagged {
+    Number(u32),
+    Text(String),
+}
+
+#[cfg(any(feature = "bincode_ser", feature = "postcard_ser"))]
+#[test]
+fn untagged_enums_need_self_describing_formats() {
+    let value = Untagged::Text("hi".into());
+    #[cfg(feature = "bincode_ser")]
+    {
+        let bytes = SendFormat::serialize(&mut Format::Bincode, &value).unwrap();
+        assert!(Format::Bincode.deserialize::<Untagged>(&bytes).is_err());
+    }
+    #[cfg(feature = "postcard_ser")]
+    {
+        let bytes = SendFormat::serialize(&mut Format::Postcard, &value).unwrap();
+        assert!(Format::Postcard.deserialize::<Untagged>(&bytes).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+//! The length prefix of stream transports is a fixed big-endian u64,
+//! so peers written in other languages can read and write frames directly.
+
+use canary::channel::frame::FrameKind;
+use canary::providers::Tcp;
+use canary::serialization::formats::{Format, SendFormat};
+use canary::serialization::framing::{decode_len, encode_len, LEN_PREFIX};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// frame of a message as a client in another language would build it
+fn message_frame(message: &str) -> Vec<u8> {
+    let mut frame = vec![FrameKind::Message as u8];
+    frame.extend(SendFormat::serialize(&mut Format::default(), &message).unwrap());
+    frame
+}
+
+#[test]
+fn prefixes_are_big_endian_u64() {
+    assert_eq!(LEN_PREFIX, 8);
+    assert_eq!(encode_len(0), [0; 8]);
+    assert_eq!(encode_len(258), [0, 0, 0, 0, 0, 0, 1, 2]);
+    assert_eq!(encode_len(0x0102_0304_0506_0708), [1, 2, 3, 4, 5, 6, 7, 8]);
+    for len in [0, 1, 255, 256, 65_535, 1 << 32, usize::MAX] {
+        assert_eq!(decode_len(encode_len(len)).unwrap(), len);
+    }
+    assert_eq!(decode_len([0, 0, 0, 0, 0, 0, 1, 2]).unwrap(), 258);
+}
+
+#[tokio::test]
+async fn channels_write_prefixed_frames() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (chan, accepted) = tokio::join!(Tcp::connect_no_backoff(addr), listener.accept());
+    let mut chan = chan.unwrap().raw();
+    chan.send("hello from rust").await.unwrap();
+    drop(chan);
+
+    let mut bytes = Vec::new();
+    accepted.unwrap().0.read_to_end(&mut bytes).await.unwrap();
+    let frame = message_frame("hello from rust");
+    let mut expected = encode_len(frame.len()).to_vec();
+    expected.extend(frame);
+    assert_eq!(bytes, expected);
+}
+
+#[tokio::test]
+async fn channels_read_hand_written_frames() {
+    let tcp = Tcp::bind("127.0.0.1:0").await.unwrap();
+    let mut stream = TcpStream::connect(tcp.local_addr().unwrap()).await.unwrap();
+    let mut chan = tcp.next().await.unwrap().raw();
+
+    // two frames in a single write, each prefixed with its length in big-endian
+    let mut bytes = Vec::new();
+    for message in ["hello from go", ""] {
+        let frame = message_frame(message);
+        bytes.extend((frame.len() as u64).to_be_bytes());
+        bytes.extend(frame);
+    }
+    stream.write_all(&bytes).await.unwrap();
+    assert_eq!(chan.receive::<String>().await.unwrap(), "hello from go");
+    assert_eq!(chan.receive::<String>().await.unwrap(), "");
+}
@@ -0,0 +1,162 @@
+//! Handshakes fail fast and clearly against peers that aren't running one.
+
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use canary::async_snow::{self, HandshakePattern, SnowConfig};
+use canary::providers::{Memory, Tcp};
+use canary::serialization::framing::{decode_len, encode_len, LEN_PREFIX};
+use canary::Result;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// accept a single connection and run the handshake on it,
+/// while `peer` drives the other end of the connection
+async fn against<F, T>(timeout: Duration, peer: impl FnOnce(TcpStream) -> F) -> (Result<()>, T)
+where
+    F: std::future::Future<Output = T>,
+{
+    let tcp = Tcp::bind("127.0.0.1:0").await.unwrap();
+    let stream = TcpStream::connect(tcp.local_addr().unwrap());
+    let (hs, stream) = tokio::join!(tcp.next(), stream);
+    let handshake = async { hs?.encrypted_with_timeout(timeout).await.map(drop) };
+    tokio::join!(handshake, peer(stream.unwrap()))
+}
+
+#[tokio::test]
+async fn silent_peers_time_out_and_are_closed() {
+    let (res, _) = against(Duration::from_millis(200), |mut stream| async move {
+        // the channel is closed once the handshake gave up
+        let mut buf = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut buf))
+            .await
+            .expect("the channel was left open")
+    })
+    .await;
+    assert_eq!(res.unwrap_err().kind(), ErrorKind::TimedOut);
+}
+
+/// run the handshake against a peer that writes `bytes` and then waits,
+/// so only what it wrote can fail the handshake
+async fn against_bytes(bytes: Vec<u8>) -> Result<()> {
+    let started = std::time::Instant::now();
+    let (res, _) = against(Duration::from_secs(10), |mut stream| async move {
+        stream.write_all(&bytes).await.unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.ok();
+    })
+    .await;
+    assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "the handshake waited"
+    );
+    res
+}
+
+/// bytes no handshake would send
+fn junk(len: u8) -> Vec<u8> {
+    (0..len).map(|i| i.wrapping_mul(37) ^ 0x5a).collect()
+}
+
+#[tokio::test]
+async fn peers_sending_junk_are_refused() {
+    let mut frame = encode_len(20).to_vec();
+    frame.extend(junk(20));
+    let error = against_bytes(frame).await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+/// error of a handshake whose peer only declares a frame of `len` bytes, without sending it
+async fn declared_frame(len: u64) -> canary::Error {
+    let prefix = len.to_be_bytes();
+    assert_eq!(prefix.len(), LEN_PREFIX);
+    against_bytes(prefix.to_vec()).await.unwrap_err()
+}
+
+#[tokio::test]
+async fn huge_length_prefixes_fail_right_away() {
+    // reserving the declared length would fail with `OutOfMemory`, or take it all
+    let error = declared_frame(u64::MAX).await;
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(
+        error.to_string().contains(&u64::MAX.to_string()),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn frames_over_the_noise_limit_are_refused_on_their_prefix() {
+    // the peer never sends the frame, so only its declared length can fail the handshake
+    let error = declared_frame(70_000).await;
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    let message = error.to_string();
+    assert!(
+        message.contains("70000") && message.contains("65535"),
+        "{}",
+        message
+    );
+}
+
+#[tokio::test]
+async fn peers_echoing_the_hello_are_given_up_on() {
+    let (res, echoed) = against(Duration::from_secs(10), |mut stream| async move {
+        // send every frame back as it came, so both sides always draw the same number
+        let mut echoed = 0;
+        loop {
+            let mut prefix = [0; LEN_PREFIX];
+            if stream.read_exact(&mut prefix).await.is_err() {
+                return echoed;
+            }
+            let mut frame = vec![0; decode_len(prefix).unwrap()];
+            stream.read_exact(&mut frame).await.unwrap();
+            stream.write_all(&prefix).await.unwrap();
+            stream.write_all(&frame).await.unwrap();
+            echoed += 1;
+        }
+    })
+    .await;
+    let error = res.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().contains("echoing"));
+    // the attempts are bounded, and the connection is closed once they ran out
+    assert_eq!(echoed, 16);
+}
+
+#[tokio::test]
+async fn unencrypted_peers_are_refused() {
+    let (a, b) = Memory::pair();
+    let mut raw = b.raw();
+    let (encrypted, sent) = tokio::join!(a.encrypted(), raw.send("hello"));
+    sent.unwrap();
+    let error = encrypted.map(drop).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(
+        error.to_string().contains("unencrypted"),
+        "the error doesn't say why: {}",
+        error
+    );
+}
+
+/// error of a responder whose peer sends `len` bytes as the first handshake message
+async fn oversized_message(len: usize) -> canary::Error {
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = (a.raw(), b.raw());
+    let config = SnowConfig {
+        initiator: Some(false),
+        ..SnowConfig::new(HandshakePattern::NN)
+    };
+    let (res, sent) = tokio::join!(
+        async_snow::new_with_config(&mut a, &config),
+        b.send(vec![0u8; len])
+    );
+    sent.unwrap();
+    res.map(drop).unwrap_err()
+}
+
+#[tokio::test]
+async fn handshake_messages_over_the_buffer_are_refused() {
+    let error = oversized_message(300).await;
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().contains("doesn't fit"), "{}", error);
+}
@@ -0,0 +1,195 @@
+//! Channels and served requests tick the metrics of the recorder the application installed.
+#![cfg(feature = "metrics")]
+
+use canary::metrics::*;
+use canary::providers::Memory;
+use metrics::{Key, Label, SharedString, Unit};
+use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+use metrics_util::{CompositeKey, MetricKind};
+
+/// Metrics recorded since the previous snapshot, since taking one resets counters and gauges.
+/// The recorder is installed for the thread of a test,
+/// which runs every task of the channels since tests use a current-thread runtime
+struct Snapshot(Vec<(CompositeKey, Option<Unit>, Option<SharedString>, DebugValue)>);
+
+impl Snapshot {
+    fn take(snapshotter: &Snapshotter) -> Self {
+        Snapshot(snapshotter.snapshot().into_vec())
+    }
+    fn get(
+        &self,
+        kind: MetricKind,
+        name: &'static str,
+        labels: &[(&'static str, &'static str)],
+    ) -> Option<&(CompositeKey, Option<Unit>, Option<SharedString>, DebugValue)> {
+        let labels: Vec<_> = labels.iter().map(|(k, v)| Label::new(*k, *v)).collect();
+        let key = CompositeKey::new(kind, Key::from_parts(name, labels));
+        self.0.iter().find(|(k, ..)| *k == key)
+    }
+    fn counter(&self, name: &'static str, labels: &[(&'static str, &'static str)]) -> u64 {
+        match self.get(MetricKind::Counter, name, labels) {
+            Some((.., DebugValue::Counter(value))) => *value,
+            _ => 0,
+        }
+    }
+    fn gauge(&self, name: &'static str, labels: &[(&'static str, &'static str)]) -> f64 {
+        match self.get(MetricKind::Gauge, name, labels) {
+            Some((.., DebugValue::Gauge(value))) => value.into_inner(),
+            _ => 0.0,
+        }
+    }
+    #[cfg(feature = "tower")]
+    fn samples(&self, name: &'static str, labels: &[(&'static str, &'static str)]) -> usize {
+        match self.get(MetricKind::Histogram, name, labels) {
+            Some((.., DebugValue::Histogram(samples))) => samples.len(),
+            _ => 0,
+        }
+    }
+}
+
+const MEMORY: &[(&str, &str)] = &[("transport", "memory")];
+
+#[tokio::test]
+async fn round_trips_tick_the_counters() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let _guard = metrics::set_default_local_recorder(&recorder);
+
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = (a.raw(), b.raw());
+    a.send("ping").await.unwrap();
+    let _: String = b.receive().await.unwrap();
+    b.send("pong").await.unwrap();
+    let _: String = a.receive().await.unwrap();
+
+    let snapshot = Snapshot::take(&snapshotter);
+    assert_eq!(snapshot.counter(FRAMES_SENT, MEMORY), 2);
+    assert_eq!(snapshot.counter(FRAMES_RECEIVED, MEMORY), 2);
+    let sent = snapshot.counter(BYTES_SENT, MEMORY);
+    assert!(sent >= "pingpong".len() as u64);
+    assert_eq!(snapshot.counter(BYTES_RECEIVED, MEMORY), sent);
+}
+
+#[tokio::test]
+async fn channels_are_counted_while_open() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let _guard = metrics::set_default_local_recorder(&recorder);
+
+    let (a, b) = Memory::pair();
+    let (a, b) = tokio::try_join!(a.encrypted(), b.encrypted()).unwrap();
+    let active = |snapshotter| Snapshot::take(snapshotter).gauge(CHANNELS_ACTIVE, MEMORY);
+    assert_eq!(active(&snapshotter), 2.0);
+
+    // each snapshot holds the change since the previous one,
+    // split halves count as one channel until both are gone
+    let (send, receive) = a.split();
+    drop(send);
+    assert_eq!(active(&snapshotter), 0.0);
+    drop(receive);
+    assert_eq!(active(&snapshotter), -1.0);
+    drop(b);
+    assert_eq!(active(&snapshotter), -1.0);
+}
+
+#[tokio::test]
+async fn failed_handshakes_are_counted() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let _guard = metrics::set_default_local_recorder(&recorder);
+
+    let (a, b) = Memory::pair();
+    let mut b = b.raw();
+    b.send("not a handshake").await.unwrap();
+    assert!(a.encrypted().await.is_err());
+    let snapshot = Snapshot::take(&snapshotter);
+    assert_eq!(snapshot.counter(HANDSHAKE_FAILURES, &[]), 1);
+}
+
+#[tokio::test]
+async fn describe_registers_every_metric() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let _guard = metrics::set_default_local_recorder(&recorder);
+
+    describe();
+    // the recorder only reports metrics once they are registered
+    let metrics = [
+        (MetricKind::Counter, BYTES_SENT),
+        (MetricKind::Counter, BYTES_RECEIVED),
+        (MetricKind::Counter, FRAMES_SENT),
+        (MetricKind::Counter, FRAMES_RECEIVED),
+        (MetricKind::Gauge, CHANNELS_ACTIVE),
+        (MetricKind::Counter, HANDSHAKE_FAILURES),
+        (MetricKind::Histogram, REQUEST_DURATION),
+        (MetricKind::Counter, SERVICE_PANICS),
+    ];
+    for (kind, name) in metrics {
+        match kind {
+            MetricKind::Counter => drop(metrics::counter!(name)),
+            MetricKind::Gauge => drop(metrics::gauge!(name)),
+            MetricKind::Histogram => drop(metrics::histogram!(name)),
+        }
+    }
+
+    let snapshot = Snapshot::take(&snapshotter);
+    for (kind, name) in metrics {
+        let (_, _, description, _) = snapshot
+            .get(kind, name, &[])
+            .unwrap_or_else(|| panic!("{} is not registered", name));
+        assert!(
+            description.as_ref().is_some_and(|d| !d.is_empty()),
+            "{} has no description",
+            name
+        );
+    }
+    let unit = |kind, name| snapshot.get(kind, name, &[]).unwrap().1;
+    assert_eq!(unit(MetricKind::Counter, BYTES_SENT), Some(Unit::Bytes));
+    assert_eq!(
+        unit(MetricKind::Histogram, REQUEST_DURATION),
+        Some(Unit::Seconds)
+    );
+}
+
+#[cfg(feature = "tower")]
+#[tokio::test]
+async fn served_requests_are_timed() {
+    use std::future::{ready, Ready};
+    use std::task::{Context, Poll};
+
+    struct Greeter;
+
+    impl tower_service::Service<String> for Greeter {
+        type Response = String;
+        type Error = canary::Error;
+        type Future = Ready<canary::Result<String>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<canary::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, name: String) -> Self::Future {
+            ready(Ok(format!("Hello {}!", name)))
+        }
+    }
+
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let _guard = metrics::set_default_local_recorder(&recorder);
+
+    let (a, b) = Memory::pair();
+    let (mut server, mut client) = (a.raw(), b.raw());
+    let client = async move {
+        for name in ["a", "b"] {
+            client.send(name).await?;
+            let _: String = client.receive().await?;
+        }
+        client.close_send().await?;
+        // kept alive until serving stops, for the acknowledgement of the close
+        Ok(client)
+    };
+    tokio::try_join!(server.serve(Greeter), client).unwrap();
+
+    let service = &[("service", std::any::type_name::<Greeter>())];
+    let snapshot = Snapshot::take(&snapshotter);
+    assert_eq!(snapshot.samples(REQUEST_DURATION, service), 2);
+}
@@ -0,0 +1,131 @@
+//! Every packet of an encrypted channel is sealed with the next nonce of its direction,
+//! so identical plaintexts never give the same ciphertext and packets can't be replayed.
+
+use canary::async_snow::{self, Decrypt, Encrypt, RefDividedSnow, StatelessTransportState};
+use canary::error::ErrorKind;
+use canary::providers::Memory;
+
+/// transports of both ends of a channel after a handshake
+async fn transports() -> (StatelessTransportState, StatelessTransportState) {
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = (a.raw(), b.raw());
+    tokio::try_join!(async_snow::new(&mut a), async_snow::new(&mut b)).unwrap()
+}
+
+fn seal(transport: &StatelessTransportState, nonce: &mut u64, plain: &[u8]) -> Vec<u8> {
+    RefDividedSnow { transport, nonce }
+        .encrypt_packets(plain)
+        .unwrap()
+}
+
+fn open(
+    transport: &StatelessTransportState,
+    nonce: &mut u64,
+    packet: &[u8],
+) -> canary::Result<Vec<u8>> {
+    RefDividedSnow { transport, nonce }.decrypt(packet)
+}
+
+#[tokio::test]
+async fn identical_plaintexts_get_distinct_ciphertexts() {
+    let (a, b) = transports().await;
+    let mut send = 0;
+    let first = seal(&a, &mut send, b"same message");
+    let second = seal(&a, &mut send, b"same message");
+    assert_ne!(first, second);
+    assert_eq!(send, 2);
+
+    let mut receive = 0;
+    assert_eq!(open(&b, &mut receive, &first).unwrap(), b"same message");
+    assert_eq!(open(&b, &mut receive, &second).unwrap(), b"same message");
+    assert_eq!(receive, 2);
+}
+
+#[tokio::test]
+async fn each_direction_counts_its_own_nonces() {
+    let (a, b) = transports().await;
+    let (mut a_send, mut b_send) = (0, 0);
+    // a sends a few packets first, which doesn't move the counter of b
+    for _ in 0..3 {
+        seal(&a, &mut a_send, b"from a");
+    }
+    let from_a = seal(&a, &mut a_send, b"hello");
+    let from_b = seal(&b, &mut b_send, b"hello");
+    assert_eq!((a_send, b_send), (4, 1));
+    // same plaintext, but each direction has its own key
+    assert_ne!(from_a, from_b);
+
+    let mut a_receive = 0;
+    assert_eq!(open(&a, &mut a_receive, &from_b).unwrap(), b"hello");
+    // the packet of a was sealed with nonce 3, so it only opens at that point of the count
+    let mut b_receive = 0;
+    assert!(open(&b, &mut b_receive, &from_a).is_err());
+    let mut b_receive = 3;
+    assert_eq!(open(&b, &mut b_receive, &from_a).unwrap(), b"hello");
+}
+
+#[tokio::test]
+async fn replayed_packets_fail() {
+    let (a, b) = transports().await;
+    let mut send = 0;
+    let packet = seal(&a, &mut send, b"transfer 100");
+    let mut receive = 0;
+    open(&b, &mut receive, &packet).unwrap();
+    let error = open(&b, &mut receive, &packet).unwrap_err();
+    assert_eq!(ErrorKind::of(&error), ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn reordered_packets_fail() {
+    let (a, b) = transports().await;
+    let mut send = 0;
+    let _first = seal(&a, &mut send, b"first");
+    let second = seal(&a, &mut send, b"second");
+    let mut receive = 0;
+    let error = open(&b, &mut receive, &second).unwrap_err();
+    assert_eq!(ErrorKind::of(&error), ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn nonces_keep_counting_across_a_rekey() {
+    let (mut a, mut b) = transports().await;
+    let mut send = 0;
+    let before = seal(&a, &mut send, b"same message");
+    a.rekey_outgoing();
+    let after = seal(&a, &mut send, b"same message");
+    assert_ne!(before, after);
+    assert_eq!(send, 2);
+
+    let mut receive = 0;
+    assert_eq!(open(&b, &mut receive, &before).unwrap(), b"same message");
+    // still on the old key, so the packet sealed after the rekey fails
+    let mut stale = receive;
+    assert!(open(&b, &mut stale, &after).is_err());
+    b.rekey_incoming();
+    assert_eq!(open(&b, &mut receive, &after).unwrap(), b"same message");
+    // a packet from before the rekey can't be replayed under the new key
+    let mut replayed = receive;
+    assert!(open(&b, &mut replayed, &before).is_err());
+}
+
+#[tokio::test]
+async fn encrypted_channels_exchange_messages_across_rekeys() {
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = tokio::try_join!(a.encrypted(), b.encrypted()).unwrap();
+    // rotate the key of a after about every message, on top of the explicit rekey
+    a.rekey_after(Some(64));
+    for i in 0..8u32 {
+        a.send(("same message", i)).await.unwrap();
+        if i == 3 {
+            a.rekey().await.unwrap();
+        }
+        b.send(i).await.unwrap();
+    }
+    for i in 0..8u32 {
+        assert_eq!(
+            b.receive::<(String, u32)>().await.unwrap(),
+            ("same message".to_string(), i)
+        );
+        assert_eq!(a.receive::<u32>().await.unwrap(), i);
+    }
+}
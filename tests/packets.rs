@@ -0,0 +1,116 @@
+//! Messages are split into packets of `PACKET_LEN` bytes, each sealed with its own tag,
+//! and put back together byte for byte.
+
+use canary::async_snow::{self, Decrypt, Encrypt, RefDividedSnow, StatelessTransportState};
+use canary::providers::Memory;
+
+/// longest plaintext sealed in a single packet, the noise maximum minus the tag
+const PACKET_LEN: usize = 65519;
+const TAG_LEN: usize = 16;
+
+/// transports of both ends of a channel after a handshake
+async fn transports() -> (StatelessTransportState, StatelessTransportState) {
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = (a.raw(), b.raw());
+    tokio::try_join!(async_snow::new(&mut a), async_snow::new(&mut b)).unwrap()
+}
+
+/// bytes that differ from packet to packet, so misplaced chunks show up
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+const SIZES: [usize; 7] = [
+    1,
+    PACKET_LEN - 1,
+    PACKET_LEN,
+    PACKET_LEN + 1,
+    2 * PACKET_LEN,
+    2 * PACKET_LEN + 1,
+    5 << 20,
+];
+
+#[tokio::test]
+async fn packets_roundtrip_across_boundaries() {
+    let (a, b) = transports().await;
+    let (mut send, mut receive) = (0, 0);
+    for len in SIZES {
+        let plain = payload(len);
+        let sealed = RefDividedSnow {
+            transport: &a,
+            nonce: &mut send,
+        }
+        .encrypt_packets(&plain)
+        .unwrap();
+        let packets = len.div_ceil(PACKET_LEN);
+        assert_eq!(
+            sealed.len(),
+            len + packets * TAG_LEN,
+            "sealing {} bytes",
+            len
+        );
+
+        let opened = RefDividedSnow {
+            transport: &b,
+            nonce: &mut receive,
+        }
+        .decrypt(&sealed)
+        .unwrap();
+        assert!(opened == plain, "{} bytes came back changed", len);
+    }
+    assert_eq!(send, receive);
+}
+
+#[tokio::test]
+async fn encrypted_messages_roundtrip_across_boundaries() {
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = tokio::try_join!(a.encrypted(), b.encrypted()).unwrap();
+    for len in SIZES {
+        let plain = payload(len);
+        // memory channels are bounded, so the message is read as it is sent
+        let (_, received) = tokio::try_join!(a.send(&plain), b.receive::<Vec<u8>>()).unwrap();
+        assert!(received == plain, "{} bytes came back changed", len);
+    }
+}
+
+/// seal a buffer the way packets always were, one `write_message` per packet
+fn reference_seal(transport: &StatelessTransportState, nonce: &mut u64, plain: &[u8]) -> Vec<u8> {
+    let mut sealed = Vec::new();
+    for packet in plain.chunks(PACKET_LEN) {
+        let mut buf = vec![0; packet.len() + TAG_LEN];
+        let len = transport.write_message(*nonce, packet, &mut buf).unwrap();
+        sealed.extend(&buf[..len]);
+        *nonce += 1;
+    }
+    sealed
+}
+
+#[tokio::test]
+async fn wire_output_matches_per_packet_sealing() {
+    let (a, b) = transports().await;
+    let (mut send, mut reference, mut receive) = (0, 0, 0);
+    for len in SIZES {
+        let plain = payload(len);
+        let sealed = RefDividedSnow {
+            transport: &a,
+            nonce: &mut send,
+        }
+        .encrypt_packets(&plain)
+        .unwrap();
+        assert!(
+            sealed == reference_seal(&a, &mut reference, &plain),
+            "{} bytes were sealed differently",
+            len
+        );
+
+        // and packets sealed one by one are read back as a whole
+        let mut opened: Vec<u8> = Vec::new();
+        for packet in sealed.chunks(PACKET_LEN + TAG_LEN) {
+            let mut buf = vec![0; packet.len()];
+            let read = b.read_message(receive, packet, &mut buf).unwrap();
+            opened.extend(&buf[..read]);
+            receive += 1;
+        }
+        assert!(opened == plain, "{} bytes came back changed", len);
+    }
+}
@@ -0,0 +1,131 @@
+//! Channels only go back to the pool once their last exchange completed.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use canary::channel::remote::RemoteError;
+use canary::providers::{Addr, Pool, PoolConfig, Tcp};
+use canary::Channel;
+
+/// serve requests one at a time: `slow:` ones are answered after a delay,
+/// `fail` with an error and anything else right away
+async fn serve(mut chan: Channel) {
+    while let Ok(Some(request)) = chan.try_receive::<String>().await {
+        if request.starts_with("slow:") {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        let sent = match request.as_str() {
+            "fail" => {
+                chan.send_error(&canary::err!(not_found, "no such thing"))
+                    .await
+            }
+            _ => chan.send(format!("reply to {}", request)).await,
+        };
+        if sent.is_err() {
+            return;
+        }
+    }
+}
+
+/// address of a server answering with `serve`
+async fn server() -> Addr {
+    let tcp = Tcp::bind("127.0.0.1:0").await.unwrap();
+    let addr = format!("tcp@{}", tcp.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    tokio::spawn(async move {
+        while let Ok(chan) = tcp.next().await {
+            tokio::spawn(async move {
+                if let Ok(chan) = chan.encrypted().await {
+                    serve(chan).await
+                }
+            });
+        }
+    });
+    addr
+}
+
+/// pool of a single channel per address, recording whether each checkout reused one
+fn pool() -> (Pool, Arc<Mutex<Vec<bool>>>) {
+    let reused = Arc::new(Mutex::new(Vec::new()));
+    let config = PoolConfig::default().max_per_addr(1).metrics({
+        let reused = reused.clone();
+        move |_, stats| reused.lock().unwrap().push(stats.reused)
+    });
+    (Pool::new(config), reused)
+}
+
+#[tokio::test]
+async fn answered_channels_are_reused() {
+    let addr = server().await;
+    let (pool, reused) = pool();
+    for i in 0..3 {
+        let mut chan = pool.get(&addr).await.unwrap();
+        chan.send(i.to_string()).await.unwrap();
+        let reply: String = chan.receive().await.unwrap();
+        assert_eq!(reply, format!("reply to {}", i));
+    }
+    assert_eq!(*reused.lock().unwrap(), [false, true, true]);
+}
+
+#[tokio::test]
+async fn error_replies_complete_the_exchange() {
+    let addr = server().await;
+    let (pool, reused) = pool();
+    let mut chan = pool.get(&addr).await.unwrap();
+    chan.send("fail").await.unwrap();
+    let error = chan.receive::<String>().await.unwrap_err();
+    assert!(RemoteError::of(&error).is_some());
+    drop(chan);
+
+    let mut chan = pool.get(&addr).await.unwrap();
+    chan.send("next").await.unwrap();
+    assert_eq!(chan.receive::<String>().await.unwrap(), "reply to next");
+    assert_eq!(*reused.lock().unwrap(), [false, true]);
+}
+
+#[tokio::test]
+async fn cancelled_requests_dont_leak_their_reply() {
+    let addr = server().await;
+    let (pool, reused) = pool();
+    let mut chan = pool.get(&addr).await.unwrap();
+    chan.send("slow:first").await.unwrap();
+    // the caller gives up before the reply comes
+    let receive = tokio::time::timeout(Duration::from_millis(20), chan.receive::<String>()).await;
+    assert!(receive.is_err());
+    drop(chan);
+
+    let mut chan = pool.get(&addr).await.unwrap();
+    chan.send("second").await.unwrap();
+    assert_eq!(chan.receive::<String>().await.unwrap(), "reply to second");
+    assert_eq!(*reused.lock().unwrap(), [false, false]);
+}
+
+#[tokio::test]
+async fn unanswered_requests_keep_the_channel_out() {
+    let addr = server().await;
+    let (pool, reused) = pool();
+    let mut chan = pool.get(&addr).await.unwrap();
+    chan.send("first").await.unwrap();
+    drop(chan);
+
+    let mut chan = pool.get(&addr).await.unwrap();
+    chan.send("second").await.unwrap();
+    assert_eq!(chan.receive::<String>().await.unwrap(), "reply to second");
+    assert_eq!(*reused.lock().unwrap(), [false, false]);
+}
+
+#[tokio::test]
+async fn untracked_use_keeps_the_channel_out() {
+    let addr = server().await;
+    let (pool, reused) = pool();
+    let mut chan = pool.get(&addr).await.unwrap();
+    // goes through `DerefMut`, which the pool can't follow
+    chan.send_result::<_, String>(Ok("first")).await.unwrap();
+    drop(chan);
+
+    let mut chan = pool.get(&addr).await.unwrap();
+    chan.send("second").await.unwrap();
+    assert_eq!(chan.receive::<String>().await.unwrap(), "reply to second");
+    assert_eq!(*reused.lock().unwrap(), [false, false]);
+}
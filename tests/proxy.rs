@@ -0,0 +1,133 @@
+//! Proxies are handed the destination the way it was named, and tunnel the channel.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::time::Duration;
+
+use canary::providers::{ConnectOptions, Destination, Proxy, Tcp};
+use canary::Channel;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// fake proxy accepting a single connection, returns its address
+async fn fake_proxy() -> (String, TcpListener) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    (listener.local_addr().unwrap().to_string(), listener)
+}
+
+/// read the SOCKS5 greeting and connect request, accept it and return the request
+async fn socks5(listener: TcpListener) -> (TcpStream, Vec<u8>) {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut greeting = [0u8; 3];
+    stream.read_exact(&mut greeting).await.unwrap();
+    assert_eq!(greeting, [5, 1, 0]);
+    stream.write_all(&[5, 0]).await.unwrap();
+
+    let mut request = vec![0u8; 4];
+    stream.read_exact(&mut request).await.unwrap();
+    let addr_len = match request[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let len = stream.read_u8().await.unwrap();
+            request.push(len);
+            len as usize
+        }
+        atyp => panic!("unexpected address type {}", atyp),
+    };
+    let mut rest = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut rest).await.unwrap();
+    request.extend(rest);
+    stream
+        .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+        .await
+        .unwrap();
+    (stream, request)
+}
+
+/// read the `CONNECT` request, accept it and return the request
+async fn http_connect(listener: TcpListener) -> (TcpStream, String) {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut request = Vec::new();
+    while !request.ends_with(b"\r\n\r\n") {
+        request.push(stream.read_u8().await.unwrap());
+    }
+    stream
+        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+        .await
+        .unwrap();
+    (stream, String::from_utf8(request).unwrap())
+}
+
+/// connect to `addrs` through the proxy, which runs `accept`
+async fn connect<T>(
+    addrs: impl Destination + Debug,
+    proxy: Proxy,
+    accept: impl Future<Output = T>,
+) -> (Channel, T) {
+    let connect = Tcp::connect_with(addrs, ConnectOptions::default().retries(0).proxy(proxy));
+    let joined = async { tokio::join!(connect, accept) };
+    let (chan, accepted) = tokio::time::timeout(Duration::from_secs(5), joined)
+        .await
+        .expect("the proxy was never reached");
+    (chan.unwrap().raw(), accepted)
+}
+
+/// check the tunnel carries the channel once the proxy accepted it
+async fn tunnels(mut proxy_side: TcpStream, mut chan: Channel) {
+    chan.send("through the tunnel").await.unwrap();
+    let mut received = [0u8; 1];
+    proxy_side.read_exact(&mut received).await.unwrap();
+}
+
+#[tokio::test]
+async fn socks5_is_given_host_names() {
+    let (addr, listener) = fake_proxy().await;
+    let proxy = Proxy::Socks5 { addr, auth: None };
+    // `.invalid` never resolves, so this only works if the proxy resolves it
+    let (chan, (stream, request)) =
+        connect("db.example.invalid:5432", proxy, socks5(listener)).await;
+
+    let mut expected = vec![5, 1, 0, 3, 18];
+    expected.extend_from_slice(b"db.example.invalid");
+    expected.extend_from_slice(&5432u16.to_be_bytes());
+    assert_eq!(request, expected);
+    tunnels(stream, chan).await;
+}
+
+#[tokio::test]
+async fn socks5_is_given_addresses() {
+    let (addr, listener) = fake_proxy().await;
+    let proxy = Proxy::Socks5 { addr, auth: None };
+    let (chan, (stream, request)) = connect("10.0.0.1:8080", proxy, socks5(listener)).await;
+
+    assert_eq!(request, [5, 1, 0, 1, 10, 0, 0, 1, 0x1f, 0x90]);
+    tunnels(stream, chan).await;
+}
+
+#[tokio::test]
+async fn http_connect_is_given_host_names() {
+    let (addr, listener) = fake_proxy().await;
+    let proxy = Proxy::HttpConnect { addr, auth: None };
+    let (chan, (stream, request)) =
+        connect(("db.example.invalid", 5432), proxy, http_connect(listener)).await;
+
+    assert_eq!(
+        request,
+        "CONNECT db.example.invalid:5432 HTTP/1.1\r\nHost: db.example.invalid:5432\r\n\r\n"
+    );
+    tunnels(stream, chan).await;
+}
+
+#[tokio::test]
+async fn http_connect_is_given_addresses() {
+    let (addr, listener) = fake_proxy().await;
+    let proxy = Proxy::HttpConnect { addr, auth: None };
+    let (chan, (stream, request)) = connect("[::1]:8080", proxy, http_connect(listener)).await;
+
+    assert_eq!(
+        request,
+        "CONNECT [::1]:8080 HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n"
+    );
+    tunnels(stream, chan).await;
+}
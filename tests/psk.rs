@@ -0,0 +1,74 @@
+//! Only peers holding the same pre-shared keys complete a handshake.
+
+use std::io::ErrorKind;
+
+use canary::async_snow::{self, HandshakePattern, SnowConfig};
+use canary::providers::Memory;
+use canary::Channel;
+
+/// configuration with a single pre-shared key at `location`
+fn config(location: u8, psk: [u8; 32]) -> SnowConfig {
+    SnowConfig {
+        psks: vec![(location, psk)],
+        ..SnowConfig::new(HandshakePattern::NN)
+    }
+}
+
+/// run the handshake on `chan`, which is dropped if it fails so the peer isn't left waiting
+async fn run(mut chan: Channel, config: SnowConfig) -> canary::Result<Channel> {
+    let transport = async_snow::new_with_config(&mut chan, &config).await?;
+    chan.encrypt(transport).map_err(drop).unwrap();
+    Ok(chan)
+}
+
+/// run the handshake between peers with the given configurations,
+/// returning the result of each side after checking a message gets through
+async fn handshake(a: SnowConfig, b: SnowConfig) -> (canary::Result<()>, canary::Result<()>) {
+    let (a_chan, b_chan) = Memory::pair();
+    let (a_chan, b_chan) = tokio::join!(run(a_chan.raw(), a), run(b_chan.raw(), b));
+    let (mut a_chan, mut b_chan) = match (a_chan, b_chan) {
+        (Ok(a), Ok(b)) => (a, b),
+        (a, b) => return (a.map(drop), b.map(drop)),
+    };
+    a_chan.send("secret").await.unwrap();
+    assert_eq!(b_chan.receive::<String>().await.unwrap(), "secret");
+    (Ok(()), Ok(()))
+}
+
+/// check at least one side refused the peer with `PermissionDenied`
+/// and the other one didn't complete the handshake either
+fn assert_refused((a, b): (canary::Result<()>, canary::Result<()>)) {
+    let (a, b) = (a.unwrap_err(), b.unwrap_err());
+    assert!(
+        a.kind() == ErrorKind::PermissionDenied || b.kind() == ErrorKind::PermissionDenied,
+        "expected a PermissionDenied error, got `{}` and `{}`",
+        a,
+        b
+    );
+}
+
+#[tokio::test]
+async fn matching_psks_complete_the_handshake() {
+    for location in [0, 2] {
+        let (a, b) = handshake(config(location, [7; 32]), config(location, [7; 32])).await;
+        a.unwrap();
+        b.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn mismatched_psk0_is_refused() {
+    assert_refused(handshake(config(0, [7; 32]), config(0, [8; 32])).await);
+}
+
+#[tokio::test]
+async fn mismatched_psk2_is_refused() {
+    assert_refused(handshake(config(2, [7; 32]), config(2, [8; 32])).await);
+}
+
+#[tokio::test]
+async fn missing_psk_is_refused() {
+    let without = SnowConfig::new(HandshakePattern::NN);
+    let (a, b) = handshake(config(0, [7; 32]), without).await;
+    assert!(a.is_err() && b.is_err());
+}
@@ -0,0 +1,92 @@
+//! Traffic keeps flowing while the keys of an encrypted channel are rotated.
+
+use canary::async_snow::{self, HandshakePattern, SnowConfig};
+use canary::providers::Memory;
+use canary::Channel;
+
+/// both ends of an encrypted channel
+async fn pair() -> (Channel, Channel) {
+    let (a, b) = Memory::pair();
+    tokio::try_join!(a.encrypted(), b.encrypted()).unwrap()
+}
+
+#[tokio::test]
+async fn manual_rekeys_keep_round_trips_going() {
+    let (mut a, mut b) = pair().await;
+    for i in 0..200u32 {
+        if i % 10 == 0 {
+            a.rekey().await.unwrap();
+        }
+        if i % 15 == 0 {
+            b.rekey().await.unwrap();
+        }
+        a.send(i).await.unwrap();
+        assert_eq!(b.receive::<u32>().await.unwrap(), i);
+        b.send(i * 2).await.unwrap();
+        assert_eq!(a.receive::<u32>().await.unwrap(), i * 2);
+    }
+}
+
+#[tokio::test]
+async fn messages_in_flight_survive_automatic_rekeys() {
+    let (a, b) = pair().await;
+    let (mut send, _) = a.split();
+    let (_, mut receive) = b.split();
+    // rotates the key every few messages, while earlier ones are still unread
+    send.rekey_after(Some(64));
+    let sending = async {
+        for i in 0..1000u32 {
+            send.send(format!("message {}", i)).await?;
+        }
+        Ok::<_, canary::Error>(())
+    };
+    let receiving = async {
+        for i in 0..1000u32 {
+            assert_eq!(receive.receive::<String>().await?, format!("message {}", i));
+        }
+        Ok::<_, canary::Error>(())
+    };
+    tokio::try_join!(sending, receiving).unwrap();
+}
+
+#[tokio::test]
+async fn large_messages_cross_rekey_boundaries() {
+    let (mut a, mut b) = pair().await;
+    a.rekey_after(Some(1024));
+    let payload: Vec<u8> = (0..300_000).map(|i| (i % 251) as u8).collect();
+    let (_, received) = tokio::try_join!(a.send(&payload), b.receive::<Vec<u8>>()).unwrap();
+    assert!(received == payload);
+    a.send("after").await.unwrap();
+    assert_eq!(b.receive::<String>().await.unwrap(), "after");
+}
+
+#[tokio::test]
+async fn handshake_config_sets_the_threshold() {
+    let (a, b) = Memory::pair();
+    let (mut a, mut b) = (a.raw(), b.raw());
+    let config = SnowConfig {
+        rekey_after_bytes: Some(64),
+        ..SnowConfig::new(HandshakePattern::NN)
+    };
+    let (a_transport, b_transport) = tokio::try_join!(
+        async_snow::new_with_config(&mut a, &config),
+        async_snow::new_with_config(&mut b, &config)
+    )
+    .unwrap();
+    a.encrypt(a_transport).map_err(drop).unwrap();
+    b.encrypt(b_transport).map_err(drop).unwrap();
+    for i in 0..100u32 {
+        a.send(i).await.unwrap();
+        assert_eq!(b.receive::<u32>().await.unwrap(), i);
+        b.send(i).await.unwrap();
+        assert_eq!(a.receive::<u32>().await.unwrap(), i);
+    }
+}
+
+#[tokio::test]
+async fn raw_channels_cant_rekey() {
+    let (a, _b) = Memory::pair();
+    let mut a = a.raw();
+    let error = a.rekey().await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+}
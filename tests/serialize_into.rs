@@ -0,0 +1,86 @@
+//! Bincode sizes a message before writing it, so `serialize_into` grows the buffer
+//! at most once however many pieces the message is written in.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use canary::serialization::formats::{Bincode, SendFormat};
+use serde::Serialize;
+
+/// the system allocator, counting the allocations and reallocations of each thread,
+/// since tests run side by side
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count() {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// how many times `f` allocated on this thread
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let out = f();
+    (out, ALLOCATIONS.with(Cell::get) - before)
+}
+
+#[derive(Serialize)]
+struct Message {
+    id: u64,
+    name: String,
+    tags: Vec<String>,
+    samples: Vec<u32>,
+}
+
+fn message() -> Message {
+    Message {
+        id: 42,
+        name: "temperature".into(),
+        tags: vec!["kitchen".into(), "celsius".into()],
+        samples: (0..1024).collect(),
+    }
+}
+
+#[test]
+fn bincode_reserves_exactly_once() {
+    let message = message();
+    // a frame starts with its kind byte, the message goes after it
+    let mut buf = vec![0u8];
+    let (size, allocations) = allocations(|| Bincode.serialize_into(&message, &mut buf).unwrap());
+    assert_eq!(allocations, 1);
+    assert_eq!(size, buf.len() - 1);
+    assert_eq!(&buf[1..], Bincode.serialize(&message).unwrap());
+}
+
+#[test]
+fn bincode_does_not_allocate_into_a_warm_buffer() {
+    let message = message();
+    let mut buf = Vec::new();
+    Bincode.serialize_into(&message, &mut buf).unwrap();
+    let written = buf.clone();
+
+    buf.clear();
+    let (size, allocations) = allocations(|| Bincode.serialize_into(&message, &mut buf).unwrap());
+    assert_eq!(allocations, 0);
+    assert_eq!(size, written.len());
+    assert_eq!(buf, written);
+}
@@ -0,0 +1,75 @@
+//! Graceful shutdown of the process-wide controller.
+//!
+//! The controller is global and only shuts down once,
+//! so the whole sequence runs in a single test.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use canary::audit::AuditKind;
+use canary::providers::Memory;
+use canary::shutdown;
+
+/// sets the flag when dropped, such as when its task is aborted
+struct SetOnDrop(Arc<AtomicBool>);
+
+impl Drop for SetOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[tokio::test]
+async fn stragglers_are_aborted_and_counted() {
+    let (events, received) = mpsc::channel();
+    canary::audit::set_sink(move |event| {
+        events.send(event).ok();
+    })
+    .unwrap();
+
+    let finished = Arc::new(AtomicBool::new(false));
+    let dropped = Arc::new(AtomicBool::new(false));
+    let outlived = Arc::new(AtomicBool::new(false));
+    let handle = shutdown::handle();
+    handle.spawn({
+        let finished = finished.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            finished.store(true, Ordering::SeqCst);
+        }
+    });
+    handle.spawn({
+        let guard = SetOnDrop(dropped.clone());
+        let outlived = outlived.clone();
+        async move {
+            let _guard = guard;
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+            outlived.store(true, Ordering::SeqCst);
+        }
+    });
+
+    handle.begin(Duration::from_millis(100)).await;
+    assert!(
+        finished.load(Ordering::SeqCst),
+        "task within the grace period didn't finish"
+    );
+    assert!(dropped.load(Ordering::SeqCst), "straggler wasn't aborted");
+    assert!(!outlived.load(Ordering::SeqCst));
+    // already completed, so this resolves right away
+    handle.completed().await;
+    assert!(handle.is_begun());
+
+    let aborted = received
+        .iter()
+        .find_map(|event| match event.kind {
+            AuditKind::ShutdownCompleted { aborted } => Some(aborted),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(aborted, 1);
+
+    // providers stop accepting once the shutdown began
+    let memory = Memory::bind("shutdown").await.unwrap();
+    assert!(memory.next().await.is_err());
+}
@@ -0,0 +1,78 @@
+//! Work offloaded with `spawn_blocking` leaves the other tasks of the executor running.
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::io::ErrorKind;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use canary::providers::Memory;
+use canary::runtime::{spawn, spawn_blocking};
+use canary::Channel;
+
+/// stand-in for an expensive hash such as argon2
+fn hash(password: &str) -> u64 {
+    password
+        .bytes()
+        .fold(0u64, |hash, byte| hash.rotate_left(5) ^ byte as u64)
+}
+
+/// hashes every password it receives, blocking until `release` lets it through
+async fn hashing_service(mut chan: Channel, release: mpsc::Receiver<()>) -> canary::Result<()> {
+    let password: String = chan.receive().await?;
+    let hash = spawn_blocking(move || {
+        release.recv().unwrap();
+        hash(&password)
+    })
+    .await?;
+    chan.send(hash).await?;
+    Ok(())
+}
+
+async fn echo_service(mut chan: Channel) -> canary::Result<()> {
+    while let Some(message) = chan.try_receive::<String>().await? {
+        chan.send(message).await?;
+    }
+    Ok(())
+}
+
+// a current-thread runtime, where blocking inline would stall every service
+#[tokio::test]
+async fn other_services_stay_responsive() {
+    let (hashing, mut hashing_client) = {
+        let (a, b) = Memory::pair();
+        (a.raw(), b.raw())
+    };
+    let (echo, mut echo_client) = {
+        let (a, b) = Memory::pair();
+        (a.raw(), b.raw())
+    };
+    let (release, released) = mpsc::channel();
+    let hashing = spawn(hashing_service(hashing, released));
+    let _echo = spawn(echo_service(echo));
+
+    hashing_client.send("hunter2").await.unwrap();
+    // the hash can't finish before the echoes, they only release it afterwards
+    for i in 0..5 {
+        let echoed: String = tokio::time::timeout(Duration::from_secs(5), async {
+            echo_client.send(i.to_string()).await?;
+            echo_client.receive().await
+        })
+        .await
+        .expect("the echo service is stalled by the hashing one")
+        .unwrap();
+        assert_eq!(echoed, i.to_string());
+    }
+    assert!(!hashing.is_finished());
+
+    release.send(()).unwrap();
+    let hashed: u64 = hashing_client.receive().await.unwrap();
+    assert_eq!(hashed, hash("hunter2"));
+    hashing.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn panics_become_errors() {
+    let error = spawn_blocking(|| panic!("hash failed")).await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Other);
+    assert_eq!(spawn_blocking(|| hash("")).await.unwrap(), 0);
+}
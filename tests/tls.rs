@@ -0,0 +1,164 @@
+#![cfg(feature = "tls")]
+//! Channels over TLS talk to plain rustls endpoints, with nothing of their own below
+//! the frames: the decrypted stream holds exactly what a TCP channel would send.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+
+use canary::providers::rustls::{
+    self,
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+    ClientConfig, RootCertStore, ServerConfig,
+};
+use canary::providers::{ClientTlsConfig, ServerTlsConfig, Tcp, Tls};
+use tokio::io::AsyncReadExt;
+
+/// self-signed certificate for `localhost` and its key
+fn certificate() -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let key = PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into());
+    (certified.cert.der().clone(), key)
+}
+
+fn roots(cert: &CertificateDer<'static>) -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots.add(cert.clone()).unwrap();
+    roots
+}
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+/// bytes a TCP channel sends for the message, which TLS should carry unchanged
+async fn tcp_bytes(message: &str) -> Vec<u8> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (chan, accepted) = tokio::join!(Tcp::connect_no_backoff(addr), listener.accept());
+    let mut chan = chan.unwrap().raw();
+    chan.send(message).await.unwrap();
+    drop(chan);
+    let mut bytes = Vec::new();
+    accepted.unwrap().0.read_to_end(&mut bytes).await.unwrap();
+    bytes
+}
+
+/// plain rustls client writing `send` and reading `receive_len` bytes back
+fn vanilla_client(
+    addr: SocketAddr,
+    root: CertificateDer<'static>,
+    client_auth: Option<(CertificateDer<'static>, PrivateKeyDer<'static>)>,
+    send: Vec<u8>,
+    receive_len: usize,
+) -> std::io::Result<Vec<u8>> {
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots(&root));
+    let config = match client_auth {
+        Some((cert, key)) => builder.with_client_auth_cert(vec![cert], key).unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+    let name = ServerName::try_from("localhost").unwrap();
+    let conn = rustls::ClientConnection::new(Arc::new(config), name).unwrap();
+    let mut stream = rustls::StreamOwned::new(conn, TcpStream::connect(addr)?);
+    stream.write_all(&send)?;
+    stream.flush()?;
+    let mut received = vec![0; receive_len];
+    stream.read_exact(&mut received)?;
+    Ok(received)
+}
+
+#[tokio::test]
+async fn tls_servers_talk_to_plain_rustls_clients() {
+    let (cert, key) = certificate();
+    let tls = Tls::bind("127.0.0.1:0", ServerTlsConfig::new(vec![cert.clone()], key))
+        .await
+        .unwrap();
+    let addr = tls.local_addr().unwrap();
+    let (from_client, from_server) = (
+        tcp_bytes("from client").await,
+        tcp_bytes("from server").await,
+    );
+
+    let len = from_server.len();
+    let client =
+        tokio::task::spawn_blocking(move || vanilla_client(addr, cert, None, from_client, len));
+    let mut chan = tls.next().await.unwrap().raw();
+    assert_eq!(chan.receive::<String>().await.unwrap(), "from client");
+    chan.send("from server").await.unwrap();
+    assert_eq!(client.await.unwrap().unwrap(), from_server);
+    // the client didn't authenticate
+    assert!(chan.peer_certificates().is_none());
+}
+
+#[tokio::test]
+async fn tls_clients_talk_to_plain_rustls_servers() {
+    let (cert, key) = certificate();
+    let config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.clone()], key)
+        .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (from_client, from_server) = (
+        tcp_bytes("from client").await,
+        tcp_bytes("from server").await,
+    );
+
+    let len = from_client.len();
+    let server = std::thread::spawn(move || {
+        let conn = rustls::ServerConnection::new(Arc::new(config)).unwrap();
+        let mut stream = rustls::StreamOwned::new(conn, listener.accept().unwrap().0);
+        let mut received = vec![0; len];
+        stream.read_exact(&mut received).unwrap();
+        stream.write_all(&from_server).unwrap();
+        stream.flush().unwrap();
+        received
+    });
+    let config = ClientTlsConfig::new(roots(&cert), "localhost").unwrap();
+    let mut chan = Tls::connect(addr, config).await.unwrap().raw();
+    chan.send("from client").await.unwrap();
+    assert_eq!(chan.receive::<String>().await.unwrap(), "from server");
+    assert_eq!(server.join().unwrap(), from_client);
+    // the certificate of the server is known to the channel
+    assert_eq!(chan.peer_certificates(), Some(&[cert][..]));
+}
+
+#[tokio::test]
+async fn client_certificates_reach_the_channel() {
+    let (server_cert, server_key) = certificate();
+    let (client_cert, client_key) = certificate();
+    let config = ServerTlsConfig::new(vec![server_cert.clone()], server_key)
+        .client_auth(roots(&client_cert));
+    let tls = Tls::bind("127.0.0.1:0", config).await.unwrap();
+    let addr = tls.local_addr().unwrap();
+    let message = tcp_bytes("authenticated").await;
+
+    // handshakes run while accepting, so the listener has to be waiting for the clients
+    let server = tokio::spawn(async move {
+        let mut chan = tls.next().await.unwrap().raw();
+        let message = chan.receive::<String>().await.unwrap();
+        (message, chan.peer_certificates().map(<[_]>::to_vec))
+    });
+
+    // a client without a certificate is turned away, and the listener moves on to the next one
+    let anonymous = {
+        let (root, message) = (server_cert.clone(), message.clone());
+        tokio::task::spawn_blocking(move || vanilla_client(addr, root, None, message, 1))
+    };
+    assert!(anonymous.await.unwrap().is_err());
+
+    let client_auth = Some((client_cert.clone(), client_key));
+    let client = tokio::task::spawn_blocking(move || {
+        vanilla_client(addr, server_cert, client_auth, message, 0)
+    });
+    client.await.unwrap().unwrap();
+    let (message, certificates) = server.await.unwrap();
+    assert_eq!(message, "authenticated");
+    assert_eq!(certificates, Some(vec![client_cert]));
+}
@@ -0,0 +1,81 @@
+//! Every format fails cleanly on input that isn't a value of the type asked for.
+
+use canary::serialization::formats::{Format, SendFormat};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Request {
+    id: u32,
+    name: String,
+    payload: Vec<u8>,
+    reply_to: Option<String>,
+}
+
+/// the bytes of `value` in `format`
+fn serialize(mut format: Format, value: &impl Serialize) -> Vec<u8> {
+    SendFormat::serialize(&mut format, value).unwrap()
+}
+
+fn request() -> Request {
+    Request {
+        id: 7,
+        name: "lookup".into(),
+        payload: vec![1, 2, 3],
+        reply_to: Some("client".into()),
+    }
+}
+
+#[test]
+fn empty_input_is_refused() {
+    for format in Format::SUPPORTED {
+        let res = format.try_deserialize::<Request>(&[]);
+        assert!(res.is_err(), "{:?} read {:?} from nothing", format, res);
+    }
+}
+
+#[test]
+fn garbage_is_refused() {
+    let garbage: &[&[u8]] = &[
+        &[0xff; 64],
+        &[0x00; 3],
+        b"not a request at all",
+        &[0xc1, 0x80, 0x7f, 0xfe, 0x13, 0x37],
+    ];
+    for format in Format::SUPPORTED {
+        for bytes in garbage {
+            let res = format.try_deserialize::<Request>(bytes);
+            assert!(res.is_err(), "{:?} read {:?} from {:?}", format, res, bytes);
+        }
+    }
+}
+
+#[test]
+fn truncated_input_is_refused() {
+    for format in Format::SUPPORTED {
+        let bytes = serialize(*format, &request());
+        assert_eq!(
+            format.try_deserialize::<Request>(&bytes).unwrap(),
+            request()
+        );
+        for len in 0..bytes.len() {
+            let res = format.try_deserialize::<Request>(&bytes[..len]);
+            assert!(
+                res.is_err(),
+                "{:?} read {:?} from the first {} of {} bytes",
+                format,
+                res,
+                len,
+                bytes.len()
+            );
+        }
+    }
+}
+
+#[test]
+fn other_types_are_refused() {
+    for format in Format::SUPPORTED {
+        let bytes = serialize(*format, &("lookup", -1i64));
+        let res = format.try_deserialize::<Request>(&bytes);
+        assert!(res.is_err(), "{:?} read {:?} from a tuple", format, res);
+    }
+}
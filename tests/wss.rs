@@ -0,0 +1,96 @@
+//! Websocket channels behave like stream channels, within the message size limit.
+
+use canary::error::ErrorKind;
+use canary::providers::{Tcp, WebSocket, WssConnectOptions};
+use canary::Channel;
+
+/// sizes of the messages sent, the larger ones spanning several noise packets
+const SIZES: [usize; 5] = [0, 1, 65_535, 200_000, 1 << 20];
+
+/// send every payload and check the peer echoes it back unchanged
+async fn echo_roundtrip(client: &mut Channel) {
+    for size in SIZES {
+        let payload: Vec<u8> = (0..size).map(|i| i as u8).collect();
+        client.send(&payload).await.unwrap();
+        let echoed: Vec<u8> = client.receive().await.unwrap();
+        assert!(echoed == payload, "payload of {} bytes changed", size);
+    }
+}
+
+/// echo every message back until the channel closes
+async fn echo(mut chan: Channel) {
+    while let Ok(Some(message)) = chan.try_receive::<Vec<u8>>().await {
+        chan.send(message).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn encrypted_service_behaves_the_same_over_tcp_and_websockets() {
+    let tcp = Tcp::bind("127.0.0.1:0").await.unwrap();
+    let addr = tcp.local_addr().unwrap();
+    let server =
+        tokio::spawn(
+            async move { echo(tcp.next().await.unwrap().encrypted().await.unwrap()).await },
+        );
+    let mut client = Tcp::connect(addr).await.unwrap().encrypted().await.unwrap();
+    echo_roundtrip(&mut client).await;
+    drop(client);
+    server.await.unwrap();
+
+    let wss = WebSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = <&tokio::net::TcpListener>::from(&wss).local_addr().unwrap();
+    let server =
+        tokio::spawn(
+            async move { echo(wss.next().await.unwrap().encrypted().await.unwrap()).await },
+        );
+    let mut client = WebSocket::connect(addr)
+        .await
+        .unwrap()
+        .encrypted()
+        .await
+        .unwrap();
+    echo_roundtrip(&mut client).await;
+    drop(client);
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn oversized_messages_are_rejected() {
+    let wss = WebSocket::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .max_message_size(1024);
+    let addr = <&tokio::net::TcpListener>::from(&wss).local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let mut chan = wss.next().await.unwrap().raw();
+        let small: Vec<u8> = chan.receive().await.unwrap();
+        assert_eq!(small.len(), 512);
+        chan.receive::<Vec<u8>>().await.unwrap_err()
+    });
+    let mut client = WebSocket::connect(addr).await.unwrap().raw();
+    client.send(vec![0u8; 512]).await.unwrap();
+    client.send(vec![0u8; 4096]).await.unwrap();
+    let error = server.await.unwrap();
+    assert_eq!(ErrorKind::of(&error), ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn clients_limit_messages_from_the_server() {
+    let wss = WebSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = <&tokio::net::TcpListener>::from(&wss).local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let mut chan = wss.next().await.unwrap().raw();
+        chan.send(vec![0u8; 4096]).await.unwrap();
+        // keep the channel open until the client read the message
+        chan.try_receive::<()>().await.ok();
+    });
+    let options = WssConnectOptions::default().max_message_size(1024);
+    let mut client = WebSocket::connect_with_options(addr, options)
+        .await
+        .unwrap()
+        .raw();
+    let error = client.receive::<Vec<u8>>().await.unwrap_err();
+    assert_eq!(ErrorKind::of(&error), ErrorKind::InvalidData);
+    drop(client);
+    server.await.unwrap();
+}